@@ -23,6 +23,16 @@ var (
 	ErrInvalidResponse = errors.New("cxdb: invalid response")
 )
 
+// Server error codes. These mirror the HTTP status taxonomy so binary and
+// HTTP clients can react to the same conditions.
+const (
+	ErrorCodeNotFound              uint32 = 404
+	ErrorCodeConflict              uint32 = 409 // missing parent or base turn
+	ErrorCodeInvalidInput          uint32 = 422
+	ErrorCodeTypeDescriptorMissing uint32 = 424
+	ErrorCodeInternal              uint32 = 500
+)
+
 // ServerError represents an error returned by the CXDB server.
 type ServerError struct {
 	Code   uint32
@@ -33,7 +43,8 @@ func (e *ServerError) Error() string {
 	return fmt.Sprintf("cxdb server error %d: %s", e.Code, e.Detail)
 }
 
-// IsServerError checks if an error is a ServerError with the given code.
+// IsServerError checks if an error is a ServerError with the given code,
+// e.g. IsServerError(err, ErrorCodeTypeDescriptorMissing).
 func IsServerError(err error, code uint32) bool {
 	var se *ServerError
 	if errors.As(err, &se) {
@@ -0,0 +1,41 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+package cxdb
+
+import (
+	"encoding/binary"
+	"fmt"
+	"testing"
+)
+
+func errorPayload(code uint32, detail string) []byte {
+	payload := make([]byte, 8+len(detail))
+	binary.LittleEndian.PutUint32(payload[0:4], code)
+	binary.LittleEndian.PutUint32(payload[4:8], uint32(len(detail)))
+	copy(payload[8:], detail)
+	return payload
+}
+
+func TestIsServerError_MissingTypeDescriptor(t *testing.T) {
+	err := parseServerError(errorPayload(424, "type_descriptor: com.example.Missing v1"))
+	if !IsServerError(err, ErrorCodeTypeDescriptorMissing) {
+		t.Errorf("expected a 424 server error, got %v", err)
+	}
+	if IsServerError(err, ErrorCodeNotFound) {
+		t.Error("a missing descriptor should not match 404")
+	}
+}
+
+func TestIsServerError_Wrapped(t *testing.T) {
+	err := fmt.Errorf("append: %w", parseServerError(errorPayload(409, "parent_turn: 7")))
+	if !IsServerError(err, ErrorCodeConflict) {
+		t.Errorf("expected a wrapped 409 server error, got %v", err)
+	}
+}
+
+func TestIsServerError_NotServerError(t *testing.T) {
+	if IsServerError(ErrClientClosed, ErrorCodeInternal) {
+		t.Error("ErrClientClosed is not a server error")
+	}
+}
@@ -0,0 +1,327 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tokio-based client, enabled with the `async` feature.
+//!
+//! Payload encoding and decoding are shared with the synchronous [`Client`];
+//! only the socket I/O is async. Requests on one `AsyncClient` are serialized
+//! over a single connection, matching the synchronous client.
+//!
+//! [`Client`]: crate::client::Client
+
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::client::{
+    encode_hello_payload, parse_hello_protocol_version, parse_hello_session_id, parse_server_error,
+    ClientOption, ClientOptions, RequestContext,
+};
+use crate::context::{parse_context_head, ContextHead};
+use crate::error::{Error, Result};
+use crate::fs::parse_blob_payload;
+use crate::protocol::{
+    read_frame, write_frame, Capabilities, Frame, MAX_FRAME_SIZE, MSG_APPEND_TURN, MSG_CTX_CREATE,
+    MSG_ERROR, MSG_GET_BLOB, MSG_GET_HEAD, MSG_GET_LAST, MSG_HELLO,
+};
+use crate::turn::{
+    encode_append_payload, encode_get_last_payload, get_last_flags, parse_append_result,
+    parse_get_last_response, AppendRequest, AppendResult, GetLastOptions, TurnRecord,
+};
+
+const HEADER_LEN: usize = 16;
+
+pub struct AsyncClient {
+    conn: Mutex<TcpStream>,
+    req_id: AtomicU64,
+    timeout: Duration,
+    session_id: AtomicU64,
+    protocol_version: AtomicU16,
+    capabilities: Option<Capabilities>,
+    client_tag: String,
+}
+
+/// Connects over plain TCP and performs the HELLO handshake.
+pub async fn dial_async(
+    addr: &str,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<AsyncClient> {
+    let mut options = ClientOptions::default();
+    for opt in opts {
+        opt(&mut options);
+    }
+
+    let stream = tokio::time::timeout(options.dial_timeout, TcpStream::connect(addr))
+        .await
+        .map_err(|_| timed_out("dial timed out"))??;
+    let _ = stream.set_nodelay(true);
+
+    let mut client = AsyncClient {
+        conn: Mutex::new(stream),
+        req_id: AtomicU64::new(0),
+        timeout: options.request_timeout,
+        session_id: AtomicU64::new(0),
+        protocol_version: AtomicU16::new(1),
+        capabilities: None,
+        client_tag: options.client_tag.clone(),
+    };
+
+    let payload = encode_hello_payload(&options.client_tag)?;
+    let ctx = RequestContext::with_timeout(options.request_timeout);
+    let frame = client.send_request(&ctx, MSG_HELLO, 0, &payload).await?;
+    if frame.header.msg_type != MSG_HELLO {
+        return Err(Error::invalid_response(format!(
+            "unexpected response type: {}",
+            frame.header.msg_type
+        )));
+    }
+    if let Some(session) = parse_hello_session_id(&frame.payload) {
+        client.session_id.store(session, Ordering::SeqCst);
+    }
+    let version = parse_hello_protocol_version(&frame.payload);
+    client.protocol_version.store(version, Ordering::SeqCst);
+    if version >= 2 {
+        client.capabilities = Capabilities::from_hello_payload(&frame.payload);
+    }
+
+    Ok(client)
+}
+
+impl AsyncClient {
+    pub fn session_id(&self) -> u64 {
+        self.session_id.load(Ordering::SeqCst)
+    }
+
+    /// Protocol version negotiated in HELLO.
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version.load(Ordering::SeqCst)
+    }
+
+    /// Features and limits the server advertised in HELLO.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    pub fn client_tag(&self) -> &str {
+        &self.client_tag
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        conn.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn create_context(
+        &self,
+        ctx: &RequestContext,
+        base_turn_id: u64,
+    ) -> Result<ContextHead> {
+        let frame = self
+            .send_request(ctx, MSG_CTX_CREATE, 0, &base_turn_id.to_le_bytes())
+            .await?;
+        parse_context_head(&frame.payload)
+    }
+
+    pub async fn get_head(&self, ctx: &RequestContext, context_id: u64) -> Result<ContextHead> {
+        let frame = self
+            .send_request(ctx, MSG_GET_HEAD, 0, &context_id.to_le_bytes())
+            .await?;
+        parse_context_head(&frame.payload)
+    }
+
+    pub async fn append_turn(
+        &self,
+        ctx: &RequestContext,
+        req: &AppendRequest,
+    ) -> Result<AppendResult> {
+        let payload = encode_append_payload(req)?;
+        let frame = self.send_request(ctx, MSG_APPEND_TURN, 0, &payload).await?;
+        parse_append_result(&frame.payload, self.protocol_version())
+    }
+
+    pub async fn get_last(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<Vec<TurnRecord>> {
+        let opts = opts.at_version(self.protocol_version());
+        let payload = encode_get_last_payload(context_id, opts)?;
+        let frame = self
+            .send_request(ctx, MSG_GET_LAST, get_last_flags(opts), &payload)
+            .await?;
+        parse_get_last_response(&frame)
+    }
+
+    pub async fn get_blob(&self, ctx: &RequestContext, hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let frame = self.send_request(ctx, MSG_GET_BLOB, 0, hash).await?;
+        parse_blob_payload(&frame.payload)
+    }
+
+    async fn send_request(
+        &self,
+        ctx: &RequestContext,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Frame> {
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let now = Instant::now();
+        let mut deadline = now + self.timeout;
+        if let Some(ctx_deadline) = ctx.deadline() {
+            deadline = deadline.min(ctx_deadline);
+        }
+        if deadline <= now {
+            return Err(Error::Timeout);
+        }
+
+        let req_id = self.req_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut request = Vec::with_capacity(HEADER_LEN + payload.len());
+        write_frame(&mut request, msg_type, flags, req_id, payload)?;
+
+        let mut conn = self.conn.lock().await;
+        let exchange = async {
+            conn.write_all(&request).await?;
+            read_frame_async(&mut conn).await
+        };
+        let frame = match tokio::time::timeout_at(deadline.into(), exchange).await {
+            Ok(result) => result?,
+            Err(_) => {
+                // Drop the connection: a late response would be paired with
+                // the next request.
+                let _ = conn.shutdown().await;
+                return Err(Error::Timeout);
+            }
+        };
+
+        if frame.header.msg_type == MSG_ERROR {
+            return Err(parse_server_error(&frame.payload));
+        }
+        Ok(frame)
+    }
+}
+
+/// Reads one frame and decodes it with the shared synchronous decoder.
+async fn read_frame_async(stream: &mut TcpStream) -> Result<Frame> {
+    let mut buf = vec![0u8; HEADER_LEN];
+    stream.read_exact(&mut buf).await?;
+    let len = u32::from_le_bytes(buf[0..4].try_into().unwrap_or_default());
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::invalid_response(format!(
+            "frame size {} exceeds maximum {}",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+    buf.resize(HEADER_LEN + len as usize, 0);
+    stream.read_exact(&mut buf[HEADER_LEN..]).await?;
+    read_frame(&mut std::io::Cursor::new(buf))
+}
+
+fn timed_out(msg: &str) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dial_async, AppendRequest, GetLastOptions, RequestContext};
+    use crate::protocol::{
+        read_frame, write_frame, MSG_APPEND_TURN, MSG_GET_BLOB, MSG_GET_HEAD, MSG_GET_LAST,
+        MSG_HELLO,
+    };
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[tokio::test]
+    async fn async_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let blob = b"async blob".to_vec();
+        let blob_hash = *blake3::hash(&blob).as_bytes();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Ok(req) = read_frame(&mut stream) {
+                let mut resp = Vec::new();
+                match req.header.msg_type {
+                    MSG_HELLO => {
+                        resp.write_u64::<LittleEndian>(42).unwrap();
+                        resp.write_u16::<LittleEndian>(1).unwrap();
+                    }
+                    MSG_GET_HEAD => {
+                        resp.extend_from_slice(&req.payload[0..8]);
+                        resp.write_u64::<LittleEndian>(5).unwrap();
+                        resp.write_u32::<LittleEndian>(4).unwrap();
+                    }
+                    MSG_APPEND_TURN => {
+                        resp.extend_from_slice(&req.payload[0..8]);
+                        resp.write_u64::<LittleEndian>(6).unwrap();
+                        resp.write_u32::<LittleEndian>(5).unwrap();
+                        resp.extend_from_slice(&[9u8; 32]);
+                    }
+                    MSG_GET_LAST => {
+                        resp.write_u32::<LittleEndian>(1).unwrap();
+                        resp.write_u64::<LittleEndian>(6).unwrap();
+                        resp.write_u64::<LittleEndian>(5).unwrap();
+                        resp.write_u32::<LittleEndian>(5).unwrap();
+                        resp.write_u32::<LittleEndian>(1).unwrap();
+                        resp.extend_from_slice(b"t");
+                        resp.write_u32::<LittleEndian>(1).unwrap();
+                        resp.write_u32::<LittleEndian>(1).unwrap();
+                        resp.write_u32::<LittleEndian>(0).unwrap();
+                        resp.write_u32::<LittleEndian>(0).unwrap();
+                        resp.extend_from_slice(&[9u8; 32]);
+                        resp.write_u32::<LittleEndian>(0).unwrap();
+                    }
+                    MSG_GET_BLOB => {
+                        resp.write_u32::<LittleEndian>(blob.len() as u32).unwrap();
+                        resp.extend_from_slice(&blob);
+                    }
+                    other => panic!("unexpected msg_type {other}"),
+                }
+                write_frame(
+                    &mut stream,
+                    req.header.msg_type,
+                    0,
+                    req.header.req_id,
+                    &resp,
+                )
+                .unwrap();
+            }
+        });
+
+        let client = dial_async(&addr.to_string(), Vec::new()).await.unwrap();
+        assert_eq!(client.session_id(), 42);
+        let ctx = RequestContext::background();
+
+        let head = client.get_head(&ctx, 3).await.unwrap();
+        assert_eq!(
+            (head.context_id, head.head_turn_id, head.head_depth),
+            (3, 5, 4)
+        );
+
+        let req = AppendRequest::new(3, "t", 1, vec![0x90]);
+        let appended = client.append_turn(&ctx, &req).await.unwrap();
+        assert_eq!((appended.turn_id, appended.depth), (6, 5));
+
+        let turns = client
+            .get_last(&ctx, 3, GetLastOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].turn_id, 6);
+        assert_eq!(turns[0].type_id, "t");
+
+        let data = client.get_blob(&ctx, &blob_hash).await.unwrap();
+        assert_eq!(data, b"async blob");
+
+        client.close().await.unwrap();
+        server.join().unwrap();
+    }
+}
@@ -2,8 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, Instant};
 
 use byteorder::{LittleEndian, WriteBytesExt};
@@ -12,8 +12,8 @@ use rustls::{ClientConfig, ClientConnection};
 
 use crate::error::{Error, Result};
 use crate::protocol::{
-    read_frame, write_frame, Frame, DEFAULT_DIAL_TIMEOUT, DEFAULT_REQUEST_TIMEOUT, MSG_ERROR,
-    MSG_HELLO,
+    read_frame, write_frame, Capabilities, Frame, DEFAULT_DIAL_TIMEOUT, DEFAULT_REQUEST_TIMEOUT,
+    MSG_ERROR, MSG_HELLO, PROTOCOL_VERSION,
 };
 
 pub type ClientOption = Arc<dyn Fn(&mut ClientOptions) + Send + Sync>;
@@ -23,6 +23,12 @@ pub struct ClientOptions {
     pub dial_timeout: Duration,
     pub request_timeout: Duration,
     pub client_tag: String,
+    /// Overrides the TLS server name (SNI and hostname verification) that is
+    /// otherwise derived from the dial address.
+    pub tls_server_name: std::option::Option<String>,
+    /// DER-encoded leaf certificate the server must present. See
+    /// [`with_tls_pinned_cert`].
+    pub tls_pinned_cert: std::option::Option<Vec<u8>>,
     pub(crate) tls_config: std::option::Option<Arc<ClientConfig>>,
 }
 
@@ -32,6 +38,8 @@ impl Default for ClientOptions {
             dial_timeout: DEFAULT_DIAL_TIMEOUT,
             request_timeout: DEFAULT_REQUEST_TIMEOUT,
             client_tag: String::new(),
+            tls_server_name: None,
+            tls_pinned_cert: None,
             tls_config: None,
         }
     }
@@ -50,6 +58,26 @@ pub fn with_client_tag(tag: impl Into<String>) -> ClientOption {
     Arc::new(move |opts| opts.client_tag = tag.clone())
 }
 
+/// Uses `name` for TLS SNI and certificate hostname verification instead of
+/// the host part of the dial address. Useful when dialing by IP address.
+pub fn with_tls_server_name(name: impl Into<String>) -> ClientOption {
+    let name = name.into();
+    Arc::new(move |opts| opts.tls_server_name = Some(name.clone()))
+}
+
+/// Pins the server's leaf certificate (DER bytes).
+///
+/// Security: pinning replaces normal chain validation. The system roots, the
+/// certificate's expiry, and the hostname are NOT checked; the connection is
+/// accepted if and only if the presented leaf is byte-for-byte equal to the
+/// pinned certificate and the handshake signature verifies against it. Rotate
+/// the pin whenever the server certificate changes, and protect the pinned
+/// certificate's private key as you would a CA key.
+pub fn with_tls_pinned_cert(der: impl Into<Vec<u8>>) -> ClientOption {
+    let der = der.into();
+    Arc::new(move |opts| opts.tls_pinned_cert = Some(der.clone()))
+}
+
 #[cfg(test)]
 pub(crate) fn with_tls_config(config: Arc<ClientConfig>) -> ClientOption {
     Arc::new(move |opts| opts.tls_config = Some(config.clone()))
@@ -123,6 +151,8 @@ pub struct Client {
     closed: AtomicBool,
     timeout: Duration,
     session_id: AtomicU64,
+    protocol_version: AtomicU16,
+    capabilities: OnceLock<Capabilities>,
     client_tag: String,
 }
 
@@ -139,6 +169,17 @@ impl Client {
         self.session_id.load(Ordering::SeqCst)
     }
 
+    /// Protocol version negotiated in HELLO: the highest both sides speak.
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version.load(Ordering::SeqCst)
+    }
+
+    /// Features and limits the server advertised in HELLO; `None` for
+    /// servers that predate capability advertisement.
+    pub fn capabilities(&self) -> std::option::Option<Capabilities> {
+        self.capabilities.get().copied()
+    }
+
     pub fn client_tag(&self) -> &str {
         &self.client_tag
     }
@@ -173,8 +214,17 @@ impl Client {
         conn.set_deadline(Some(effective_deadline))?;
 
         let req_id = self.req_id.fetch_add(1, Ordering::SeqCst) + 1;
-        write_frame(&mut *conn, msg_type, flags, req_id, payload)?;
-        let frame = read_frame(&mut *conn)?;
+        let frame = write_frame(&mut *conn, msg_type, flags, req_id, payload)
+            .and_then(|_| read_frame(&mut *conn))
+            .map_err(|err| {
+                if !is_socket_timeout(&err) {
+                    return err;
+                }
+                // A response may still arrive after we give up, which would
+                // desynchronize request/response pairing on this socket.
+                let _ = conn.close();
+                Error::Timeout
+            })?;
 
         conn.set_deadline(None)?;
 
@@ -200,11 +250,7 @@ impl Client {
     }
 
     fn send_hello(&self, client_tag: &str) -> Result<()> {
-        let mut payload = Vec::with_capacity(2 + 2 + client_tag.len() + 4);
-        payload.write_u16::<LittleEndian>(1)?; // protocol version
-        payload.write_u16::<LittleEndian>(client_tag.len() as u16)?;
-        payload.extend_from_slice(client_tag.as_bytes());
-        payload.write_u32::<LittleEndian>(0)?; // no metadata
+        let payload = encode_hello_payload(client_tag)?;
 
         let ctx = RequestContext::with_timeout(self.timeout);
         let frame = self.send_request_with_flags(&ctx, MSG_HELLO, 0, &payload)?;
@@ -216,17 +262,43 @@ impl Client {
             )));
         }
 
-        if frame.payload.len() >= 8 {
-            let mut bytes = [0u8; 8];
-            bytes.copy_from_slice(&frame.payload[0..8]);
-            let session = u64::from_le_bytes(bytes);
+        if let Some(session) = parse_hello_session_id(&frame.payload) {
             self.session_id.store(session, Ordering::SeqCst);
         }
+        let version = parse_hello_protocol_version(&frame.payload);
+        self.protocol_version.store(version, Ordering::SeqCst);
+        if version >= 2 {
+            if let Some(caps) = Capabilities::from_hello_payload(&frame.payload) {
+                let _ = self.capabilities.set(caps);
+            }
+        }
 
         Ok(())
     }
 }
 
+pub(crate) fn encode_hello_payload(client_tag: &str) -> Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(2 + 2 + client_tag.len() + 4);
+    payload.write_u16::<LittleEndian>(PROTOCOL_VERSION)?; // max supported version
+    payload.write_u16::<LittleEndian>(client_tag.len() as u16)?;
+    payload.extend_from_slice(client_tag.as_bytes());
+    payload.write_u32::<LittleEndian>(0)?; // no metadata
+    Ok(payload)
+}
+
+pub(crate) fn parse_hello_session_id(payload: &[u8]) -> std::option::Option<u64> {
+    let bytes: [u8; 8] = payload.get(0..8)?.try_into().ok()?;
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// Negotiated version from a HELLO response; servers that omit it speak v1.
+pub(crate) fn parse_hello_protocol_version(payload: &[u8]) -> u16 {
+    payload
+        .get(8..10)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .unwrap_or(1)
+}
+
 pub fn dial(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
     let mut options = ClientOptions::default();
     for opt in opts {
@@ -234,14 +306,53 @@ pub fn dial(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<
     }
 
     let stream = connect_tcp(addr, options.dial_timeout)?;
-    let conn = Connection::Plain(stream);
+    handshake(Connection::Plain(stream), &options)
+}
+
+/// Dial a server listening on a Unix domain socket (`CXDB_UNIX_SOCKET`).
+/// The dial timeout does not apply; local socket connects don't block.
+#[cfg(unix)]
+pub fn dial_unix(
+    path: impl AsRef<std::path::Path>,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<Client> {
+    let mut options = ClientOptions::default();
+    for opt in opts {
+        opt(&mut options);
+    }
+
+    let stream = std::os::unix::net::UnixStream::connect(path).map_err(Error::Io)?;
+    handshake(Connection::Unix(stream), &options)
+}
+
+/// A byte stream the client can run the protocol over.
+pub trait ClientStream: std::io::Read + std::io::Write + Send {}
+
+impl<T: std::io::Read + std::io::Write + Send> ClientStream for T {}
+
+/// Run the protocol over an already-connected stream, such as a custom
+/// transport or an in-memory duplex in tests. Request deadlines are not
+/// enforced on such streams; the stream itself must time out if needed.
+pub fn connect_stream(
+    stream: impl ClientStream + 'static,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<Client> {
+    let mut options = ClientOptions::default();
+    for opt in opts {
+        opt(&mut options);
+    }
+    handshake(Connection::Stream(Box::new(stream)), &options)
+}
 
+fn handshake(conn: Connection, options: &ClientOptions) -> Result<Client> {
     let client = Client {
         conn: Mutex::new(conn),
         req_id: AtomicU64::new(0),
         closed: AtomicBool::new(false),
         timeout: options.request_timeout,
         session_id: AtomicU64::new(0),
+        protocol_version: AtomicU16::new(1),
+        capabilities: OnceLock::new(),
         client_tag: options.client_tag.clone(),
     };
 
@@ -262,32 +373,22 @@ pub fn dial_tls(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Res
     }
 
     let stream = connect_tcp(addr, options.dial_timeout)?;
-    let config = match options.tls_config.take() {
-        Some(cfg) => cfg,
-        None => Arc::new(default_tls_config()?),
+    let config = match (options.tls_config.take(), options.tls_pinned_cert.take()) {
+        (Some(cfg), _) => cfg,
+        (None, Some(der)) => Arc::new(pinned_tls_config(der)?),
+        (None, None) => Arc::new(default_tls_config()?),
     };
 
-    let server_name = server_name_from_addr(addr)?;
+    let server_name = match &options.tls_server_name {
+        Some(name) => ServerName::try_from(name.clone())
+            .map_err(|_| Error::Tls(format!("invalid server name: {name}")))?,
+        None => server_name_from_addr(addr)?,
+    };
     let conn =
         ClientConnection::new(config, server_name).map_err(|err| Error::Tls(err.to_string()))?;
 
     let stream = rustls::StreamOwned::new(conn, stream);
-
-    let client = Client {
-        conn: Mutex::new(Connection::Tls(Box::new(stream))),
-        req_id: AtomicU64::new(0),
-        closed: AtomicBool::new(false),
-        timeout: options.request_timeout,
-        session_id: AtomicU64::new(0),
-        client_tag: options.client_tag.clone(),
-    };
-
-    if let Err(err) = client.send_hello(&options.client_tag) {
-        let _ = client.close();
-        return Err(err);
-    }
-
-    Ok(client)
+    handshake(Connection::Tls(Box::new(stream)), &options)
 }
 
 fn connect_tcp(addr: &str, timeout: Duration) -> Result<TcpStream> {
@@ -326,6 +427,82 @@ fn default_tls_config() -> Result<ClientConfig> {
     Ok(config)
 }
 
+fn pinned_tls_config(der: Vec<u8>) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = Arc::new(PinnedCertVerifier {
+        pinned: der,
+        provider: provider.clone(),
+    });
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|err| Error::Tls(err.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    Ok(config)
+}
+
+/// Accepts exactly one leaf certificate, ignoring roots, expiry and hostname.
+/// Handshake signatures are still verified so the peer must hold the key.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pinned: Vec<u8>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.pinned.as_slice() {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
 fn server_name_from_addr(addr: &str) -> Result<ServerName<'static>> {
     let host = if addr.starts_with('[') {
         // IPv6 in brackets
@@ -341,7 +518,19 @@ fn server_name_from_addr(addr: &str) -> Result<ServerName<'static>> {
         .map_err(|_| Error::Tls(format!("invalid server name: {host}")))
 }
 
-fn parse_server_error(payload: &[u8]) -> Error {
+/// Socket read/write timeouts surface as `WouldBlock` on Unix and `TimedOut`
+/// on Windows.
+fn is_socket_timeout(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Io(io_err) if matches!(
+            io_err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )
+    )
+}
+
+pub(crate) fn parse_server_error(payload: &[u8]) -> Error {
     if payload.len() < 8 {
         return Error::server(0, "unknown error");
     }
@@ -358,6 +547,9 @@ fn parse_server_error(payload: &[u8]) -> Error {
 pub(crate) enum Connection {
     Plain(TcpStream),
     Tls(Box<rustls::StreamOwned<ClientConnection, TcpStream>>),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+    Stream(Box<dyn ClientStream>),
 }
 
 impl Connection {
@@ -373,6 +565,12 @@ impl Connection {
                 tcp.set_read_timeout(timeout).map_err(Error::Io)?;
                 tcp.set_write_timeout(timeout).map_err(Error::Io)?;
             }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.set_read_timeout(timeout).map_err(Error::Io)?;
+                stream.set_write_timeout(timeout).map_err(Error::Io)?;
+            }
+            Connection::Stream(_) => {}
         }
         Ok(())
     }
@@ -386,6 +584,11 @@ impl Connection {
                 .get_mut()
                 .shutdown(std::net::Shutdown::Both)
                 .map_err(Error::Io),
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.shutdown(std::net::Shutdown::Both).map_err(Error::Io)
+            }
+            Connection::Stream(_) => Ok(()),
         }
     }
 }
@@ -395,6 +598,9 @@ impl std::io::Read for Connection {
         match self {
             Connection::Plain(stream) => stream.read(buf),
             Connection::Tls(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+            Connection::Stream(stream) => stream.read(buf),
         }
     }
 }
@@ -404,6 +610,9 @@ impl std::io::Write for Connection {
         match self {
             Connection::Plain(stream) => stream.write(buf),
             Connection::Tls(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+            Connection::Stream(stream) => stream.write(buf),
         }
     }
 
@@ -411,6 +620,9 @@ impl std::io::Write for Connection {
         match self {
             Connection::Plain(stream) => stream.flush(),
             Connection::Tls(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+            Connection::Stream(stream) => stream.flush(),
         }
     }
 }
@@ -418,8 +630,12 @@ impl std::io::Write for Connection {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::{read_frame, write_frame, FrameHeader, MSG_HELLO};
+    use crate::protocol::{
+        read_frame, write_frame, FrameHeader, CAP_COMPRESSION_ZSTD, CAP_CONDITIONAL_APPEND,
+        CAP_CQL, CAP_S3_SYNC, MSG_APPEND_TURN, MSG_CTX_CREATE, MSG_HELLO,
+    };
     use crate::test_util::{decode_hex, load_fixture};
+    use crate::turn::AppendRequest;
     use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
     use rustls::ServerConfig;
     use std::net::TcpListener;
@@ -553,6 +769,270 @@ mod tests {
         server_handle.join().unwrap();
     }
 
+    /// Accepts `conns` TLS connections, answering HELLO on each handshake that
+    /// succeeds. Returns how many handshakes completed.
+    fn start_tls_hello_server(
+        cert: rustls::pki_types::CertificateDer<'static>,
+        key: rustls::pki_types::PrivateKeyDer<'static>,
+        conns: usize,
+    ) -> (std::net::SocketAddr, thread::JoinHandle<usize>) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let server_config = Arc::new(
+            ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut ok = 0;
+            for _ in 0..conns {
+                let (tcp, _) = listener.accept().unwrap();
+                let conn = rustls::ServerConnection::new(server_config.clone()).unwrap();
+                let mut stream = rustls::StreamOwned::new(conn, tcp);
+                let Ok(frame) = read_frame(&mut stream) else {
+                    continue;
+                };
+                let mut resp = Vec::new();
+                resp.write_u64::<LittleEndian>(99).unwrap();
+                resp.write_u16::<LittleEndian>(1).unwrap();
+                write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+                ok += 1;
+            }
+            ok
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn tls_pinned_cert_is_required_for_self_signed_server() {
+        let (cert, key) = generate_cert();
+        let (other_cert, _) = generate_cert();
+        let (addr, server) = start_tls_hello_server(cert.clone(), key, 3);
+        let addr = addr.to_string();
+
+        // System roots do not trust the self-signed certificate.
+        let err = dial_tls(&addr, vec![with_tls_server_name("localhost")]).err();
+        assert!(matches!(err, Some(Error::Io(_)) | Some(Error::Tls(_))));
+
+        // A different pinned certificate is rejected.
+        let err = dial_tls(&addr, vec![with_tls_pinned_cert(other_cert.to_vec())]).err();
+        assert!(err.is_some());
+
+        // Pinning the presented certificate succeeds, even when dialing by IP.
+        let client = dial_tls(&addr, vec![with_tls_pinned_cert(cert.to_vec())]).unwrap();
+        assert_eq!(client.session_id(), 99);
+
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dial_unix_round_trips_over_a_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cxdb.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let frame = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(7).unwrap();
+            resp.write_u16::<LittleEndian>(1).unwrap();
+            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            let req = read_frame(&mut stream).unwrap();
+            let mut head = Vec::new();
+            head.write_u64::<LittleEndian>(1).unwrap();
+            head.write_u64::<LittleEndian>(42).unwrap();
+            head.write_u32::<LittleEndian>(3).unwrap();
+            write_frame(
+                &mut stream,
+                req.header.msg_type,
+                0,
+                req.header.req_id,
+                &head,
+            )
+            .unwrap();
+        });
+
+        let client = dial_unix(&path, Vec::new()).unwrap();
+        assert_eq!(client.session_id(), 7);
+        let head = client.get_head(&RequestContext::background(), 1).unwrap();
+        assert_eq!(head.head_turn_id, 42);
+        assert_eq!(head.head_depth, 3);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connect_stream_runs_over_an_in_memory_duplex() {
+        let (client_end, mut server_end) = crate::test_util::duplex();
+
+        let handle = thread::spawn(move || {
+            let frame = read_frame(&mut server_end).unwrap();
+            assert_eq!(frame.header.msg_type, MSG_HELLO);
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(5).unwrap();
+            resp.write_u16::<LittleEndian>(2).unwrap();
+            resp.write_u32::<LittleEndian>(CAP_COMPRESSION_ZSTD | CAP_S3_SYNC)
+                .unwrap();
+            resp.write_u32::<LittleEndian>(1 << 20).unwrap();
+            resp.write_u32::<LittleEndian>(16).unwrap();
+            resp.write_u32::<LittleEndian>(4096).unwrap();
+            write_frame(&mut server_end, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            // The refused conditional append never reaches the wire.
+            let req = read_frame(&mut server_end).unwrap();
+            assert_eq!(req.header.msg_type, MSG_CTX_CREATE);
+            let mut head = Vec::new();
+            head.write_u64::<LittleEndian>(9).unwrap();
+            head.write_u64::<LittleEndian>(0).unwrap();
+            head.write_u32::<LittleEndian>(0).unwrap();
+            write_frame(
+                &mut server_end,
+                req.header.msg_type,
+                0,
+                req.header.req_id,
+                &head,
+            )
+            .unwrap();
+        });
+
+        let client = connect_stream(client_end, Vec::new()).unwrap();
+        assert_eq!(client.session_id(), 5);
+        assert_eq!(client.protocol_version(), 2);
+        let caps = client.capabilities().expect("capabilities");
+        assert!(caps.has(CAP_COMPRESSION_ZSTD) && caps.has(CAP_S3_SYNC));
+        assert!(!caps.has(CAP_CQL));
+        assert_eq!(caps.max_frame_size, 1 << 20);
+        assert_eq!(caps.max_batch_contexts, 16);
+        assert_eq!(caps.max_batch_turns, 4096);
+        let err = client
+            .append_turn_if_head(
+                &RequestContext::background(),
+                &AppendRequest::new(9, "test.Type", 1, vec![0x90]),
+                0,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)), "{err:?}");
+        let head = client
+            .create_context(&RequestContext::background(), 0)
+            .unwrap();
+        assert_eq!(head.context_id, 9);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn conditional_append_sends_the_expected_head_when_advertised() {
+        let (client_end, mut server_end) = crate::test_util::duplex();
+
+        let handle = thread::spawn(move || {
+            let frame = read_frame(&mut server_end).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(5).unwrap();
+            resp.write_u16::<LittleEndian>(2).unwrap();
+            resp.write_u32::<LittleEndian>(CAP_CONDITIONAL_APPEND)
+                .unwrap();
+            resp.write_u32::<LittleEndian>(1 << 20).unwrap();
+            resp.write_u32::<LittleEndian>(16).unwrap();
+            resp.write_u32::<LittleEndian>(4096).unwrap();
+            write_frame(&mut server_end, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            let req = read_frame(&mut server_end).unwrap();
+            assert_eq!(req.header.msg_type, MSG_APPEND_TURN);
+            assert_eq!(req.header.flags, 2);
+            let tail: [u8; 8] = req.payload[req.payload.len() - 8..].try_into().unwrap();
+            assert_eq!(u64::from_le_bytes(tail), 41);
+
+            let mut err_payload = Vec::new();
+            err_payload.write_u32::<LittleEndian>(409).unwrap();
+            err_payload.extend_from_slice(b"head mismatch: expected 41, actual 42");
+            write_frame(
+                &mut server_end,
+                crate::protocol::MSG_ERROR,
+                0,
+                req.header.req_id,
+                &err_payload,
+            )
+            .unwrap();
+        });
+
+        let client = connect_stream(client_end, Vec::new()).unwrap();
+        let err = client
+            .append_turn_if_head(
+                &RequestContext::background(),
+                &AppendRequest::new(9, "test.Type", 1, vec![0x90]),
+                41,
+            )
+            .unwrap_err();
+        match err {
+            Error::Server(server_err) => assert!(server_err.is_conflict(), "{server_err:?}"),
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn version_1_server_gets_no_version_2_requests() {
+        let (client_end, mut server_end) = crate::test_util::duplex();
+
+        let handle = thread::spawn(move || {
+            let frame = read_frame(&mut server_end).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(5).unwrap();
+            resp.write_u16::<LittleEndian>(1).unwrap();
+            resp.write_u32::<LittleEndian>(CAP_COMPRESSION_ZSTD)
+                .unwrap();
+            write_frame(&mut server_end, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+            server_end
+        });
+
+        let client = connect_stream(client_end, Vec::new()).unwrap();
+        assert_eq!(client.protocol_version(), 1);
+        assert!(client.capabilities().is_none());
+        let err = client
+            .get_last_batch(&RequestContext::background(), &[(1, 1)], false)
+            .unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)), "{err:?}");
+        drop(handle.join().unwrap());
+    }
+
+    #[test]
+    fn stalled_server_times_out_at_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let frame = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(1).unwrap();
+            resp.write_u16::<LittleEndian>(1).unwrap();
+            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+            // Read the request but never answer it.
+            let _ = read_frame(&mut stream);
+            let _ = done_rx.recv();
+        });
+
+        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        let ctx = RequestContext::with_timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        let err = client.get_head(&ctx, 1).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            matches!(err, Error::Timeout),
+            "expected timeout, got {err:?}"
+        );
+        assert!(!err.is_retryable());
+
+        let _ = done_tx.send(());
+        handle.join().unwrap();
+    }
+
     #[test]
     fn default_timeouts_match_go() {
         let opts = ClientOptions::default();
@@ -610,6 +1090,58 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn missing_type_descriptor_is_distinct_server_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let frame = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(1).unwrap();
+            resp.write_u16::<LittleEndian>(1).unwrap();
+            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            let req = read_frame(&mut stream).unwrap();
+            let detail = b"type descriptor";
+            let mut err_payload = Vec::new();
+            err_payload
+                .write_u32::<LittleEndian>(crate::error::ERROR_CODE_TYPE_DESCRIPTOR_MISSING)
+                .unwrap();
+            err_payload
+                .write_u32::<LittleEndian>(detail.len() as u32)
+                .unwrap();
+            err_payload.extend_from_slice(detail);
+            write_frame(
+                &mut stream,
+                crate::protocol::MSG_ERROR,
+                0,
+                req.header.req_id,
+                &err_payload,
+            )
+            .unwrap();
+        });
+
+        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let err = client
+            .send_request(&ctx, crate::protocol::MSG_GET_LAST, &[0u8; 16])
+            .unwrap_err();
+        assert!(crate::IsServerError(&err, 424));
+        assert!(!crate::is_server_error(&err, 404));
+        match err {
+            Error::Server(server) => {
+                assert!(server.is_type_descriptor_missing());
+                assert!(!server.is_not_found());
+            }
+            other => panic!("expected server error, got {other:?}"),
+        }
+
+        handle.join().unwrap();
+    }
+
     fn hello_payload(tag: &str) -> Vec<u8> {
         let mut payload = Vec::new();
         payload.write_u16::<LittleEndian>(1).unwrap();
@@ -37,7 +37,7 @@ impl Client {
     }
 }
 
-fn parse_context_head(payload: &[u8]) -> Result<ContextHead> {
+pub(crate) fn parse_context_head(payload: &[u8]) -> Result<ContextHead> {
     if payload.len() < 20 {
         return Err(Error::invalid_response(format!(
             "context head too short ({} bytes)",
@@ -16,8 +16,18 @@ pub enum Error {
     Timeout,
     Cancelled,
     QueueFull,
+    /// The protocol version negotiated with the server lacks the operation.
+    Unsupported(String),
 }
 
+/// Server error codes. These mirror the HTTP status taxonomy so binary and
+/// HTTP clients can react to the same conditions.
+pub const ERROR_CODE_NOT_FOUND: u32 = 404;
+pub const ERROR_CODE_CONFLICT: u32 = 409;
+pub const ERROR_CODE_INVALID_INPUT: u32 = 422;
+pub const ERROR_CODE_TYPE_DESCRIPTOR_MISSING: u32 = 424;
+pub const ERROR_CODE_INTERNAL: u32 = 500;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ServerError {
     pub code: u32,
@@ -30,6 +40,37 @@ impl fmt::Display for ServerError {
     }
 }
 
+impl ServerError {
+    /// The requested context, turn, or blob does not exist.
+    pub fn is_not_found(&self) -> bool {
+        self.code == ERROR_CODE_NOT_FOUND
+    }
+
+    /// The parent or base turn referenced by the request does not exist.
+    pub fn is_conflict(&self) -> bool {
+        self.code == ERROR_CODE_CONFLICT
+    }
+
+    /// The registry has no descriptor for the requested type.
+    pub fn is_type_descriptor_missing(&self) -> bool {
+        self.code == ERROR_CODE_TYPE_DESCRIPTOR_MISSING
+    }
+
+    /// Returns the kind of missing resource (`context`, `turn`,
+    /// `parent_turn`, `blob`, `type_descriptor`, `fs_snapshot`, `bundle`) for
+    /// not-found style errors.
+    /// The server prefixes the detail with the kind, e.g. `blob: blob`.
+    pub fn not_found_kind(&self) -> Option<&str> {
+        if !matches!(
+            self.code,
+            ERROR_CODE_NOT_FOUND | ERROR_CODE_CONFLICT | ERROR_CODE_TYPE_DESCRIPTOR_MISSING
+        ) {
+            return None;
+        }
+        self.detail.split_once(':').map(|(kind, _)| kind)
+    }
+}
+
 impl std::error::Error for ServerError {}
 
 impl fmt::Display for Error {
@@ -45,6 +86,7 @@ impl fmt::Display for Error {
             Error::Timeout => write!(f, "cxdb: deadline exceeded"),
             Error::Cancelled => write!(f, "cxdb: request cancelled"),
             Error::QueueFull => write!(f, "cxdb: request queue full"),
+            Error::Unsupported(msg) => write!(f, "cxdb: unsupported: {msg}"),
         }
     }
 }
@@ -82,6 +124,49 @@ pub fn is_server_error(err: &Error, code: u32) -> bool {
 }
 
 impl Error {
+    /// Whether the request may succeed if re-sent on a fresh connection.
+    ///
+    /// Connection-level failures (reset, refused, broken pipe, socket timeout)
+    /// are retryable. Server responses, cancellation, deadline expiry and a
+    /// closed client are not: re-dialing will not change their outcome.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ClientClosed
+            | Error::ContextNotFound
+            | Error::TurnNotFound
+            | Error::Server(_)
+            | Error::Timeout
+            | Error::Cancelled
+            | Error::QueueFull
+            | Error::Unsupported(_) => false,
+            Error::Io(io_err) => match io_err.kind() {
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::NotConnected => true,
+                _ => contains_connection_pattern(&io_err.to_string()),
+            },
+            Error::Tls(msg) | Error::InvalidResponse(msg) => contains_connection_pattern(msg),
+        }
+    }
+
+    /// Whether the condition is expected to clear on its own, so the caller
+    /// may retry later (possibly after backing off).
+    ///
+    /// This is a superset of [`Error::is_retryable`]: it also covers a full
+    /// request queue, an expired deadline and server-side 5xx failures.
+    /// Not-found and validation errors are never transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout | Error::QueueFull => true,
+            Error::Server(err) => err.code >= 500,
+            _ => self.is_retryable(),
+        }
+    }
+
     pub fn invalid_response(msg: impl Into<String>) -> Self {
         Error::InvalidResponse(msg.into())
     }
@@ -93,3 +178,95 @@ impl Error {
         })
     }
 }
+
+fn contains_connection_pattern(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    let patterns = [
+        "connection reset",
+        "connection refused",
+        "broken pipe",
+        "use of closed network connection",
+        "network is unreachable",
+        "no route to host",
+        "connection timed out",
+        "i/o timeout",
+    ];
+    patterns.iter().any(|p| msg.contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io(kind: std::io::ErrorKind) -> Error {
+        Error::Io(std::io::Error::new(kind, "io"))
+    }
+
+    #[test]
+    fn connection_errors_are_retryable_and_transient() {
+        for err in [
+            io(std::io::ErrorKind::ConnectionReset),
+            io(std::io::ErrorKind::BrokenPipe),
+            io(std::io::ErrorKind::TimedOut),
+            io(std::io::ErrorKind::UnexpectedEof),
+            Error::Tls("connection refused".into()),
+            Error::Io(std::io::Error::other("use of closed network connection")),
+        ] {
+            assert!(err.is_retryable(), "{err:?} should be retryable");
+            assert!(err.is_transient(), "{err:?} should be transient");
+        }
+    }
+
+    #[test]
+    fn server_validation_and_not_found_are_fatal() {
+        for code in [
+            ERROR_CODE_NOT_FOUND,
+            ERROR_CODE_CONFLICT,
+            ERROR_CODE_INVALID_INPUT,
+            ERROR_CODE_TYPE_DESCRIPTOR_MISSING,
+        ] {
+            let err = Error::server(code, "nope");
+            assert!(!err.is_retryable());
+            assert!(!err.is_transient());
+        }
+        assert!(!io(std::io::ErrorKind::InvalidData).is_retryable());
+        assert!(!Error::invalid_response("bad frame").is_retryable());
+    }
+
+    #[test]
+    fn local_conditions_are_transient_but_not_retryable() {
+        for err in [
+            Error::Timeout,
+            Error::QueueFull,
+            Error::server(ERROR_CODE_INTERNAL, "disk"),
+        ] {
+            assert!(!err.is_retryable(), "{err:?} should not be retryable");
+            assert!(err.is_transient(), "{err:?} should be transient");
+        }
+        for err in [Error::ClientClosed, Error::Cancelled] {
+            assert!(!err.is_retryable());
+            assert!(!err.is_transient());
+        }
+    }
+
+    #[test]
+    fn not_found_kind_parses_detail_prefix() {
+        let blob = ServerError {
+            code: ERROR_CODE_NOT_FOUND,
+            detail: "blob: blob".into(),
+        };
+        assert_eq!(blob.not_found_kind(), Some("blob"));
+
+        let ctx = ServerError {
+            code: ERROR_CODE_NOT_FOUND,
+            detail: "context: context".into(),
+        };
+        assert_eq!(ctx.not_found_kind(), Some("context"));
+
+        let invalid = ServerError {
+            code: ERROR_CODE_INVALID_INPUT,
+            detail: "bad: input".into(),
+        };
+        assert_eq!(invalid.not_found_kind(), None);
+    }
+}
@@ -223,6 +223,7 @@ impl FollowState {
             GetLastOptions {
                 limit: missing,
                 include_payload: true,
+                ..Default::default()
             },
         )?;
 
@@ -6,8 +6,8 @@ use std::io::Read;
 
 use crate::client::{Client, RequestContext};
 use crate::error::{Error, Result};
-use crate::protocol::{ENCODING_MSGPACK, MSG_APPEND_TURN, MSG_ATTACH_FS, MSG_PUT_BLOB};
-use crate::turn::{AppendRequest, AppendResult};
+use crate::protocol::{MSG_APPEND_TURN, MSG_ATTACH_FS, MSG_GET_BLOB, MSG_PUT_BLOB};
+use crate::turn::{encode_append_payload, parse_append_result, AppendRequest, AppendResult};
 
 #[derive(Debug, Clone)]
 pub struct AttachFsRequest {
@@ -80,6 +80,11 @@ impl Client {
         })
     }
 
+    pub fn get_blob(&self, ctx: &RequestContext, hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let frame = self.send_request(ctx, MSG_GET_BLOB, hash)?;
+        parse_blob_payload(&frame.payload)
+    }
+
     pub fn put_blob_if_absent(
         &self,
         ctx: &RequestContext,
@@ -95,29 +100,7 @@ impl Client {
         req: &AppendRequest,
         fs_root_hash: Option<[u8; 32]>,
     ) -> Result<AppendResult> {
-        let encoding = if req.encoding == 0 {
-            ENCODING_MSGPACK
-        } else {
-            req.encoding
-        };
-
-        let hash = blake3::hash(&req.payload);
-        let mut payload = Vec::with_capacity(128 + req.payload.len());
-        payload.write_u64::<LittleEndian>(req.context_id)?;
-        payload.write_u64::<LittleEndian>(req.parent_turn_id)?;
-        payload.write_u32::<LittleEndian>(req.type_id.len() as u32)?;
-        payload.extend_from_slice(req.type_id.as_bytes());
-        payload.write_u32::<LittleEndian>(req.type_version)?;
-        payload.write_u32::<LittleEndian>(encoding)?;
-        payload.write_u32::<LittleEndian>(req.compression)?;
-        payload.write_u32::<LittleEndian>(req.payload.len() as u32)?;
-        payload.extend_from_slice(hash.as_bytes());
-        payload.write_u32::<LittleEndian>(req.payload.len() as u32)?;
-        payload.extend_from_slice(&req.payload);
-        payload.write_u32::<LittleEndian>(req.idempotency_key.len() as u32)?;
-        if !req.idempotency_key.is_empty() {
-            payload.extend_from_slice(&req.idempotency_key);
-        }
+        let mut payload = encode_append_payload(req)?;
 
         let mut flags = 0u16;
         if let Some(hash) = fs_root_hash {
@@ -126,30 +109,30 @@ impl Client {
         }
 
         let frame = self.send_request_with_flags(ctx, MSG_APPEND_TURN, flags, &payload)?;
-        if frame.payload.len() < 52 {
-            return Err(Error::invalid_response(format!(
-                "append response too short ({} bytes)",
-                frame.payload.len()
-            )));
-        }
-        let mut cursor = std::io::Cursor::new(frame.payload);
-        let context_id = cursor.read_u64::<LittleEndian>()?;
-        let turn_id = cursor.read_u64::<LittleEndian>()?;
-        let depth = cursor.read_u32::<LittleEndian>()?;
-        let mut hash = [0u8; 32];
-        cursor.read_exact(&mut hash)?;
-        Ok(AppendResult {
-            context_id,
-            turn_id,
-            depth,
-            payload_hash: hash,
-        })
+        parse_append_result(&frame.payload, self.protocol_version())
+    }
+}
+
+pub(crate) fn parse_blob_payload(payload: &[u8]) -> Result<Vec<u8>> {
+    if payload.len() < 4 {
+        return Err(Error::invalid_response(format!(
+            "get blob response too short ({} bytes)",
+            payload.len()
+        )));
     }
+    let mut cursor = std::io::Cursor::new(payload);
+    let len = cursor.read_u32::<LittleEndian>()? as usize;
+    let mut data = vec![0u8; len];
+    cursor
+        .read_exact(&mut data)
+        .map_err(|_| Error::invalid_response("get blob response truncated"))?;
+    Ok(data)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::ENCODING_MSGPACK;
     use crate::test_util::{decode_hex, load_fixture};
 
     fn build_append_payload(req: &AppendRequest, fs_root_hash: Option<[u8; 32]>) -> Vec<u8> {
@@ -6,6 +6,8 @@
 //! Exposes a synchronous TCP/TLS client, reconnecting wrapper, fstree snapshots,
 //! SSE subscription helpers, and canonical conversation types plus msgpack helpers.
 
+#[cfg(feature = "async")]
+pub mod async_client;
 pub mod client;
 pub mod context;
 pub mod encoding;
@@ -25,9 +27,13 @@ pub mod types;
 
 #[cfg(test)]
 mod test_util;
+#[cfg(feature = "async")]
+pub use crate::async_client::{dial_async, AsyncClient};
+#[cfg(unix)]
+pub use crate::client::dial_unix;
 pub use crate::client::{
-    dial, dial_tls, with_client_tag, with_dial_timeout, with_request_timeout, Client, ClientOption,
-    RequestContext,
+    connect_stream, dial, dial_tls, with_client_tag, with_dial_timeout, with_request_timeout,
+    with_tls_pinned_cert, with_tls_server_name, Client, ClientOption, ClientStream, RequestContext,
 };
 pub use crate::context::ContextHead;
 pub use crate::encoding::{decode_msgpack, decode_msgpack_into, encode_msgpack};
@@ -42,15 +48,17 @@ pub use crate::follow::{
     FollowTurn, TurnClient,
 };
 pub use crate::fs::{AttachFsRequest, AttachFsResult, PutBlobRequest, PutBlobResult};
+pub use crate::protocol::Capabilities;
 pub use crate::reconnect::{
-    dial_reconnecting, dial_tls_reconnecting, DialFunc, ReconnectOption, ReconnectingClient,
+    dial_reconnecting, dial_tls_reconnecting, ConnState, DialFunc, ReconnectOption,
+    ReconnectingClient,
 };
 pub use crate::subscribe::{
     subscribe_events, with_error_buffer, with_event_buffer, with_headers, with_http_client,
     with_max_event_bytes, with_subscribe_max_retry_delay, with_subscribe_retry_delay, Event,
     SubscribeError, SubscribeOption,
 };
-pub use crate::turn::{AppendRequest, AppendResult, GetLastOptions, TurnRecord};
+pub use crate::turn::{AppendRequest, AppendResult, ContextTurns, GetLastOptions, TurnRecord};
 
 // Re-export shared constants for parity with Go names.
 #[allow(non_upper_case_globals)]
@@ -172,6 +180,16 @@ pub fn WithClientTag(tag: impl Into<String>) -> ClientOption {
     with_client_tag(tag)
 }
 
+#[allow(non_snake_case)]
+pub fn WithTLSServerName(name: impl Into<String>) -> ClientOption {
+    with_tls_server_name(name)
+}
+
+#[allow(non_snake_case)]
+pub fn WithTLSPinnedCert(der: impl Into<Vec<u8>>) -> ClientOption {
+    with_tls_pinned_cert(der)
+}
+
 #[allow(non_snake_case)]
 pub fn Dial(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
     dial(addr, opts)
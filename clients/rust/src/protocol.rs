@@ -8,6 +8,53 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::error::{Error, Result};
 
+/// Highest wire protocol version this client speaks; advertised in HELLO.
+/// Version 2 adds GET_LAST_BATCH, the HELLO capability block, the append
+/// ack's `head_turn_id`, and the GET_LAST hash-omission and varint options;
+/// the client only uses them when the server negotiated version 2.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Capability bits in the HELLO response; see [`Capabilities`].
+pub const CAP_COMPRESSION_ZSTD: u32 = 1 << 0;
+pub const CAP_FS_SNAPSHOTS: u32 = 1 << 1;
+pub const CAP_GET_LAST_BATCH: u32 = 1 << 2;
+pub const CAP_CQL: u32 = 1 << 3;
+pub const CAP_S3_SYNC: u32 = 1 << 4;
+pub const CAP_CONDITIONAL_APPEND: u32 = 1 << 5;
+
+/// Optional features and limits advertised by the server in HELLO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub flags: u32,
+    pub max_frame_size: u32,
+    pub max_batch_contexts: u32,
+    /// Most turns one GET_LAST_BATCH request may return in total; 0 if the
+    /// server predates the field.
+    pub max_batch_turns: u32,
+}
+
+impl Capabilities {
+    pub fn has(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// Parse the capability block that follows session_id and
+    /// protocol_version in a HELLO response. Older servers omit it.
+    pub fn from_hello_payload(payload: &[u8]) -> Option<Self> {
+        let word = |at: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                payload.get(at..at + 4)?.try_into().ok()?,
+            ))
+        };
+        Some(Self {
+            flags: word(10)?,
+            max_frame_size: word(14)?,
+            max_batch_contexts: word(18)?,
+            max_batch_turns: word(22).unwrap_or(0),
+        })
+    }
+}
+
 pub const MSG_HELLO: u16 = 1;
 pub const MSG_CTX_CREATE: u16 = 2;
 pub const MSG_CTX_FORK: u16 = 3;
@@ -17,8 +64,15 @@ pub const MSG_GET_LAST: u16 = 6;
 pub const MSG_GET_BLOB: u16 = 9;
 pub const MSG_ATTACH_FS: u16 = 10;
 pub const MSG_PUT_BLOB: u16 = 11;
+pub const MSG_GET_LAST_BATCH: u16 = 12;
 pub const MSG_ERROR: u16 = 255;
 
+/// GET_LAST response flag: items carry no payload hash.
+pub const FLAG_GET_LAST_NO_HASH: u16 = 1 << 0;
+/// GET_LAST request and response flag: integers in the response body are
+/// LEB128 varints.
+pub const FLAG_GET_LAST_VARINT: u16 = 1 << 1;
+
 pub const ENCODING_MSGPACK: u32 = 1;
 pub const COMPRESSION_NONE: u32 = 0;
 pub const COMPRESSION_ZSTD: u32 = 1;
@@ -58,15 +112,7 @@ pub fn write_frame<W: Write>(
 }
 
 pub fn read_frame<R: Read>(reader: &mut R) -> Result<Frame> {
-    let len = match reader.read_u32::<LittleEndian>() {
-        Ok(v) => v,
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                return Err(Error::invalid_response("frame header truncated"));
-            }
-            return Err(Error::Io(err));
-        }
-    };
+    let len = u32::from_le_bytes(read_len_prefix(reader)?);
 
     if len > MAX_FRAME_SIZE {
         return Err(Error::invalid_response(format!(
@@ -104,6 +150,29 @@ pub fn read_frame<R: Read>(reader: &mut R) -> Result<Frame> {
     })
 }
 
+/// Reads the 4-byte length prefix. EOF before any byte arrives means the peer
+/// closed the connection between frames, which is reported as an I/O error so
+/// it is classified as a connection failure; EOF mid-prefix is a bad frame.
+fn read_len_prefix<R: Read>(reader: &mut R) -> Result<[u8; 4]> {
+    let mut buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed by peer",
+                )))
+            }
+            Ok(0) => return Err(Error::invalid_response("frame header truncated")),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Error::Io(err)),
+        }
+    }
+    Ok(buf)
+}
+
 fn map_header_error(err: std::io::Error) -> Error {
     if err.kind() == std::io::ErrorKind::UnexpectedEof {
         Error::invalid_response("frame header truncated")
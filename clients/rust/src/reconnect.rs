@@ -4,10 +4,10 @@
 #![allow(clippy::type_complexity)]
 
 use std::cmp;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use crossbeam_channel::{bounded, select, Receiver, Sender};
 
@@ -23,6 +23,19 @@ pub type DialFunc = Arc<dyn Fn() -> Result<Client> + Send + Sync>;
 
 pub type ReconnectOption = Arc<dyn Fn(&mut ReconnectConfig) + Send + Sync>;
 
+pub type StateListener = Arc<dyn Fn(ConnState) + Send + Sync>;
+
+/// Connection lifecycle transitions reported to a state listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// A connection was established (initial dial or successful reconnect).
+    Connected { session_id: u64 },
+    /// The current connection failed with a connection-level error.
+    Disconnected,
+    /// A re-dial attempt is starting. `attempt` counts from 1.
+    Reconnecting { attempt: usize },
+}
+
 #[derive(Clone)]
 pub struct ReconnectConfig {
     pub max_retries: usize,
@@ -31,6 +44,9 @@ pub struct ReconnectConfig {
     pub queue_size: usize,
     pub on_reconnect: Option<Arc<dyn Fn(u64) + Send + Sync>>,
     pub dial_func: Option<DialFunc>,
+    pub state_listener: Option<StateListener>,
+    /// Re-send idempotent requests on the new connection after a reconnect.
+    pub replay: bool,
 }
 
 impl Default for ReconnectConfig {
@@ -42,6 +58,8 @@ impl Default for ReconnectConfig {
             queue_size: DEFAULT_QUEUE_SIZE,
             on_reconnect: None,
             dial_func: None,
+            state_listener: None,
+            replay: true,
         }
     }
 }
@@ -70,6 +88,26 @@ where
     Arc::new(move |cfg| cfg.on_reconnect = Some(f.clone()))
 }
 
+/// Registers a callback invoked on every connect, disconnect and reconnect
+/// attempt. It runs on the sender thread and should return quickly.
+pub fn with_state_listener<F>(f: F) -> ReconnectOption
+where
+    F: Fn(ConnState) + Send + Sync + 'static,
+{
+    let f: StateListener = Arc::new(f);
+    Arc::new(move |cfg| cfg.state_listener = Some(f.clone()))
+}
+
+/// Controls whether requests interrupted by a dropped connection are re-sent
+/// after reconnecting. Only idempotent operations are replayed: reads, blob
+/// uploads, fs attachment, and appends that carry an idempotency key.
+/// Context creation and appends without a key surface the connection error.
+/// The server answers a replayed keyed append with the turn it already
+/// created, so a retry never duplicates it.
+pub fn with_replay(enabled: bool) -> ReconnectOption {
+    Arc::new(move |cfg| cfg.replay = enabled)
+}
+
 #[cfg(test)]
 pub(crate) fn with_dial_func(func: DialFunc) -> ReconnectOption {
     Arc::new(move |cfg| cfg.dial_func = Some(func.clone()))
@@ -88,6 +126,11 @@ struct Inner {
     retry_delay: Duration,
     max_retry_delay: Duration,
     on_reconnect: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+    state_listener: Option<StateListener>,
+    replay: bool,
+
+    reconnect_count: AtomicU64,
+    last_connected_at: Mutex<Option<SystemTime>>,
 
     queue_tx: Sender<QueuedRequest>,
     queue_rx: Receiver<QueuedRequest>,
@@ -99,6 +142,7 @@ struct Inner {
 struct QueuedRequest {
     ctx: RequestContext,
     op: Arc<dyn Fn(&Client) -> Result<()> + Send + Sync>,
+    replayable: bool,
     result_tx: Sender<Result<()>>,
 }
 
@@ -155,6 +199,10 @@ fn dial_reconnecting_inner(
         retry_delay: cfg.retry_delay,
         max_retry_delay: cfg.max_retry_delay,
         on_reconnect: cfg.on_reconnect.clone(),
+        state_listener: cfg.state_listener.clone(),
+        replay: cfg.replay,
+        reconnect_count: AtomicU64::new(0),
+        last_connected_at: Mutex::new(None),
         queue_tx,
         queue_rx: queue_rx.clone(),
         shutdown_tx: shutdown_tx.clone(),
@@ -162,6 +210,14 @@ fn dial_reconnecting_inner(
         closed: AtomicBool::new(false),
     });
 
+    let session_id = inner
+        .client
+        .lock()
+        .ok()
+        .and_then(|c| c.as_ref().map(|client| client.session_id()))
+        .unwrap_or(0);
+    inner.mark_connected(session_id);
+
     let worker_inner = inner.clone();
     let handle = thread::spawn(move || sender_loop(worker_inner));
 
@@ -208,6 +264,16 @@ impl ReconnectingClient {
         self.inner.queue_rx.len()
     }
 
+    /// Number of successful reconnects since the client was dialed.
+    pub fn reconnect_count(&self) -> u64 {
+        self.inner.reconnect_count.load(Ordering::SeqCst)
+    }
+
+    /// Wall-clock time the current (or most recent) connection was established.
+    pub fn last_connected_at(&self) -> Option<SystemTime> {
+        self.inner.last_connected_at.lock().ok().and_then(|t| *t)
+    }
+
     pub fn create_context(
         &self,
         ctx: &RequestContext,
@@ -216,7 +282,7 @@ impl ReconnectingClient {
         let result = Arc::new(Mutex::new(None));
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "CreateContext", move |client| {
+        self.enqueue_with(ctx, "CreateContext", false, move |client| {
             let head = client.create_context(&ctx_clone, base_turn_id)?;
             *result_clone.lock().unwrap() = Some(head);
             Ok(())
@@ -233,7 +299,7 @@ impl ReconnectingClient {
         let result = Arc::new(Mutex::new(None));
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "ForkContext", move |client| {
+        self.enqueue_with(ctx, "ForkContext", false, move |client| {
             let head = client.fork_context(&ctx_clone, base_turn_id)?;
             *result_clone.lock().unwrap() = Some(head);
             Ok(())
@@ -266,9 +332,10 @@ impl ReconnectingClient {
     ) -> Result<crate::turn::AppendResult> {
         let result = Arc::new(Mutex::new(None));
         let req = req.clone();
+        let replayable = !req.idempotency_key.is_empty();
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "AppendTurn", move |client| {
+        self.enqueue_with(ctx, "AppendTurn", replayable, move |client| {
             let res = client.append_turn(&ctx_clone, &req)?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
@@ -295,6 +362,19 @@ impl ReconnectingClient {
         Ok(value)
     }
 
+    pub fn get_blob(&self, ctx: &RequestContext, hash: [u8; 32]) -> Result<Vec<u8>> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue(ctx, "GetBlob", move |client| {
+            let res = client.get_blob(&ctx_clone, &hash)?;
+            *result_clone.lock().unwrap() = Some(res);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
     pub fn attach_fs(
         &self,
         ctx: &RequestContext,
@@ -357,9 +437,10 @@ impl ReconnectingClient {
     ) -> Result<crate::turn::AppendResult> {
         let result = Arc::new(Mutex::new(None));
         let req = req.clone();
+        let replayable = !req.idempotency_key.is_empty();
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "AppendTurnWithFs", move |client| {
+        self.enqueue_with(ctx, "AppendTurnWithFs", replayable, move |client| {
             let res = client.append_turn_with_fs(&ctx_clone, &req, fs_root_hash)?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
@@ -368,7 +449,20 @@ impl ReconnectingClient {
         Ok(value)
     }
 
-    fn enqueue<F>(&self, ctx: &RequestContext, _desc: &str, op: F) -> Result<()>
+    fn enqueue<F>(&self, ctx: &RequestContext, desc: &str, op: F) -> Result<()>
+    where
+        F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
+    {
+        self.enqueue_with(ctx, desc, true, op)
+    }
+
+    fn enqueue_with<F>(
+        &self,
+        ctx: &RequestContext,
+        _desc: &str,
+        replayable: bool,
+        op: F,
+    ) -> Result<()>
     where
         F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
     {
@@ -388,6 +482,7 @@ impl ReconnectingClient {
         let req = QueuedRequest {
             ctx: ctx.clone(),
             op: Arc::new(op),
+            replayable,
             result_tx,
         };
 
@@ -400,6 +495,21 @@ impl ReconnectingClient {
     }
 }
 
+impl Inner {
+    fn notify(&self, state: ConnState) {
+        if let Some(listener) = &self.state_listener {
+            listener(state);
+        }
+    }
+
+    fn mark_connected(&self, session_id: u64) {
+        if let Ok(mut at) = self.last_connected_at.lock() {
+            *at = Some(SystemTime::now());
+        }
+        self.notify(ConnState::Connected { session_id });
+    }
+}
+
 fn sender_loop(inner: Arc<Inner>) {
     loop {
         select! {
@@ -443,17 +553,26 @@ fn process_request(inner: &Arc<Inner>, req: QueuedRequest) {
     };
 
     let op = req.op.clone();
+    let replay = inner.replay && req.replayable;
+    let mut replays = 0;
     let mut err = (op)(&client);
-    if let Err(ref e) = err {
-        if is_connection_error(e) {
-            if let Err(reconn_err) = reconnect(inner, &req.ctx) {
-                err = Err(reconn_err);
-            } else {
-                let client = inner.client.lock().ok().and_then(|c| c.as_ref().cloned());
-                if let Some(client) = client {
-                    err = (op)(&client);
-                }
-            }
+    while let Err(ref e) = err {
+        if !e.is_retryable() {
+            break;
+        }
+        // Always re-dial so later requests find a live connection, but only
+        // re-send this one when it is safe to execute twice.
+        if let Err(reconn_err) = reconnect(inner, &req.ctx) {
+            err = Err(reconn_err);
+            break;
+        }
+        if !replay || replays >= inner.max_retries {
+            break;
+        }
+        replays += 1;
+        match inner.client.lock().ok().and_then(|c| c.as_ref().cloned()) {
+            Some(client) => err = (op)(&client),
+            None => break,
         }
     }
 
@@ -464,6 +583,7 @@ fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
     let mut delay = inner.retry_delay;
     let mut last_err: Option<Error> = None;
 
+    inner.notify(ConnState::Disconnected);
     for attempt in 1..=inner.max_retries {
         if attempt > 1 {
             sleep_with_cancel(delay, ctx, inner)?;
@@ -480,6 +600,7 @@ fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
             }
         }
 
+        inner.notify(ConnState::Reconnecting { attempt });
         match (inner.dial_func)() {
             Ok(client) => {
                 let client = Arc::new(client);
@@ -487,6 +608,8 @@ fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
                 if let Ok(mut guard) = inner.client.lock() {
                     *guard = Some(client);
                 }
+                inner.reconnect_count.fetch_add(1, Ordering::SeqCst);
+                inner.mark_connected(session_id);
                 if let Some(cb) = &inner.on_reconnect {
                     cb(session_id);
                 }
@@ -573,27 +696,10 @@ fn wait_for_result(result_rx: &Receiver<Result<()>>, ctx: &RequestContext) -> Re
     }
 }
 
+/// Reports whether an error indicates a broken connection that warrants a
+/// re-dial. Equivalent to [`Error::is_retryable`].
 pub fn is_connection_error(err: &Error) -> bool {
-    match err {
-        Error::ClientClosed => false,
-        Error::Server(_) => false,
-        Error::Timeout => false,
-        Error::Cancelled => false,
-        Error::QueueFull => false,
-        Error::Io(io_err) => match io_err.kind() {
-            std::io::ErrorKind::ConnectionReset
-            | std::io::ErrorKind::ConnectionAborted
-            | std::io::ErrorKind::BrokenPipe
-            | std::io::ErrorKind::ConnectionRefused
-            | std::io::ErrorKind::TimedOut
-            | std::io::ErrorKind::UnexpectedEof
-            | std::io::ErrorKind::NotConnected => true,
-            _ => contains_connection_pattern(&io_err.to_string()),
-        },
-        Error::Tls(msg) => contains_connection_pattern(msg),
-        Error::InvalidResponse(msg) => contains_connection_pattern(msg),
-        _ => contains_connection_pattern(&err.to_string()),
-    }
+    err.is_retryable()
 }
 
 #[allow(non_snake_case)]
@@ -601,21 +707,6 @@ pub fn IsConnectionError(err: &Error) -> bool {
     is_connection_error(err)
 }
 
-fn contains_connection_pattern(msg: &str) -> bool {
-    let msg = msg.to_lowercase();
-    let patterns = [
-        "connection reset",
-        "connection refused",
-        "broken pipe",
-        "use of closed network connection",
-        "network is unreachable",
-        "no route to host",
-        "connection timed out",
-        "i/o timeout",
-    ];
-    patterns.iter().any(|p| msg.contains(p))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -646,6 +737,190 @@ mod tests {
         (addr.to_string(), stop_tx, handle)
     }
 
+    /// Serves `conns` sequential connections. Each answers HELLO, then the
+    /// first connection drops after reading one request while later ones
+    /// answer every request with a fixed context head.
+    fn start_flaky_server(conns: usize) -> (String, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut served = 0;
+            for i in 0..conns {
+                let (mut stream, _) = listener.accept().unwrap();
+                let frame = read_frame(&mut stream).unwrap();
+                assert_eq!(frame.header.msg_type, MSG_HELLO);
+                let mut resp = Vec::new();
+                resp.write_u64::<LittleEndian>(i as u64 + 1).unwrap();
+                resp.write_u16::<LittleEndian>(1).unwrap();
+                write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+                if i == 0 {
+                    let _ = read_frame(&mut stream);
+                    drop(stream);
+                    continue;
+                }
+                while let Ok(req) = read_frame(&mut stream) {
+                    let mut head = Vec::new();
+                    head.write_u64::<LittleEndian>(7).unwrap();
+                    head.write_u64::<LittleEndian>(3).unwrap();
+                    head.write_u32::<LittleEndian>(2).unwrap();
+                    write_frame(
+                        &mut stream,
+                        req.header.msg_type,
+                        0,
+                        req.header.req_id,
+                        &head,
+                    )
+                    .unwrap();
+                    served += 1;
+                }
+            }
+            served
+        });
+        (addr.to_string(), handle)
+    }
+
+    fn flaky_dial_func(addr: &str, dial_count: Arc<AtomicUsize>) -> DialFunc {
+        let addr = addr.to_string();
+        Arc::new(move || {
+            // Initial dial succeeds, first reconnect attempt fails.
+            if dial_count.fetch_add(1, AtomicOrdering::SeqCst) == 1 {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "refused",
+                )));
+            }
+            dial(&addr, Vec::<ClientOption>::new())
+        })
+    }
+
+    #[test]
+    fn read_is_replayed_after_reconnect() {
+        let (addr, server) = start_flaky_server(2);
+        let dial_count = Arc::new(AtomicUsize::new(0));
+        let client = dial_reconnecting_inner(
+            &addr,
+            false,
+            vec![
+                with_dial_func(flaky_dial_func(&addr, dial_count.clone())),
+                with_retry_delay(Duration::from_millis(10)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        let head = client
+            .get_head(&RequestContext::with_timeout(Duration::from_secs(5)), 7)
+            .unwrap();
+        assert_eq!(head.context_id, 7);
+        assert_eq!(head.head_turn_id, 3);
+        assert_eq!(dial_count.load(AtomicOrdering::SeqCst), 3);
+        assert_eq!(client.session_id(), 2);
+
+        client.close().unwrap();
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn deadline_exceeded_request_is_not_replayed() {
+        let (addr, stop_tx, server) = start_hello_server();
+        let dial_count = Arc::new(AtomicUsize::new(0));
+        let counted = dial_count.clone();
+        let dial_addr = addr.clone();
+        let client = dial_reconnecting_inner(
+            &addr,
+            false,
+            vec![
+                with_dial_func(Arc::new(move || {
+                    counted.fetch_add(1, AtomicOrdering::SeqCst);
+                    dial(&dial_addr, Vec::<ClientOption>::new())
+                })),
+                with_retry_delay(Duration::from_millis(10)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        // The server never answers, so the read runs out its deadline.
+        let err = client
+            .get_head(&RequestContext::with_timeout(Duration::from_millis(200)), 7)
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout), "unexpected error: {err:?}");
+        assert_eq!(dial_count.load(AtomicOrdering::SeqCst), 1);
+
+        let _ = stop_tx.send(());
+        server.join().unwrap();
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn append_without_idempotency_key_is_not_replayed() {
+        let (addr, server) = start_flaky_server(2);
+        let dial_count = Arc::new(AtomicUsize::new(0));
+        let client = dial_reconnecting_inner(
+            &addr,
+            false,
+            vec![
+                with_dial_func(flaky_dial_func(&addr, dial_count.clone())),
+                with_retry_delay(Duration::from_millis(10)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        let req = crate::turn::AppendRequest::new(7, "test.Type", 1, vec![0x90]);
+        let err = client
+            .append_turn(&RequestContext::with_timeout(Duration::from_secs(5)), &req)
+            .unwrap_err();
+        assert!(err.is_retryable(), "unexpected error: {err:?}");
+        // The connection was still re-established for subsequent requests.
+        assert_eq!(client.session_id(), 2);
+
+        client.close().unwrap();
+        assert_eq!(server.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn state_listener_reports_reconnect_sequence() {
+        let (addr, server) = start_flaky_server(2);
+        let dial_count = Arc::new(AtomicUsize::new(0));
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let states_clone = states.clone();
+        let client = dial_reconnecting_inner(
+            &addr,
+            false,
+            vec![
+                with_dial_func(flaky_dial_func(&addr, dial_count)),
+                with_retry_delay(Duration::from_millis(10)),
+                with_state_listener(move |state| states_clone.lock().unwrap().push(state)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+        assert_eq!(client.reconnect_count(), 0);
+        let first_connected = client.last_connected_at().unwrap();
+
+        client
+            .get_head(&RequestContext::with_timeout(Duration::from_secs(5)), 7)
+            .unwrap();
+
+        assert_eq!(
+            *states.lock().unwrap(),
+            vec![
+                ConnState::Connected { session_id: 1 },
+                ConnState::Disconnected,
+                ConnState::Reconnecting { attempt: 1 },
+                ConnState::Reconnecting { attempt: 2 },
+                ConnState::Connected { session_id: 2 },
+            ]
+        );
+        assert_eq!(client.reconnect_count(), 1);
+        assert!(client.last_connected_at().unwrap() >= first_connected);
+
+        client.close().unwrap();
+        server.join().unwrap();
+    }
+
     #[test]
     fn is_connection_error_matches_basic_cases() {
         assert!(!is_connection_error(&Error::ClientClosed));
@@ -711,6 +986,7 @@ mod tests {
         let queued_req = QueuedRequest {
             ctx: RequestContext::background(),
             op: Arc::new(|_| Ok(())),
+            replayable: true,
             result_tx: queued_tx,
         };
         client.inner.queue_tx.try_send(queued_req).unwrap();
@@ -768,6 +1044,7 @@ mod tests {
         let queued_req = QueuedRequest {
             ctx: RequestContext::background(),
             op: Arc::new(|_| Ok(())),
+            replayable: true,
             result_tx: queued_tx,
         };
         client.inner.queue_tx.try_send(queued_req).unwrap();
@@ -32,3 +32,62 @@ pub fn load_fixture(name: &str) -> Fixture {
 pub fn decode_hex(hex_str: &str) -> Vec<u8> {
     hex::decode(hex_str).unwrap_or_else(|err| panic!("hex decode failed: {err}"))
 }
+
+/// One end of an in-memory byte duplex; see [`duplex`].
+#[cfg(test)]
+pub struct DuplexEnd {
+    tx: std::sync::mpsc::Sender<Vec<u8>>,
+    rx: std::sync::mpsc::Receiver<Vec<u8>>,
+    pending: std::collections::VecDeque<u8>,
+}
+
+/// A connected pair of in-memory streams. Bytes written to one end are read
+/// from the other; reads return EOF once the peer is dropped.
+#[cfg(test)]
+pub fn duplex() -> (DuplexEnd, DuplexEnd) {
+    let (a_tx, a_rx) = std::sync::mpsc::channel();
+    let (b_tx, b_rx) = std::sync::mpsc::channel();
+    (
+        DuplexEnd {
+            tx: a_tx,
+            rx: b_rx,
+            pending: Default::default(),
+        },
+        DuplexEnd {
+            tx: b_tx,
+            rx: a_rx,
+            pending: Default::default(),
+        },
+    )
+}
+
+#[cfg(test)]
+impl std::io::Read for DuplexEnd {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv() {
+                Ok(chunk) => self.pending.extend(chunk),
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for DuplexEnd {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx
+            .send(buf.to_vec())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
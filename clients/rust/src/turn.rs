@@ -6,7 +6,10 @@ use std::io::Read;
 
 use crate::client::{Client, RequestContext};
 use crate::error::{Error, Result};
-use crate::protocol::{ENCODING_MSGPACK, MSG_APPEND_TURN, MSG_GET_LAST};
+use crate::protocol::{
+    Frame, CAP_CONDITIONAL_APPEND, ENCODING_MSGPACK, FLAG_GET_LAST_NO_HASH, FLAG_GET_LAST_VARINT,
+    MSG_APPEND_TURN, MSG_GET_LAST, MSG_GET_LAST_BATCH,
+};
 
 #[derive(Debug, Clone)]
 pub struct AppendRequest {
@@ -49,6 +52,7 @@ pub struct TurnRecord {
     pub type_version: u32,
     pub encoding: u32,
     pub compression: u32,
+    /// All zeros when the turn was read with `include_hash: false`.
     pub payload_hash: [u8; 32],
     pub payload: Vec<u8>,
 }
@@ -59,12 +63,37 @@ pub struct AppendResult {
     pub turn_id: u64,
     pub depth: u32,
     pub payload_hash: [u8; 32],
+    /// Context head after the append. Differs from `turn_id` when appending
+    /// to a non-head parent started a side branch. `None` from servers that
+    /// predate branch tracking or negotiated protocol version 1.
+    pub head_turn_id: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct GetLastOptions {
     pub limit: u32,
     pub include_payload: bool,
+    /// Set to false to have the server leave out each turn's 32-byte hash,
+    /// e.g. when the caller recomputes it from the payload anyway.
+    pub include_hash: bool,
+    /// Ask for the compact response encoding, with ids, depths and lengths
+    /// as varints. Mostly pays off for contexts with small turn ids.
+    pub varint: bool,
+}
+
+impl GetLastOptions {
+    /// These options as a server at `protocol_version` understands them:
+    /// leaving out hashes and the varint encoding need version 2.
+    pub(crate) fn at_version(self, protocol_version: u16) -> Self {
+        if protocol_version >= 2 {
+            return self;
+        }
+        Self {
+            include_hash: true,
+            varint: false,
+            ..self
+        }
+    }
 }
 
 impl Default for GetLastOptions {
@@ -72,43 +101,52 @@ impl Default for GetLastOptions {
         Self {
             limit: 10,
             include_payload: false,
+            include_hash: true,
+            varint: false,
         }
     }
 }
 
+/// Turns returned for one context of a [`Client::get_last_batch`] call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextTurns {
+    pub context_id: u64,
+    pub turns: Vec<TurnRecord>,
+}
+
 impl Client {
     pub fn append_turn(&self, ctx: &RequestContext, req: &AppendRequest) -> Result<AppendResult> {
-        let encoding = if req.encoding == 0 {
-            ENCODING_MSGPACK
-        } else {
-            req.encoding
-        };
-
-        let hash = blake3::hash(&req.payload);
-
-        let mut payload = Vec::with_capacity(128 + req.payload.len());
-        payload.write_u64::<LittleEndian>(req.context_id)?;
-        payload.write_u64::<LittleEndian>(req.parent_turn_id)?;
-
-        payload.write_u32::<LittleEndian>(req.type_id.len() as u32)?;
-        payload.extend_from_slice(req.type_id.as_bytes());
-        payload.write_u32::<LittleEndian>(req.type_version)?;
-
-        payload.write_u32::<LittleEndian>(encoding)?;
-        payload.write_u32::<LittleEndian>(req.compression)?;
-        payload.write_u32::<LittleEndian>(req.payload.len() as u32)?; // uncompressed len
-        payload.extend_from_slice(hash.as_bytes());
-
-        payload.write_u32::<LittleEndian>(req.payload.len() as u32)?;
-        payload.extend_from_slice(&req.payload);
+        let payload = encode_append_payload(req)?;
+        let frame = self.send_request(ctx, MSG_APPEND_TURN, &payload)?;
+        parse_append_result(&frame.payload, self.protocol_version())
+    }
 
-        payload.write_u32::<LittleEndian>(req.idempotency_key.len() as u32)?;
-        if !req.idempotency_key.is_empty() {
-            payload.extend_from_slice(&req.idempotency_key);
+    /// Appends only if the context head is still `expected_head_turn_id`.
+    /// If another writer got there first the server refuses with a conflict
+    /// ([`ServerError::is_conflict`](crate::ServerError::is_conflict)) whose
+    /// detail names the actual head.
+    ///
+    /// Servers that don't advertise [`CAP_CONDITIONAL_APPEND`] ignore the
+    /// expected head and append unconditionally, so against them this fails
+    /// with [`Error::Unsupported`] without sending anything.
+    pub fn append_turn_if_head(
+        &self,
+        ctx: &RequestContext,
+        req: &AppendRequest,
+        expected_head_turn_id: u64,
+    ) -> Result<AppendResult> {
+        if !self
+            .capabilities()
+            .is_some_and(|caps| caps.has(CAP_CONDITIONAL_APPEND))
+        {
+            return Err(Error::Unsupported(
+                "conditional append needs a server advertising CAP_CONDITIONAL_APPEND".into(),
+            ));
         }
-
-        let frame = self.send_request(ctx, MSG_APPEND_TURN, &payload)?;
-        parse_append_result(&frame.payload)
+        let mut payload = encode_append_payload(req)?;
+        payload.write_u64::<LittleEndian>(expected_head_turn_id)?;
+        let frame = self.send_request_with_flags(ctx, MSG_APPEND_TURN, 2, &payload)?;
+        parse_append_result(&frame.payload, self.protocol_version())
     }
 
     pub fn get_last(
@@ -117,18 +155,99 @@ impl Client {
         context_id: u64,
         opts: GetLastOptions,
     ) -> Result<Vec<TurnRecord>> {
-        let limit = if opts.limit == 0 { 10 } else { opts.limit };
-        let mut payload = Vec::with_capacity(16);
-        payload.write_u64::<LittleEndian>(context_id)?;
-        payload.write_u32::<LittleEndian>(limit)?;
-        payload.write_u32::<LittleEndian>(if opts.include_payload { 1 } else { 0 })?;
-
-        let frame = self.send_request(ctx, MSG_GET_LAST, &payload)?;
-        parse_turn_records(&frame.payload)
+        let opts = opts.at_version(self.protocol_version());
+        let payload = encode_get_last_payload(context_id, opts)?;
+        let frame =
+            self.send_request_with_flags(ctx, MSG_GET_LAST, get_last_flags(opts), &payload)?;
+        parse_get_last_response(&frame)
+    }
+
+    /// Fetches the last turns of several contexts in one round trip. Each
+    /// entry is a `(context_id, limit)` pair; results come back in the same
+    /// order. The whole call fails if any context does not exist, and with
+    /// [`Error::Unsupported`] if the server negotiated protocol version 1.
+    pub fn get_last_batch(
+        &self,
+        ctx: &RequestContext,
+        entries: &[(u64, u32)],
+        include_payload: bool,
+    ) -> Result<Vec<ContextTurns>> {
+        if self.protocol_version() < 2 {
+            return Err(Error::Unsupported(
+                "GET_LAST_BATCH needs protocol version 2".into(),
+            ));
+        }
+        let mut payload = Vec::with_capacity(8 + entries.len() * 12);
+        payload.write_u32::<LittleEndian>(if include_payload { 1 } else { 0 })?;
+        payload.write_u32::<LittleEndian>(entries.len() as u32)?;
+        for &(context_id, limit) in entries {
+            payload.write_u64::<LittleEndian>(context_id)?;
+            payload.write_u32::<LittleEndian>(if limit == 0 { 10 } else { limit })?;
+        }
+
+        let frame = self.send_request(ctx, MSG_GET_LAST_BATCH, &payload)?;
+        parse_turn_batch(&frame.payload)
+    }
+}
+
+/// Encodes an APPEND_TURN payload (without the optional fs root trailer).
+pub(crate) fn encode_append_payload(req: &AppendRequest) -> Result<Vec<u8>> {
+    let encoding = if req.encoding == 0 {
+        ENCODING_MSGPACK
+    } else {
+        req.encoding
+    };
+
+    let hash = blake3::hash(&req.payload);
+
+    let mut payload = Vec::with_capacity(128 + req.payload.len());
+    payload.write_u64::<LittleEndian>(req.context_id)?;
+    payload.write_u64::<LittleEndian>(req.parent_turn_id)?;
+
+    payload.write_u32::<LittleEndian>(req.type_id.len() as u32)?;
+    payload.extend_from_slice(req.type_id.as_bytes());
+    payload.write_u32::<LittleEndian>(req.type_version)?;
+
+    payload.write_u32::<LittleEndian>(encoding)?;
+    payload.write_u32::<LittleEndian>(req.compression)?;
+    payload.write_u32::<LittleEndian>(req.payload.len() as u32)?; // uncompressed len
+    payload.extend_from_slice(hash.as_bytes());
+
+    payload.write_u32::<LittleEndian>(req.payload.len() as u32)?;
+    payload.extend_from_slice(&req.payload);
+
+    payload.write_u32::<LittleEndian>(req.idempotency_key.len() as u32)?;
+    if !req.idempotency_key.is_empty() {
+        payload.extend_from_slice(&req.idempotency_key);
+    }
+    Ok(payload)
+}
+
+pub(crate) fn encode_get_last_payload(context_id: u64, opts: GetLastOptions) -> Result<Vec<u8>> {
+    let limit = if opts.limit == 0 { 10 } else { opts.limit };
+    let mut payload = Vec::with_capacity(16);
+    payload.write_u64::<LittleEndian>(context_id)?;
+    payload.write_u32::<LittleEndian>(limit)?;
+    payload.write_u32::<LittleEndian>(if opts.include_payload { 1 } else { 0 })?;
+    // Omitted unless set so default requests stay readable by older servers.
+    if !opts.include_hash {
+        payload.write_u32::<LittleEndian>(0)?;
+    }
+    Ok(payload)
+}
+
+/// Request frame flags for a GET_LAST with `opts`.
+pub(crate) fn get_last_flags(opts: GetLastOptions) -> u16 {
+    if opts.varint {
+        FLAG_GET_LAST_VARINT
+    } else {
+        0
     }
 }
 
-fn parse_append_result(payload: &[u8]) -> Result<AppendResult> {
+/// Decodes an APPEND_TURN ack; `head_turn_id` is only read at protocol
+/// version 2.
+pub(crate) fn parse_append_result(payload: &[u8], protocol_version: u16) -> Result<AppendResult> {
     if payload.len() < 52 {
         return Err(Error::invalid_response(format!(
             "append response too short ({} bytes)",
@@ -141,43 +260,80 @@ fn parse_append_result(payload: &[u8]) -> Result<AppendResult> {
     let depth = cursor.read_u32::<LittleEndian>()?;
     let mut hash = [0u8; 32];
     cursor.read_exact(&mut hash)?;
+    let head_turn_id = if protocol_version >= 2 && payload.len() >= 60 {
+        Some(cursor.read_u64::<LittleEndian>()?)
+    } else {
+        None
+    };
     Ok(AppendResult {
         context_id,
         turn_id,
         depth,
         payload_hash: hash,
+        head_turn_id,
     })
 }
 
-fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
-    if payload.len() < 4 {
+/// Decodes a GET_LAST response, using its frame flags to tell whether the
+/// items carry hashes.
+pub(crate) fn parse_get_last_response(frame: &Frame) -> Result<Vec<TurnRecord>> {
+    parse_turn_records_with(&frame.payload, frame.header.flags)
+}
+
+pub(crate) fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
+    parse_turn_records_with(payload, 0)
+}
+
+/// Decodes a GET_LAST body laid out as the response `flags` describe.
+fn parse_turn_records_with(payload: &[u8], flags: u16) -> Result<Vec<TurnRecord>> {
+    let include_hash = flags & FLAG_GET_LAST_NO_HASH == 0;
+    let varint = flags & FLAG_GET_LAST_VARINT != 0;
+    if payload.len() < if varint { 1 } else { 4 } {
         return Err(Error::invalid_response("turn records too short"));
     }
 
     let mut cursor = std::io::Cursor::new(payload);
-    let count = cursor.read_u32::<LittleEndian>()?;
+    let read_u64 = |cursor: &mut std::io::Cursor<&[u8]>| -> Result<u64> {
+        if varint {
+            read_uvarint(cursor)
+        } else {
+            Ok(cursor.read_u64::<LittleEndian>()?)
+        }
+    };
+    let read_u32 = |cursor: &mut std::io::Cursor<&[u8]>| -> Result<u32> {
+        if varint {
+            u32::try_from(read_uvarint(cursor)?)
+                .map_err(|_| Error::invalid_response("varint exceeds u32"))
+        } else {
+            Ok(cursor.read_u32::<LittleEndian>()?)
+        }
+    };
+
+    let count = read_u32(&mut cursor)?;
     let mut records = Vec::with_capacity(count as usize);
 
     for _ in 0..count {
-        let turn_id = cursor.read_u64::<LittleEndian>()?;
-        let parent_id = cursor.read_u64::<LittleEndian>()?;
-        let depth = cursor.read_u32::<LittleEndian>()?;
+        let turn_id = read_u64(&mut cursor)?;
+        let parent_id = read_u64(&mut cursor)?;
+        let depth = read_u32(&mut cursor)?;
 
-        let type_len = cursor.read_u32::<LittleEndian>()? as usize;
+        let type_len = read_u32(&mut cursor)? as usize;
         let mut type_bytes = vec![0u8; type_len];
         cursor.read_exact(&mut type_bytes)?;
         let type_id = String::from_utf8(type_bytes)
             .map_err(|_| Error::invalid_response("type_id not utf8"))?;
 
-        let type_version = cursor.read_u32::<LittleEndian>()?;
-        let encoding = cursor.read_u32::<LittleEndian>()?;
-        let compression = cursor.read_u32::<LittleEndian>()?;
+        let type_version = read_u32(&mut cursor)?;
+        let encoding = read_u32(&mut cursor)?;
+        let compression = read_u32(&mut cursor)?;
 
-        let _uncompressed_len = cursor.read_u32::<LittleEndian>()?;
+        let _uncompressed_len = read_u32(&mut cursor)?;
         let mut payload_hash = [0u8; 32];
-        cursor.read_exact(&mut payload_hash)?;
+        if include_hash {
+            cursor.read_exact(&mut payload_hash)?;
+        }
 
-        let payload_len = cursor.read_u32::<LittleEndian>()? as usize;
+        let payload_len = read_u32(&mut cursor)? as usize;
         let mut payload_bytes = vec![0u8; payload_len];
         cursor.read_exact(&mut payload_bytes)?;
 
@@ -197,6 +353,38 @@ fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
     Ok(records)
 }
 
+/// Reads an unsigned LEB128 value: seven bits per byte, low bits first.
+fn read_uvarint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::invalid_response("varint longer than 10 bytes"))
+}
+
+fn parse_turn_batch(payload: &[u8]) -> Result<Vec<ContextTurns>> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let count = cursor
+        .read_u32::<LittleEndian>()
+        .map_err(|_| Error::invalid_response("turn batch too short"))?;
+    let mut groups = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let context_id = cursor.read_u64::<LittleEndian>()?;
+        let len = cursor.read_u32::<LittleEndian>()? as usize;
+        let mut body = vec![0u8; len];
+        cursor.read_exact(&mut body)?;
+        groups.push(ContextTurns {
+            context_id,
+            turns: parse_turn_records(&body)?,
+        });
+    }
+    Ok(groups)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -304,4 +492,208 @@ mod tests {
         payload.write_u32::<LittleEndian>(1).unwrap();
         assert_eq!(decode_hex(&fixture.payload_hex), payload);
     }
+
+    #[test]
+    fn get_last_without_hashes_round_trips() {
+        use crate::client::dial;
+        use crate::protocol::{read_frame, write_frame, MSG_HELLO};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let frame = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(1).unwrap();
+            resp.write_u16::<LittleEndian>(2).unwrap();
+            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_LAST);
+            assert_eq!(req.payload.len(), 20);
+            assert_eq!(&req.payload[16..], &0u32.to_le_bytes());
+
+            let mut body = Vec::new();
+            body.write_u32::<LittleEndian>(2).unwrap();
+            for (id, data) in [(7u64, &b"\xa1a"[..]), (8, b"\xa2bc")] {
+                body.write_u64::<LittleEndian>(id).unwrap();
+                body.write_u64::<LittleEndian>(id - 1).unwrap();
+                body.write_u32::<LittleEndian>(id as u32).unwrap();
+                body.write_u32::<LittleEndian>(1).unwrap();
+                body.extend_from_slice(b"t");
+                body.write_u32::<LittleEndian>(1).unwrap();
+                body.write_u32::<LittleEndian>(ENCODING_MSGPACK).unwrap();
+                body.write_u32::<LittleEndian>(0).unwrap();
+                body.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+                body.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+                body.extend_from_slice(data);
+            }
+            write_frame(
+                &mut stream,
+                MSG_GET_LAST,
+                FLAG_GET_LAST_NO_HASH,
+                req.header.req_id,
+                &body,
+            )
+            .unwrap();
+        });
+
+        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let turns = client
+            .get_last(
+                &ctx,
+                1,
+                GetLastOptions {
+                    include_payload: true,
+                    include_hash: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(turns.len(), 2);
+        assert_eq!((turns[0].turn_id, turns[0].parent_id), (7, 6));
+        assert_eq!(turns[0].payload, b"\xa1a");
+        assert_eq!((turns[1].turn_id, turns[1].depth), (8, 8));
+        assert_eq!(turns[1].type_id, "t");
+        assert_eq!(turns[1].payload, b"\xa2bc");
+        assert!(turns.iter().all(|t| t.payload_hash == [0u8; 32]));
+    }
+
+    #[test]
+    fn get_last_fixed_and_varint_decode_identically() {
+        use crate::client::dial;
+        use crate::protocol::{read_frame, write_frame, MSG_HELLO};
+        use std::net::TcpListener;
+        use std::thread;
+
+        fn put_uvarint(buf: &mut Vec<u8>, mut v: u64) {
+            while v >= 0x80 {
+                buf.push((v as u8) | 0x80);
+                v >>= 7;
+            }
+            buf.push(v as u8);
+        }
+
+        fn turns_body(varint: bool) -> Vec<u8> {
+            let mut body = Vec::new();
+            let put = |body: &mut Vec<u8>, v: u64, wide: bool| {
+                if varint {
+                    put_uvarint(body, v);
+                } else if wide {
+                    body.write_u64::<LittleEndian>(v).unwrap();
+                } else {
+                    body.write_u32::<LittleEndian>(v as u32).unwrap();
+                }
+            };
+            put(&mut body, 3, false);
+            for (id, data) in [(1u64, &b"\xa1a"[..]), (2, &[0x90; 200]), (1 << 40, b"")] {
+                put(&mut body, id, true);
+                put(&mut body, id.saturating_sub(1), true);
+                put(&mut body, id % 1000, false);
+                put(&mut body, 9, false);
+                body.extend_from_slice(b"cxdb.Item");
+                put(&mut body, 2, false);
+                put(&mut body, ENCODING_MSGPACK as u64, false);
+                put(&mut body, 0, false);
+                put(&mut body, data.len() as u64, false);
+                body.extend_from_slice(blake3::hash(data).as_bytes());
+                put(&mut body, data.len() as u64, false);
+                body.extend_from_slice(data);
+            }
+            body
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let frame = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(1).unwrap();
+            resp.write_u16::<LittleEndian>(2).unwrap();
+            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+
+            let mut sizes = Vec::new();
+            for _ in 0..2 {
+                let req = read_frame(&mut stream).unwrap();
+                let flags = req.header.flags & FLAG_GET_LAST_VARINT;
+                let body = turns_body(flags != 0);
+                sizes.push(body.len());
+                write_frame(&mut stream, MSG_GET_LAST, flags, req.header.req_id, &body).unwrap();
+            }
+            sizes
+        });
+
+        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let mut results = Vec::new();
+        for varint in [false, true] {
+            let opts = GetLastOptions {
+                include_payload: true,
+                varint,
+                ..Default::default()
+            };
+            results.push(client.get_last(&ctx, 1, opts).unwrap());
+        }
+        let sizes = handle.join().unwrap();
+
+        assert!(sizes[1] < sizes[0]);
+        assert_eq!(results[0], results[1]);
+        let turns = &results[0];
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[2].turn_id, 1 << 40);
+        assert_eq!(turns[1].payload, vec![0x90; 200]);
+        assert_eq!(turns[0].payload_hash, *blake3::hash(b"\xa1a").as_bytes());
+    }
+
+    #[test]
+    fn parse_turn_batch_splits_groups() {
+        fn turns_body(turn_ids: &[u64]) -> Vec<u8> {
+            let mut body = Vec::new();
+            body.write_u32::<LittleEndian>(turn_ids.len() as u32)
+                .unwrap();
+            for &id in turn_ids {
+                body.write_u64::<LittleEndian>(id).unwrap();
+                body.write_u64::<LittleEndian>(id - 1).unwrap();
+                body.write_u32::<LittleEndian>(0).unwrap();
+                body.write_u32::<LittleEndian>(1).unwrap();
+                body.extend_from_slice(b"t");
+                body.write_u32::<LittleEndian>(1).unwrap();
+                body.write_u32::<LittleEndian>(ENCODING_MSGPACK).unwrap();
+                body.write_u32::<LittleEndian>(0).unwrap();
+                body.write_u32::<LittleEndian>(0).unwrap();
+                body.extend_from_slice(&[0u8; 32]);
+                body.write_u32::<LittleEndian>(0).unwrap();
+            }
+            body
+        }
+
+        let mut payload = Vec::new();
+        payload.write_u32::<LittleEndian>(2).unwrap();
+        for (ctx, ids) in [(4u64, vec![7u64, 8]), (5, vec![9])] {
+            let body = turns_body(&ids);
+            payload.write_u64::<LittleEndian>(ctx).unwrap();
+            payload
+                .write_u32::<LittleEndian>(body.len() as u32)
+                .unwrap();
+            payload.extend_from_slice(&body);
+        }
+
+        let groups = parse_turn_batch(&payload).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].context_id, 4);
+        let ids: Vec<u64> = groups[0].turns.iter().map(|t| t.turn_id).collect();
+        assert_eq!(ids, vec![7, 8]);
+        assert_eq!(groups[1].context_id, 5);
+        assert_eq!(groups[1].turns[0].turn_id, 9);
+    }
 }
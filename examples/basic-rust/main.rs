@@ -125,6 +125,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let options = cxdb::GetLastOptions {
         limit: 10,
         include_payload: true,
+        ..Default::default()
     };
     let turns = client.get_last(&ctx, context_id, options)?;
 
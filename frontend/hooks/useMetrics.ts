@@ -63,8 +63,10 @@ export function generateMockMetrics(): MetricsSnapshot {
       contexts_total: 812 + Math.floor(Math.random() * 5),
       turns_total: 92014 + Math.floor(Math.random() * 50),
       blobs_total: 4812 + Math.floor(Math.random() * 20),
+      blobs_compressed_total: 3950 + Math.floor(Math.random() * 20),
       registry_types_total: 62,
       registry_bundles_total: 9,
+      registry_bundles_skipped: 0,
       heads_total: 812,
     },
     storage: {
@@ -74,6 +76,7 @@ export function generateMockMetrics(): MetricsSnapshot {
       heads_table_bytes: 1_048_576,
       blobs_pack_bytes: 21_474_836_480,
       blobs_index_bytes: 134_217_728,
+      compression_ratio: 2.7,
       data_dir_total_bytes: 53_687_091_200,
       data_dir_free_bytes: 21_474_836_480,
     },
@@ -81,6 +84,13 @@ export function generateMockMetrics(): MetricsSnapshot {
       snapshots_total: 127 + Math.floor(Math.random() * 10),
       index_bytes: 5_588 + Math.floor(Math.random() * 1000),
       content_bytes: 536_870_912 + Math.floor(Math.random() * 100_000_000),
+      tree_cache_hits: 18_200 + Math.floor(Math.random() * 500),
+      tree_cache_misses: 940 + Math.floor(Math.random() * 50),
+    },
+    index_warmup: {
+      indexed: 4_210,
+      total: 4_210,
+      complete: true,
     },
     perf: {
       append_tps_1m: 35 + Math.random() * 15,
@@ -99,14 +109,20 @@ export function generateMockMetrics(): MetricsSnapshot {
       http_req_tps_history: Array.from({ length: 12 }, () => 50 + Math.random() * 20),
       http_errors_tps_1m: Math.random() * 0.4,
       http_errors_tps_5m: 0.1,
-      append_latency_ms: { p50: 3.2, p95: 8.4, p99: 12.1, max: 19.7, count: 512 },
-      get_last_latency_ms: { p50: 1.7, p95: 4.2, p99: 6.1, max: 9.5, count: 512 },
-      get_blob_latency_ms: { p50: 0.9, p95: 2.5, p99: 4.7, max: 6.2, count: 256 },
-      http_latency_ms: { p50: 2.1, p95: 6.4, p99: 9.8, max: 14.0, count: 1024 },
+      append_latency_ms: { p50: 3.2, p95: 8.4, p99: 12.1, max: 19.7, count: 512, window_secs: 42.5 },
+      get_last_latency_ms: { p50: 1.7, p95: 4.2, p99: 6.1, max: 9.5, count: 512, window_secs: 42.5 },
+      get_blob_latency_ms: { p50: 0.9, p95: 2.5, p99: 4.7, max: 6.2, count: 256, window_secs: 42.5 },
+      http_latency_ms: { p50: 2.1, p95: 6.4, p99: 9.8, max: 14.0, count: 1024, window_secs: 42.5 },
+      append_latency_buckets: { le_1ms: 40, le_5ms: 380, le_10ms: 470, le_25ms: 512, le_inf: 512 },
+      get_last_latency_buckets: { le_1ms: 90, le_5ms: 470, le_10ms: 512, le_25ms: 512, le_inf: 512 },
+      get_blob_latency_buckets: { le_1ms: 150, le_5ms: 250, le_10ms: 256, le_25ms: 256, le_inf: 256 },
+      http_latency_buckets: { le_1ms: 120, le_5ms: 820, le_10ms: 1010, le_25ms: 1024, le_inf: 1024 },
+      latency_window: { max_samples: 2048, max_age_secs: null },
     },
     errors: {
       total: 12,
       by_type: { binary: 5, http: 7 },
+      by_op: { append_turn: 5, 'GET /v1/contexts/:id/turns': 7 },
     },
   };
 }
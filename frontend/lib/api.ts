@@ -263,6 +263,8 @@ export interface SearchResponse {
   total_count: number;
   elapsed_ms: number;
   query: string;
+  /** True while the server is still warming its indexes after startup. */
+  partial: boolean;
 }
 
 /**
@@ -13,6 +13,8 @@ export interface FsListResponse {
   path: string;
   fs_root_hash: string;
   entries: FsEntry[];
+  /** Name cursor for the next page when `limit` truncated the listing. */
+  next_after?: string | null;
 }
 
 export interface FsFileResponse {
@@ -94,6 +94,8 @@ export interface ContextEntry {
   client_tag?: string;
   is_live?: boolean;
   last_activity_at?: number;
+  // Open /v1/events streams following this context
+  follower_count?: number;
   // Filesystem snapshot indicator
   has_fs_snapshot?: boolean;
   // Context metadata (from first turn)
@@ -122,6 +124,9 @@ export interface SessionInfo {
   connected_at: number;
   last_activity_at: number;
   context_count: number;
+  request_count: number;
+  bytes_in: number;
+  bytes_out: number;
 }
 
 // SSE event types
@@ -173,6 +178,7 @@ export interface ErrorOccurredEvent {
   status_code: number;
   message: string;
   path?: string;
+  correlation_id?: string;
 }
 
 // Union type for all SSE events
@@ -212,6 +218,13 @@ export interface LatencyStats {
   p99: number;
   max: number;
   count: number;
+  /** Age in seconds of the oldest sample behind the percentiles. */
+  window_secs: number | null;
+}
+
+export interface LatencyWindow {
+  max_samples: number;
+  max_age_secs: number | null;
 }
 
 export interface MemoryMetrics {
@@ -245,8 +258,10 @@ export interface ObjectMetrics {
   contexts_total: number;
   turns_total: number;
   blobs_total: number;
+  blobs_compressed_total: number;
   registry_types_total: number;
   registry_bundles_total: number;
+  registry_bundles_skipped: number;
   heads_total: number;
 }
 
@@ -257,6 +272,7 @@ export interface StorageMetrics {
   heads_table_bytes: number;
   blobs_pack_bytes: number;
   blobs_index_bytes: number;
+  compression_ratio: number;
   data_dir_total_bytes: number;
   data_dir_free_bytes: number;
 }
@@ -282,25 +298,47 @@ export interface PerfMetrics {
   get_last_latency_ms: LatencyStats;
   get_blob_latency_ms: LatencyStats;
   http_latency_ms: LatencyStats;
+  /** Cumulative counts keyed `le_<bound>ms`, ending with `le_inf`. */
+  append_latency_buckets: Record<string, number>;
+  get_last_latency_buckets: Record<string, number>;
+  get_blob_latency_buckets: Record<string, number>;
+  http_latency_buckets: Record<string, number>;
+  latency_window: LatencyWindow;
 }
 
 export interface ErrorMetrics {
   total: number;
   by_type: Record<string, number>;
+  by_op: Record<string, number>;
 }
 
 export interface ErrorEntry {
   timestamp_ms: number;
   kind: string;
+  op: string;
   status_code: number;
   message: string;
   path?: string;
+  correlation_id?: string;
 }
 
 export interface FilesystemMetrics {
   snapshots_total: number;
   index_bytes: number;
   content_bytes: number;
+  tree_cache_hits: number;
+  tree_cache_misses: number;
+}
+
+export interface IndexWarmupMetrics {
+  indexed: number;
+  total: number;
+  complete: boolean;
+}
+
+export interface WebhookMetrics {
+  delivered_total: number;
+  dropped_total: number;
 }
 
 export interface MetricsSnapshot {
@@ -311,6 +349,8 @@ export interface MetricsSnapshot {
   objects: ObjectMetrics;
   storage: StorageMetrics;
   filesystem: FilesystemMetrics;
+  index_warmup: IndexWarmupMetrics;
+  webhook: WebhookMetrics;
   perf: PerfMetrics;
   errors: ErrorMetrics;
 }
@@ -1,18 +1,28 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use crc32fast::Hasher;
 
-use crate::error::{Result, StoreError};
+use crate::data_mode;
+use crate::error::{NotFoundKind, Result, StoreError};
 
 const BLOB_MAGIC: u32 = 0x42534C42; // 'B''S''L''B'
 const BLOB_VERSION: u16 = 1;
+/// A record whose stored bytes are sealed with the store's `BlobKey`:
+/// nonce(12) + ChaCha20-Poly1305 ciphertext of the codec'd bytes + tag(16).
+/// The header is the same as `BLOB_VERSION`'s and stays in the clear.
+const BLOB_VERSION_ENCRYPTED: u16 = 2;
+const NONCE_LEN: usize = 12;
+/// magic(4) + version(2) + codec(2) + raw_len(4) + stored_len(4) + hash(32).
+const BLOB_HEADER_LEN: u64 = 4 + 2 + 2 + 4 + 4 + 32;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlobCodec {
@@ -34,22 +44,115 @@ pub struct BlobStore {
     pack_file: File,
     idx_file: File,
     index: HashMap<[u8; 32], BlobIndexEntry>,
+    /// Number of `get` calls that went to the pack file.
+    pack_reads: u64,
+    options: BlobStoreOptions,
+}
+
+/// Blob store tuning.
+#[derive(Debug, Clone, Default)]
+pub struct BlobStoreOptions {
+    /// Blobs shorter than this many bytes are stored raw without trying
+    /// zstd. Small hot-path turns rarely shrink enough to pay for the
+    /// encode; 0 always tries.
+    pub compress_min_len: usize,
+    /// Encrypt blobs written from now on. Blobs already in the pack keep
+    /// the form they were written in and still read; reading an encrypted
+    /// blob needs the key it was written with.
+    pub encryption_key: Option<BlobKey>,
+}
+
+/// A 32-byte ChaCha20-Poly1305 key for blob bodies at rest.
+#[derive(Clone)]
+pub struct BlobKey(Key);
+
+impl BlobKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(Key::from(bytes))
+    }
+
+    /// Parse a key written as 64 hex characters.
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let bytes: [u8; 32] = hex::decode(s.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                StoreError::InvalidInput("encryption key must be 64 hex characters".into())
+            })?;
+        Ok(Self::new(bytes))
+    }
+
+    /// The key in `CXDB_ENCRYPTION_KEY`, if set. A malformed key is an
+    /// error rather than a silent fall back to plaintext.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("CXDB_ENCRYPTION_KEY") {
+            Ok(v) if !v.trim().is_empty() => Self::from_hex(&v).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Seal `stored` bound to its blob's content hash.
+    fn seal(&self, hash: &[u8; 32], stored: &[u8]) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.0)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: stored,
+                    aad: hash,
+                },
+            )
+            .map_err(|_| StoreError::Internal("blob encryption failed".into()))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, hash: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(StoreError::Corrupt("encrypted blob too short".into()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at NONCE_LEN");
+        ChaCha20Poly1305::new(&self.0)
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: hash,
+                },
+            )
+            .map_err(|_| {
+                StoreError::Corrupt("blob decryption failed (wrong CXDB_ENCRYPTION_KEY?)".into())
+            })
+    }
+}
+
+impl std::fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlobKey(..)")
+    }
 }
 
 impl BlobStore {
     pub fn open(dir: &Path) -> Result<Self> {
-        std::fs::create_dir_all(dir)?;
+        Self::open_with_options(dir, BlobStoreOptions::default())
+    }
+
+    pub fn open_with_options(dir: &Path, options: BlobStoreOptions) -> Result<Self> {
+        data_mode::create_dir_all(dir)?;
         let pack_path = dir.join("blobs.pack");
         let idx_path = dir.join("blobs.idx");
 
-        let pack_file = OpenOptions::new()
+        let pack_file = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
             .write(true)
             .open(&pack_path)?;
 
-        let idx_file = OpenOptions::new()
+        let idx_file = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
@@ -62,9 +165,20 @@ impl BlobStore {
             pack_file,
             idx_file,
             index: HashMap::new(),
+            pack_reads: 0,
+            options,
         };
 
         store.load_index()?;
+        // A compaction interrupted between swapping the pack and the index
+        // leaves the marker behind; the index may describe the old pack, so
+        // rebuild it from whichever pack is in place.
+        let marker = compact_marker_path(&store.pack_path);
+        if marker.exists() {
+            tracing::warn!("blob compaction was interrupted; rebuilding blobs.idx from the pack");
+            store.reindex()?;
+            std::fs::remove_file(&marker)?;
+        }
         Ok(store)
     }
 
@@ -143,6 +257,11 @@ impl BlobStore {
         Ok(())
     }
 
+    /// Blobs read from the pack file since open.
+    pub fn pack_reads(&self) -> u64 {
+        self.pack_reads
+    }
+
     pub fn contains(&self, hash: &[u8; 32]) -> bool {
         self.index.contains_key(hash)
     }
@@ -154,13 +273,22 @@ impl BlobStore {
 
         let mut stored_bytes = raw_bytes.to_vec();
         let mut codec = BlobCodec::None;
-        if let Ok(compressed) = zstd::encode_all(raw_bytes, 1) {
-            if compressed.len() < raw_bytes.len() {
-                stored_bytes = compressed;
-                codec = BlobCodec::Zstd;
+        if raw_bytes.len() >= self.options.compress_min_len {
+            if let Ok(compressed) = zstd::encode_all(raw_bytes, 1) {
+                if compressed.len() < raw_bytes.len() {
+                    stored_bytes = compressed;
+                    codec = BlobCodec::Zstd;
+                }
             }
         }
 
+        // The content hash stays over the plaintext, so dedup is unchanged.
+        let mut version = BLOB_VERSION;
+        if let Some(key) = &self.options.encryption_key {
+            stored_bytes = key.seal(&hash, &stored_bytes)?;
+            version = BLOB_VERSION_ENCRYPTED;
+        }
+
         let raw_len = raw_bytes.len() as u32;
         let stored_len = stored_bytes.len() as u32;
 
@@ -168,7 +296,7 @@ impl BlobStore {
 
         let mut header = Vec::with_capacity(4 + 2 + 2 + 4 + 4 + 32);
         header.write_u32::<LittleEndian>(BLOB_MAGIC)?;
-        header.write_u16::<LittleEndian>(BLOB_VERSION)?;
+        header.write_u16::<LittleEndian>(version)?;
         header.write_u16::<LittleEndian>(codec as u16)?;
         header.write_u32::<LittleEndian>(raw_len)?;
         header.write_u32::<LittleEndian>(stored_len)?;
@@ -184,24 +312,19 @@ impl BlobStore {
         self.pack_file.write_u32::<LittleEndian>(crc)?;
         self.pack_file.flush()?;
 
-        // append to index
-        let mut idx_entry = Vec::with_capacity(32 + 8 + 4 + 4 + 2 + 2);
-        idx_entry.extend_from_slice(&hash);
-        idx_entry.write_u64::<LittleEndian>(offset)?;
-        idx_entry.write_u32::<LittleEndian>(raw_len)?;
-        idx_entry.write_u32::<LittleEndian>(stored_len)?;
-        idx_entry.write_u16::<LittleEndian>(codec as u16)?;
-        idx_entry.write_u16::<LittleEndian>(0)?;
-        self.idx_file.seek(SeekFrom::End(0))?;
-        self.idx_file.write_all(&idx_entry)?;
-        self.idx_file.flush()?;
-
         let entry = BlobIndexEntry {
             offset,
             raw_len,
             stored_len,
             codec,
         };
+        // append to index
+        let mut idx_entry = Vec::with_capacity(32 + 8 + 4 + 4 + 2 + 2);
+        write_index_entry(&mut idx_entry, &hash, &entry)?;
+        self.idx_file.seek(SeekFrom::End(0))?;
+        self.idx_file.write_all(&idx_entry)?;
+        self.idx_file.flush()?;
+
         self.index.insert(hash, entry.clone());
         Ok(entry)
     }
@@ -210,73 +333,184 @@ impl BlobStore {
         let entry = self
             .index
             .get(hash)
-            .ok_or_else(|| StoreError::NotFound("blob".into()))?
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Blob, "blob"))?
             .clone();
+        self.pack_reads += 1;
 
         self.pack_file.seek(SeekFrom::Start(entry.offset))?;
-
-        let magic = self.pack_file.read_u32::<LittleEndian>()?;
-        if magic != BLOB_MAGIC {
-            return Err(StoreError::Corrupt("invalid blob magic".into()));
-        }
-        let version = self.pack_file.read_u16::<LittleEndian>()?;
-        if version != BLOB_VERSION {
-            return Err(StoreError::Corrupt("unsupported blob version".into()));
+        let record = read_pack_record(&mut self.pack_file)?;
+        if &record.hash != hash {
+            return Err(StoreError::Corrupt("blob hash mismatch".into()));
         }
-        let codec_raw = self.pack_file.read_u16::<LittleEndian>()?;
-        let raw_len = self.pack_file.read_u32::<LittleEndian>()?;
-        let stored_len = self.pack_file.read_u32::<LittleEndian>()?;
-        let mut stored_hash = [0u8; 32];
-        self.pack_file.read_exact(&mut stored_hash)?;
 
-        if &stored_hash != hash {
-            return Err(StoreError::Corrupt("blob hash mismatch".into()));
+        let stored = if record.encrypted {
+            let key = self.options.encryption_key.as_ref().ok_or_else(|| {
+                StoreError::Internal("blob is encrypted and CXDB_ENCRYPTION_KEY is not set".into())
+            })?;
+            key.open(hash, &record.stored)?
+        } else {
+            record.stored
+        };
+        let raw_bytes = match record.codec {
+            BlobCodec::None => stored,
+            BlobCodec::Zstd => zstd::decode_all(&stored[..])
+                .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?,
+        };
+
+        if raw_bytes.len() as u32 != record.raw_len {
+            return Err(StoreError::Corrupt("blob length mismatch".into()));
         }
 
-        let mut stored_bytes = vec![0u8; stored_len as usize];
-        self.pack_file.read_exact(&mut stored_bytes)?;
-        let crc = self.pack_file.read_u32::<LittleEndian>()?;
+        Ok(raw_bytes)
+    }
 
-        let mut header = Vec::with_capacity(4 + 2 + 2 + 4 + 4 + 32);
-        header.write_u32::<LittleEndian>(magic)?;
-        header.write_u16::<LittleEndian>(version)?;
-        header.write_u16::<LittleEndian>(codec_raw)?;
-        header.write_u32::<LittleEndian>(raw_len)?;
-        header.write_u32::<LittleEndian>(stored_len)?;
-        header.extend_from_slice(&stored_hash);
+    /// Hashes of every indexed blob.
+    pub fn hashes(&self) -> Vec<[u8; 32]> {
+        self.index.keys().copied().collect()
+    }
 
-        let mut hasher = Hasher::new();
-        hasher.update(&header);
-        hasher.update(&stored_bytes);
-        let actual_crc = hasher.finalize();
-        if crc != actual_crc {
-            return Err(StoreError::Corrupt("blob crc mismatch".into()));
+    /// Drop the index entries of blobs not in `keep` and return how many
+    /// went. Their bytes stay in the pack until `compact`.
+    pub fn retain(&mut self, keep: &HashSet<[u8; 32]>) -> Result<usize> {
+        let before = self.index.len();
+        self.index.retain(|hash, _| keep.contains(hash));
+        let removed = before - self.index.len();
+        if removed > 0 {
+            self.write_index()?;
         }
+        Ok(removed)
+    }
 
-        let codec = match codec_raw {
-            0 => BlobCodec::None,
-            1 => BlobCodec::Zstd,
-            _ => return Err(StoreError::Corrupt("unknown blob codec".into())),
-        };
+    /// Rewrite the pack with only the indexed blobs, reclaiming the space of
+    /// records dropped by `retain` or orphaned by a torn index append.
+    /// Returns the pack size before and after. The two files can't be
+    /// swapped in one step, so a marker file spans the swap; `open` finds it
+    /// after a crash and rebuilds the index from the pack.
+    pub fn compact(&mut self) -> Result<(u64, u64)> {
+        let before = file_len(&self.pack_path);
+        let mut entries: Vec<([u8; 32], BlobIndexEntry)> = self
+            .index
+            .iter()
+            .map(|(hash, entry)| (*hash, entry.clone()))
+            .collect();
+        entries.sort_by_key(|(_, entry)| entry.offset);
+
+        let mut tmp_name = self.pack_path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        let mut tmp = data_mode::open_options()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        let mut offset = 0u64;
+        let mut record = Vec::new();
+        for (_, entry) in entries.iter_mut() {
+            let len = BLOB_HEADER_LEN + entry.stored_len as u64 + 4;
+            record.resize(len as usize, 0);
+            self.pack_file.seek(SeekFrom::Start(entry.offset))?;
+            self.pack_file.read_exact(&mut record)?;
+            tmp.write_all(&record)?;
+            entry.offset = offset;
+            offset += len;
+        }
+        tmp.sync_all()?;
+        drop(tmp);
 
-        let raw_bytes = match codec {
-            BlobCodec::None => stored_bytes,
-            BlobCodec::Zstd => zstd::decode_all(&stored_bytes[..])
-                .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?,
-        };
+        let marker = compact_marker_path(&self.pack_path);
+        data_mode::write_file_atomic(&marker, &[])?;
+        std::fs::rename(&tmp_path, &self.pack_path)?;
+        self.pack_file = data_mode::open_options()
+            .read(true)
+            .write(true)
+            .open(&self.pack_path)?;
+        self.index = entries.into_iter().collect();
+        self.write_index()?;
+        std::fs::remove_file(&marker)?;
+        Ok((before, offset))
+    }
 
-        if raw_bytes.len() as u32 != raw_len {
-            return Err(StoreError::Corrupt("blob length mismatch".into()));
+    /// Rebuild blobs.idx by scanning the pack. Unreadable stretches (a torn
+    /// tail, a corrupt record) are skipped a byte at a time until a valid
+    /// record starts, so one bad record doesn't hide the ones after it.
+    /// Returns the blobs indexed and the bytes skipped.
+    pub fn reindex(&mut self) -> Result<(usize, u64)> {
+        let len = file_len(&self.pack_path);
+        let mut index = HashMap::new();
+        let mut offset = 0u64;
+        let mut skipped = 0u64;
+        self.pack_file.seek(SeekFrom::Start(0))?;
+        let mut reader = std::io::BufReader::new(&self.pack_file);
+        while offset < len {
+            let record = match read_pack_record(&mut reader) {
+                Ok(record) => record,
+                Err(StoreError::Corrupt(_)) => {
+                    offset += 1;
+                    skipped += 1;
+                    reader.seek(SeekFrom::Start(offset))?;
+                    continue;
+                }
+                Err(StoreError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    offset += 1;
+                    skipped += 1;
+                    reader.seek(SeekFrom::Start(offset))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let stored_len = record.stored.len() as u32;
+            index.insert(
+                record.hash,
+                BlobIndexEntry {
+                    offset,
+                    raw_len: record.raw_len,
+                    stored_len,
+                    codec: record.codec,
+                },
+            );
+            offset += BLOB_HEADER_LEN + stored_len as u64 + 4;
         }
+        drop(reader);
+        self.index = index;
+        self.write_index()?;
+        Ok((self.index.len(), skipped))
+    }
 
-        Ok(raw_bytes)
+    /// Replace blobs.idx with the in-memory index.
+    fn write_index(&mut self) -> Result<()> {
+        let mut entries: Vec<(&[u8; 32], &BlobIndexEntry)> = self.index.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.offset);
+        let mut buf = Vec::with_capacity(entries.len() * (32 + 8 + 4 + 4 + 2 + 2));
+        for (hash, entry) in entries {
+            write_index_entry(&mut buf, hash, entry)?;
+        }
+        data_mode::write_file_atomic(&self.idx_path, &buf)?;
+        self.idx_file = data_mode::open_options()
+            .read(true)
+            .write(true)
+            .open(&self.idx_path)?;
+        Ok(())
     }
 
     pub fn stats(&self) -> BlobStoreStats {
+        let (mut raw_total, mut stored_total, mut compressed) = (0u64, 0u64, 0usize);
+        for entry in self.index.values() {
+            raw_total += entry.raw_len as u64;
+            stored_total += entry.stored_len as u64;
+            if entry.codec != BlobCodec::None {
+                compressed += 1;
+            }
+        }
         BlobStoreStats {
             blobs_total: self.index.len(),
+            blobs_compressed_total: compressed,
             pack_bytes: file_len(&self.pack_path),
             idx_bytes: file_len(&self.idx_path),
+            compression_ratio: if stored_total == 0 {
+                1.0
+            } else {
+                raw_total as f64 / stored_total as f64
+            },
         }
     }
 
@@ -285,6 +519,11 @@ impl BlobStore {
         self.index.get(hash).map(|e| e.raw_len)
     }
 
+    /// The codec a blob is stored with in the pack.
+    pub fn codec(&self, hash: &[u8; 32]) -> Option<BlobCodec> {
+        self.index.get(hash).map(|e| e.codec)
+    }
+
     /// Get the stored (compressed) length of a blob without loading its content.
     pub fn stored_len(&self, hash: &[u8; 32]) -> Option<u32> {
         self.index.get(hash).map(|e| e.stored_len)
@@ -294,8 +533,91 @@ impl BlobStore {
 #[derive(Debug, Clone)]
 pub struct BlobStoreStats {
     pub blobs_total: usize,
+    /// Blobs stored with a codec other than `None`.
+    pub blobs_compressed_total: usize,
     pub pack_bytes: u64,
     pub idx_bytes: u64,
+    /// Sum of raw lengths over sum of stored lengths; 1.0 when empty.
+    pub compression_ratio: f64,
+}
+
+/// A CRC-checked pack record, still in its stored encoding.
+struct PackRecord {
+    hash: [u8; 32],
+    codec: BlobCodec,
+    raw_len: u32,
+    /// Written with `BLOB_VERSION_ENCRYPTED`.
+    encrypted: bool,
+    stored: Vec<u8>,
+}
+
+fn read_pack_record(reader: &mut impl Read) -> Result<PackRecord> {
+    let magic = reader.read_u32::<LittleEndian>()?;
+    if magic != BLOB_MAGIC {
+        return Err(StoreError::Corrupt("invalid blob magic".into()));
+    }
+    let version = reader.read_u16::<LittleEndian>()?;
+    if version != BLOB_VERSION && version != BLOB_VERSION_ENCRYPTED {
+        return Err(StoreError::Corrupt("unsupported blob version".into()));
+    }
+    let codec_raw = reader.read_u16::<LittleEndian>()?;
+    let raw_len = reader.read_u32::<LittleEndian>()?;
+    let stored_len = reader.read_u32::<LittleEndian>()?;
+    let mut hash = [0u8; 32];
+    reader.read_exact(&mut hash)?;
+
+    // `take` rather than a pre-sized buffer: a corrupt length must not
+    // allocate gigabytes before the short read is noticed.
+    let mut stored = Vec::new();
+    reader.take(stored_len as u64).read_to_end(&mut stored)?;
+    if stored.len() != stored_len as usize {
+        return Err(StoreError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    let crc = reader.read_u32::<LittleEndian>()?;
+
+    let mut header = Vec::with_capacity(BLOB_HEADER_LEN as usize);
+    header.write_u32::<LittleEndian>(magic)?;
+    header.write_u16::<LittleEndian>(version)?;
+    header.write_u16::<LittleEndian>(codec_raw)?;
+    header.write_u32::<LittleEndian>(raw_len)?;
+    header.write_u32::<LittleEndian>(stored_len)?;
+    header.extend_from_slice(&hash);
+
+    let mut hasher = Hasher::new();
+    hasher.update(&header);
+    hasher.update(&stored);
+    if crc != hasher.finalize() {
+        return Err(StoreError::Corrupt("blob crc mismatch".into()));
+    }
+
+    let codec = match codec_raw {
+        0 => BlobCodec::None,
+        1 => BlobCodec::Zstd,
+        _ => return Err(StoreError::Corrupt("unknown blob codec".into())),
+    };
+    Ok(PackRecord {
+        hash,
+        codec,
+        raw_len,
+        encrypted: version == BLOB_VERSION_ENCRYPTED,
+        stored,
+    })
+}
+
+/// Append one 52-byte blobs.idx entry to `buf`.
+/// Present while `compact` swaps the pack and index.
+fn compact_marker_path(pack_path: &Path) -> PathBuf {
+    pack_path.with_file_name("blobs.compacting")
+}
+
+fn write_index_entry(buf: &mut Vec<u8>, hash: &[u8; 32], entry: &BlobIndexEntry) -> Result<()> {
+    buf.extend_from_slice(hash);
+    buf.write_u64::<LittleEndian>(entry.offset)?;
+    buf.write_u32::<LittleEndian>(entry.raw_len)?;
+    buf.write_u32::<LittleEndian>(entry.stored_len)?;
+    buf.write_u16::<LittleEndian>(entry.codec as u16)?;
+    buf.write_u16::<LittleEndian>(0)?;
+    Ok(())
 }
 
 fn file_len(path: &PathBuf) -> u64 {
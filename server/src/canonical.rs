@@ -0,0 +1,54 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Canonical msgpack encoding for content-addressed dedup.
+//!
+//! Two encoders can write the same logical value with different map-key
+//! order or integer widths, giving different bytes and different content
+//! hashes. [`canonicalize_msgpack`] re-encodes a value with map entries
+//! sorted by their encoded key bytes and rmpv's minimal-width integers, so
+//! logically identical payloads hash the same.
+
+use rmpv::Value;
+
+use crate::error::{Result, StoreError};
+
+/// Re-encode a single msgpack value in canonical form. Trailing bytes or
+/// undecodable input are rejected.
+pub fn canonicalize_msgpack(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut cursor = std::io::Cursor::new(bytes);
+    let value = rmpv::decode::read_value(&mut cursor)
+        .map_err(|e| StoreError::InvalidInput(format!("payload is not valid msgpack: {e}")))?;
+    if cursor.position() as usize != bytes.len() {
+        return Err(StoreError::InvalidInput(
+            "payload has trailing bytes after msgpack value".into(),
+        ));
+    }
+    encode(&sort_maps(value))
+}
+
+fn sort_maps(value: Value) -> Value {
+    match value {
+        Value::Map(entries) => {
+            let mut keyed: Vec<(Vec<u8>, Value, Value)> = entries
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = sort_maps(k);
+                    let key_bytes = encode(&k).unwrap_or_default();
+                    (key_bytes, k, sort_maps(v))
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Map(keyed.into_iter().map(|(_, k, v)| (k, v)).collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(sort_maps).collect()),
+        other => other,
+    }
+}
+
+fn encode(value: &Value) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, value)
+        .map_err(|e| StoreError::InvalidInput(format!("msgpack encode failed: {e}")))?;
+    Ok(out)
+}
@@ -0,0 +1,290 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Offline maintenance and bulk-load subcommands for the server binary.
+//!
+//! `cxdb-server <command> [--data-dir DIR]` opens the store, runs one
+//! operation, prints a summary and exits. Run these with the server stopped:
+//! they take the data directory for themselves and don't coordinate with a
+//! live process. `dump` only reads, so it is safe against a live store.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::import::import_jsonl;
+use crate::registry::{Registry, RegistryOptions};
+use crate::store::{Store, StoreOptions};
+use crate::turn_store::{dump_records, ContextHead, RecordDump, TurnRecord, UnreadableTail};
+
+pub const USAGE: &str = "\
+usage: cxdb-server [COMMAND] [--data-dir DIR] [--context N] [--file PATH]
+
+With no command, serves the binary and HTTP protocols.
+
+commands:
+  verify    check turn links and that every referenced blob matches its hash
+  gc        drop blobs no live turn or fs snapshot references
+  compact   rewrite the blob pack without dropped blobs
+  reindex   rebuild turns.idx and blobs.idx from their logs
+  dump      print head and turn records as stored, without repairing anything;
+            --context N limits it to one context's heads and head chain
+  import    append the turns in a JSONL file (--file PATH), one new context
+            per distinct \"context\" value
+
+--data-dir defaults to CXDB_DATA_DIR (./data).";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Verify,
+    Compact,
+    Gc,
+    Reindex,
+    Dump { context: Option<u64> },
+    Import { file: PathBuf },
+}
+
+impl Command {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "verify" => Some(Self::Verify),
+            "compact" => Some(Self::Compact),
+            "gc" => Some(Self::Gc),
+            "reindex" => Some(Self::Reindex),
+            "dump" => Some(Self::Dump { context: None }),
+            "import" => Some(Self::Import {
+                file: PathBuf::new(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub command: Command,
+    pub data_dir: PathBuf,
+}
+
+/// Parse the arguments after the program name. `Ok(None)` means no command
+/// was given and the server should start as usual.
+pub fn parse_args(args: &[String]) -> std::result::Result<Option<Invocation>, String> {
+    let Some((first, rest)) = args.split_first() else {
+        return Ok(None);
+    };
+    let mut command = Command::parse(first).ok_or_else(|| format!("unknown command: {first}"))?;
+
+    let mut data_dir = None;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| rest.next().cloned())
+                .ok_or_else(|| format!("{flag} needs a value"))
+        };
+        match (flag, &mut command) {
+            ("--data-dir", _) => data_dir = Some(PathBuf::from(value()?)),
+            ("--context", Command::Dump { context }) => {
+                let value = value()?;
+                let id = value
+                    .parse()
+                    .map_err(|_| format!("invalid context id: {value}"))?;
+                *context = Some(id);
+            }
+            ("--file", Command::Import { file }) => *file = PathBuf::from(value()?),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+    let data_dir = data_dir.unwrap_or_else(|| Config::from_env().data_dir);
+    match &command {
+        // Importing may seed a fresh data dir.
+        Command::Import { file } if file.as_os_str().is_empty() => {
+            return Err("import needs --file".to_string());
+        }
+        Command::Import { .. } => {}
+        _ if !data_dir.is_dir() => {
+            return Err(format!("data dir {} does not exist", data_dir.display()));
+        }
+        _ => {}
+    }
+    Ok(Some(Invocation { command, data_dir }))
+}
+
+/// Run `invocation` against its data dir, writing a summary to `out`.
+/// Returns whether the command fully succeeded; `verify`, `dump` and
+/// `import` can fail without an error.
+pub fn run(invocation: &Invocation, out: &mut impl Write) -> Result<bool> {
+    if let Command::Dump { context } = invocation.command {
+        // Opening the store would truncate the corrupt tails dump reports.
+        let dump = dump_records(&invocation.data_dir.join("turns"))?;
+        write_dump(&dump, context, out)?;
+        return Ok(dump.heads_tail.is_none() && dump.turns_tail.is_none());
+    }
+    let mut store = Store::open_with_options(&invocation.data_dir, StoreOptions::from_env()?)?;
+    match &invocation.command {
+        Command::Import { file } => {
+            let input = BufReader::new(std::fs::File::open(file)?);
+            let registry = Registry::open_with_options(
+                &invocation.data_dir.join("registry"),
+                RegistryOptions::from_env(),
+            )?;
+            let report = import_jsonl(&mut store, &registry, input)?;
+            for (line, reason) in &report.rejected {
+                writeln!(out, "rejected line {line}: {reason}")?;
+            }
+            writeln!(
+                out,
+                "import: {} contexts created, {} turns appended, {} lines rejected",
+                report.contexts_created,
+                report.turns_appended,
+                report.rejected.len()
+            )?;
+            Ok(report.rejected.is_empty())
+        }
+        Command::Verify => {
+            let report = store.verify()?;
+            for problem in &report.problems {
+                writeln!(out, "problem: {problem}")?;
+            }
+            writeln!(
+                out,
+                "verify: {} turns, {} blobs checked, {} unreferenced blobs, {} problems",
+                report.turns_checked,
+                report.blobs_checked,
+                report.blobs_unreferenced,
+                report.problems.len()
+            )?;
+            Ok(report.is_ok())
+        }
+        Command::Gc => {
+            let report = store.gc()?;
+            writeln!(
+                out,
+                "gc: kept {} blobs, removed {}",
+                report.blobs_kept, report.blobs_removed
+            )?;
+            Ok(true)
+        }
+        Command::Compact => {
+            let report = store.compact()?;
+            writeln!(
+                out,
+                "compact: blob pack {} -> {} bytes",
+                report.pack_bytes_before, report.pack_bytes_after
+            )?;
+            Ok(true)
+        }
+        Command::Reindex => {
+            let report = store.reindex()?;
+            writeln!(
+                out,
+                "reindex: {} turns, {} blobs indexed, {} unreadable pack bytes skipped",
+                report.turns_indexed, report.blobs_indexed, report.pack_bytes_skipped
+            )?;
+            Ok(true)
+        }
+        Command::Dump { .. } => unreachable!("handled above"),
+    }
+}
+
+fn write_dump(dump: &RecordDump, context: Option<u64>, out: &mut impl Write) -> Result<()> {
+    let heads: Vec<&(u64, ContextHead)> = dump
+        .heads
+        .iter()
+        .filter(|(_, head)| context.is_none_or(|id| head.context_id == id))
+        .collect();
+    let turns: Vec<&(u64, TurnRecord)> = match context {
+        None => dump.turns.iter().collect(),
+        Some(_) => head_chain(dump, heads.last().map(|(_, head)| head.head_turn_id)),
+    };
+
+    writeln!(out, "heads.tbl: {} records", heads.len())?;
+    writeln!(
+        out,
+        "{:>10}  {:>10}  {:>10}  {:>6}  {:>6}  {:>5}  created_at",
+        "offset", "context", "head_turn", "depth", "turns", "flags"
+    )?;
+    for (offset, head) in heads {
+        writeln!(
+            out,
+            "{:>10}  {:>10}  {:>10}  {:>6}  {:>6}  {:>5}  {}",
+            offset,
+            head.context_id,
+            head.head_turn_id,
+            head.head_depth,
+            head.turn_count,
+            head.flags,
+            format_unix_ms(head.created_at_unix_ms)
+        )?;
+    }
+    write_tail(out, "heads.tbl", dump.heads_tail.as_ref())?;
+
+    writeln!(out)?;
+    writeln!(out, "turns.log: {} records", turns.len())?;
+    writeln!(
+        out,
+        "{:>10}  {:>10}  {:>10}  {:>6}  {:>5}  {:<64}  created_at",
+        "offset", "turn", "parent", "depth", "codec", "payload_hash"
+    )?;
+    for (offset, turn) in turns {
+        writeln!(
+            out,
+            "{:>10}  {:>10}  {:>10}  {:>6}  {:>5}  {}  {}",
+            offset,
+            turn.turn_id,
+            turn.parent_turn_id,
+            turn.depth,
+            turn.codec,
+            hex::encode(turn.payload_hash),
+            format_unix_ms(turn.created_at_unix_ms)
+        )?;
+    }
+    write_tail(out, "turns.log", dump.turns_tail.as_ref())?;
+    Ok(())
+}
+
+/// The turns from `head_turn_id` back to the root, in file order.
+fn head_chain(dump: &RecordDump, head_turn_id: Option<u64>) -> Vec<&(u64, TurnRecord)> {
+    let by_id: HashMap<u64, &(u64, TurnRecord)> = dump
+        .turns
+        .iter()
+        .map(|entry| (entry.1.turn_id, entry))
+        .collect();
+    let mut chain = Vec::new();
+    let mut next = head_turn_id.unwrap_or(0);
+    while let Some(entry) = by_id.get(&next) {
+        chain.push(*entry);
+        next = entry.1.parent_turn_id;
+        if chain.len() > by_id.len() {
+            break;
+        }
+    }
+    chain.sort_by_key(|(offset, _)| *offset);
+    chain
+}
+
+fn write_tail(out: &mut impl Write, file: &str, tail: Option<&UnreadableTail>) -> Result<()> {
+    if let Some(tail) = tail {
+        writeln!(
+            out,
+            "{file}: unreadable from offset {} ({} bytes): {}",
+            tail.offset, tail.len, tail.reason
+        )?;
+    }
+    Ok(())
+}
+
+fn format_unix_ms(ms: u64) -> String {
+    i64::try_from(ms)
+        .ok()
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        .unwrap_or_else(|| ms.to_string())
+}
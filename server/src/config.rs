@@ -3,24 +3,100 @@
 
 use std::env;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::projection::{BytesRender, EnumRender, RenderOptions, TimeRender, U64Format};
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub data_dir: PathBuf,
-    pub bind_addr: String,
+    /// Binary protocol listen addresses; one listener per entry.
+    pub bind_addrs: Vec<String>,
     pub http_bind_addr: String,
+    /// When set, metrics/errors/admin routes move to this address and the
+    /// main HTTP listener serves only the data API.
+    pub admin_bind_addr: Option<String>,
+    /// Longest the binary accept loop waits for a connection before
+    /// rechecking the shutdown flag. New connections wake it immediately.
+    pub accept_poll_interval: Duration,
+    /// Listen backlog for the binary protocol socket.
+    pub listen_backlog: i32,
+    /// Also serve the binary protocol on this Unix domain socket (Unix only).
+    pub unix_socket: Option<PathBuf>,
+    /// Render options for typed turn reads that don't set them in the query.
+    pub render_defaults: RenderOptions,
 }
 
 impl Config {
     pub fn from_env() -> Self {
         let data_dir = env::var("CXDB_DATA_DIR").unwrap_or_else(|_| "./data".to_string());
-        let bind_addr = env::var("CXDB_BIND").unwrap_or_else(|_| "127.0.0.1:9009".to_string());
+        let bind_addrs = env::var("CXDB_BIND")
+            .or_else(|_| env::var("CXDB_BIND_ADDR"))
+            .map(|v| parse_addr_list(&v))
+            .ok()
+            .filter(|addrs| !addrs.is_empty())
+            .unwrap_or_else(|| vec!["127.0.0.1:9009".to_string()]);
         let http_bind_addr =
             env::var("CXDB_HTTP_BIND").unwrap_or_else(|_| "127.0.0.1:9010".to_string());
+        let admin_bind_addr = env::var("CXDB_ADMIN_BIND_ADDR")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let accept_poll_ms = env::var("CXDB_ACCEPT_POLL_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(100)
+            .clamp(1, 10_000);
+        let listen_backlog = env::var("CXDB_LISTEN_BACKLOG")
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok())
+            .unwrap_or(1024)
+            .max(1);
         Self {
             data_dir: PathBuf::from(data_dir),
-            bind_addr,
+            bind_addrs,
             http_bind_addr,
+            admin_bind_addr,
+            accept_poll_interval: Duration::from_millis(accept_poll_ms),
+            listen_backlog,
+            unix_socket: env::var("CXDB_UNIX_SOCKET")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(PathBuf::from),
+            render_defaults: render_defaults_from_env(),
         }
     }
 }
+
+/// `CXDB_DEFAULT_BYTES_RENDER`, `CXDB_DEFAULT_U64_FORMAT`,
+/// `CXDB_DEFAULT_ENUM_RENDER` and `CXDB_DEFAULT_TIME_RENDER` take the same
+/// values as the matching query params. Unset or unrecognized values keep
+/// the built-in default.
+fn render_defaults_from_env() -> RenderOptions {
+    let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_lowercase());
+    let defaults = RenderOptions::default();
+    RenderOptions {
+        bytes_render: var("CXDB_DEFAULT_BYTES_RENDER")
+            .and_then(|v| BytesRender::parse(&v))
+            .unwrap_or(defaults.bytes_render),
+        u64_format: var("CXDB_DEFAULT_U64_FORMAT")
+            .and_then(|v| U64Format::parse(&v))
+            .unwrap_or(defaults.u64_format),
+        enum_render: var("CXDB_DEFAULT_ENUM_RENDER")
+            .and_then(|v| EnumRender::parse(&v))
+            .unwrap_or(defaults.enum_render),
+        time_render: var("CXDB_DEFAULT_TIME_RENDER")
+            .and_then(|v| TimeRender::parse(&v))
+            .unwrap_or(defaults.time_render),
+        ..defaults
+    }
+}
+
+/// Split a comma-separated address list, e.g. `127.0.0.1:9009,[::1]:9009`.
+fn parse_addr_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
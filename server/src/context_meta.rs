@@ -0,0 +1,245 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Metadata overlays for contexts.
+//!
+//! Context metadata normally comes from the first turn's payload (key 30). An
+//! overlay is metadata supplied out-of-band, e.g. at create time, so a context
+//! can be titled and labelled before any turn exists. Overlay fields take
+//! precedence over the first-turn values.
+//!
+//! # Storage Format
+//!
+//! The overlay log (`meta/overlays.log`) is an append-only file of records:
+//! - context_id: u64
+//! - json_len: u32
+//! - json: [u8; json_len]   // MetadataOverlay as JSON
+//! - crc32: u32             // over context_id..json
+//!
+//! Last-write-wins semantics per context_id (like heads.tbl). A torn or
+//! corrupt tail is truncated on open.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher;
+use serde::{Deserialize, Serialize};
+
+use crate::data_mode;
+use crate::error::{Result, StoreError};
+use crate::store::ContextMetadata;
+
+/// Largest overlay record accepted, to keep a corrupt length from
+/// triggering a huge allocation on load.
+const MAX_OVERLAY_JSON_BYTES: usize = 64 * 1024;
+
+/// Caller-supplied context metadata. Unset fields leave the first-turn value
+/// in place.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataOverlay {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    /// Registry bundle to project this context's turns with, so later
+    /// schema changes don't alter how its history renders.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_id: Option<String>,
+}
+
+impl MetadataOverlay {
+    pub fn is_empty(&self) -> bool {
+        !self.has_metadata_fields() && self.bundle_id.is_none()
+    }
+
+    fn has_metadata_fields(&self) -> bool {
+        self.client_tag.is_some() || self.title.is_some() || self.labels.is_some()
+    }
+
+    /// Parse an overlay from a JSON object with optional `client_tag`,
+    /// `title`, `labels`, and `bundle_id` fields.
+    pub fn from_json(value: &serde_json::Value) -> Result<Self> {
+        serde_json::from_value(value.clone())
+            .map_err(|e| StoreError::InvalidInput(format!("invalid metadata: {e}")))
+    }
+
+    /// Merge onto first-turn metadata; overlay fields win.
+    pub fn apply(&self, base: Option<ContextMetadata>) -> Option<ContextMetadata> {
+        if !self.has_metadata_fields() {
+            return base;
+        }
+        let mut merged = base.unwrap_or_default();
+        if let Some(tag) = &self.client_tag {
+            merged.client_tag = Some(tag.clone());
+        }
+        if let Some(title) = &self.title {
+            merged.title = Some(title.clone());
+        }
+        if let Some(labels) = &self.labels {
+            merged.labels = Some(labels.clone());
+        }
+        Some(merged)
+    }
+}
+
+/// How client tags are canonicalized before they are stored, indexed or
+/// compared, so `Kilroy` and ` kilroy` can name the same client. The default
+/// leaves tags exactly as given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagNormalization {
+    pub lowercase: bool,
+    pub trim: bool,
+}
+
+impl TagNormalization {
+    /// From `CXDB_TAG_NORMALIZE`, a comma-separated list of `lowercase` and
+    /// `trim`. A list with unknown entries falls back to the default;
+    /// [`TagNormalization::check_env`] rejects it at startup.
+    pub fn from_env() -> Self {
+        std::env::var("CXDB_TAG_NORMALIZE")
+            .ok()
+            .and_then(|v| Self::parse(&v).ok())
+            .unwrap_or_default()
+    }
+
+    /// Reject unknown `CXDB_TAG_NORMALIZE` entries at startup, so a typo
+    /// doesn't silently leave tags unnormalized.
+    pub fn check_env() -> Result<()> {
+        match std::env::var("CXDB_TAG_NORMALIZE") {
+            Ok(v) => Self::parse(&v).map(|_| ()),
+            Err(_) => Ok(()),
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut normalization = Self::default();
+        for step in spec.split(',').map(str::trim) {
+            match step {
+                "lowercase" => normalization.lowercase = true,
+                "trim" => normalization.trim = true,
+                "" => {}
+                other => {
+                    return Err(StoreError::InvalidInput(format!(
+                    "CXDB_TAG_NORMALIZE entries must be \"lowercase\" or \"trim\", got {other:?}"
+                )))
+                }
+            }
+        }
+        Ok(normalization)
+    }
+
+    pub fn apply(&self, tag: &str) -> String {
+        let tag = if self.trim { tag.trim() } else { tag };
+        if self.lowercase {
+            tag.to_lowercase()
+        } else {
+            tag.to_string()
+        }
+    }
+}
+
+pub struct MetadataOverlayLog {
+    file: File,
+    overlays: HashMap<u64, MetadataOverlay>,
+}
+
+impl MetadataOverlayLog {
+    /// Open or create the overlay log under `dir`.
+    pub fn open(dir: &Path) -> Result<Self> {
+        data_mode::create_dir_all(dir)?;
+        let file = data_mode::open_options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("overlays.log"))?;
+
+        let mut log = Self {
+            file,
+            overlays: HashMap::new(),
+        };
+        log.load()?;
+        Ok(log)
+    }
+
+    fn load(&mut self) -> Result<()> {
+        self.overlays.clear();
+        self.file.seek(SeekFrom::Start(0))?;
+
+        loop {
+            let start = self.file.stream_position()?;
+            match self.read_record() {
+                Ok(Some((context_id, overlay))) => {
+                    self.overlays.insert(context_id, overlay);
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    self.file.set_len(start)?;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_record(&mut self) -> Result<Option<(u64, MetadataOverlay)>> {
+        let context_id = match self.file.read_u64::<LittleEndian>() {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(StoreError::Io(e)),
+        };
+        let len = self.file.read_u32::<LittleEndian>()? as usize;
+        if len > MAX_OVERLAY_JSON_BYTES {
+            return Err(StoreError::Corrupt("overlay record too large".into()));
+        }
+        let mut json = vec![0u8; len];
+        self.file.read_exact(&mut json)?;
+        let crc = self.file.read_u32::<LittleEndian>()?;
+        if crc != compute_crc(context_id, &json) {
+            return Err(StoreError::Corrupt("overlay crc mismatch".into()));
+        }
+        let overlay = serde_json::from_slice(&json)
+            .map_err(|e| StoreError::Corrupt(format!("overlay json: {e}")))?;
+        Ok(Some((context_id, overlay)))
+    }
+
+    /// Record the overlay for a context, replacing any previous one.
+    pub fn put(&mut self, context_id: u64, overlay: MetadataOverlay) -> Result<()> {
+        let json = serde_json::to_vec(&overlay)
+            .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+        if json.len() > MAX_OVERLAY_JSON_BYTES {
+            return Err(StoreError::InvalidInput("metadata too large".into()));
+        }
+
+        let mut buf = Vec::with_capacity(16 + json.len());
+        buf.write_u64::<LittleEndian>(context_id)?;
+        buf.write_u32::<LittleEndian>(json.len() as u32)?;
+        buf.extend_from_slice(&json);
+        buf.write_u32::<LittleEndian>(compute_crc(context_id, &json))?;
+
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+
+        self.overlays.insert(context_id, overlay);
+        Ok(())
+    }
+
+    pub fn get(&self, context_id: u64) -> Option<&MetadataOverlay> {
+        self.overlays.get(&context_id)
+    }
+}
+
+fn compute_crc(context_id: u64, json: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(&context_id.to_le_bytes());
+    hasher.update(&(json.len() as u32).to_le_bytes());
+    hasher.update(json);
+    hasher.finalize()
+}
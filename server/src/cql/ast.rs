@@ -106,6 +106,7 @@ pub enum FieldName {
     Root,
     Created,
     Depth,
+    TurnCount,
     IsLive,
 }
 
@@ -125,6 +126,7 @@ impl FieldName {
             "root" => Some(Self::Root),
             "created" => Some(Self::Created),
             "depth" => Some(Self::Depth),
+            "turn_count" => Some(Self::TurnCount),
             "is_live" => Some(Self::IsLive),
             _ => None,
         }
@@ -144,6 +146,7 @@ impl FieldName {
             Self::Root => "root",
             Self::Created => "created",
             Self::Depth => "depth",
+            Self::TurnCount => "turn_count",
             Self::IsLive => "is_live",
         }
     }
@@ -162,6 +165,7 @@ impl FieldName {
             Self::Root,
             Self::Created,
             Self::Depth,
+            Self::TurnCount,
             Self::IsLive,
         ]
     }
@@ -184,6 +188,8 @@ pub enum CqlErrorType {
     UnknownField,
     InvalidOperator,
     InvalidValue,
+    /// The query is too large or too expensive; see `CqlLimits`.
+    LimitExceeded,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -3,41 +3,265 @@
 
 //! CQL Query Executor - Evaluates CQL AST against secondary indexes.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 use super::ast::{CqlError, CqlErrorType, Expression, FieldName, Operator, Value};
 use super::indexes::SecondaryIndexes;
 
-/// Execute a CQL expression against the secondary indexes.
+/// Bounds on what one query may cost. A query past any of them fails with
+/// `CqlErrorType::LimitExceeded` instead of running to completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CqlLimits {
+    /// Comparisons in the query, counting each `IN` list item as one.
+    /// Checked before anything runs; 0 means no limit.
+    pub max_clauses: usize,
+    /// Largest set of matching contexts any part of the query may produce;
+    /// 0 means no limit.
+    pub max_candidates: usize,
+    /// Wall time the executor may spend before giving up.
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for CqlLimits {
+    fn default() -> Self {
+        Self {
+            max_clauses: 64,
+            max_candidates: 0,
+            time_budget: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+impl CqlLimits {
+    /// No limits at all.
+    pub fn unlimited() -> Self {
+        Self {
+            max_clauses: 0,
+            max_candidates: 0,
+            time_budget: None,
+        }
+    }
+
+    /// Reads `CXDB_CQL_MAX_CLAUSES`, `CXDB_CQL_MAX_CANDIDATES` and
+    /// `CXDB_CQL_TIME_BUDGET_MS` (0 disables the budget).
+    pub fn from_env() -> Self {
+        let env_usize = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+        };
+        let defaults = Self::default();
+        Self {
+            max_clauses: env_usize("CXDB_CQL_MAX_CLAUSES").unwrap_or(defaults.max_clauses),
+            max_candidates: env_usize("CXDB_CQL_MAX_CANDIDATES").unwrap_or(defaults.max_candidates),
+            time_budget: match env_usize("CXDB_CQL_TIME_BUDGET_MS") {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms as u64)),
+                None => defaults.time_budget,
+            },
+        }
+    }
+}
+
+/// Execute a CQL expression against the secondary indexes with the default
+/// [`CqlLimits`].
 pub fn execute(
     expr: &Expression,
     indexes: &SecondaryIndexes,
     live_contexts: &HashSet<u64>,
 ) -> Result<HashSet<u64>, CqlError> {
+    execute_with_limits(expr, indexes, live_contexts, &CqlLimits::default())
+}
+
+/// Execute a CQL expression, failing with `LimitExceeded` once it goes past
+/// `limits`.
+pub fn execute_with_limits(
+    expr: &Expression,
+    indexes: &SecondaryIndexes,
+    live_contexts: &HashSet<u64>,
+    limits: &CqlLimits,
+) -> Result<HashSet<u64>, CqlError> {
+    let clauses = count_clauses(expr);
+    if limits.max_clauses > 0 && clauses > limits.max_clauses {
+        return Err(limit_exceeded(format!(
+            "Query has {clauses} clauses; the limit is {}",
+            limits.max_clauses
+        )));
+    }
+    let budget = Budget {
+        limits,
+        deadline: limits.time_budget.map(|budget| Instant::now() + budget),
+    };
+    evaluate(expr, indexes, live_contexts, &budget)
+}
+
+struct Budget<'a> {
+    limits: &'a CqlLimits,
+    deadline: Option<Instant>,
+}
+
+impl Budget<'_> {
+    fn check_time(&self) -> Result<(), CqlError> {
+        match (self.deadline, self.limits.time_budget) {
+            (Some(deadline), Some(budget)) if Instant::now() > deadline => Err(limit_exceeded(
+                format!("Query ran past its {} ms time budget", budget.as_millis()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_candidates(&self, candidates: &HashSet<u64>) -> Result<(), CqlError> {
+        let max = self.limits.max_candidates;
+        if max > 0 && candidates.len() > max {
+            return Err(limit_exceeded(format!(
+                "Query matched more than {max} candidate contexts; narrow it down"
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn limit_exceeded(message: String) -> CqlError {
+    CqlError {
+        error_type: CqlErrorType::LimitExceeded,
+        message,
+        position: None,
+        field: None,
+    }
+}
+
+fn count_clauses(expr: &Expression) -> usize {
     match expr {
+        Expression::And { left, right } | Expression::Or { left, right } => {
+            count_clauses(left) + count_clauses(right)
+        }
+        Expression::Not { inner } => count_clauses(inner),
+        Expression::Comparison { value, .. } => value.as_list().map_or(1, |items| items.len()),
+    }
+}
+
+fn evaluate(
+    expr: &Expression,
+    indexes: &SecondaryIndexes,
+    live_contexts: &HashSet<u64>,
+    budget: &Budget,
+) -> Result<HashSet<u64>, CqlError> {
+    budget.check_time()?;
+    let result = match expr {
         Expression::And { left, right } => {
-            let left_result = execute(left, indexes, live_contexts)?;
-            let right_result = execute(right, indexes, live_contexts)?;
-            Ok(left_result.intersection(&right_result).copied().collect())
+            let left_result = evaluate(left, indexes, live_contexts, budget)?;
+            let right_result = evaluate(right, indexes, live_contexts, budget)?;
+            left_result.intersection(&right_result).copied().collect()
         }
         Expression::Or { left, right } => {
-            let left_result = execute(left, indexes, live_contexts)?;
-            let right_result = execute(right, indexes, live_contexts)?;
-            Ok(left_result.union(&right_result).copied().collect())
+            let left_result = evaluate(left, indexes, live_contexts, budget)?;
+            let right_result = evaluate(right, indexes, live_contexts, budget)?;
+            left_result.union(&right_result).copied().collect()
         }
         Expression::Not { inner } => {
-            let inner_result = execute(inner, indexes, live_contexts)?;
-            Ok(indexes
+            let inner_result = evaluate(inner, indexes, live_contexts, budget)?;
+            indexes
                 .all_contexts()
                 .difference(&inner_result)
                 .copied()
-                .collect())
+                .collect()
         }
         Expression::Comparison {
             field,
             operator,
             value,
-        } => execute_comparison(field, *operator, value, indexes, live_contexts),
+        } => execute_comparison(field, *operator, value, indexes, live_contexts)?,
+    };
+    budget.check_candidates(&result)?;
+    Ok(result)
+}
+
+/// How many of `expr`'s comparisons each of `candidates` satisfies, for
+/// ranking by relevance. A comparison under an odd number of `NOT`s counts
+/// for the contexts it excludes.
+pub fn match_counts(
+    expr: &Expression,
+    indexes: &SecondaryIndexes,
+    live_contexts: &HashSet<u64>,
+    candidates: &HashSet<u64>,
+) -> Result<HashMap<u64, usize>, CqlError> {
+    let mut counts = HashMap::with_capacity(candidates.len());
+    visit_comparisons(
+        expr,
+        false,
+        indexes,
+        live_contexts,
+        &mut |_, negated, matched| {
+            for id in candidates {
+                if matched.contains(id) != negated {
+                    *counts.entry(*id).or_default() += 1;
+                }
+            }
+        },
+    )?;
+    Ok(counts)
+}
+
+/// The fields of `expr`'s comparisons each of `candidates` satisfies, in
+/// query order without repeats. Negated comparisons say nothing about a
+/// field's value, so they never appear.
+pub fn matched_fields(
+    expr: &Expression,
+    indexes: &SecondaryIndexes,
+    live_contexts: &HashSet<u64>,
+    candidates: &HashSet<u64>,
+) -> Result<HashMap<u64, Vec<FieldName>>, CqlError> {
+    let mut fields: HashMap<u64, Vec<FieldName>> = HashMap::new();
+    visit_comparisons(
+        expr,
+        false,
+        indexes,
+        live_contexts,
+        &mut |field, negated, matched| {
+            if negated {
+                return;
+            }
+            for id in candidates.intersection(matched) {
+                let entry = fields.entry(*id).or_default();
+                if !entry.contains(&field) {
+                    entry.push(field);
+                }
+            }
+        },
+    )?;
+    Ok(fields)
+}
+
+/// Run each comparison in `expr` on its own and pass its field, whether it
+/// sits under an odd number of `NOT`s, and the contexts it matched.
+fn visit_comparisons(
+    expr: &Expression,
+    negated: bool,
+    indexes: &SecondaryIndexes,
+    live_contexts: &HashSet<u64>,
+    visit: &mut dyn FnMut(FieldName, bool, &HashSet<u64>),
+) -> Result<(), CqlError> {
+    match expr {
+        Expression::And { left, right } | Expression::Or { left, right } => {
+            visit_comparisons(left, negated, indexes, live_contexts, visit)?;
+            visit_comparisons(right, negated, indexes, live_contexts, visit)
+        }
+        Expression::Not { inner } => {
+            visit_comparisons(inner, !negated, indexes, live_contexts, visit)
+        }
+        Expression::Comparison {
+            field,
+            operator,
+            value,
+        } => {
+            let matched = execute_comparison(field, *operator, value, indexes, live_contexts)?;
+            // execute_comparison rejected unknown fields already.
+            if let Some(field) = FieldName::from_str(field) {
+                visit(field, negated, &matched);
+            }
+            Ok(())
+        }
     }
 }
 
@@ -68,6 +292,7 @@ fn execute_comparison(
         FieldName::Root => execute_root(operator, value, indexes),
         FieldName::Created => execute_created(operator, value, indexes),
         FieldName::Depth => execute_depth(operator, value, indexes),
+        FieldName::TurnCount => execute_turn_count(operator, value, indexes),
         FieldName::IsLive => execute_is_live(operator, value, live_contexts, indexes),
     }
 }
@@ -516,6 +741,42 @@ fn execute_depth(
     }
 }
 
+fn execute_turn_count(
+    operator: Operator,
+    value: &Value,
+    indexes: &SecondaryIndexes,
+) -> Result<HashSet<u64>, CqlError> {
+    let count = value.as_u64().ok_or_else(|| CqlError {
+        error_type: CqlErrorType::InvalidValue,
+        message: "Expected numeric value for turn_count".into(),
+        position: None,
+        field: None,
+    })?;
+
+    match operator {
+        Operator::Eq => Ok(indexes.lookup_turn_count(count..=count)),
+        Operator::Neq => {
+            let matches = indexes.lookup_turn_count(count..=count);
+            Ok(indexes
+                .all_contexts()
+                .difference(&matches)
+                .copied()
+                .collect())
+        }
+        Operator::Gt => Ok(indexes
+            .lookup_turn_count((std::ops::Bound::Excluded(count), std::ops::Bound::Unbounded))),
+        Operator::Gte => Ok(indexes.lookup_turn_count(count..)),
+        Operator::Lt => Ok(indexes.lookup_turn_count(..count)),
+        Operator::Lte => Ok(indexes.lookup_turn_count(..=count)),
+        _ => Err(CqlError {
+            error_type: CqlErrorType::InvalidOperator,
+            message: format!("Operator {:?} not supported for turn_count field", operator),
+            position: None,
+            field: None,
+        }),
+    }
+}
+
 fn execute_is_live(
     operator: Operator,
     value: &Value,
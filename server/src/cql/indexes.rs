@@ -8,6 +8,7 @@
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 
+use crate::context_meta::TagNormalization;
 use crate::store::ContextMetadata;
 use crate::turn_store::ContextHead;
 
@@ -54,8 +55,15 @@ pub struct SecondaryIndexes {
     // Depth index
     depth_btree: BTreeMap<u32, HashSet<u64>>,
 
+    // Turn count index, kept current on every append
+    turn_count_btree: BTreeMap<u64, HashSet<u64>>,
+
     // Track all indexed context IDs for NOT operations
     all_context_ids: HashSet<u64>,
+
+    /// Applied to tags in exact and prefix lookups. Indexed tags arrive
+    /// already normalized by the store.
+    tag_normalization: TagNormalization,
 }
 
 impl SecondaryIndexes {
@@ -63,6 +71,13 @@ impl SecondaryIndexes {
         Self::default()
     }
 
+    pub fn with_tag_normalization(tag_normalization: TagNormalization) -> Self {
+        Self {
+            tag_normalization,
+            ..Self::default()
+        }
+    }
+
     /// Build indexes from existing context metadata cache.
     pub fn build_from_cache(
         &mut self,
@@ -90,6 +105,10 @@ impl SecondaryIndexes {
                 .entry(head.head_depth)
                 .or_default()
                 .insert(head.context_id);
+            self.turn_count_btree
+                .entry(head.turn_count)
+                .or_default()
+                .insert(head.context_id);
         }
 
         // Sort the sorted indexes
@@ -249,6 +268,50 @@ impl SecondaryIndexes {
             .insert(context_id);
     }
 
+    /// Index a batch of existing contexts, re-sorting once at the end.
+    /// Used by the background warmup, where `add_context`'s per-context
+    /// re-sort would be quadratic.
+    pub fn add_contexts(&mut self, contexts: &[(Option<ContextMetadata>, ContextHead)]) {
+        for (metadata, head) in contexts {
+            self.all_context_ids.insert(head.context_id);
+            if let Some(metadata) = metadata {
+                self.index_metadata(head.context_id, metadata);
+            }
+            self.created_btree
+                .entry(head.created_at_unix_ms)
+                .or_default()
+                .insert(head.context_id);
+            self.depth_btree
+                .entry(head.head_depth)
+                .or_default()
+                .insert(head.context_id);
+            self.turn_count_btree
+                .entry(head.turn_count)
+                .or_default()
+                .insert(head.context_id);
+        }
+        self.sort_indexes();
+    }
+
+    /// Move a context to its new turn count after an append.
+    ///
+    /// Counts only grow by one per append, so the previous bucket is
+    /// `turn_count - 1`; a context not indexed yet is simply inserted.
+    pub fn update_turn_count(&mut self, context_id: u64, turn_count: u64) {
+        if let Some(previous) = turn_count.checked_sub(1) {
+            if let Some(ids) = self.turn_count_btree.get_mut(&previous) {
+                ids.remove(&context_id);
+                if ids.is_empty() {
+                    self.turn_count_btree.remove(&previous);
+                }
+            }
+        }
+        self.turn_count_btree
+            .entry(turn_count)
+            .or_default()
+            .insert(context_id);
+    }
+
     /// Get all context IDs (for NOT operations).
     pub fn all_contexts(&self) -> &HashSet<u64> {
         &self.all_context_ids
@@ -259,7 +322,10 @@ impl SecondaryIndexes {
     // =========================================================================
 
     pub fn lookup_tag_exact(&self, value: &str) -> HashSet<u64> {
-        self.tag_exact.get(value).cloned().unwrap_or_default()
+        self.tag_exact
+            .get(&self.tag_normalization.apply(value))
+            .cloned()
+            .unwrap_or_default()
     }
 
     pub fn lookup_tag_exact_ci(&self, value: &str) -> HashSet<u64> {
@@ -284,6 +350,13 @@ impl SecondaryIndexes {
         self.label_exact.get(value).cloned().unwrap_or_default()
     }
 
+    /// Whether any indexed context carries a label starting with `prefix`.
+    pub fn has_label_prefix(&self, prefix: &str) -> bool {
+        self.label_exact
+            .iter()
+            .any(|(label, ids)| label.starts_with(prefix) && !ids.is_empty())
+    }
+
     pub fn lookup_user_exact(&self, value: &str) -> HashSet<u64> {
         self.user_exact.get(value).cloned().unwrap_or_default()
     }
@@ -327,7 +400,7 @@ impl SecondaryIndexes {
     // =========================================================================
 
     pub fn lookup_tag_prefix(&self, prefix: &str) -> HashSet<u64> {
-        self.prefix_search(&self.tag_sorted, prefix)
+        self.prefix_search(&self.tag_sorted, &self.tag_normalization.apply(prefix))
     }
 
     pub fn lookup_tag_prefix_ci(&self, prefix: &str) -> HashSet<u64> {
@@ -455,6 +528,13 @@ impl SecondaryIndexes {
         self.depth_btree.get(&depth).cloned().unwrap_or_default()
     }
 
+    pub fn lookup_turn_count(&self, range: impl std::ops::RangeBounds<u64>) -> HashSet<u64> {
+        self.turn_count_btree
+            .range(range)
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect()
+    }
+
     /// Get index statistics.
     pub fn stats(&self) -> IndexStats {
         IndexStats {
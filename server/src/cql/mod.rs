@@ -54,6 +54,6 @@ pub mod indexes;
 pub mod parser;
 
 pub use ast::{CqlError, CqlQuery, Expression, FieldName, Operator, Value};
-pub use executor::execute;
+pub use executor::{execute, execute_with_limits, match_counts, matched_fields, CqlLimits};
 pub use indexes::{IndexStats, SecondaryIndexes};
 pub use parser::parse;
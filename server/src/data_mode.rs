@@ -0,0 +1,200 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Permissions for directories and files created under the data dir.
+//!
+//! Stored turns may contain PII, so on Unix the store creates files `0600`
+//! and directories `0700` by default. `CXDB_DATA_MODE` overrides the file
+//! mode as an octal string (e.g. `0640`); directories get the matching
+//! execute bits. `CXDB_DATA_MODE=umask` leaves modes to the process umask.
+//!
+//! Modes are applied when a file is created; the process umask can only
+//! narrow them further. Directories [`create_dir_all`] creates get exactly
+//! the directory mode. Existing files and directories keep their
+//! permissions, so an operator's own chmod is never undone.
+
+use crate::error::{Result, StoreError};
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+
+const DEFAULT_FILE_MODE: u32 = 0o600;
+
+/// File and directory modes for the data dir.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataMode {
+    pub file_mode: u32,
+    pub dir_mode: u32,
+}
+
+impl DataMode {
+    pub fn from_file_mode(file_mode: u32) -> Self {
+        let file_mode = file_mode & 0o777;
+        // Any class that can read a file also needs to traverse its directory.
+        let exec = (file_mode & 0o444) >> 2;
+        Self {
+            file_mode,
+            dir_mode: file_mode | exec,
+        }
+    }
+
+    /// Read `CXDB_DATA_MODE`. Returns `None` when modes are left to the
+    /// umask. Unparseable values fall back to the default; [`check_env`]
+    /// rejects them at startup.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("CXDB_DATA_MODE") {
+            Ok(v) if v.trim().eq_ignore_ascii_case("umask") => None,
+            Ok(v) => Some(Self::from_file_mode(
+                parse_octal(&v).unwrap_or(DEFAULT_FILE_MODE),
+            )),
+            Err(_) => Some(Self::default()),
+        }
+    }
+}
+
+impl Default for DataMode {
+    fn default() -> Self {
+        Self::from_file_mode(DEFAULT_FILE_MODE)
+    }
+}
+
+/// Reject an unparseable `CXDB_DATA_MODE` at startup, so a typo doesn't
+/// silently leave the data dir at the default mode.
+pub fn check_env() -> Result<()> {
+    match std::env::var("CXDB_DATA_MODE") {
+        Ok(v) => check_value(&v),
+        Err(_) => Ok(()),
+    }
+}
+
+fn check_value(value: &str) -> Result<()> {
+    if value.trim().eq_ignore_ascii_case("umask") || parse_octal(value).is_some() {
+        return Ok(());
+    }
+    Err(StoreError::InvalidInput(format!(
+        "CXDB_DATA_MODE must be an octal mode up to 0777 or \"umask\", got {value:?}"
+    )))
+}
+
+fn parse_octal(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let digits = value
+        .strip_prefix("0o")
+        .or_else(|| value.strip_prefix("0O"))
+        .unwrap_or(value);
+    u32::from_str_radix(digits, 8).ok().filter(|m| *m <= 0o777)
+}
+
+/// Create `dir` and any missing parents with the configured directory mode.
+/// Only the directories created here are chmod'ed, past the umask.
+pub fn create_dir_all(dir: &Path) -> io::Result<()> {
+    let mode = DataMode::from_env();
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        let missing: Vec<&Path> = dir.ancestors().take_while(|p| !p.exists()).collect();
+        builder.mode(mode.dir_mode);
+        builder.create(dir)?;
+        for created in missing {
+            std::fs::set_permissions(created, std::fs::Permissions::from_mode(mode.dir_mode))?;
+        }
+        return Ok(());
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    builder.create(dir)
+}
+
+/// `OpenOptions` that create new files with the configured file mode.
+pub fn open_options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    if let Some(mode) = DataMode::from_env() {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode.file_mode);
+    }
+    options
+}
+
+/// Like `std::fs::write`, but new files get the configured file mode.
+pub fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    let mut file = open_options()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(path)?;
+    file.write_all(contents)
+}
+
+/// `write_file` to `<path>.tmp`, synced, then renamed over `path`, so a
+/// crash mid-write never leaves a truncated `path` behind.
+pub fn write_file_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+    {
+        use std::io::Write;
+        let mut file = open_options()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_mode_adds_execute_for_readable_classes() {
+        assert_eq!(DataMode::default().dir_mode, 0o700);
+        assert_eq!(DataMode::from_file_mode(0o640).dir_mode, 0o750);
+        assert_eq!(DataMode::from_file_mode(0o644).dir_mode, 0o755);
+    }
+
+    #[test]
+    fn parses_octal_modes() {
+        assert_eq!(parse_octal("0600"), Some(0o600));
+        assert_eq!(parse_octal("0o640"), Some(0o640));
+        assert_eq!(parse_octal("640"), Some(0o640));
+        assert_eq!(parse_octal("0999"), None);
+        assert_eq!(parse_octal("01000"), None);
+    }
+
+    #[test]
+    fn startup_check_rejects_unparseable_modes() {
+        assert!(check_value("0640").is_ok());
+        assert!(check_value(" UMASK ").is_ok());
+        assert!(matches!(
+            check_value("rw-r-----"),
+            Err(StoreError::InvalidInput(msg)) if msg.contains("CXDB_DATA_MODE")
+        ));
+        assert!(check_value("0999").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_dir_all_leaves_existing_directories_alone() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let existing = dir.path().join("existing");
+        std::fs::create_dir(&existing).expect("create");
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o755)).expect("chmod");
+
+        create_dir_all(&existing.join("a/b")).expect("create_dir_all");
+        create_dir_all(&existing).expect("create_dir_all");
+        let mode = |p: &Path| std::fs::metadata(p).expect("metadata").permissions().mode() & 0o777;
+        assert_eq!(mode(&existing), 0o755);
+        assert_eq!(mode(&existing.join("a")), 0o700);
+        assert_eq!(mode(&existing.join("a/b")), 0o700);
+    }
+}
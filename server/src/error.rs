@@ -1,6 +1,10 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,10 +13,118 @@ pub enum StoreError {
     Io(#[from] std::io::Error),
     #[error("corrupt data: {0}")]
     Corrupt(String),
-    #[error("not found: {0}")]
-    NotFound(String),
+    #[error("not found: {1}")]
+    NotFound(NotFoundKind, String),
     #[error("invalid input: {0}")]
     InvalidInput(String),
+    /// A request too malformed to act on, such as an unparseable query
+    /// parameter or a path that escapes its root; answered with 400.
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    /// A request contradicts one already applied, such as an idempotency
+    /// key reused for a different payload; answered with 409.
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// A conditional append named a head the context has since moved past.
+    #[error("head mismatch: expected {expected}, actual {actual}")]
+    HeadMismatch { expected: u64, actual: u64 },
+    /// A server-side failure the request didn't cause, such as a failed
+    /// encryption or compression step.
+    #[error("internal error: {0}")]
+    Internal(String),
+    /// A request handler panicked; the panic was caught and the connection
+    /// kept open. The payload is for the error log only (see
+    /// [`StoreError::client_message`]).
+    #[error("handler panicked: {0}")]
+    Panicked(String),
+}
+
+impl StoreError {
+    pub fn not_found(kind: NotFoundKind, msg: impl Into<String>) -> Self {
+        StoreError::NotFound(kind, msg.into())
+    }
+
+    /// The message sent back to the client for this error, given its logged
+    /// `detail`. A panic payload can name server internals, so the client
+    /// only gets a generic message and the correlation id the full one is
+    /// logged under.
+    pub fn client_message(&self, detail: &str, correlation_id: Option<&str>) -> String {
+        match (self, correlation_id) {
+            (StoreError::Panicked(_), Some(id)) => {
+                format!("internal error (correlation id {id})")
+            }
+            (StoreError::Panicked(_), None) => "internal error".to_string(),
+            _ => detail.to_string(),
+        }
+    }
+}
+
+/// A fresh id to file an error under when the request didn't bring its own,
+/// so a client holding a generic error can still find the logged detail.
+pub fn new_correlation_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    format!("cxdb-{now_ms:x}-{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// What a `StoreError::NotFound` refers to. Error encoders use this instead of
+/// matching on the message so the wire code stays stable if wording changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotFoundKind {
+    Context,
+    Turn,
+    /// The parent or fork base a write names; answered with 409, not 404.
+    ParentTurn,
+    Blob,
+    TypeDescriptor,
+    FsSnapshot,
+    Bundle,
+    Route,
+    Session,
+}
+
+impl NotFoundKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotFoundKind::Context => "context",
+            NotFoundKind::Turn => "turn",
+            NotFoundKind::ParentTurn => "parent_turn",
+            NotFoundKind::Blob => "blob",
+            NotFoundKind::TypeDescriptor => "type_descriptor",
+            NotFoundKind::FsSnapshot => "fs_snapshot",
+            NotFoundKind::Bundle => "bundle",
+            NotFoundKind::Route => "route",
+            NotFoundKind::Session => "session",
+        }
+    }
+}
+
+impl fmt::Display for NotFoundKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 pub type Result<T> = std::result::Result<T, StoreError>;
+
+/// Run one request's handling, turning a panic into `StoreError::Panicked`
+/// so the caller can answer with a 500 and keep serving the connection.
+///
+/// Shared state is behind mutexes that recover from poisoning (see
+/// [`crate::lock`]), so continuing after a caught panic is safe.
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(StoreError::Panicked(msg))
+        }
+    }
+}
@@ -5,8 +5,15 @@
 //!
 //! This module provides an EventBus that broadcasts store events to SSE subscribers.
 //! Events originate from the binary protocol handler and are fanned out to all
-//! connected HTTP SSE clients.
+//! connected HTTP SSE clients. A subscriber can follow specific contexts, in
+//! which case it only receives their events and counts toward their
+//! follower counts.
+//!
+//! The bus also keeps the most recent events in a bounded history, each
+//! with an increasing id, so clients can poll for what they missed instead
+//! of holding a stream open.
 
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 
@@ -73,13 +80,40 @@ pub enum StoreEvent {
         message: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         path: Option<String>,
+        /// Caller's correlation id: the HTTP `X-Correlation-Id` header, or
+        /// the provenance `correlation_id` of a binary append.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
     },
 }
 
 impl StoreEvent {
+    /// Contexts the event is about, for delivery to context followers.
+    pub fn context_ids(&self) -> Vec<u64> {
+        let ids: Vec<&String> = match self {
+            StoreEvent::ContextCreated { context_id, .. }
+            | StoreEvent::ContextMetadataUpdated { context_id, .. }
+            | StoreEvent::TurnAppended { context_id, .. } => vec![context_id],
+            StoreEvent::ContextLinked {
+                child_context_id,
+                parent_context_id,
+                ..
+            } => vec![child_context_id, parent_context_id],
+            StoreEvent::ClientDisconnected { contexts, .. } => contexts.iter().collect(),
+            StoreEvent::ClientConnected { .. } | StoreEvent::ErrorOccurred { .. } => Vec::new(),
+        };
+        ids.into_iter().filter_map(|id| id.parse().ok()).collect()
+    }
+
     /// Convert event to SSE format: (event_type, json_data).
     pub fn to_sse(&self) -> (&'static str, String) {
-        let event_type = match self {
+        let (event_type, data) = self.sse_data();
+        (event_type, data.to_string())
+    }
+
+    /// The SSE event name, e.g. `turn_appended`.
+    pub fn event_type(&self) -> &'static str {
+        match self {
             StoreEvent::ContextCreated { .. } => "context_created",
             StoreEvent::ContextMetadataUpdated { .. } => "context_metadata_updated",
             StoreEvent::ContextLinked { .. } => "context_linked",
@@ -87,8 +121,11 @@ impl StoreEvent {
             StoreEvent::ClientConnected { .. } => "client_connected",
             StoreEvent::ClientDisconnected { .. } => "client_disconnected",
             StoreEvent::ErrorOccurred { .. } => "error_occurred",
-        };
+        }
+    }
 
+    /// The SSE event name and its `data` object.
+    pub fn sse_data(&self) -> (&'static str, serde_json::Value) {
         // Serialize without the type tag (frontend expects flat structure)
         let data = match self {
             StoreEvent::ContextCreated {
@@ -186,6 +223,7 @@ impl StoreEvent {
                 status_code,
                 message,
                 path,
+                correlation_id,
             } => {
                 let mut obj = serde_json::json!({
                     "timestamp_ms": timestamp_ms,
@@ -196,17 +234,43 @@ impl StoreEvent {
                 if let Some(p) = path {
                     obj["path"] = serde_json::Value::String(p.clone());
                 }
+                if let Some(id) = correlation_id {
+                    obj["correlation_id"] = serde_json::Value::String(id.clone());
+                }
                 obj
             }
         };
 
-        (event_type, data.to_string())
+        (self.event_type(), data)
     }
 }
 
+/// Live subscribers following each context.
+type FollowerCounts = Arc<Mutex<HashMap<u64, usize>>>;
+
 /// A subscriber to the event bus.
 pub struct EventSubscriber {
     rx: Receiver<StoreEvent>,
+    /// Contexts this subscriber follows, released from the bus's follower
+    /// counts on drop.
+    following: Option<(FollowerCounts, HashSet<u64>)>,
+}
+
+impl Drop for EventSubscriber {
+    fn drop(&mut self) {
+        let Some((followers, contexts)) = self.following.take() else {
+            return;
+        };
+        let mut followers = followers.lock().unwrap();
+        for context_id in contexts {
+            if let Some(count) = followers.get_mut(&context_id) {
+                *count -= 1;
+                if *count == 0 {
+                    followers.remove(&context_id);
+                }
+            }
+        }
+    }
 }
 
 impl EventSubscriber {
@@ -226,33 +290,139 @@ impl EventSubscriber {
     }
 }
 
+struct Subscription {
+    tx: Sender<StoreEvent>,
+    /// `None` receives every event.
+    contexts: Option<HashSet<u64>>,
+}
+
+/// Events kept for `history` by [`EventBus::new`].
+pub const DEFAULT_EVENT_HISTORY: usize = 1024;
+
+/// Recent events, oldest first, and the id the next event gets.
+struct EventHistory {
+    events: VecDeque<(u64, StoreEvent)>,
+    capacity: usize,
+    next_id: u64,
+}
+
 /// Thread-safe event bus for broadcasting store events to SSE subscribers.
 pub struct EventBus {
-    subscribers: Arc<Mutex<Vec<Sender<StoreEvent>>>>,
+    subscribers: Arc<Mutex<Vec<Subscription>>>,
+    followers: FollowerCounts,
+    history: Mutex<EventHistory>,
 }
 
 impl EventBus {
     /// Create a new event bus.
     pub fn new() -> Self {
+        Self::with_history(DEFAULT_EVENT_HISTORY)
+    }
+
+    /// Create a bus that keeps the last `capacity` events for `history`.
+    pub fn with_history(capacity: usize) -> Self {
         Self {
             subscribers: Arc::new(Mutex::new(Vec::new())),
+            followers: Arc::new(Mutex::new(HashMap::new())),
+            history: Mutex::new(EventHistory {
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+                next_id: 1,
+            }),
         }
     }
 
+    /// A bus sized by `CXDB_EVENT_HISTORY` (default 1024 events).
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("CXDB_EVENT_HISTORY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_EVENT_HISTORY);
+        Self::with_history(capacity)
+    }
+
     /// Subscribe to events. Returns a subscriber that receives all future events.
     pub fn subscribe(&self) -> EventSubscriber {
         let (tx, rx) = mpsc::channel();
         let mut subs = self.subscribers.lock().unwrap();
-        subs.push(tx);
-        EventSubscriber { rx }
+        subs.push(Subscription { tx, contexts: None });
+        EventSubscriber {
+            rx,
+            following: None,
+        }
+    }
+
+    /// Subscribe to events about `contexts` only. Each context's follower
+    /// count includes the subscriber until it is dropped.
+    pub fn subscribe_contexts(&self, contexts: HashSet<u64>) -> EventSubscriber {
+        {
+            let mut followers = self.followers.lock().unwrap();
+            for context_id in &contexts {
+                *followers.entry(*context_id).or_insert(0) += 1;
+            }
+        }
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(Subscription {
+            tx,
+            contexts: Some(contexts.clone()),
+        });
+        EventSubscriber {
+            rx,
+            following: Some((Arc::clone(&self.followers), contexts)),
+        }
+    }
+
+    /// Live subscribers following `context_id`.
+    pub fn follower_count(&self, context_id: u64) -> usize {
+        self.followers
+            .lock()
+            .unwrap()
+            .get(&context_id)
+            .copied()
+            .unwrap_or(0)
     }
 
     /// Publish an event to all subscribers.
     /// Disconnected subscribers are automatically removed.
     pub fn publish(&self, event: StoreEvent) {
         let mut subs = self.subscribers.lock().unwrap();
-        // Send to all, remove disconnected
-        subs.retain(|tx| tx.send(event.clone()).is_ok());
+        {
+            let mut history = self.history.lock().unwrap();
+            let id = history.next_id;
+            history.next_id += 1;
+            if history.capacity > 0 {
+                if history.events.len() == history.capacity {
+                    history.events.pop_front();
+                }
+                history.events.push_back((id, event.clone()));
+            }
+        }
+        let context_ids = event.context_ids();
+        // Send to all, remove disconnected. Followers of other contexts
+        // are skipped and pruned on a later event they do receive.
+        subs.retain(|sub| match &sub.contexts {
+            Some(contexts) if !context_ids.iter().any(|id| contexts.contains(id)) => true,
+            _ => sub.tx.send(event.clone()).is_ok(),
+        });
+    }
+
+    /// Buffered events with an id above `since_id`, oldest first and at most
+    /// `limit` of them. `types` holds SSE event names; empty matches all.
+    pub fn history(
+        &self,
+        since_id: u64,
+        types: &HashSet<String>,
+        limit: usize,
+    ) -> Vec<(u64, StoreEvent)> {
+        let history = self.history.lock().unwrap();
+        let start = history.events.partition_point(|(id, _)| *id <= since_id);
+        history
+            .events
+            .range(start..)
+            .filter(|(_, event)| types.is_empty() || types.contains(event.event_type()))
+            .take(limit)
+            .cloned()
+            .collect()
     }
 
     /// Get the current number of subscribers.
@@ -310,6 +480,45 @@ mod tests {
         assert!(sub2.recv_timeout(Duration::from_millis(100)).is_some());
     }
 
+    #[test]
+    fn context_followers_get_only_their_events_and_are_counted() {
+        let bus = EventBus::new();
+        let all = bus.subscribe();
+        let follower = bus.subscribe_contexts(HashSet::from([5]));
+        let second = bus.subscribe_contexts(HashSet::from([5, 6]));
+        assert_eq!(bus.follower_count(5), 2);
+        assert_eq!(bus.follower_count(6), 1);
+        assert_eq!(bus.follower_count(7), 0);
+
+        let appended = |context_id: &str| StoreEvent::TurnAppended {
+            context_id: context_id.to_string(),
+            turn_id: "1".to_string(),
+            parent_turn_id: "0".to_string(),
+            depth: 0,
+            declared_type_id: None,
+            declared_type_version: None,
+        };
+        bus.publish(appended("6"));
+        bus.publish(appended("5"));
+
+        let timeout = Duration::from_millis(100);
+        match follower.recv_timeout(timeout) {
+            Some(StoreEvent::TurnAppended { context_id, .. }) => assert_eq!(context_id, "5"),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(follower.try_recv().is_none());
+        assert!(second.recv_timeout(timeout).is_some());
+        assert!(second.recv_timeout(timeout).is_some());
+        assert!(all.recv_timeout(timeout).is_some());
+        assert!(all.recv_timeout(timeout).is_some());
+
+        drop(second);
+        assert_eq!(bus.follower_count(5), 1);
+        assert_eq!(bus.follower_count(6), 0);
+        drop(follower);
+        assert_eq!(bus.follower_count(5), 0);
+    }
+
     #[test]
     fn test_event_to_sse() {
         let event = StoreEvent::ContextMetadataUpdated {
@@ -341,6 +550,22 @@ mod tests {
         assert!(data.contains("\"parent_context_id\":\"5\""));
     }
 
+    #[test]
+    fn history_keeps_the_newest_events_with_increasing_ids() {
+        let bus = EventBus::with_history(2);
+        for tag in ["a", "b", "c"] {
+            bus.publish(StoreEvent::ClientConnected {
+                session_id: "1".to_string(),
+                client_tag: tag.to_string(),
+            });
+        }
+
+        let history = bus.history(0, &HashSet::new(), 10);
+        let ids: Vec<u64> = history.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(bus.history(3, &HashSet::new(), 10).is_empty());
+    }
+
     #[test]
     fn test_subscriber_cleanup() {
         let bus = EventBus::new();
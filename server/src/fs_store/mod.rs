@@ -31,16 +31,19 @@
 //! ```
 
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher;
 use rmpv::Value;
 
 use crate::blob_store::BlobStore;
-use crate::error::{Result, StoreError};
+use crate::data_mode;
+use crate::error::{NotFoundKind, Result, StoreError};
+use crate::lru::LruCache;
 use crate::turn_store::TurnStore;
 
 /// Entry kinds for filesystem tree entries.
@@ -112,10 +115,10 @@ pub struct FsRootsIndex {
 impl FsRootsIndex {
     /// Open or create the filesystem roots index.
     pub fn open(dir: &Path) -> Result<Self> {
-        std::fs::create_dir_all(dir)?;
+        data_mode::create_dir_all(dir)?;
         let path = dir.join("roots.idx");
 
-        let file = OpenOptions::new()
+        let file = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
@@ -268,6 +271,61 @@ pub struct FsRootsStats {
     pub content_bytes: u64,
 }
 
+/// Default `TreeCache` capacity, in trees.
+pub const DEFAULT_TREE_CACHE_ENTRIES: usize = 1024;
+
+/// LRU cache of parsed tree objects keyed by tree hash. Trees are
+/// content-addressed, so a cached entry never goes stale.
+pub struct TreeCache {
+    trees: LruCache<[u8; 32], Arc<Vec<TreeEntry>>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl TreeCache {
+    /// A cache holding up to `capacity` trees; 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            trees: LruCache::new(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The parsed entries of a tree, from the cache or the blob store.
+    pub fn load(
+        &mut self,
+        blob_store: &mut BlobStore,
+        tree_hash: &[u8; 32],
+    ) -> Result<Arc<Vec<TreeEntry>>> {
+        if let Some(entries) = self.trees.get(tree_hash) {
+            self.hits += 1;
+            return Ok(Arc::clone(entries));
+        }
+
+        self.misses += 1;
+        let entries = Arc::new(load_tree_entries(blob_store, tree_hash)?);
+        self.trees.insert(*tree_hash, Arc::clone(&entries));
+        Ok(entries)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+}
+
 /// Load and deserialize tree entries from the blob store.
 pub fn load_tree_entries(
     blob_store: &mut BlobStore,
@@ -369,6 +427,7 @@ fn parse_tree_entry(value: &Value) -> Result<TreeEntry> {
 /// Returns (hash, is_directory).
 pub fn resolve_path(
     blob_store: &mut BlobStore,
+    cache: &mut TreeCache,
     root_hash: &[u8; 32],
     path: &str,
 ) -> Result<([u8; 32], bool)> {
@@ -389,12 +448,14 @@ pub fn resolve_path(
     let mut current_hash = *root_hash;
 
     for (i, part) in parts.iter().enumerate() {
-        let entries = load_tree_entries(blob_store, &current_hash)?;
+        let entries = cache.load(blob_store, &current_hash)?;
 
-        let entry = entries
-            .iter()
-            .find(|e| e.name == *part)
-            .ok_or_else(|| StoreError::NotFound(format!("path component not found: {part}")))?;
+        let entry = entries.iter().find(|e| e.name == *part).ok_or_else(|| {
+            StoreError::not_found(
+                NotFoundKind::FsSnapshot,
+                format!("path component not found: {part}"),
+            )
+        })?;
 
         let entry_hash = entry.hash_array()?;
         let is_last = i == parts.len() - 1;
@@ -417,6 +478,7 @@ pub fn resolve_path(
 /// Get a file's content by path from a filesystem snapshot.
 pub fn get_file_at_path(
     blob_store: &mut BlobStore,
+    cache: &mut TreeCache,
     root_hash: &[u8; 32],
     path: &str,
 ) -> Result<(Vec<u8>, TreeEntry)> {
@@ -433,12 +495,14 @@ pub fn get_file_at_path(
     let mut current_hash = *root_hash;
 
     for (i, part) in parts.iter().enumerate() {
-        let entries = load_tree_entries(blob_store, &current_hash)?;
+        let entries = cache.load(blob_store, &current_hash)?;
 
-        let entry = entries
-            .iter()
-            .find(|e| e.name == *part)
-            .ok_or_else(|| StoreError::NotFound(format!("path component not found: {part}")))?;
+        let entry = entries.iter().find(|e| e.name == *part).ok_or_else(|| {
+            StoreError::not_found(
+                NotFoundKind::FsSnapshot,
+                format!("path component not found: {part}"),
+            )
+        })?;
 
         let entry_hash = entry.hash_array()?;
         let is_last = i == parts.len() - 1;
@@ -474,6 +538,153 @@ pub fn get_file_at_path(
     unreachable!()
 }
 
+/// Aggregate counts for a snapshot subtree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsTreeStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    /// Sum of file sizes, as recorded in the tree entries.
+    pub total_bytes: u64,
+    /// Path components from the subtree root to its deepest entry; 1 for
+    /// a directory holding only files, 0 for an empty one.
+    pub max_depth: u32,
+}
+
+impl FsTreeStats {
+    /// Stats for a single entry, not counting a directory's contents.
+    fn of_entry(entry: &TreeEntry) -> Self {
+        let mut stats = Self::default();
+        match entry.kind_enum() {
+            EntryKind::File => {
+                stats.files = 1;
+                stats.total_bytes = entry.size;
+            }
+            EntryKind::Directory => stats.dirs = 1,
+            EntryKind::Symlink => stats.symlinks = 1,
+        }
+        stats
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.symlinks += other.symlinks;
+        self.total_bytes += other.total_bytes;
+    }
+}
+
+/// Walk a tree and aggregate its entries. Identical subtrees share a hash,
+/// so each is walked once and its stats reused from `memo`; they still
+/// count once per place they appear.
+pub fn tree_stats(
+    blob_store: &mut BlobStore,
+    cache: &mut TreeCache,
+    tree_hash: &[u8; 32],
+    memo: &mut HashMap<[u8; 32], FsTreeStats>,
+) -> Result<FsTreeStats> {
+    if let Some(stats) = memo.get(tree_hash) {
+        return Ok(*stats);
+    }
+
+    let mut stats = FsTreeStats::default();
+    for entry in cache.load(blob_store, tree_hash)?.iter() {
+        stats.add(&FsTreeStats::of_entry(entry));
+        let mut depth = 1;
+        if entry.kind_enum() == EntryKind::Directory {
+            let sub = tree_stats(blob_store, cache, &entry.hash_array()?, memo)?;
+            stats.add(&sub);
+            depth += sub.max_depth;
+        }
+        stats.max_depth = stats.max_depth.max(depth);
+    }
+
+    memo.insert(*tree_hash, stats);
+    Ok(stats)
+}
+
+/// Stats for whatever is at `path`: the subtree for a directory, or the
+/// single entry otherwise.
+pub fn stat_path(
+    blob_store: &mut BlobStore,
+    cache: &mut TreeCache,
+    root_hash: &[u8; 32],
+    path: &str,
+) -> Result<FsTreeStats> {
+    let (tree_hash, is_dir) = resolve_path(blob_store, cache, root_hash, path)?;
+    if is_dir {
+        return tree_stats(blob_store, cache, &tree_hash, &mut HashMap::new());
+    }
+
+    let trimmed = path.trim_matches('/');
+    let (parent, name) = trimmed.rsplit_once('/').unwrap_or(("", trimmed));
+    let (parent_hash, _) = resolve_path(blob_store, cache, root_hash, parent)?;
+    let entries = cache.load(blob_store, &parent_hash)?;
+    let entry = entries
+        .iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| StoreError::not_found(NotFoundKind::FsSnapshot, "path not found"))?;
+    Ok(FsTreeStats::of_entry(entry))
+}
+
+/// Most symlinks followed for one lookup before giving up, as with `ELOOP`.
+pub const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Like `get_file_at_path`, but a symlink at the final component is
+/// resolved to its target within the same snapshot. Returns the target's
+/// content, its entry, and the path it resolved to.
+pub fn get_file_following_symlinks(
+    blob_store: &mut BlobStore,
+    cache: &mut TreeCache,
+    root_hash: &[u8; 32],
+    path: &str,
+) -> Result<(Vec<u8>, TreeEntry, String)> {
+    let mut path = path.to_string();
+    for _ in 0..=MAX_SYMLINK_HOPS {
+        let (content, entry) = get_file_at_path(blob_store, cache, root_hash, &path)?;
+        if entry.kind_enum() != EntryKind::Symlink {
+            return Ok((content, entry, path));
+        }
+        let target = String::from_utf8(content)
+            .map_err(|_| StoreError::Corrupt(format!("symlink target is not utf-8: {path}")))?;
+        path = resolve_symlink_target(&path, &target)?;
+    }
+    Err(StoreError::InvalidInput(format!(
+        "too many levels of symbolic links: {path}"
+    )))
+}
+
+/// Join a relative symlink target onto the directory holding the link.
+/// Absolute targets and targets that climb above the snapshot root are
+/// rejected rather than resolved against the host filesystem.
+fn resolve_symlink_target(link_path: &str, target: &str) -> Result<String> {
+    if target.starts_with('/') {
+        return Err(StoreError::InvalidInput(format!(
+            "symlink target is absolute: {target}"
+        )));
+    }
+    let mut parts: Vec<&str> = link_path
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    parts.pop();
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(StoreError::InvalidInput(format!(
+                        "symlink target escapes snapshot: {target}"
+                    )));
+                }
+            }
+            name => parts.push(name),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -514,4 +725,138 @@ mod tests {
         // Last write wins
         assert_eq!(index.get(1), Some(hash2));
     }
+
+    fn put_tree(store: &mut BlobStore, entries: &[(&str, EntryKind, [u8; 32], u64)]) -> [u8; 32] {
+        let tree = Value::Array(
+            entries
+                .iter()
+                .map(|(name, kind, hash, size)| {
+                    Value::Map(vec![
+                        (Value::from(1), Value::from(*name)),
+                        (Value::from(2), Value::from(*kind as u8)),
+                        (Value::from(3), Value::from(0o644)),
+                        (Value::from(4), Value::from(*size)),
+                        (Value::from(5), Value::Binary(hash.to_vec())),
+                    ])
+                })
+                .collect(),
+        );
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &tree).unwrap();
+        let hash = *blake3::hash(&bytes).as_bytes();
+        store.put_if_absent(hash, &bytes).unwrap();
+        hash
+    }
+
+    #[test]
+    fn tree_stats_count_shared_subtrees_per_occurrence() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = BlobStore::open(tmpdir.path()).unwrap();
+        let file = [0x11u8; 32];
+        let link = [0x22u8; 32];
+
+        // lib/ holds two files and a symlink and appears three times: as
+        // src/lib, src/deep/lib, and vendor/lib.
+        let lib = put_tree(
+            &mut store,
+            &[
+                ("a.rs", EntryKind::File, file, 10),
+                ("b.rs", EntryKind::File, file, 32),
+                ("cur", EntryKind::Symlink, link, 4),
+            ],
+        );
+        let deep = put_tree(&mut store, &[("lib", EntryKind::Directory, lib, 0)]);
+        let src = put_tree(
+            &mut store,
+            &[
+                ("lib", EntryKind::Directory, lib, 0),
+                ("deep", EntryKind::Directory, deep, 0),
+            ],
+        );
+        let vendor = put_tree(&mut store, &[("lib", EntryKind::Directory, lib, 0)]);
+        let root = put_tree(
+            &mut store,
+            &[
+                ("README", EntryKind::File, file, 100),
+                ("src", EntryKind::Directory, src, 0),
+                ("vendor", EntryKind::Directory, vendor, 0),
+            ],
+        );
+
+        let mut cache = TreeCache::new(0);
+        let stats = stat_path(&mut store, &mut cache, &root, "").unwrap();
+        assert_eq!(
+            stats,
+            FsTreeStats {
+                files: 1 + 3 * 2,
+                dirs: 2 + 1 + 3,
+                symlinks: 3,
+                total_bytes: 100 + 3 * 42,
+                // src/deep/lib/a.rs
+                max_depth: 4,
+            }
+        );
+
+        let stats = stat_path(&mut store, &mut cache, &root, "src/lib").unwrap();
+        assert_eq!((stats.files, stats.max_depth), (2, 1));
+        let stats = stat_path(&mut store, &mut cache, &root, "README").unwrap();
+        assert_eq!((stats.files, stats.total_bytes), (1, 100));
+
+        let empty = put_tree(&mut store, &[]);
+        assert_eq!(
+            tree_stats(&mut store, &mut cache, &empty, &mut HashMap::new()).unwrap(),
+            FsTreeStats::default()
+        );
+    }
+
+    #[test]
+    fn tree_cache_serves_repeat_lookups_without_reading_the_pack() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = BlobStore::open(tmpdir.path()).unwrap();
+        let file = [0x11u8; 32];
+        let a = put_tree(&mut store, &[("x", EntryKind::File, file, 1)]);
+        let b = put_tree(&mut store, &[("y", EntryKind::File, file, 2)]);
+        let root = put_tree(
+            &mut store,
+            &[
+                ("a", EntryKind::Directory, a, 0),
+                ("b", EntryKind::Directory, b, 0),
+            ],
+        );
+
+        let mut cache = TreeCache::new(2);
+        let (hash, _) = resolve_path(&mut store, &mut cache, &root, "a").unwrap();
+        cache.load(&mut store, &hash).unwrap();
+        let reads = store.pack_reads();
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+
+        // Listing `a` again is served from the cache.
+        let (hash, _) = resolve_path(&mut store, &mut cache, &root, "a").unwrap();
+        assert_eq!(cache.load(&mut store, &hash).unwrap()[0].name, "x");
+        assert_eq!(store.pack_reads(), reads);
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+        // Loading `b` evicts root, the least recently used tree; reading
+        // root back in then evicts `a`.
+        cache.load(&mut store, &b).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(store.pack_reads(), reads + 1);
+        let (hash, _) = resolve_path(&mut store, &mut cache, &root, "a").unwrap();
+        cache.load(&mut store, &hash).unwrap();
+        assert_eq!(store.pack_reads(), reads + 3);
+    }
+
+    #[test]
+    fn symlink_targets_resolve_relative_to_the_link() {
+        assert_eq!(
+            resolve_symlink_target("src/lib/current", "../v2/main.rs").unwrap(),
+            "src/v2/main.rs"
+        );
+        assert_eq!(
+            resolve_symlink_target("link", "./dir/file").unwrap(),
+            "dir/file"
+        );
+        assert!(resolve_symlink_target("link", "/etc/passwd").is_err());
+        assert!(resolve_symlink_target("a/link", "../../outside").is_err());
+    }
 }
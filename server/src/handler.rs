@@ -0,0 +1,1301 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Binary protocol connection handler.
+//!
+//! [`handle_client`] serves one connection until EOF or an admin
+//! disconnect. It is generic over the stream so the same logic runs over TCP,
+//! Unix sockets, or an in-memory duplex in tests.
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use byteorder::WriteBytesExt;
+
+use crate::error::{catch_panic, new_correlation_id, Result, StoreError};
+use crate::events::{EventBus, StoreEvent};
+use crate::listener::ClientStream;
+use crate::lock::lock_or_recover;
+use crate::metrics::{Metrics, SessionTracker};
+use crate::protocol::{
+    encode_append_ack, encode_attach_fs_resp, encode_ctx_create_resp, encode_error,
+    encode_get_head_resp, encode_get_last_batch_resp, encode_hello_resp, encode_put_blob_resp,
+    encode_turns_with, map_store_error, negotiate_protocol_version, parse_append_turn,
+    parse_attach_fs, parse_ctx_create_request, parse_ctx_fork, parse_get_blob, parse_get_head,
+    parse_get_last, parse_get_last_batch, parse_hello, parse_put_blob, read_frame, write_frame,
+    MsgType, ServerCapabilities, FLAG_GET_LAST_NO_HASH, FLAG_GET_LAST_VARINT,
+    MAX_GET_LAST_BATCH_TURNS, MIN_PROTOCOL_VERSION,
+};
+use crate::store::{payload_correlation_id, Store};
+
+/// Serve binary protocol frames from `stream` until the peer disconnects.
+pub fn handle_client<S: ClientStream>(
+    mut stream: S,
+    store: Arc<Mutex<Store>>,
+    metrics: Arc<Metrics>,
+    session_tracker: Arc<SessionTracker>,
+    event_bus: Arc<EventBus>,
+    capabilities: ServerCapabilities,
+    peer_addr: String,
+) -> Result<()> {
+    let session = metrics.register_session();
+    let session_id = session.session_id();
+    // Client tag will be set when HELLO is received
+    let mut client_tag_received = false;
+    let mut client_tag = String::new();
+    // Connections that skip HELLO speak version 1.
+    let mut protocol_version = MIN_PROTOCOL_VERSION;
+    let mut request_count = 0u64;
+    let mut bytes_in = 0u64;
+    let mut bytes_out = 0u64;
+    let disconnect = session_tracker.attach_control(session_id, stream.interrupter());
+
+    loop {
+        if disconnect.load(Ordering::SeqCst) {
+            break;
+        }
+        let (header, payload) = match read_frame(&mut stream) {
+            Ok(v) => v,
+            Err(StoreError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            // An admin disconnect shuts the socket down under the read.
+            Err(_) if disconnect.load(Ordering::SeqCst) => break,
+            Err(e) => return Err(e),
+        };
+
+        request_count += 1;
+        bytes_in += FRAME_HEADER_LEN + payload.len() as u64;
+        metrics.record_session_activity(session_id);
+        session_tracker.record_activity(session_id);
+        let msg_type = header.msg_type;
+        let req_id = header.req_id;
+
+        let op_start = std::time::Instant::now();
+        let mut resp_flags = 0u16;
+        // A panic in one frame's handling becomes a 500 error frame; the
+        // connection stays open for the next request.
+        let response = catch_panic(|| match msg_type {
+            x if MsgType::from_u16(x).is_some_and(|m| m.min_version() > protocol_version) => {
+                Err(StoreError::InvalidInput(format!(
+                    "{} is not available at protocol version {protocol_version}",
+                    MsgType::op_name(x)
+                )))
+            }
+            x if x == MsgType::Hello as u16 => {
+                let hello = parse_hello(&payload)?;
+                protocol_version = negotiate_protocol_version(&hello)?;
+                // Register session with client tag and peer address
+                if !client_tag_received {
+                    client_tag = session_tracker.register(
+                        session_id,
+                        hello.client_tag.clone(),
+                        Some(peer_addr.clone()),
+                    );
+                    client_tag_received = true;
+
+                    // Publish ClientConnected event
+                    event_bus.publish(StoreEvent::ClientConnected {
+                        session_id: session_id.to_string(),
+                        client_tag: client_tag.clone(),
+                    });
+                }
+                let resp = encode_hello_resp(session_id, protocol_version, &capabilities)?;
+                Ok((MsgType::Hello as u16, resp))
+            }
+            x if x == MsgType::CtxCreate as u16 => {
+                // If no HELLO was sent, register under the peer address
+                if !client_tag_received {
+                    client_tag = session_tracker.register(
+                        session_id,
+                        String::new(),
+                        Some(peer_addr.clone()),
+                    );
+                    client_tag_received = true;
+                }
+                let req = parse_ctx_create_request(&payload)?;
+                let has_overlay = !req.metadata.is_empty();
+                let mut store = lock_or_recover(&store, "store");
+                let head = store.create_context_with_metadata(req.base_turn_id, req.metadata)?;
+                // Associate context with this session
+                session_tracker.add_context(session_id, head.context_id);
+
+                // Publish ContextCreated event
+                event_bus.publish(StoreEvent::ContextCreated {
+                    context_id: head.context_id.to_string(),
+                    session_id: session_id.to_string(),
+                    client_tag: client_tag.clone(),
+                    created_at: unix_ms(),
+                });
+                if has_overlay {
+                    if let Some(meta) = store.get_context_metadata(head.context_id) {
+                        event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                            context_id: head.context_id.to_string(),
+                            client_tag: meta.client_tag,
+                            title: meta.title,
+                            labels: meta.labels,
+                            has_provenance: meta.provenance.is_some(),
+                        });
+                    }
+                }
+
+                let resp =
+                    encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+                Ok((MsgType::CtxCreate as u16, resp))
+            }
+            x if x == MsgType::CtxFork as u16 => {
+                // If no HELLO was sent, register under the peer address
+                if !client_tag_received {
+                    client_tag = session_tracker.register(
+                        session_id,
+                        String::new(),
+                        Some(peer_addr.clone()),
+                    );
+                    client_tag_received = true;
+                }
+                let base_turn_id = parse_ctx_fork(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                let head = store.fork_context(base_turn_id)?;
+                // Associate forked context with this session
+                session_tracker.add_context(session_id, head.context_id);
+
+                // Publish ContextCreated event for forked context
+                event_bus.publish(StoreEvent::ContextCreated {
+                    context_id: head.context_id.to_string(),
+                    session_id: session_id.to_string(),
+                    client_tag: client_tag.clone(),
+                    created_at: unix_ms(),
+                });
+
+                let resp =
+                    encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+                Ok((MsgType::CtxFork as u16, resp))
+            }
+            x if x == MsgType::GetHead as u16 => {
+                let context_id = parse_get_head(&payload)?;
+                let store = lock_or_recover(&store, "store");
+                let head = store.get_head(context_id)?;
+                let resp = encode_get_head_resp(&head)?;
+                Ok((MsgType::GetHead as u16, resp))
+            }
+            x if x == MsgType::AppendTurn as u16 => {
+                let req = parse_append_turn(&payload, header.flags)?;
+                let declared_type_id_clone = req.declared_type_id.clone();
+                let declared_type_version = req.declared_type_version;
+                let mut store = lock_or_recover(&store, "store");
+                if !req.idempotency_key.is_empty() {
+                    // A retry of an append that already went through gets
+                    // the original turn back, not a second copy.
+                    if let Some(record) = store.idempotent_turn(
+                        req.context_id,
+                        &req.idempotency_key,
+                        &req.content_hash,
+                    )? {
+                        metrics.record_append(op_start.elapsed());
+                        let head_turn_id = store.get_head(req.context_id)?.head_turn_id;
+                        let resp = encode_append_ack(
+                            protocol_version,
+                            req.context_id,
+                            record.turn_id,
+                            record.depth,
+                            &record.payload_hash,
+                            head_turn_id,
+                        )?;
+                        return Ok((MsgType::AppendTurn as u16, resp));
+                    }
+                }
+                if let Some(expected) = req.expected_head_turn_id {
+                    store.check_head(req.context_id, expected)?;
+                }
+                let (record, metadata) = store.append_turn(
+                    req.context_id,
+                    req.parent_turn_id,
+                    req.declared_type_id,
+                    req.declared_type_version,
+                    req.encoding,
+                    req.compression,
+                    req.uncompressed_len,
+                    req.content_hash,
+                    &req.payload_bytes,
+                )?;
+                // If fs_root_hash was provided, attach it to this turn
+                if let Some(fs_root_hash) = req.fs_root_hash {
+                    store.attach_fs(record.turn_id, fs_root_hash)?;
+                }
+                if !req.idempotency_key.is_empty() {
+                    store.record_idempotency_key(
+                        req.context_id,
+                        req.idempotency_key,
+                        req.content_hash,
+                        record.turn_id,
+                    )?;
+                }
+                metrics.record_append(op_start.elapsed());
+
+                // Publish TurnAppended event
+                event_bus.publish(StoreEvent::TurnAppended {
+                    context_id: req.context_id.to_string(),
+                    turn_id: record.turn_id.to_string(),
+                    parent_turn_id: record.parent_turn_id.to_string(),
+                    depth: record.depth,
+                    declared_type_id: Some(declared_type_id_clone),
+                    declared_type_version: Some(declared_type_version),
+                });
+
+                // If metadata was extracted (first turn), publish ContextMetadataUpdated
+                if let Some(meta) = metadata {
+                    event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                        context_id: req.context_id.to_string(),
+                        client_tag: meta.client_tag,
+                        title: meta.title,
+                        labels: meta.labels,
+                        has_provenance: meta.provenance.is_some(),
+                    });
+
+                    if let Some(prov) = meta.provenance {
+                        if let Some(parent_context_id) = prov.parent_context_id {
+                            event_bus.publish(StoreEvent::ContextLinked {
+                                child_context_id: req.context_id.to_string(),
+                                parent_context_id: parent_context_id.to_string(),
+                                root_context_id: prov.root_context_id.map(|v| v.to_string()),
+                                spawn_reason: prov.spawn_reason,
+                            });
+                        }
+                    }
+                }
+
+                let head_turn_id = store.get_head(req.context_id)?.head_turn_id;
+                let resp = encode_append_ack(
+                    protocol_version,
+                    req.context_id,
+                    record.turn_id,
+                    record.depth,
+                    &record.payload_hash,
+                    head_turn_id,
+                )?;
+                Ok((MsgType::AppendTurn as u16, resp))
+            }
+            x if x == MsgType::AttachFs as u16 => {
+                let req = parse_attach_fs(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                store.attach_fs(req.turn_id, req.fs_root_hash)?;
+                let resp = encode_attach_fs_resp(req.turn_id, &req.fs_root_hash)?;
+                Ok((MsgType::AttachFs as u16, resp))
+            }
+            x if x == MsgType::PutBlob as u16 => {
+                let req = parse_put_blob(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                // Verify hash matches
+                let actual_hash = blake3::hash(&req.data);
+                if actual_hash.as_bytes() != &req.hash {
+                    return Err(StoreError::InvalidInput("blob hash mismatch".into()));
+                }
+                let was_new = !store.blob_store.contains(&req.hash);
+                store.blob_store.put_if_absent(req.hash, &req.data)?;
+                let resp = encode_put_blob_resp(&req.hash, was_new)?;
+                Ok((MsgType::PutBlob as u16, resp))
+            }
+            x if x == MsgType::GetLast as u16 => {
+                let req = parse_get_last(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                let items = store.get_last(req.context_id, req.limit, req.include_payload != 0)?;
+                metrics.record_get_last(op_start.elapsed());
+                // Version 1 clients never ask for either; ignore stray bits.
+                if protocol_version >= 2 {
+                    if !req.include_hash {
+                        resp_flags |= FLAG_GET_LAST_NO_HASH;
+                    }
+                    resp_flags |= header.flags & FLAG_GET_LAST_VARINT;
+                }
+                let resp = encode_turns_with(&items, resp_flags)?;
+                Ok((MsgType::GetLast as u16, resp))
+            }
+            x if x == MsgType::GetLastBatch as u16 => {
+                let req = parse_get_last_batch(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                let groups = store.get_last_batch(
+                    &req.entries,
+                    req.include_payload != 0,
+                    MAX_GET_LAST_BATCH_TURNS,
+                )?;
+                metrics.record_get_last(op_start.elapsed());
+                let resp = encode_get_last_batch_resp(&groups)?;
+                Ok((MsgType::GetLastBatch as u16, resp))
+            }
+            x if x == MsgType::GetBlob as u16 => {
+                let hash = parse_get_blob(&payload)?;
+                let mut store = lock_or_recover(&store, "store");
+                let bytes = store.get_blob(&hash)?;
+                metrics.record_get_blob(op_start.elapsed());
+                let mut resp = Vec::new();
+                resp.write_u32::<byteorder::LittleEndian>(bytes.len() as u32)?;
+                resp.extend_from_slice(&bytes);
+                Ok((MsgType::GetBlob as u16, resp))
+            }
+            #[cfg(test)]
+            x if x == tests::PANIC_MSG_TYPE => panic!("test hook"),
+            _ => Err(StoreError::InvalidInput("unknown msg_type".into())),
+        });
+
+        let resp_len = match response {
+            Ok((resp_type, resp_payload)) => {
+                write_frame(&mut stream, resp_type, resp_flags, req_id, &resp_payload)?;
+                stream.flush()?;
+                resp_payload.len()
+            }
+            Err(err) => {
+                let (code, detail) = map_store_error(&err);
+                let mut correlation_id = append_correlation_id(msg_type, header.flags, &payload);
+                if matches!(err, StoreError::Panicked(_)) {
+                    correlation_id.get_or_insert_with(new_correlation_id);
+                    tracing::error!(
+                        op = MsgType::op_name(msg_type),
+                        correlation_id = correlation_id.as_deref(),
+                        "{detail}"
+                    );
+                }
+                let client_message = err.client_message(&detail, correlation_id.as_deref());
+                tracing::debug!(
+                    op = MsgType::op_name(msg_type),
+                    code,
+                    correlation_id = correlation_id.as_deref(),
+                    "binary request failed: {detail}"
+                );
+                metrics.record_error_correlated(
+                    "binary",
+                    MsgType::op_name(msg_type),
+                    code as u16,
+                    &detail,
+                    None,
+                    correlation_id.as_deref(),
+                );
+                event_bus.publish(StoreEvent::ErrorOccurred {
+                    timestamp_ms: unix_ms(),
+                    kind: "binary".to_string(),
+                    status_code: code as u16,
+                    message: client_message.clone(),
+                    path: None,
+                    correlation_id,
+                });
+                let payload = encode_error(code, &client_message)?;
+                write_frame(&mut stream, MsgType::Error as u16, 0, req_id, &payload)?;
+                stream.flush()?;
+                payload.len()
+            }
+        };
+        bytes_out += FRAME_HEADER_LEN + resp_len as u64;
+        session_tracker.record_traffic(session_id, request_count, bytes_in, bytes_out);
+    }
+
+    // Unregister session on disconnect and publish event
+    let orphaned_contexts = session_tracker.unregister(session_id);
+    event_bus.publish(StoreEvent::ClientDisconnected {
+        session_id: session_id.to_string(),
+        client_tag,
+        contexts: orphaned_contexts.iter().map(|id| id.to_string()).collect(),
+    });
+
+    Ok(())
+}
+
+/// Bytes of framing ahead of each payload: len, msg_type, flags, req_id.
+const FRAME_HEADER_LEN: u64 = 4 + 2 + 2 + 8;
+
+/// Get current time in milliseconds since Unix epoch.
+/// The provenance correlation id of a failed APPEND_TURN, when its payload
+/// parses and was sent uncompressed. Only looked up on the error path so
+/// successful appends don't pay for a second decode.
+fn append_correlation_id(msg_type: u16, flags: u16, payload: &[u8]) -> Option<String> {
+    if msg_type != MsgType::AppendTurn as u16 {
+        return None;
+    }
+    let req = parse_append_turn(payload, flags).ok()?;
+    if req.compression != 0 {
+        return None;
+    }
+    payload_correlation_id(&req.payload_bytes)
+}
+
+fn unix_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ClientSession;
+    use crate::protocol::{
+        FrameHeader, CAP_COMPRESSION_ZSTD, CAP_CONDITIONAL_APPEND, CAP_GET_LAST_BATCH, CAP_S3_SYNC,
+        MAX_GET_LAST_BATCH, MAX_PROTOCOL_VERSION,
+    };
+    use byteorder::{LittleEndian, ReadBytesExt};
+    use std::io::{Cursor, Read, Write};
+    use tempfile::tempdir;
+
+    /// Message type the handler panics on in tests.
+    pub(super) const PANIC_MSG_TYPE: u16 = u16::MAX;
+
+    /// Scripted duplex: reads come from pre-encoded request frames, writes
+    /// are collected for inspection.
+    struct MemoryStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl ClientStream for MemoryStream {}
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Captures the tracker's sessions when input runs out, i.e. while the
+    /// connection is still registered.
+    struct SnapshotAtEof {
+        stream: MemoryStream,
+        tracker: Arc<SessionTracker>,
+        sessions: Option<Vec<ClientSession>>,
+    }
+
+    impl Read for SnapshotAtEof {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.stream.read(buf)?;
+            if n == 0 && self.sessions.is_none() {
+                self.sessions = Some(self.tracker.get_active_sessions());
+            }
+            Ok(n)
+        }
+    }
+
+    impl ClientStream for SnapshotAtEof {}
+
+    impl Write for SnapshotAtEof {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn append_payload(context_id: u64, data: &[u8]) -> Vec<u8> {
+        let type_id = b"com.example.Note";
+        let mut buf = Vec::new();
+        buf.write_u64::<LittleEndian>(context_id).unwrap();
+        buf.write_u64::<LittleEndian>(0).unwrap();
+        buf.write_u32::<LittleEndian>(type_id.len() as u32).unwrap();
+        buf.extend_from_slice(type_id);
+        buf.write_u32::<LittleEndian>(1).unwrap(); // type version
+        buf.write_u32::<LittleEndian>(1).unwrap(); // msgpack
+        buf.write_u32::<LittleEndian>(0).unwrap(); // uncompressed
+        buf.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        buf.extend_from_slice(blake3::hash(data).as_bytes());
+        buf.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        buf.extend_from_slice(data);
+        buf.write_u32::<LittleEndian>(0).unwrap(); // no idempotency key
+        buf
+    }
+
+    /// Feed `input` frames through `handle_client` and return the response
+    /// frames.
+    fn run_session(store: &Arc<Mutex<Store>>, input: Vec<u8>) -> Vec<(FrameHeader, Vec<u8>)> {
+        run_session_with(store, ServerCapabilities::new(false), input)
+    }
+
+    /// Like [`run_session`], after a HELLO negotiating the current version,
+    /// whose response is dropped so frame indices match `input`.
+    fn run_current_session(
+        store: &Arc<Mutex<Store>>,
+        input: Vec<u8>,
+    ) -> Vec<(FrameHeader, Vec<u8>)> {
+        let mut hello = Vec::new();
+        write_frame(
+            &mut hello,
+            MsgType::Hello as u16,
+            0,
+            0,
+            &hello_payload(MAX_PROTOCOL_VERSION, None),
+        )
+        .unwrap();
+        hello.extend(input);
+        let mut frames = run_session(store, hello);
+        assert_eq!(frames[0].0.msg_type, MsgType::Hello as u16);
+        frames.remove(0);
+        frames
+    }
+
+    fn run_session_with(
+        store: &Arc<Mutex<Store>>,
+        capabilities: ServerCapabilities,
+        input: Vec<u8>,
+    ) -> Vec<(FrameHeader, Vec<u8>)> {
+        let dir = tempdir().expect("tempdir");
+        let mut stream = MemoryStream {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        handle_client(
+            &mut stream,
+            Arc::clone(store),
+            Arc::new(Metrics::new(dir.path().to_path_buf())),
+            Arc::new(SessionTracker::new()),
+            Arc::new(EventBus::new()),
+            capabilities,
+            "memory".to_string(),
+        )
+        .expect("handle client");
+
+        let mut output = Cursor::new(stream.output);
+        let mut frames = Vec::new();
+        while (output.position() as usize) < output.get_ref().len() {
+            frames.push(read_frame(&mut output).expect("frame"));
+        }
+        frames
+    }
+
+    fn hello_payload(max_version: u16, min_version: Option<u16>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u16::<LittleEndian>(max_version).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap(); // no client tag
+        buf.write_u32::<LittleEndian>(0).unwrap(); // no metadata
+        if let Some(min) = min_version {
+            buf.write_u16::<LittleEndian>(min).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn newer_client_negotiates_down_to_server_version() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::Hello as u16,
+            0,
+            1,
+            &hello_payload(MAX_PROTOCOL_VERSION + 1, None),
+        )
+        .unwrap();
+        let frames = run_session(&store, input);
+        assert_eq!(frames[0].0.msg_type, MsgType::Hello as u16);
+        let mut resp = Cursor::new(&frames[0].1);
+        resp.read_u64::<LittleEndian>().unwrap(); // session id
+        assert_eq!(
+            resp.read_u16::<LittleEndian>().unwrap(),
+            MAX_PROTOCOL_VERSION
+        );
+        assert_eq!(MAX_PROTOCOL_VERSION, 2);
+    }
+
+    #[test]
+    fn hello_advertises_configured_capabilities() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        for s3_sync in [false, true] {
+            let mut input = Vec::new();
+            write_frame(
+                &mut input,
+                MsgType::Hello as u16,
+                0,
+                1,
+                &hello_payload(2, None),
+            )
+            .unwrap();
+            let frames = run_session_with(&store, ServerCapabilities::new(s3_sync), input);
+            let mut resp = Cursor::new(&frames[0].1);
+            resp.set_position(10);
+            let flags = resp.read_u32::<LittleEndian>().unwrap();
+            assert_eq!(flags & CAP_S3_SYNC != 0, s3_sync);
+            assert_ne!(flags & CAP_COMPRESSION_ZSTD, 0);
+            assert_ne!(flags & CAP_GET_LAST_BATCH, 0);
+            assert_ne!(flags & CAP_CONDITIONAL_APPEND, 0);
+            assert_eq!(resp.read_u32::<LittleEndian>().unwrap(), 64 * 1024 * 1024);
+            assert_eq!(
+                resp.read_u32::<LittleEndian>().unwrap() as usize,
+                MAX_GET_LAST_BATCH
+            );
+            assert_eq!(
+                resp.read_u32::<LittleEndian>().unwrap() as usize,
+                MAX_GET_LAST_BATCH_TURNS
+            );
+        }
+    }
+
+    #[test]
+    fn version_1_client_gets_the_version_1_wire_format() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::Hello as u16,
+            0,
+            1,
+            &hello_payload(1, None),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            2,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            3,
+            &append_payload(1, b"\xa1a"),
+        )
+        .unwrap();
+        // Version 2 options on GET_LAST are ignored, and GET_LAST_BATCH is
+        // refused.
+        let mut get_last = Vec::new();
+        get_last.write_u64::<LittleEndian>(1).unwrap();
+        get_last.write_u32::<LittleEndian>(10).unwrap();
+        get_last.write_u32::<LittleEndian>(0).unwrap();
+        get_last.write_u32::<LittleEndian>(0).unwrap(); // no hashes
+        write_frame(
+            &mut input,
+            MsgType::GetLast as u16,
+            FLAG_GET_LAST_VARINT,
+            4,
+            &get_last,
+        )
+        .unwrap();
+        let mut batch = Vec::new();
+        batch.write_u32::<LittleEndian>(0).unwrap();
+        batch.write_u32::<LittleEndian>(1).unwrap();
+        batch.write_u64::<LittleEndian>(1).unwrap();
+        batch.write_u32::<LittleEndian>(1).unwrap();
+        write_frame(&mut input, MsgType::GetLastBatch as u16, 0, 5, &batch).unwrap();
+
+        let frames = run_session(&store, input);
+        let (hello, ack, last, batch) = (&frames[0], &frames[2], &frames[3], &frames[4]);
+        assert_eq!(hello.1.len(), 10);
+        assert_eq!(u16::from_le_bytes([hello.1[8], hello.1[9]]), 1);
+        assert_eq!(ack.0.msg_type, MsgType::AppendTurn as u16);
+        assert_eq!(ack.1.len(), 8 + 8 + 4 + 32);
+        assert_eq!(last.0.msg_type, MsgType::GetLast as u16);
+        assert_eq!(last.0.flags, 0);
+        let mut body = Cursor::new(&last.1);
+        assert_eq!(body.read_u32::<LittleEndian>().unwrap(), 1);
+        assert_eq!(batch.0.msg_type, MsgType::Error as u16);
+        let detail = String::from_utf8_lossy(&batch.1[8..]);
+        assert!(detail.contains("protocol version 1"), "{detail}");
+
+        // Connections that skip HELLO get the same.
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            1,
+            &append_payload(1, b"\xa1b"),
+        )
+        .unwrap();
+        let frames = run_session(&store, input);
+        assert_eq!(frames[0].1.len(), 8 + 8 + 4 + 32);
+    }
+
+    #[test]
+    fn client_requiring_unsupported_version_gets_error_frame() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        let hello = hello_payload(3, Some(MAX_PROTOCOL_VERSION + 1));
+        write_frame(&mut input, MsgType::Hello as u16, 0, 1, &hello).unwrap();
+        // The connection stays usable at the default version.
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            2,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        let frames = run_session(&store, input);
+        assert_eq!(frames[0].0.msg_type, MsgType::Error as u16);
+        let detail = String::from_utf8_lossy(&frames[0].1);
+        assert!(detail.contains("unsupported protocol version"), "{detail}");
+        assert_eq!(frames[1].0.msg_type, MsgType::CtxCreate as u16);
+    }
+
+    #[test]
+    fn panicking_request_gets_an_error_frame_and_the_session_continues() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(&mut input, PANIC_MSG_TYPE, 0, 1, &[]).unwrap();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            2,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        let frames = run_current_session(&store, input);
+        assert_eq!(frames.len(), 2);
+
+        let (header, body) = &frames[0];
+        assert_eq!(header.msg_type, MsgType::Error as u16);
+        assert_eq!(header.req_id, 1);
+        assert_eq!(u32::from_le_bytes(body[..4].try_into().unwrap()), 500);
+        let detail = String::from_utf8_lossy(body);
+        assert!(
+            detail.contains("internal error (correlation id "),
+            "{detail}"
+        );
+        assert!(!detail.contains("test hook"), "{detail}");
+
+        assert_eq!(frames[1].0.msg_type, MsgType::CtxCreate as u16);
+        assert_eq!(frames[1].0.req_id, 2);
+    }
+
+    #[test]
+    fn serves_a_session_over_an_in_memory_stream() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let data = b"\x81\xa4text\xa2hi";
+        let mut input = Vec::new();
+        write_frame(&mut input, MsgType::Hello as u16, 0, 1, &[]).unwrap();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            2,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            3,
+            &append_payload(1, data),
+        )
+        .unwrap();
+        let mut get_last = Vec::new();
+        get_last.write_u64::<LittleEndian>(1).unwrap();
+        get_last.write_u32::<LittleEndian>(10).unwrap();
+        get_last.write_u32::<LittleEndian>(1).unwrap();
+        write_frame(&mut input, MsgType::GetLast as u16, 0, 4, &get_last).unwrap();
+
+        let frames = run_session(&store, input);
+        let types: Vec<u16> = frames.iter().map(|(h, _)| h.msg_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                MsgType::Hello as u16,
+                MsgType::CtxCreate as u16,
+                MsgType::AppendTurn as u16,
+                MsgType::GetLast as u16,
+            ]
+        );
+        let req_ids: Vec<u64> = frames.iter().map(|(h, _)| h.req_id).collect();
+        assert_eq!(req_ids, vec![1, 2, 3, 4]);
+
+        let ctx_id = Cursor::new(&frames[1].1)
+            .read_u64::<LittleEndian>()
+            .unwrap();
+        assert_eq!(ctx_id, 1);
+        let mut turns = Cursor::new(&frames[3].1);
+        assert_eq!(turns.read_u32::<LittleEndian>().unwrap(), 1);
+        let body = frames[3].1.as_slice();
+        assert!(body.ends_with(data));
+        let head = lock_or_recover(&store, "store").get_head(1).unwrap();
+        assert_eq!(head.head_turn_id, 1);
+    }
+
+    #[test]
+    fn get_last_without_hashes_sets_response_flag() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            1,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        for (req_id, data) in [(2, &b"\xa1a"[..]), (3, b"\xa1b")] {
+            write_frame(
+                &mut input,
+                MsgType::AppendTurn as u16,
+                0,
+                req_id,
+                &append_payload(1, data),
+            )
+            .unwrap();
+        }
+        for (req_id, include_hash) in [(4, None), (5, Some(0u32)), (6, Some(1))] {
+            let mut get_last = Vec::new();
+            get_last.write_u64::<LittleEndian>(1).unwrap();
+            get_last.write_u32::<LittleEndian>(10).unwrap();
+            get_last.write_u32::<LittleEndian>(1).unwrap();
+            if let Some(v) = include_hash {
+                get_last.write_u32::<LittleEndian>(v).unwrap();
+            }
+            write_frame(&mut input, MsgType::GetLast as u16, 0, req_id, &get_last).unwrap();
+        }
+
+        let frames = run_current_session(&store, input);
+        let (with_hash, without_hash, explicit) = (&frames[3], &frames[4], &frames[5]);
+        assert_eq!(with_hash.0.flags, 0);
+        assert_eq!(explicit.0.flags, 0);
+        assert_eq!(explicit.1, with_hash.1);
+        assert_eq!(without_hash.0.flags, FLAG_GET_LAST_NO_HASH);
+        assert_eq!(without_hash.1.len(), with_hash.1.len() - 2 * 32);
+        assert!(without_hash.1.ends_with(b"\xa1b"));
+    }
+
+    #[test]
+    fn get_last_varint_flag_is_echoed_and_shrinks_the_body() {
+        use crate::protocol::read_uvarint;
+
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            1,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            2,
+            &append_payload(1, b"\xa1a"),
+        )
+        .unwrap();
+        let mut get_last = Vec::new();
+        get_last.write_u64::<LittleEndian>(1).unwrap();
+        get_last.write_u32::<LittleEndian>(10).unwrap();
+        get_last.write_u32::<LittleEndian>(0).unwrap();
+        for (req_id, flags) in [(3, 0), (4, FLAG_GET_LAST_VARINT)] {
+            write_frame(
+                &mut input,
+                MsgType::GetLast as u16,
+                flags,
+                req_id,
+                &get_last,
+            )
+            .unwrap();
+        }
+
+        let frames = run_current_session(&store, input);
+        let (fixed, varint) = (&frames[2], &frames[3]);
+        assert_eq!(fixed.0.flags, 0);
+        assert_eq!(varint.0.flags, FLAG_GET_LAST_VARINT);
+        assert!(varint.1.len() < fixed.1.len());
+
+        let mut fixed_body = Cursor::new(&fixed.1);
+        let mut body = Cursor::new(&varint.1);
+        let count = fixed_body.read_u32::<LittleEndian>().unwrap() as u64;
+        assert_eq!(read_uvarint(&mut body).unwrap(), count);
+        let turn_id = fixed_body.read_u64::<LittleEndian>().unwrap();
+        assert_eq!(read_uvarint(&mut body).unwrap(), turn_id);
+        let parent_turn_id = fixed_body.read_u64::<LittleEndian>().unwrap();
+        assert_eq!(read_uvarint(&mut body).unwrap(), parent_turn_id);
+        let depth = fixed_body.read_u32::<LittleEndian>().unwrap() as u64;
+        assert_eq!(read_uvarint(&mut body).unwrap(), depth);
+    }
+
+    #[test]
+    fn conditional_append_rejects_a_stale_expected_head() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            1,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            2,
+            &append_payload(1, b"\xa1a"),
+        )
+        .unwrap();
+        // Turn 1 is now the head: expecting the empty head (0) is stale.
+        for (req_id, expected) in [(3, 0u64), (4, 1)] {
+            let mut payload = append_payload(1, b"\xa1b");
+            payload.write_u64::<LittleEndian>(expected).unwrap();
+            write_frame(&mut input, MsgType::AppendTurn as u16, 2, req_id, &payload).unwrap();
+        }
+
+        let frames = run_session(&store, input);
+        let (stale, current) = (&frames[2], &frames[3]);
+        assert_eq!(stale.0.msg_type, MsgType::Error as u16);
+        let mut err = Cursor::new(&stale.1);
+        assert_eq!(err.read_u32::<LittleEndian>().unwrap(), 409);
+        let detail = String::from_utf8_lossy(&stale.1[8..]);
+        assert_eq!(detail, "head mismatch: expected 0, actual 1");
+
+        assert_eq!(current.0.msg_type, MsgType::AppendTurn as u16);
+        let head = lock_or_recover(&store, "store").get_head(1).unwrap();
+        assert_eq!((head.head_turn_id, head.turn_count), (2, 2));
+    }
+
+    #[test]
+    fn get_last_batch_over_the_socket() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        for req_id in [1, 2] {
+            write_frame(
+                &mut input,
+                MsgType::CtxCreate as u16,
+                0,
+                req_id,
+                &0u64.to_le_bytes(),
+            )
+            .unwrap();
+        }
+        for (req_id, context_id, data) in
+            [(3, 1, &b"\xa1a"[..]), (4, 1, b"\xa1b"), (5, 2, b"\xa1c")]
+        {
+            write_frame(
+                &mut input,
+                MsgType::AppendTurn as u16,
+                0,
+                req_id,
+                &append_payload(context_id, data),
+            )
+            .unwrap();
+        }
+        let batch = |entries: &[(u64, u32)]| {
+            let mut payload = Vec::new();
+            payload.write_u32::<LittleEndian>(1).unwrap(); // include payload
+            payload
+                .write_u32::<LittleEndian>(entries.len() as u32)
+                .unwrap();
+            for (context_id, limit) in entries {
+                payload.write_u64::<LittleEndian>(*context_id).unwrap();
+                payload.write_u32::<LittleEndian>(*limit).unwrap();
+            }
+            payload
+        };
+        write_frame(
+            &mut input,
+            MsgType::GetLastBatch as u16,
+            0,
+            6,
+            &batch(&[(2, 10), (1, 1)]),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            MsgType::GetLastBatch as u16,
+            0,
+            7,
+            &batch(&[(1, 1), (99, 1)]),
+        )
+        .unwrap();
+
+        let frames = run_current_session(&store, input);
+        let (header, body) = &frames[5];
+        assert_eq!(header.msg_type, MsgType::GetLastBatch as u16);
+        assert_eq!(header.req_id, 6);
+        let mut resp = Cursor::new(body);
+        assert_eq!(resp.read_u32::<LittleEndian>().unwrap(), 2);
+        let mut groups = Vec::new();
+        for _ in 0..2 {
+            let context_id = resp.read_u64::<LittleEndian>().unwrap();
+            let len = resp.read_u32::<LittleEndian>().unwrap() as usize;
+            let mut group = vec![0u8; len];
+            resp.read_exact(&mut group).unwrap();
+            // Each group is a GET_LAST body: turn count first.
+            let turns = u32::from_le_bytes(group[..4].try_into().unwrap());
+            groups.push((context_id, turns, group));
+        }
+        assert_eq!((groups[0].0, groups[0].1), (2, 1));
+        assert!(groups[0].2.ends_with(b"\xa1c"));
+        assert_eq!((groups[1].0, groups[1].1), (1, 1));
+        assert!(groups[1].2.ends_with(b"\xa1b"));
+
+        // A missing context fails the whole batch; the session carries on.
+        let (header, body) = &frames[6];
+        assert_eq!(header.msg_type, MsgType::Error as u16);
+        assert_eq!(header.req_id, 7);
+        assert_eq!(u32::from_le_bytes(body[..4].try_into().unwrap()), 404);
+    }
+
+    #[test]
+    fn retried_keyed_append_returns_the_original_turn() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let keyed = |context_id: u64, data: &[u8], key: &[u8]| {
+            let mut payload = append_payload(context_id, data);
+            payload.truncate(payload.len() - 4);
+            payload.write_u32::<LittleEndian>(key.len() as u32).unwrap();
+            payload.extend_from_slice(key);
+            payload
+        };
+        let mut input = Vec::new();
+        for req_id in [1, 2] {
+            write_frame(
+                &mut input,
+                MsgType::CtxCreate as u16,
+                0,
+                req_id,
+                &0u64.to_le_bytes(),
+            )
+            .unwrap();
+        }
+        let appends = [
+            keyed(1, b"\xa1a", b"k1"),
+            keyed(1, b"\xa1a", b"k1"),
+            keyed(1, b"\xa1b", b"k2"),
+            keyed(2, b"\xa1a", b"k1"),
+        ];
+        for (i, payload) in appends.iter().enumerate() {
+            write_frame(
+                &mut input,
+                MsgType::AppendTurn as u16,
+                0,
+                3 + i as u64,
+                payload,
+            )
+            .unwrap();
+        }
+
+        let frames = run_session(&store, input);
+        let turn_ids: Vec<u64> = frames[2..]
+            .iter()
+            .map(|(header, body)| {
+                assert_eq!(header.msg_type, MsgType::AppendTurn as u16);
+                let mut ack = Cursor::new(body);
+                ack.read_u64::<LittleEndian>().unwrap();
+                ack.read_u64::<LittleEndian>().unwrap()
+            })
+            .collect();
+        assert_eq!(turn_ids[0], turn_ids[1]);
+        assert_ne!(turn_ids[2], turn_ids[0]);
+        assert_ne!(turn_ids[3], turn_ids[0]);
+
+        {
+            let guard = lock_or_recover(&store, "store");
+            assert_eq!(guard.get_head(1).unwrap().turn_count, 2);
+            assert_eq!(guard.get_head(2).unwrap().turn_count, 1);
+        }
+        // The key survives a restart.
+        drop(store);
+        let reopened = Arc::new(Mutex::new(Store::open(dir.path()).expect("reopen store")));
+        let mut input = Vec::new();
+        write_frame(&mut input, MsgType::AppendTurn as u16, 0, 9, &appends[0]).unwrap();
+        // The same key with a different payload is a conflict, not a replay.
+        let reused = keyed(1, b"\xa1c", b"k1");
+        write_frame(&mut input, MsgType::AppendTurn as u16, 0, 10, &reused).unwrap();
+        let frames = run_session(&reopened, input);
+        let mut ack = Cursor::new(&frames[0].1);
+        ack.read_u64::<LittleEndian>().unwrap();
+        assert_eq!(ack.read_u64::<LittleEndian>().unwrap(), turn_ids[0]);
+        assert_eq!(frames[1].0.msg_type, MsgType::Error as u16);
+        assert_eq!(
+            u32::from_le_bytes(frames[1].1[..4].try_into().unwrap()),
+            409
+        );
+        let head = lock_or_recover(&reopened, "store").get_head(1).unwrap();
+        assert_eq!(head.turn_count, 2);
+    }
+
+    #[test]
+    fn failed_append_records_provenance_correlation_id() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+        let metrics = Arc::new(Metrics::new(dir.path().to_path_buf()));
+
+        // {30: {10: {12: "run-7"}}}: context_metadata.provenance.correlation_id
+        let data = b"\x81\x1e\x81\x0a\x81\x0c\xa5run-7";
+        let mut input = Vec::new();
+        write_frame(
+            &mut input,
+            MsgType::AppendTurn as u16,
+            0,
+            1,
+            &append_payload(5, data),
+        )
+        .unwrap();
+        let mut stream = MemoryStream {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        handle_client(
+            &mut stream,
+            store,
+            Arc::clone(&metrics),
+            Arc::new(SessionTracker::new()),
+            Arc::new(EventBus::new()),
+            ServerCapabilities::new(false),
+            "memory".to_string(),
+        )
+        .expect("handle client");
+
+        let errors = metrics.recent_errors(1);
+        assert_eq!(errors[0].op, "append_turn");
+        assert_eq!(errors[0].status_code, 404);
+        assert_eq!(errors[0].correlation_id.as_deref(), Some("run-7"));
+    }
+
+    #[test]
+    fn session_counts_requests_and_bytes() {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+
+        let mut input = Vec::new();
+        write_frame(&mut input, MsgType::Hello as u16, 0, 1, &[]).unwrap();
+        write_frame(
+            &mut input,
+            MsgType::CtxCreate as u16,
+            0,
+            2,
+            &0u64.to_le_bytes(),
+        )
+        .unwrap();
+        for (req_id, data) in [(3, &b"\xa1a"[..]), (4, b"\xa1b"), (5, b"\xa1c")] {
+            write_frame(
+                &mut input,
+                MsgType::AppendTurn as u16,
+                0,
+                req_id,
+                &append_payload(1, data),
+            )
+            .unwrap();
+        }
+        let bytes_in = input.len() as u64;
+
+        let tracker = Arc::new(SessionTracker::new());
+        let mut stream = SnapshotAtEof {
+            stream: MemoryStream {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            },
+            tracker: Arc::clone(&tracker),
+            sessions: None,
+        };
+        handle_client(
+            &mut stream,
+            Arc::clone(&store),
+            Arc::new(Metrics::new(dir.path().to_path_buf())),
+            Arc::clone(&tracker),
+            Arc::new(EventBus::new()),
+            ServerCapabilities::new(false),
+            "memory".to_string(),
+        )
+        .expect("handle client");
+
+        let sessions = stream.sessions.expect("snapshot");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].request_count, 5);
+        assert_eq!(sessions[0].bytes_in, bytes_in);
+        assert_eq!(sessions[0].bytes_out, stream.stream.output.len() as u64);
+        assert_eq!(
+            lock_or_recover(&store, "store")
+                .get_head(1)
+                .unwrap()
+                .turn_count,
+            3
+        );
+    }
+
+    #[test]
+    fn admin_disconnect_interrupts_a_blocked_read() {
+        use crate::listener::Connection;
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).expect("open store")));
+        let tracker = Arc::new(SessionTracker::new());
+        let event_bus = Arc::new(EventBus::new());
+        let events = event_bus.subscribe();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).expect("connect");
+        let (server_stream, peer) = listener.accept().expect("accept");
+
+        let (done_tx, done_rx) = mpsc::channel();
+        {
+            let metrics = Arc::new(Metrics::new(dir.path().to_path_buf()));
+            let tracker = Arc::clone(&tracker);
+            let event_bus = Arc::clone(&event_bus);
+            std::thread::spawn(move || {
+                let result = handle_client(
+                    Connection::Tcp(server_stream),
+                    store,
+                    metrics,
+                    tracker,
+                    event_bus,
+                    ServerCapabilities::new(false),
+                    peer.to_string(),
+                );
+                let _ = done_tx.send(result.is_ok());
+            });
+        }
+
+        write_frame(&mut client, MsgType::Hello as u16, 0, 1, &[]).unwrap();
+        let (_, resp) = read_frame(&mut client).expect("hello response");
+        let session_id = Cursor::new(&resp).read_u64::<LittleEndian>().unwrap();
+
+        // The handler is now blocked reading the next frame.
+        assert!(!tracker.request_disconnect(session_id + 1));
+        assert!(tracker.request_disconnect(session_id));
+        let clean_exit = done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("handler loop exits");
+        assert!(clean_exit);
+        assert!(tracker.get_active_sessions().is_empty());
+        assert!(!tracker.request_disconnect(session_id));
+
+        let disconnected = std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(1)))
+            .find(|e| matches!(e, StoreEvent::ClientDisconnected { .. }));
+        match disconnected {
+            Some(StoreEvent::ClientDisconnected { session_id: id, .. }) => {
+                assert_eq!(id, session_id.to_string())
+            }
+            other => panic!("expected ClientDisconnected, got {other:?}"),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -13,55 +16,352 @@ use serde_json::{json, Map, Value as JsonValue};
 use tiny_http::{Header, Method, Response, Server, StatusCode};
 use url::Url;
 
-use crate::error::{Result, StoreError};
+use crate::context_meta::MetadataOverlay;
+use crate::error::{catch_panic, new_correlation_id, NotFoundKind, Result, StoreError};
 use crate::events::{EventBus, StoreEvent};
 use crate::fs_store::EntryKind;
-use crate::metrics::{Metrics, SessionTracker};
+use crate::js_safe::make_js_safe;
+use crate::lock::lock_or_recover;
+use crate::metrics::{ErrorFilter, Metrics, SessionTracker};
 use crate::projection::{BytesRender, EnumRender, RenderOptions, TimeRender, U64Format};
 use crate::registry::{
-    FieldSpec, ItemsSpec, PutOutcome, Registry, RegistryBundle, RendererSpec, TypeVersionSpec,
+    FieldSpec, ItemsSpec, PutOutcome, Registry, RegistryBundle, RendererFilter, RendererSpec,
+    TypeVersionSpec,
 };
-use crate::store::Store;
+use crate::store::{ContextStats, SearchOptions, SearchOrder, Store, TopContextsBy};
 
 type HttpResponse = (u16, Response<std::io::Cursor<Vec<u8>>>);
 
+/// Which routes an HTTP listener answers; anything else is a 404.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HttpRoutes {
+    All,
+    Data,
+    Admin,
+}
+
+impl HttpRoutes {
+    fn serves(self, segments: &[&str]) -> bool {
+        if segments == ["healthz"] {
+            return true;
+        }
+        let admin = matches!(
+            segments,
+            ["v1", "metrics", ..] | ["v1", "errors"] | ["v1", "admin", ..]
+        );
+        match self {
+            HttpRoutes::All => true,
+            HttpRoutes::Data => !admin,
+            HttpRoutes::Admin => admin,
+        }
+    }
+}
+
+/// HTTP gateway settings that don't belong to the store or registry.
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// Extension (lowercase, no dot) to Content-Type for fs file serving,
+    /// consulted before the built-in table.
+    pub mime_overrides: HashMap<String, String>,
+    /// Render options for typed turn reads; each query param overrides its
+    /// own field.
+    pub render_defaults: RenderOptions,
+}
+
+impl HttpOptions {
+    /// Reads `CXDB_MIME_OVERRIDES`, the path of a JSON object mapping
+    /// extensions to MIME types, e.g. `{"mjs": "text/javascript"}`.
+    pub fn from_env() -> Result<Self> {
+        let mime_overrides = match std::env::var("CXDB_MIME_OVERRIDES") {
+            Ok(path) if !path.trim().is_empty() => load_mime_overrides(Path::new(&path))?,
+            _ => HashMap::new(),
+        };
+        Ok(Self {
+            mime_overrides,
+            ..Self::default()
+        })
+    }
+}
+
+/// Typed-view render options from the query, falling back to `defaults`
+/// for params that are absent or unrecognized.
+fn render_options(params: &HashMap<String, String>, defaults: &RenderOptions) -> RenderOptions {
+    let param = |name: &str| params.get(name).map(|v| v.as_str());
+    RenderOptions {
+        bytes_render: param("bytes_render")
+            .and_then(BytesRender::parse)
+            .unwrap_or(defaults.bytes_render),
+        u64_format: param("u64_format")
+            .and_then(U64Format::parse)
+            .unwrap_or(defaults.u64_format),
+        enum_render: param("enum_render")
+            .and_then(EnumRender::parse)
+            .unwrap_or(defaults.enum_render),
+        time_render: param("time_render")
+            .and_then(TimeRender::parse)
+            .unwrap_or(defaults.time_render),
+        include_unknown: param("include_unknown")
+            .map(|v| v == "1")
+            .unwrap_or(defaults.include_unknown),
+        // A request may project less deeply than configured, never deeper:
+        // each level costs a registry lookup and a nested map.
+        max_depth: param("max_depth")
+            .and_then(|v| v.parse::<usize>().ok())
+            .map_or(defaults.max_depth, |depth| depth.min(defaults.max_depth)),
+        strict: param("strict").map(|v| v == "1").unwrap_or(defaults.strict),
+        bundle_id: defaults.bundle_id.clone(),
+    }
+}
+
+/// Parse a MIME override file. Keys may be written with or without the
+/// leading dot and in any case.
+pub fn load_mime_overrides(path: &Path) -> Result<HashMap<String, String>> {
+    let bytes = std::fs::read(path)?;
+    let raw: HashMap<String, String> = serde_json::from_slice(&bytes).map_err(|e| {
+        StoreError::InvalidInput(format!("invalid MIME overrides {}: {e}", path.display()))
+    })?;
+    Ok(raw
+        .into_iter()
+        .map(|(ext, mime)| (ext.trim_start_matches('.').to_lowercase(), mime))
+        .collect())
+}
+
+/// Starts the HTTP gateway on `bind_addr`. With `admin_bind_addr` set, a
+/// second listener there serves the metrics/errors/admin routes and the main
+/// listener stops serving them, so the two ports can be firewalled separately.
 pub fn start_http(
     bind_addr: String,
+    admin_bind_addr: Option<String>,
+    store: Arc<Mutex<Store>>,
+    registry: Arc<Mutex<Registry>>,
+    metrics: Arc<Metrics>,
+    session_tracker: Arc<SessionTracker>,
+    event_bus: Arc<EventBus>,
+) -> Result<HttpServer> {
+    start_http_with_options(
+        bind_addr,
+        admin_bind_addr,
+        store,
+        registry,
+        metrics,
+        session_tracker,
+        event_bus,
+        HttpOptions::default(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn start_http_with_options(
+    bind_addr: String,
+    admin_bind_addr: Option<String>,
+    store: Arc<Mutex<Store>>,
+    registry: Arc<Mutex<Registry>>,
+    metrics: Arc<Metrics>,
+    session_tracker: Arc<SessionTracker>,
+    event_bus: Arc<EventBus>,
+    options: HttpOptions,
+) -> Result<HttpServer> {
+    let options = Arc::new(options);
+    let mut listeners = Vec::new();
+    let main_routes = match admin_bind_addr {
+        Some(admin_addr) => {
+            listeners.push(spawn_http(
+                &admin_addr,
+                HttpRoutes::Admin,
+                Arc::clone(&store),
+                Arc::clone(&registry),
+                Arc::clone(&metrics),
+                Arc::clone(&session_tracker),
+                Arc::clone(&event_bus),
+                Arc::clone(&options),
+            )?);
+            HttpRoutes::Data
+        }
+        None => HttpRoutes::All,
+    };
+    listeners.push(spawn_http(
+        &bind_addr,
+        main_routes,
+        Arc::clone(&store),
+        Arc::clone(&registry),
+        Arc::clone(&metrics),
+        session_tracker,
+        event_bus,
+        options,
+    )?);
+    let stop = Arc::new(AtomicBool::new(false));
+    let ticker = spawn_metrics_ticker(store, registry, metrics, Arc::clone(&stop));
+    Ok(HttpServer {
+        listeners,
+        ticker,
+        stop,
+    })
+}
+
+/// A running HTTP gateway. Dropping the handle leaves it serving for the
+/// life of the process; [`HttpServer::shutdown`] stops it.
+pub struct HttpServer {
+    listeners: Vec<(Arc<Server>, thread::JoinHandle<()>)>,
+    ticker: thread::JoinHandle<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl HttpServer {
+    /// Close the listeners and stop the metrics ticker, waiting for requests
+    /// in flight on the listener threads to finish.
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.ticker.thread().unpark();
+        for (server, _) in &self.listeners {
+            server.unblock();
+        }
+        for (_, handle) in self.listeners {
+            let _ = handle.join();
+        }
+        let _ = self.ticker.join();
+    }
+}
+
+/// Computes one metrics snapshot per tick and fans it out to every
+/// `/v1/metrics/stream` subscriber. Ticks with no subscribers are skipped so
+/// an idle dashboard costs nothing. Runs until `stop` is set.
+fn spawn_metrics_ticker(
+    store: Arc<Mutex<Store>>,
+    registry: Arc<Mutex<Registry>>,
+    metrics: Arc<Metrics>,
+    stop: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    let interval = metrics.stream_interval();
+    thread::spawn(move || loop {
+        // Parked rather than slept so `shutdown` can wake it early.
+        let deadline = Instant::now() + interval;
+        while !stop.load(Ordering::SeqCst) && Instant::now() < deadline {
+            thread::park_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        if metrics.stream_subscriber_count() == 0 {
+            continue;
+        }
+        let snapshot = {
+            let mut store = lock_or_recover(&store, "store");
+            let registry = lock_or_recover(&registry, "registry");
+            metrics.snapshot(&mut store, &registry)
+        };
+        match snapshot
+            .to_json()
+            .and_then(|json| serde_json::to_string(&json))
+        {
+            Ok(json) => metrics.publish_stream(Arc::from(json)),
+            Err(err) => tracing::error!("metrics stream encode error: {err}"),
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_http(
+    bind_addr: &str,
+    routes: HttpRoutes,
     store: Arc<Mutex<Store>>,
     registry: Arc<Mutex<Registry>>,
     metrics: Arc<Metrics>,
     session_tracker: Arc<SessionTracker>,
     event_bus: Arc<EventBus>,
-) -> Result<thread::JoinHandle<()>> {
-    let server = Server::http(&bind_addr)
-        .map_err(|e| StoreError::InvalidInput(format!("http bind error: {e}")))?;
+    options: Arc<HttpOptions>,
+) -> Result<(Arc<Server>, thread::JoinHandle<()>)> {
+    let server = Arc::new(
+        Server::http(bind_addr)
+            .map_err(|e| StoreError::InvalidInput(format!("http bind error: {e}")))?,
+    );
+    let listener = Arc::clone(&server);
     let handle = thread::spawn(move || {
-        for request in server.incoming_requests() {
+        for request in listener.incoming_requests() {
             if let Err(err) = handle_request(
                 request,
+                routes,
                 &store,
                 &registry,
                 &metrics,
                 &session_tracker,
                 &event_bus,
+                &options,
             ) {
-                eprintln!("http error: {err}");
+                tracing::error!("http error: {err}");
             }
         }
     });
-    Ok(handle)
+    Ok((server, handle))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_request(
     mut request: tiny_http::Request,
+    routes: HttpRoutes,
     store: &Arc<Mutex<Store>>,
     registry: &Arc<Mutex<Registry>>,
     metrics: &Arc<Metrics>,
     session_tracker: &Arc<SessionTracker>,
     event_bus: &Arc<EventBus>,
+    options: &HttpOptions,
 ) -> Result<()> {
     let start = Instant::now();
     let request_path = request.url().to_string();
+    // Per-operation label for error counts, e.g. "GET /v1/contexts/:id/turns",
+    // set by whichever router arm matches. Requests that match none stay
+    // "unmatched" so the label set is bounded whatever paths clients send.
+    let route = Cell::new("unmatched");
+    let correlation_id = extract_correlation_id(&request);
+    let format = BodyFormat::from_request(&request);
+
+    let respond_error = |request: tiny_http::Request, err: StoreError| -> Result<()> {
+        let (status, message) = map_error(&err);
+        let mut correlation_id = correlation_id.clone();
+        if matches!(err, StoreError::Panicked(_)) {
+            correlation_id.get_or_insert_with(new_correlation_id);
+            tracing::error!(
+                op = route.get(),
+                correlation_id = correlation_id.as_deref(),
+                "{message}"
+            );
+        }
+        let client_message = err.client_message(&message, correlation_id.as_deref());
+        metrics.record_http(status, start.elapsed());
+        tracing::debug!(
+            op = route.get(),
+            status,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            correlation_id = correlation_id.as_deref(),
+            "http request failed: {message}"
+        );
+        metrics.record_error_correlated(
+            "http",
+            route.get(),
+            status,
+            &message,
+            Some(&request_path),
+            correlation_id.as_deref(),
+        );
+        event_bus.publish(StoreEvent::ErrorOccurred {
+            timestamp_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            kind: "http".to_string(),
+            status_code: status,
+            message: client_message.clone(),
+            path: Some(request_path.clone()),
+            correlation_id: correlation_id.clone(),
+        });
+        let bytes = serde_json::to_vec(&error_body(&err, status, &client_message))
+            .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+        let response = Response::from_data(bytes)
+            .with_status_code(StatusCode(status))
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            );
+        request.respond(response).map_err(StoreError::Io)
+    };
 
     // Check for SSE request early - it needs special handling
     let url_str = format!("http://localhost{}", request.url());
@@ -72,12 +372,40 @@ fn handle_request(
             .unwrap_or_default();
         let segments_ref: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
 
-        if request.method() == &Method::Get && segments_ref.as_slice() == ["v1", "events"] {
-            return handle_sse_stream(request, event_bus);
+        if request.method() == &Method::Get
+            && segments_ref.as_slice() == ["v1", "events"]
+            && routes.serves(&segments_ref)
+        {
+            route.set("GET /v1/events");
+            let params = parse_query(url.query().unwrap_or(""));
+            let follow = match params.get("context_id") {
+                Some(raw) => {
+                    let ids: std::result::Result<HashSet<u64>, _> =
+                        raw.split(',').map(|id| id.trim().parse::<u64>()).collect();
+                    match ids {
+                        Ok(ids) if !ids.is_empty() => Some(ids),
+                        _ => {
+                            let err = StoreError::BadRequest(
+                                "context_id must be a comma-separated list of ids".into(),
+                            );
+                            return respond_error(request, err);
+                        }
+                    }
+                }
+                None => None,
+            };
+            return handle_sse_stream(request, event_bus, follow);
+        }
+        if request.method() == &Method::Get
+            && segments_ref.as_slice() == ["v1", "metrics", "stream"]
+            && routes.serves(&segments_ref)
+        {
+            route.set("GET /v1/metrics/stream");
+            return handle_metrics_stream(request, metrics);
         }
     }
 
-    let result: Result<HttpResponse> = (|| {
+    let result: Result<HttpResponse> = catch_panic(|| {
         let method = request.method().clone();
         let url_str = format!("http://localhost{}", request.url());
         let url =
@@ -87,24 +415,41 @@ fn handle_request(
             .map(|c| c.map(|s| s.to_string()).collect())
             .unwrap_or_default();
         let segments_ref: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        if !routes.serves(&segments_ref) {
+            return Err(StoreError::not_found(NotFoundKind::Route, "route"));
+        }
+        // URL parsing collapses dot segments, so `fs/../x` would quietly route
+        // somewhere else; check the fs path as it was sent instead.
+        if let Some(raw_fs_path) = raw_fs_path(request.url()) {
+            check_fs_path(raw_fs_path).map_err(StoreError::BadRequest)?;
+        }
 
         match (method, segments_ref.as_slice()) {
             // Health check endpoint
-            (Method::Get, ["healthz"]) => Ok((
-                200,
-                Response::from_data(b"ok".to_vec())
-                    .with_status_code(StatusCode(200))
-                    .with_header(
-                        Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
-                    ),
-            )),
-            (Method::Put, ["v1", "registry", "bundles", _bundle_id_raw]) => {
+            (Method::Get, ["healthz"]) => {
+                route.set("GET /healthz");
+                Ok((
+                    200,
+                    Response::from_data(b"ok".to_vec())
+                        .with_status_code(StatusCode(200))
+                        .with_header(
+                            Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
+                        ),
+                ))
+            }
+            (Method::Put, ["v1", "registry", "bundles", path_bundle_id]) => {
+                route.set("PUT /v1/registry/bundles/:id");
                 let mut body = Vec::new();
                 request.as_reader().read_to_end(&mut body)?;
                 let bundle: RegistryBundle = serde_json::from_slice(&body)
                     .map_err(|e| StoreError::InvalidInput(format!("invalid json: {e}")))?;
-                let body_id = bundle.bundle_id.clone();
-                let mut registry = registry.lock().unwrap();
+                // A body without its own bundle_id is stored under the path's.
+                let body_id = if bundle.bundle_id.is_empty() {
+                    path_bundle_id.to_string()
+                } else {
+                    bundle.bundle_id.clone()
+                };
+                let mut registry = lock_or_recover(registry, "registry");
                 match registry.put_bundle(&body_id, &body)? {
                     PutOutcome::AlreadyExists => Ok((
                         204,
@@ -132,11 +477,13 @@ fn handle_request(
                 }
             }
             (Method::Get, ["v1", "registry", "bundles", bundle_id]) => {
-                let registry = registry.lock().unwrap();
-                let bundle = registry
+                route.set("GET /v1/registry/bundles/:id");
+                let registry = lock_or_recover(registry, "registry");
+                let (bundle, hash) = registry
                     .get_bundle(bundle_id)
-                    .ok_or_else(|| StoreError::NotFound("bundle".into()))?;
-                let etag = format!("\"{}\"", blake3::hash(bundle).to_hex());
+                    .zip(registry.bundle_hash(bundle_id))
+                    .ok_or_else(|| StoreError::not_found(NotFoundKind::Bundle, "bundle"))?;
+                let etag = format!("\"{}\"", hash.to_hex());
                 if let Some(header) = request
                     .headers()
                     .iter()
@@ -161,53 +508,102 @@ fn handle_request(
                 ))
             }
             (Method::Get, ["v1", "registry", "types", type_id, "versions", version]) => {
+                route.set("GET /v1/registry/types/:id/versions/:id");
                 let version: u32 = version
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid version".into()))?;
-                let registry = registry.lock().unwrap();
-                let spec = registry
-                    .get_type_version(type_id, version)
-                    .ok_or_else(|| StoreError::NotFound("type version".into()))?;
+                let registry = lock_or_recover(registry, "registry");
+                let spec = registry.get_type_version(type_id, version).ok_or_else(|| {
+                    StoreError::not_found(NotFoundKind::TypeDescriptor, "type version")
+                })?;
                 let json = type_version_to_json(spec);
-                let bytes = serde_json::to_vec(&json)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+                encode_response(200, &json, format)
+            }
+            (Method::Get, ["v1", "registry", "types", type_id, "versions", version, "proto"]) => {
+                route.set("GET /v1/registry/types/:id/versions/:id/proto");
+                let version: u32 = version
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid version".into()))?;
+                let registry = lock_or_recover(registry, "registry");
+                let spec = registry.get_type_version(type_id, version).ok_or_else(|| {
+                    StoreError::not_found(NotFoundKind::TypeDescriptor, "type version")
+                })?;
+                let schema = crate::projection::proto_schema(type_id, spec, &registry);
                 Ok((
                     200,
-                    Response::from_data(bytes)
+                    Response::from_data(schema.into_bytes())
                         .with_status_code(StatusCode(200))
                         .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
+                            Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
                         ),
                 ))
             }
+            (
+                Method::Get,
+                ["v1", "registry", "types", type_id, "versions", version, "jsonschema"],
+            ) => {
+                route.set("GET /v1/registry/types/:id/versions/:id/jsonschema");
+                let version: u32 = version
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid version".into()))?;
+                let registry = lock_or_recover(registry, "registry");
+                let spec = registry.get_type_version(type_id, version).ok_or_else(|| {
+                    StoreError::not_found(NotFoundKind::TypeDescriptor, "type version")
+                })?;
+                let schema = crate::registry::json_schema(type_id, spec, &registry);
+                encode_response(200, &schema, format)
+            }
             (Method::Get, ["v1", "registry", "renderers"]) => {
-                let registry = registry.lock().unwrap();
-                let renderers = registry.get_all_renderers();
+                route.set("GET /v1/registry/renderers");
+                let params = parse_query(url.query().unwrap_or(""));
+                let filter = RendererFilter {
+                    type_prefix: params.get("type_prefix").cloned(),
+                    builtin_only: params
+                        .get("builtin_only")
+                        .map(|v| v == "1")
+                        .unwrap_or(false),
+                };
+                let limit = params
+                    .get("limit")
+                    .map(|v| {
+                        v.parse::<usize>()
+                            .map_err(|_| StoreError::InvalidInput("invalid limit".into()))
+                    })
+                    .transpose()?;
+                let after = params.get("after").map(|s| s.as_str()).unwrap_or("");
+                let registry = lock_or_recover(registry, "registry");
+                let mut renderers: Vec<(String, RendererSpec)> = registry
+                    .get_renderers(&filter)
+                    .into_iter()
+                    .filter(|(type_id, _)| type_id.as_str() > after)
+                    .collect();
+                let mut next_after = None;
+                if let Some(limit) = limit {
+                    if renderers.len() > limit {
+                        renderers.truncate(limit);
+                        next_after = renderers.last().map(|(type_id, _)| type_id.clone());
+                    }
+                }
                 let renderers_json: serde_json::Map<String, JsonValue> = renderers
                     .into_iter()
                     .map(|(type_id, spec)| (type_id, renderer_spec_to_json(&spec)))
                     .collect();
-                let resp = json!({ "renderers": JsonValue::Object(renderers_json) });
-                let bytes = serde_json::to_vec(&resp)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                let resp = json!({
+                    "renderers": JsonValue::Object(renderers_json),
+                    "next_after": next_after,
+                });
+                encode_response(200, &resp, format)
             }
             (Method::Get, ["v1", "contexts"]) => {
+                route.set("GET /v1/contexts");
                 let params = parse_query(url.query().unwrap_or(""));
                 let limit = params
                     .get("limit")
                     .and_then(|v| v.parse::<u32>().ok())
                     .unwrap_or(20);
-                let tag_filter = params.get("tag").cloned();
+                let tag_filter = params
+                    .get("tag")
+                    .map(|tag| session_tracker.normalize_tag(tag));
                 let include_provenance = params
                     .get("include_provenance")
                     .map(|v| v == "1")
@@ -216,9 +612,22 @@ fn handle_request(
                     .get("include_lineage")
                     .map(|v| v == "1")
                     .unwrap_or(false);
+                let include_empty = params
+                    .get("include_empty")
+                    .map(|v| v != "0")
+                    .unwrap_or(true);
 
-                let mut store = store.lock().unwrap();
-                let contexts = store.list_recent_contexts(limit);
+                let mut store = lock_or_recover(store, "store");
+                let contexts = if include_empty {
+                    store.list_recent_contexts(limit)
+                } else {
+                    // Filter before truncating so empty contexts don't eat
+                    // into the limit.
+                    let mut contexts = store.list_recent_contexts(u32::MAX);
+                    contexts.retain(|c| c.head_turn_id != 0);
+                    contexts.truncate(limit as usize);
+                    contexts
+                };
 
                 let contexts_json: Vec<JsonValue> = contexts
                     .iter()
@@ -226,6 +635,7 @@ fn handle_request(
                         let obj = context_to_json(
                             &mut store,
                             session_tracker,
+                            event_bus,
                             c.context_id,
                             include_provenance,
                             include_lineage,
@@ -254,11 +664,15 @@ fn handle_request(
                             "client_tag": s.client_tag,
                             "connected_at": s.connected_at,
                             "last_activity_at": s.last_activity_at,
-                            "context_count": s.contexts_created.len(),
+                            "context_count": s.context_count(),
+                            "request_count": s.request_count,
+                            "bytes_in": s.bytes_in,
+                            "bytes_out": s.bytes_out,
                         });
                         if let Some(ref addr) = s.peer_addr {
                             session_obj["peer_addr"] = JsonValue::String(addr.clone());
                         }
+                        make_js_safe(&mut session_obj);
                         session_obj
                     })
                     .collect();
@@ -273,25 +687,35 @@ fn handle_request(
                     "active_tags": active_tags,
                 });
 
-                let bytes = serde_json::to_vec(&resp)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                encode_response(200, &resp, format)
             }
             (Method::Post, ["v1", "contexts"]) => {
-                let base_turn_id = parse_base_turn_id(&mut request, 0, false)?;
+                route.set("POST /v1/contexts");
+                let body = parse_json_body(&mut request)?;
+                let base_turn_id = base_turn_id_from_body(&body, 0, false)?;
+                let overlay = match body.get("metadata") {
+                    Some(value) => MetadataOverlay::from_json(value)?,
+                    None => MetadataOverlay::default(),
+                };
+                if let Some(bundle_id) = &overlay.bundle_id {
+                    if !lock_or_recover(registry, "registry").has_bundle(bundle_id) {
+                        return Err(StoreError::InvalidInput(format!(
+                            "unknown registry bundle {bundle_id}"
+                        )));
+                    }
+                }
                 let client_tag = extract_http_client_tag(&request);
 
-                let head = {
-                    let mut store = store.lock().unwrap();
-                    store.create_context(base_turn_id)?
+                let (head, metadata) = {
+                    let mut store = lock_or_recover(store, "store");
+                    let has_overlay = !overlay.is_empty();
+                    let head = store.create_context_with_metadata(base_turn_id, overlay)?;
+                    let metadata = if has_overlay {
+                        store.get_context_metadata(head.context_id)
+                    } else {
+                        None
+                    };
+                    (head, metadata)
                 };
 
                 event_bus.publish(StoreEvent::ContextCreated {
@@ -300,6 +724,15 @@ fn handle_request(
                     client_tag,
                     created_at: unix_ms(),
                 });
+                if let Some(meta) = metadata {
+                    event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                        context_id: head.context_id.to_string(),
+                        client_tag: meta.client_tag,
+                        title: meta.title,
+                        labels: meta.labels,
+                        has_provenance: meta.provenance.is_some(),
+                    });
+                }
 
                 let resp = json!({
                     "context_id": head.context_id.to_string(),
@@ -319,12 +752,32 @@ fn handle_request(
                 ))
             }
             (Method::Post, ["v1", "contexts", "create"]) => {
-                let base_turn_id = parse_base_turn_id(&mut request, 0, false)?;
+                route.set("POST /v1/contexts/create");
+                let body = parse_json_body(&mut request)?;
+                let base_turn_id = base_turn_id_from_body(&body, 0, false)?;
+                let overlay = match body.get("metadata") {
+                    Some(value) => MetadataOverlay::from_json(value)?,
+                    None => MetadataOverlay::default(),
+                };
+                if let Some(bundle_id) = &overlay.bundle_id {
+                    if !lock_or_recover(registry, "registry").has_bundle(bundle_id) {
+                        return Err(StoreError::InvalidInput(format!(
+                            "unknown registry bundle {bundle_id}"
+                        )));
+                    }
+                }
                 let client_tag = extract_http_client_tag(&request);
 
-                let head = {
-                    let mut store = store.lock().unwrap();
-                    store.create_context(base_turn_id)?
+                let (head, metadata) = {
+                    let mut store = lock_or_recover(store, "store");
+                    let has_overlay = !overlay.is_empty();
+                    let head = store.create_context_with_metadata(base_turn_id, overlay)?;
+                    let metadata = if has_overlay {
+                        store.get_context_metadata(head.context_id)
+                    } else {
+                        None
+                    };
+                    (head, metadata)
                 };
 
                 event_bus.publish(StoreEvent::ContextCreated {
@@ -333,6 +786,15 @@ fn handle_request(
                     client_tag,
                     created_at: unix_ms(),
                 });
+                if let Some(meta) = metadata {
+                    event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                        context_id: head.context_id.to_string(),
+                        client_tag: meta.client_tag,
+                        title: meta.title,
+                        labels: meta.labels,
+                        has_provenance: meta.provenance.is_some(),
+                    });
+                }
 
                 let resp = json!({
                     "context_id": head.context_id.to_string(),
@@ -352,11 +814,12 @@ fn handle_request(
                 ))
             }
             (Method::Post, ["v1", "contexts", "fork"]) => {
+                route.set("POST /v1/contexts/fork");
                 let base_turn_id = parse_base_turn_id(&mut request, 0, true)?;
                 let client_tag = extract_http_client_tag(&request);
 
                 let head = {
-                    let mut store = store.lock().unwrap();
+                    let mut store = lock_or_recover(store, "store");
                     store.fork_context(base_turn_id)?
                 };
 
@@ -386,6 +849,7 @@ fn handle_request(
             }
             // CQL search endpoint
             (Method::Get, ["v1", "contexts", "search"]) => {
+                route.set("GET /v1/contexts/search");
                 let params = parse_query(url.query().unwrap_or(""));
                 let query = params.get("q").cloned().unwrap_or_default();
                 let limit = params.get("limit").and_then(|v| v.parse::<u32>().ok());
@@ -407,11 +871,33 @@ fn handle_request(
                     ));
                 }
 
+                let order = match params.get("order").map(|v| v.as_str()) {
+                    None | Some("recent") => SearchOrder::Recent,
+                    Some("relevance") => SearchOrder::Relevance,
+                    Some("activity") => {
+                        SearchOrder::Activity(session_tracker.get_live_context_activity())
+                    }
+                    Some(other) => {
+                        return Err(StoreError::InvalidInput(format!(
+                            "invalid order: {other} (expected recent, relevance or activity)"
+                        )))
+                    }
+                };
+
                 // Get live context IDs from session tracker
                 let live_contexts = session_tracker.get_live_context_ids();
 
-                let store = store.lock().unwrap();
-                match store.search_contexts(&query, &live_contexts, limit) {
+                let store = lock_or_recover(store, "store");
+                let search_options = SearchOptions {
+                    order,
+                    explain_matches: params.get("explain_matches").is_some_and(|v| v == "1"),
+                };
+                match store.search_contexts_with_options(
+                    &query,
+                    &live_contexts,
+                    limit,
+                    &search_options,
+                ) {
                     Ok(result) => {
                         // Fetch full context details for matching IDs
                         let contexts_json: Vec<JsonValue> = result
@@ -426,6 +912,7 @@ fn handle_request(
                                     "context_id": context_id.to_string(),
                                     "head_turn_id": head.head_turn_id.to_string(),
                                     "head_depth": head.head_depth,
+                                    "turn_count": head.turn_count,
                                     "created_at_unix_ms": head.created_at_unix_ms,
                                     "is_live": is_live,
                                 });
@@ -441,8 +928,13 @@ fn handle_request(
                                     }
                                     if let Some(ref title) = metadata.title {
                                         obj["title"] = JsonValue::String(title.clone());
+                                    } else if let Some(ref title) = metadata.derived_title {
+                                        obj["derived_title"] = JsonValue::String(title.clone());
                                     }
                                 }
+                                if let Some(matched_on) = result.matched_on.get(&context_id) {
+                                    obj["matched_on"] = JsonValue::Object(matched_on.clone());
+                                }
 
                                 Some(obj)
                             })
@@ -453,23 +945,10 @@ fn handle_request(
                             "total_count": result.total_count,
                             "elapsed_ms": result.elapsed_ms,
                             "query": result.query.raw,
+                            "partial": result.partial,
                         });
 
-                        let bytes = serde_json::to_vec(&resp).map_err(|e| {
-                            StoreError::InvalidInput(format!("json encode error: {e}"))
-                        })?;
-                        Ok((
-                            200,
-                            Response::from_data(bytes)
-                                .with_status_code(StatusCode(200))
-                                .with_header(
-                                    Header::from_bytes(
-                                        &b"Content-Type"[..],
-                                        &b"application/json"[..],
-                                    )
-                                    .unwrap(),
-                                ),
-                        ))
+                        encode_response(200, &resp, format)
                     }
                     Err(cql_error) => {
                         let resp = json!({
@@ -498,6 +977,7 @@ fn handle_request(
             }
             // Get context details
             (Method::Get, ["v1", "contexts", context_id]) => {
+                route.set("GET /v1/contexts/:id");
                 let context_id: u64 = context_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
@@ -511,29 +991,21 @@ fn handle_request(
                     .map(|v| v == "1")
                     .unwrap_or(true);
 
-                let mut store = store.lock().unwrap();
+                let mut store = lock_or_recover(store, "store");
                 let obj = context_to_json(
                     &mut store,
                     session_tracker,
+                    event_bus,
                     context_id,
                     include_provenance,
                     include_lineage,
                 )?;
 
-                let bytes = serde_json::to_vec(&obj)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                encode_response(200, &obj, format)
             }
             // Get children/descendants for a specific context
             (Method::Get, ["v1", "contexts", context_id, "children"]) => {
+                route.set("GET /v1/contexts/:id/children");
                 let context_id: u64 = context_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
@@ -555,7 +1027,7 @@ fn handle_request(
                     .and_then(|v| v.parse::<u32>().ok())
                     .unwrap_or(256);
 
-                let mut store = store.lock().unwrap();
+                let mut store = lock_or_recover(store, "store");
                 // Validate parent context exists
                 store.get_head(context_id)?;
 
@@ -573,6 +1045,7 @@ fn handle_request(
                         context_to_json(
                             &mut store,
                             session_tracker,
+                            event_bus,
                             *child_id,
                             include_provenance,
                             include_lineage,
@@ -588,25 +1061,77 @@ fn handle_request(
                     "children": children,
                 });
 
-                let bytes = serde_json::to_vec(&resp)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                encode_response(200, &resp, format)
+            }
+            // Storage consumed by a single context
+            (Method::Get, ["v1", "contexts", context_id, "stats"]) => {
+                route.set("GET /v1/contexts/:id/stats");
+                let context_id: u64 = context_id
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
+
+                let stats = {
+                    let mut store = lock_or_recover(store, "store");
+                    store.context_stats(context_id)?
+                };
+
+                encode_response(200, &context_stats_to_json(&stats), format)
+            }
+            (Method::Get, ["v1", "contexts", context_id, "digest"]) => {
+                route.set("GET /v1/contexts/:id/digest");
+                let context_id: u64 = context_id
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
+
+                let digest = {
+                    let mut store = lock_or_recover(store, "store");
+                    store.head_digest(context_id)?
+                };
+
+                let resp = json!({
+                    "context_id": digest.context_id.to_string(),
+                    "head_turn_id": digest.head_turn_id.to_string(),
+                    "head_depth": digest.head_depth,
+                    "digest": hex::encode(digest.digest),
+                });
+                encode_response(200, &resp, format)
+            }
+            (Method::Get, ["v1", "contexts", context_id, "branches"]) => {
+                route.set("GET /v1/contexts/:id/branches");
+                let context_id: u64 = context_id
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
+
+                let branches = {
+                    let mut store = lock_or_recover(store, "store");
+                    store.branches(context_id)?
+                };
+                let branches_json: Vec<JsonValue> = branches
+                    .iter()
+                    .map(|b| {
+                        json!({
+                            "tip_turn_id": b.tip_turn_id.to_string(),
+                            "depth": b.depth,
+                            "is_head": b.is_head,
+                            "fork_turn_id": b.fork_turn_id.to_string(),
+                        })
+                    })
+                    .collect();
+                let resp = json!({
+                    "context_id": context_id.to_string(),
+                    "branches": branches_json,
+                });
+
+                encode_response(200, &resp, format)
             }
             // Get provenance for a specific context
             (Method::Get, ["v1", "contexts", context_id, "provenance"]) => {
+                route.set("GET /v1/contexts/:id/provenance");
                 let context_id: u64 = context_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
 
-                let mut store = store.lock().unwrap();
+                let mut store = lock_or_recover(store, "store");
                 store.get_head(context_id)?;
                 let metadata = store.get_context_metadata(context_id);
 
@@ -638,20 +1163,14 @@ fn handle_request(
                     })
                 };
 
-                let bytes = serde_json::to_vec(&resp)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                encode_response(200, &resp, format)
             }
-            (Method::Post, ["v1", "contexts", context_id, "append"])
-            | (Method::Post, ["v1", "contexts", context_id, "turns"]) => {
+            (Method::Post, ["v1", "contexts", context_id, verb @ ("append" | "turns")]) => {
+                route.set(if *verb == "append" {
+                    "POST /v1/contexts/:id/append"
+                } else {
+                    "POST /v1/contexts/:id/turns"
+                });
                 let context_id: u64 = context_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
@@ -660,6 +1179,10 @@ fn handle_request(
                 let type_id = get_required_string(&body, "type_id")?;
                 let type_version = get_required_u32(&body, "type_version")?;
                 let parent_turn_id = get_optional_u64(&body, "parent_turn_id")?.unwrap_or(0);
+                let set_head = get_optional_bool(&body, "set_head")?.unwrap_or(false);
+                let expected_head_turn_id = get_optional_u64(&body, "expected_head_turn_id")?;
+                let idempotency_key =
+                    get_optional_string(&body, "idempotency_key")?.filter(|key| !key.is_empty());
                 let payload_json = body
                     .get("data")
                     .or_else(|| body.get("payload"))
@@ -668,14 +1191,27 @@ fn handle_request(
                     })?;
 
                 let payload_bytes = {
-                    let registry = registry.lock().unwrap();
+                    let registry = lock_or_recover(registry, "registry");
                     encode_http_payload(payload_json, &type_id, type_version, &registry)?
                 };
 
                 let hash = blake3::hash(&payload_bytes);
-                let (record, metadata) = {
-                    let mut store = store.lock().unwrap();
-                    store.append_turn(
+                let (record, metadata, head_turn_id, replayed) = 'append: {
+                    let mut store = lock_or_recover(store, "store");
+                    if let Some(key) = &idempotency_key {
+                        // A retry of an append that already went through gets
+                        // the original turn back, not a second copy.
+                        if let Some(record) =
+                            store.idempotent_turn(context_id, key.as_bytes(), hash.as_bytes())?
+                        {
+                            let head_turn_id = store.get_head(context_id)?.head_turn_id;
+                            break 'append (record, None, head_turn_id, true);
+                        }
+                    }
+                    if let Some(expected) = expected_head_turn_id {
+                        store.check_head(context_id, expected)?;
+                    }
+                    let (record, metadata) = store.append_turn(
                         context_id,
                         parent_turn_id,
                         type_id.clone(),
@@ -685,17 +1221,35 @@ fn handle_request(
                         payload_bytes.len() as u32,
                         *hash.as_bytes(),
                         &payload_bytes,
-                    )?
+                    )?;
+                    if let Some(key) = idempotency_key {
+                        store.record_idempotency_key(
+                            context_id,
+                            key.into_bytes(),
+                            *hash.as_bytes(),
+                            record.turn_id,
+                        )?;
+                    }
+                    // The new turn is either the head already or a fresh
+                    // branch tip, so promoting it cannot fail.
+                    let head_turn_id = if set_head {
+                        store.set_head(context_id, record.turn_id)?.head_turn_id
+                    } else {
+                        store.get_head(context_id)?.head_turn_id
+                    };
+                    (record, metadata, head_turn_id, false)
                 };
 
-                event_bus.publish(StoreEvent::TurnAppended {
-                    context_id: context_id.to_string(),
-                    turn_id: record.turn_id.to_string(),
-                    parent_turn_id: record.parent_turn_id.to_string(),
-                    depth: record.depth,
-                    declared_type_id: Some(type_id.clone()),
-                    declared_type_version: Some(type_version),
-                });
+                if !replayed {
+                    event_bus.publish(StoreEvent::TurnAppended {
+                        context_id: context_id.to_string(),
+                        turn_id: record.turn_id.to_string(),
+                        parent_turn_id: record.parent_turn_id.to_string(),
+                        depth: record.depth,
+                        declared_type_id: Some(type_id.clone()),
+                        declared_type_version: Some(type_version),
+                    });
+                }
 
                 if let Some(meta) = metadata {
                     event_bus.publish(StoreEvent::ContextMetadataUpdated {
@@ -722,7 +1276,9 @@ fn handle_request(
                     "context_id": context_id.to_string(),
                     "turn_id": record.turn_id.to_string(),
                     "depth": record.depth,
-                    "content_hash": hex::encode(hash.as_bytes()),
+                    "content_hash": hex::encode(record.payload_hash),
+                    "head_turn_id": head_turn_id.to_string(),
+                    "branched": head_turn_id != record.turn_id,
                 });
                 let bytes = serde_json::to_vec(&resp)
                     .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
@@ -737,6 +1293,7 @@ fn handle_request(
                 ))
             }
             (Method::Get, ["v1", "contexts", context_id, "turns"]) => {
+                route.set("GET /v1/contexts/:id/turns");
                 let context_id: u64 = context_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid context_id".into()))?;
@@ -749,61 +1306,77 @@ fn handle_request(
                     .get("before_turn_id")
                     .and_then(|v| v.parse::<u64>().ok())
                     .unwrap_or(0);
+                let around_depth = params
+                    .get("around_depth")
+                    .map(|v| {
+                        v.parse::<u32>()
+                            .map_err(|_| StoreError::InvalidInput("invalid around_depth".into()))
+                    })
+                    .transpose()?;
+                let include_inherited = params
+                    .get("include_inherited")
+                    .map(|v| v != "0" && !v.eq_ignore_ascii_case("false"))
+                    .unwrap_or(true);
+                if around_depth.is_some() && before_turn_id != 0 {
+                    return Err(StoreError::InvalidInput(
+                        "around_depth and before_turn_id are mutually exclusive".into(),
+                    ));
+                }
                 let view = params.get("view").map(|v| v.as_str()).unwrap_or("typed");
                 let type_hint_mode = params
                     .get("type_hint_mode")
                     .map(|v| v.as_str())
                     .unwrap_or("inherit");
 
-                let bytes_render = match params.get("bytes_render").map(|v| v.as_str()) {
-                    Some("hex") => BytesRender::Hex,
-                    Some("len_only") => BytesRender::LenOnly,
-                    _ => BytesRender::Base64,
-                };
-                let u64_format = match params.get("u64_format").map(|v| v.as_str()) {
-                    Some("string") => U64Format::String,
-                    _ => U64Format::Number,
-                };
-                let enum_render = match params.get("enum_render").map(|v| v.as_str()) {
-                    Some("number") => EnumRender::Number,
-                    Some("both") => EnumRender::Both,
-                    _ => EnumRender::Label,
-                };
-                let time_render = match params.get("time_render").map(|v| v.as_str()) {
-                    Some("unix_ms") => TimeRender::UnixMs,
-                    _ => TimeRender::Iso,
-                };
-                let include_unknown = params
-                    .get("include_unknown")
-                    .map(|v| v == "1")
-                    .unwrap_or(false);
-
                 let as_type_id = params.get("as_type_id").cloned();
                 let as_type_version = params
                     .get("as_type_version")
                     .and_then(|v| v.parse::<u32>().ok());
 
+                let mut store = lock_or_recover(store, "store");
+                let head = store.get_head(context_id)?;
+                let pinned_bundle_id = params
+                    .get("bundle_id")
+                    .cloned()
+                    .or_else(|| store.pinned_bundle_id(context_id));
                 let options = RenderOptions {
-                    bytes_render,
-                    u64_format,
-                    enum_render,
-                    time_render,
-                    include_unknown,
+                    bundle_id: pinned_bundle_id.clone(),
+                    ..render_options(&params, &options.render_defaults)
                 };
-
-                let mut store = store.lock().unwrap();
-                let head = store.get_head(context_id)?;
                 let t0 = Instant::now();
-                let turns = if before_turn_id == 0 {
+                let turns = if let Some(depth) = around_depth {
+                    store.get_at_depth(context_id, depth, limit, true)?
+                } else if before_turn_id == 0 {
                     store.get_last(context_id, limit, true)?
                 } else {
                     store.get_before(context_id, before_turn_id, limit, true)?
                 };
                 metrics.record_get_last(t0.elapsed());
+                let owners: Vec<u64> = turns
+                    .iter()
+                    .map(|t| store.turn_context(t.record.turn_id).unwrap_or(context_id))
+                    .collect();
+                // Turns above the fork point are this context's own; everything
+                // from the fork base down belongs to an ancestor context.
+                let first_own = if include_inherited {
+                    0
+                } else {
+                    owners
+                        .iter()
+                        .rposition(|owner| *owner != context_id)
+                        .map_or(0, |i| i + 1)
+                };
+                let (turns, owners) = (&turns[first_own..], &owners[first_own..]);
 
-                let registry = registry.lock().unwrap();
+                let registry = lock_or_recover(registry, "registry");
+                if let Some(bundle_id) = &pinned_bundle_id {
+                    if !registry.has_bundle(bundle_id) {
+                        return Err(StoreError::not_found(NotFoundKind::Bundle, "bundle"));
+                    }
+                }
                 let mut out_turns = Vec::new();
-                for item in turns.iter() {
+                let mut type_errors = Vec::new();
+                for (item, owner) in turns.iter().zip(owners) {
                     let declared_type_id = item.meta.declared_type_id.clone();
                     let declared_type_version = item.meta.declared_type_version;
 
@@ -818,9 +1391,26 @@ fn handle_request(
                             (id, ver)
                         }
                         "latest" => {
-                            let latest = registry
-                                .get_latest_type_version(&declared_type_id)
-                                .ok_or_else(|| StoreError::NotFound("type descriptor".into()))?;
+                            // With a pinned bundle, "latest" means the latest that
+                            // bundle defines; types it doesn't define keep their
+                            // declared version.
+                            let latest = match pinned_bundle_id.as_deref() {
+                                Some(bundle_id) => registry
+                                    .get_latest_type_version_in_bundle(bundle_id, &declared_type_id)
+                                    .or_else(|| {
+                                        registry.get_type_version(
+                                            &declared_type_id,
+                                            declared_type_version,
+                                        )
+                                    }),
+                                None => registry.get_latest_type_version(&declared_type_id),
+                            }
+                            .ok_or_else(|| {
+                                StoreError::not_found(
+                                    NotFoundKind::TypeDescriptor,
+                                    "type descriptor",
+                                )
+                            })?;
                             (declared_type_id.clone(), latest.version)
                         }
                         _ => (declared_type_id.clone(), declared_type_version),
@@ -836,6 +1426,7 @@ fn handle_request(
                         JsonValue::String(item.record.parent_turn_id.to_string()),
                     );
                     turn_obj.insert("depth".into(), JsonValue::Number(item.record.depth.into()));
+                    turn_obj.insert("context_id".into(), JsonValue::String(owner.to_string()));
                     turn_obj.insert(
                         "declared_type".into(),
                         json!({
@@ -847,13 +1438,24 @@ fn handle_request(
                     if view == "typed" || view == "both" {
                         let desc = registry
                             .get_type_version(&decoded_type_id, decoded_type_version)
-                            .ok_or_else(|| StoreError::NotFound("type descriptor".into()))?;
+                            .ok_or_else(|| {
+                                StoreError::not_found(
+                                    NotFoundKind::TypeDescriptor,
+                                    "type descriptor",
+                                )
+                            })?;
                         let payload = item
                             .payload
                             .as_ref()
                             .ok_or_else(|| StoreError::InvalidInput("payload not loaded".into()))?;
                         let projected =
                             crate::projection::project_msgpack(payload, desc, &registry, &options)?;
+                        type_errors.extend(
+                            projected
+                                .type_errors
+                                .iter()
+                                .map(|e| format!("turn {}: {e}", item.record.turn_id)),
+                        );
                         turn_obj.insert(
                             "decoded_as".into(),
                             json!({
@@ -885,40 +1487,65 @@ fn handle_request(
                             "uncompressed_len".into(),
                             JsonValue::Number((raw_payload.len() as u32).into()),
                         );
-                        match bytes_render {
-                            BytesRender::Base64 => {
-                                turn_obj.insert(
-                                    "bytes_b64".into(),
-                                    JsonValue::String(
-                                        base64::engine::general_purpose::STANDARD
-                                            .encode(raw_payload),
-                                    ),
-                                );
-                            }
-                            BytesRender::Hex => {
-                                turn_obj.insert(
-                                    "bytes_hex".into(),
-                                    JsonValue::String(hex::encode(raw_payload)),
-                                );
-                            }
-                            BytesRender::LenOnly => {
-                                turn_obj.insert(
-                                    "bytes_len".into(),
-                                    JsonValue::Number((raw_payload.len() as u64).into()),
-                                );
-                            }
-                        }
+                        insert_rendered_bytes(
+                            &mut turn_obj,
+                            "bytes",
+                            raw_payload,
+                            options.bytes_render,
+                        );
+                    }
+
+                    if view == "protobuf" {
+                        let desc = registry
+                            .get_type_version(&decoded_type_id, decoded_type_version)
+                            .ok_or_else(|| {
+                                StoreError::not_found(
+                                    NotFoundKind::TypeDescriptor,
+                                    "type descriptor",
+                                )
+                            })?;
+                        let payload = item
+                            .payload
+                            .as_ref()
+                            .ok_or_else(|| StoreError::InvalidInput("payload not loaded".into()))?;
+                        let message =
+                            crate::projection::project_protobuf(payload, desc, &registry)?;
+                        turn_obj.insert(
+                            "decoded_as".into(),
+                            json!({
+                                "type_id": decoded_type_id,
+                                "type_version": decoded_type_version,
+                            }),
+                        );
+                        insert_rendered_bytes(
+                            &mut turn_obj,
+                            "protobuf",
+                            &message,
+                            options.bytes_render,
+                        );
                     }
 
                     out_turns.push(JsonValue::Object(turn_obj));
                 }
 
-                let next_before = turns.first().map(|t| t.record.turn_id.to_string());
+                if !type_errors.is_empty() {
+                    return Err(StoreError::InvalidInput(format!(
+                        "type mismatches: {}",
+                        type_errors.join("; ")
+                    )));
+                }
+
+                // Nothing older is this context's own once inherited turns were cut.
+                let next_before = if first_own > 0 {
+                    None
+                } else {
+                    turns.first().map(|t| t.record.turn_id.to_string())
+                };
                 let meta = json!({
                     "context_id": context_id.to_string(),
                     "head_turn_id": head.head_turn_id.to_string(),
                     "head_depth": head.head_depth,
-                    "registry_bundle_id": registry.last_bundle_id(),
+                    "registry_bundle_id": pinned_bundle_id.or_else(|| registry.last_bundle_id()),
                 });
 
                 let resp = json!({
@@ -927,6 +1554,58 @@ fn handle_request(
                     "next_before_turn_id": next_before,
                 });
 
+                encode_response(200, &resp, format)
+            }
+            (Method::Post, ["v1", "admin", "sessions", session_id, "disconnect"]) => {
+                route.set("POST /v1/admin/sessions/:id/disconnect");
+                let session_id: u64 = session_id
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid session_id".into()))?;
+                if !session_tracker.request_disconnect(session_id) {
+                    return Err(StoreError::not_found(NotFoundKind::Session, "session"));
+                }
+                let resp = json!({
+                    "session_id": session_id.to_string(),
+                    "disconnecting": true,
+                });
+                let bytes = serde_json::to_vec(&resp)
+                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+                Ok((
+                    202,
+                    Response::from_data(bytes)
+                        .with_status_code(StatusCode(202))
+                        .with_header(
+                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                                .unwrap(),
+                        ),
+                ))
+            }
+            (Method::Get, ["v1", "admin", "top-contexts"]) => {
+                route.set("GET /v1/admin/top-contexts");
+                let params = parse_query(url.query().unwrap_or(""));
+                let by_param = params.get("by").map(String::as_str).unwrap_or("bytes");
+                let by = TopContextsBy::parse(by_param).ok_or_else(|| {
+                    StoreError::InvalidInput(format!(
+                        "invalid by: {by_param} (expected bytes or turns)"
+                    ))
+                })?;
+                let limit: usize = params
+                    .get("limit")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(10)
+                    .min(1000);
+
+                let top = crate::store::top_contexts(store, by, limit);
+
+                let contexts: Vec<JsonValue> =
+                    top.contexts.iter().map(context_stats_to_json).collect();
+                let skipped: Vec<String> = top.skipped.iter().map(|id| id.to_string()).collect();
+                let resp = json!({
+                    "by": by_param,
+                    "count": contexts.len(),
+                    "contexts": contexts,
+                    "skipped": skipped,
+                });
                 let bytes = serde_json::to_vec(&resp)
                     .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
                 Ok((
@@ -940,10 +1619,13 @@ fn handle_request(
                 ))
             }
             (Method::Get, ["v1", "metrics"]) => {
-                let mut store = store.lock().unwrap();
-                let registry = registry.lock().unwrap();
+                route.set("GET /v1/metrics");
+                let mut store = lock_or_recover(store, "store");
+                let registry = lock_or_recover(registry, "registry");
                 let snapshot = metrics.snapshot(&mut store, &registry);
-                let bytes = serde_json::to_vec(&snapshot)
+                let bytes = snapshot
+                    .to_json()
+                    .and_then(|json| serde_json::to_vec(&json))
                     .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
                 Ok((
                     200,
@@ -956,14 +1638,34 @@ fn handle_request(
                 ))
             }
             (Method::Get, ["v1", "errors"]) => {
+                route.set("GET /v1/errors");
                 let params = parse_query(url.query().unwrap_or(""));
                 let limit: usize = params
                     .get("limit")
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(50)
                     .min(256);
-                let entries = metrics.recent_errors(limit);
-                let bytes = serde_json::to_vec(&json!({ "errors": entries }))
+                let status_code = params
+                    .get("status")
+                    .map(|v| {
+                        v.parse::<u16>()
+                            .map_err(|_| StoreError::InvalidInput("invalid status".into()))
+                    })
+                    .transpose()?;
+                let since_ms = params
+                    .get("since_ms")
+                    .map(|v| {
+                        v.parse::<u64>()
+                            .map_err(|_| StoreError::InvalidInput("invalid since_ms".into()))
+                    })
+                    .transpose()?;
+                let filter = ErrorFilter {
+                    kind: params.get("kind").cloned(),
+                    status_code,
+                    since_ms,
+                };
+                let result = metrics.query_errors(&filter, limit);
+                let bytes = serde_json::to_vec(&result)
                     .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
                 Ok((
                     200,
@@ -975,63 +1677,116 @@ fn handle_request(
                         ),
                 ))
             }
+            // Buffered events, for clients that poll instead of streaming
+            (Method::Get, ["v1", "events", "history"]) => {
+                route.set("GET /v1/events/history");
+                let params = parse_query(url.query().unwrap_or(""));
+                let since_id = params
+                    .get("since_id")
+                    .map(|v| {
+                        v.parse::<u64>()
+                            .map_err(|_| StoreError::InvalidInput("invalid since_id".into()))
+                    })
+                    .transpose()?
+                    .unwrap_or(0);
+                let limit: usize = params
+                    .get("limit")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(100)
+                    .min(1000);
+                let types: HashSet<String> = params
+                    .get("types")
+                    .map(|v| {
+                        v.split(',')
+                            .map(|t| t.trim().to_string())
+                            .filter(|t| !t.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let events: Vec<JsonValue> = event_bus
+                    .history(since_id, &types, limit)
+                    .into_iter()
+                    .map(|(id, event)| {
+                        let (event_type, data) = event.sse_data();
+                        json!({"id": id, "type": event_type, "data": data})
+                    })
+                    .collect();
+                encode_response(200, &events, format)
+            }
+            // Raw payload bytes of one turn, exactly as stored
+            (Method::Get, ["v1", "turns", turn_id, "raw"]) => {
+                route.set("GET /v1/turns/:id/raw");
+                let turn_id: u64 = turn_id
+                    .parse()
+                    .map_err(|_| StoreError::InvalidInput("invalid turn_id".into()))?;
+                let turn = lock_or_recover(store, "store").get_turn(turn_id)?;
+                let etag = format!("\"{}\"", hex::encode(turn.record.payload_hash));
+                if let Some(header) = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("If-None-Match"))
+                {
+                    if header.value.as_str() == etag {
+                        return Ok((
+                            304,
+                            Response::from_data(Vec::new()).with_status_code(StatusCode(304)),
+                        ));
+                    }
+                }
+                let content_type: &[u8] = if turn.meta.encoding == 1 {
+                    b"application/msgpack"
+                } else {
+                    b"application/octet-stream"
+                };
+                let version = turn.meta.declared_type_version.to_string();
+                Ok((
+                    200,
+                    Response::from_data(turn.payload.unwrap_or_default())
+                        .with_status_code(StatusCode(200))
+                        .with_header(
+                            Header::from_bytes(&b"Content-Type"[..], content_type).unwrap(),
+                        )
+                        .with_header(Header::from_bytes(&b"ETag"[..], etag.as_bytes()).unwrap())
+                        .with_header(
+                            Header::from_bytes(
+                                &b"X-Type-Id"[..],
+                                turn.meta.declared_type_id.as_bytes(),
+                            )
+                            .unwrap(),
+                        )
+                        .with_header(
+                            Header::from_bytes(&b"X-Type-Version"[..], version.as_bytes()).unwrap(),
+                        ),
+                ))
+            }
             // Filesystem snapshot: list directory entries
             (Method::Get, ["v1", "turns", turn_id, "fs"]) => {
+                route.set("GET /v1/turns/:id/fs");
                 let turn_id: u64 = turn_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid turn_id".into()))?;
                 let params = parse_query(url.query().unwrap_or(""));
                 let path = params.get("path").map(|s| s.as_str()).unwrap_or("");
+                check_fs_path(path).map_err(StoreError::BadRequest)?;
 
-                let mut store = store.lock().unwrap();
+                let mut store = lock_or_recover(store, "store");
+                if params.get("stat").is_some_and(|v| v == "1") {
+                    let stats = store.fs_stat(turn_id, path)?;
+                    return fs_stat_response(turn_id, path, &stats);
+                }
 
                 // Get fs_root for this turn
-                let fs_root = store
-                    .get_fs_root(turn_id)
-                    .ok_or_else(|| StoreError::NotFound("no fs snapshot for turn".into()))?;
+                let fs_root = store.get_fs_root(turn_id).ok_or_else(|| {
+                    StoreError::not_found(NotFoundKind::FsSnapshot, "no fs snapshot for turn")
+                })?;
 
                 // List entries at the given path
                 let entries = store.list_fs_entries(turn_id, path)?;
-
-                let entries_json: Vec<JsonValue> = entries
-                    .iter()
-                    .map(|e| {
-                        let kind_str = match EntryKind::from(e.kind) {
-                            EntryKind::File => "file",
-                            EntryKind::Directory => "dir",
-                            EntryKind::Symlink => "symlink",
-                        };
-                        json!({
-                            "name": e.name,
-                            "kind": kind_str,
-                            "mode": format!("{:o}", e.mode),
-                            "size": e.size,
-                            "hash": hex::encode(&e.hash),
-                        })
-                    })
-                    .collect();
-
-                let resp = json!({
-                    "turn_id": turn_id.to_string(),
-                    "path": path,
-                    "fs_root_hash": hex::encode(fs_root),
-                    "entries": entries_json,
-                });
-
-                let bytes = serde_json::to_vec(&resp)
-                    .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-                Ok((
-                    200,
-                    Response::from_data(bytes)
-                        .with_status_code(StatusCode(200))
-                        .with_header(
-                            Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
-                                .unwrap(),
-                        ),
-                ))
+                fs_listing_response(turn_id, path, &fs_root, entries, &params)
             }
             // Filesystem snapshot: get file content or directory listing
             (Method::Get, ["v1", "turns", turn_id, "fs", rest @ ..]) => {
+                route.set("GET /v1/turns/:id/fs/*");
                 let turn_id: u64 = turn_id
                     .parse()
                     .map_err(|_| StoreError::InvalidInput("invalid turn_id".into()))?;
@@ -1043,12 +1798,25 @@ fn handle_request(
 
                 let params = parse_query(url.query().unwrap_or(""));
                 let as_json = params.get("format").map(|s| s.as_str()) == Some("json");
+                // Without `follow`, a symlink is served as its target path.
+                let follow = params.get("follow").map(|v| v == "1").unwrap_or(false);
 
-                let mut store = store.lock().unwrap();
+                let mut store = lock_or_recover(store, "store");
+                if params.get("stat").is_some_and(|v| v == "1") {
+                    let stats = store.fs_stat(turn_id, &path)?;
+                    return fs_stat_response(turn_id, &path, &stats);
+                }
 
                 // First try to get it as a file
-                match store.get_fs_file(turn_id, &path) {
-                    Ok((content, entry)) => {
+                let file = if follow {
+                    store.get_fs_file_following(turn_id, &path)
+                } else {
+                    store
+                        .get_fs_file(turn_id, &path)
+                        .map(|(content, entry)| (content, entry, path.clone()))
+                };
+                match file {
+                    Ok((content, entry, resolved_path)) => {
                         if as_json {
                             // Return as JSON with base64 content
                             let kind_str = match EntryKind::from(entry.kind) {
@@ -1056,7 +1824,7 @@ fn handle_request(
                                 EntryKind::Directory => "dir",
                                 EntryKind::Symlink => "symlink",
                             };
-                            let resp = json!({
+                            let mut resp = json!({
                                 "turn_id": turn_id.to_string(),
                                 "path": path,
                                 "name": entry.name,
@@ -1069,6 +1837,9 @@ fn handle_request(
                                     &content
                                 ),
                             });
+                            if resolved_path != path {
+                                resp["resolved_path"] = JsonValue::String(resolved_path);
+                            }
                             let bytes = serde_json::to_vec(&resp).map_err(|e| {
                                 StoreError::InvalidInput(format!("json encode error: {e}"))
                             })?;
@@ -1086,119 +1857,77 @@ fn handle_request(
                             ))
                         } else {
                             // Return raw content
-                            let content_type = guess_content_type(&path);
-                            Ok((
-                                200,
-                                Response::from_data(content)
-                                    .with_status_code(StatusCode(200))
-                                    .with_header(
-                                        Header::from_bytes(
-                                            &b"Content-Type"[..],
-                                            content_type.as_bytes(),
-                                        )
-                                        .unwrap(),
+                            let is_symlink = entry.kind_enum() == EntryKind::Symlink;
+                            let content_type = if is_symlink {
+                                "text/plain"
+                            } else {
+                                guess_content_type(&resolved_path, &options.mime_overrides)
+                            };
+                            let mut response = Response::from_data(content)
+                                .with_status_code(StatusCode(200))
+                                .with_header(
+                                    Header::from_bytes(
+                                        &b"Content-Type"[..],
+                                        content_type.as_bytes(),
                                     )
-                                    .with_header(
-                                        Header::from_bytes(
-                                            &b"X-Fs-Hash"[..],
-                                            hex::encode(&entry.hash).as_bytes(),
-                                        )
-                                        .unwrap(),
+                                    .unwrap(),
+                                )
+                                .with_header(
+                                    Header::from_bytes(
+                                        &b"X-Fs-Hash"[..],
+                                        hex::encode(&entry.hash).as_bytes(),
                                     )
-                                    .with_header(
-                                        Header::from_bytes(
-                                            &b"X-Fs-Mode"[..],
-                                            format!("{:o}", entry.mode).as_bytes(),
-                                        )
-                                        .unwrap(),
-                                    ),
-                            ))
+                                    .unwrap(),
+                                )
+                                .with_header(
+                                    Header::from_bytes(
+                                        &b"X-Fs-Mode"[..],
+                                        format!("{:o}", entry.mode).as_bytes(),
+                                    )
+                                    .unwrap(),
+                                );
+                            if is_symlink {
+                                response.add_header(
+                                    Header::from_bytes(&b"X-Fs-Symlink"[..], &b"true"[..]).unwrap(),
+                                );
+                            }
+                            Ok((200, response))
                         }
                     }
                     Err(StoreError::InvalidInput(msg)) if msg.contains("directory") => {
                         // Path is a directory - return listing instead
                         let fs_root = store.get_fs_root(turn_id).ok_or_else(|| {
-                            StoreError::NotFound("no fs snapshot for turn".into())
+                            StoreError::not_found(
+                                NotFoundKind::FsSnapshot,
+                                "no fs snapshot for turn",
+                            )
                         })?;
 
                         let entries = store.list_fs_entries(turn_id, &path)?;
-
-                        let entries_json: Vec<JsonValue> = entries
-                            .iter()
-                            .map(|e| {
-                                let kind_str = match EntryKind::from(e.kind) {
-                                    EntryKind::File => "file",
-                                    EntryKind::Directory => "dir",
-                                    EntryKind::Symlink => "symlink",
-                                };
-                                json!({
-                                    "name": e.name,
-                                    "kind": kind_str,
-                                    "mode": format!("{:o}", e.mode),
-                                    "size": e.size,
-                                    "hash": hex::encode(&e.hash),
-                                })
-                            })
-                            .collect();
-
-                        let resp = json!({
-                            "turn_id": turn_id.to_string(),
-                            "path": path,
-                            "fs_root_hash": hex::encode(fs_root),
-                            "entries": entries_json,
-                        });
-
-                        let bytes = serde_json::to_vec(&resp).map_err(|e| {
-                            StoreError::InvalidInput(format!("json encode error: {e}"))
-                        })?;
-                        Ok((
-                            200,
-                            Response::from_data(bytes)
-                                .with_status_code(StatusCode(200))
-                                .with_header(
-                                    Header::from_bytes(
-                                        &b"Content-Type"[..],
-                                        &b"application/json"[..],
-                                    )
-                                    .unwrap(),
-                                ),
-                        ))
+                        fs_listing_response(turn_id, &path, &fs_root, entries, &params)
                     }
                     Err(e) => Err(e),
                 }
             }
-            _ => Err(StoreError::NotFound("route".into())),
+            #[cfg(test)]
+            (Method::Get, ["v1", "test", "panic"]) => panic!("test hook panic"),
+            _ => Err(StoreError::not_found(NotFoundKind::Route, "route")),
         }
-    })();
+    });
 
     match result {
         Ok((status, response)) => {
             metrics.record_http(status, start.elapsed());
+            tracing::debug!(
+                op = route.get(),
+                status,
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                correlation_id = correlation_id.as_deref(),
+                "http request"
+            );
             request.respond(response).map_err(StoreError::Io)
         }
-        Err(err) => {
-            let (status, message) = map_error(&err);
-            metrics.record_http(status, start.elapsed());
-            metrics.record_error("http", status, &message, Some(&request_path));
-            event_bus.publish(StoreEvent::ErrorOccurred {
-                timestamp_ms: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .map(|d| d.as_millis() as u64)
-                    .unwrap_or(0),
-                kind: "http".to_string(),
-                status_code: status,
-                message: message.clone(),
-                path: Some(request_path.clone()),
-            });
-            let bytes = serde_json::to_vec(&json!({"error": {"code": status, "message": message}}))
-                .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
-            let response = Response::from_data(bytes)
-                .with_status_code(StatusCode(status))
-                .with_header(
-                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
-                );
-            request.respond(response).map_err(StoreError::Io)
-        }
+        Err(err) => respond_error(request, err),
     }
 }
 
@@ -1206,49 +1935,23 @@ fn handle_request(
 ///
 /// This function takes ownership of the request and streams events to the client.
 /// It spawns a thread to handle the long-lived connection.
-fn handle_sse_stream(request: tiny_http::Request, event_bus: &Arc<EventBus>) -> Result<()> {
+fn handle_sse_stream(
+    request: tiny_http::Request,
+    event_bus: &Arc<EventBus>,
+    follow: Option<HashSet<u64>>,
+) -> Result<()> {
     let event_bus = Arc::clone(event_bus);
 
-    // Build SSE headers
-    let headers = vec![
-        Header::from_bytes(&b"Content-Type"[..], &b"text/event-stream"[..]).unwrap(),
-        Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap(),
-        Header::from_bytes(&b"Connection"[..], &b"keep-alive"[..]).unwrap(),
-        Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap(),
-    ];
-
-    // Create a response with chunked transfer encoding
-    // We use an empty data vector and will write to the underlying stream
-    let response = Response::empty(200);
-    let mut response = response.with_status_code(StatusCode(200));
-    for header in headers {
-        response = response.with_header(header);
-    }
-
-    // Get the raw writer from the request
-    // tiny_http's into_writer() takes ownership and returns a Write trait object
-    let mut writer = request.into_writer();
-
-    // Write HTTP response headers manually since we're taking raw control
-    let status_line = "HTTP/1.1 200 OK\r\n";
-    let headers_str = "Content-Type: text/event-stream\r\n\
-                       Cache-Control: no-cache\r\n\
-                       Connection: keep-alive\r\n\
-                       Access-Control-Allow-Origin: *\r\n\
-                       Transfer-Encoding: chunked\r\n\r\n";
-
-    if writer.write_all(status_line.as_bytes()).is_err() {
+    let Some(mut writer) = start_sse_response(request) else {
         return Ok(()); // Client disconnected
-    }
-    if writer.write_all(headers_str.as_bytes()).is_err() {
-        return Ok(());
-    }
-    if writer.flush().is_err() {
-        return Ok(());
-    }
+    };
 
-    // Subscribe to event bus
-    let subscriber = event_bus.subscribe();
+    // Subscribe to event bus; following specific contexts counts toward
+    // their `follower_count` until the stream closes.
+    let subscriber = match follow {
+        Some(contexts) => event_bus.subscribe_contexts(contexts),
+        None => event_bus.subscribe(),
+    };
 
     // Spawn thread to stream events
     thread::spawn(move || {
@@ -1286,6 +1989,72 @@ fn handle_sse_stream(request: tiny_http::Request, event_bus: &Arc<EventBus>) ->
     Ok(())
 }
 
+/// Handle SSE stream for /v1/metrics/stream.
+///
+/// Snapshots are computed by the shared ticker (see `spawn_metrics_ticker`),
+/// so each connection only forwards the serialized JSON it is handed.
+fn handle_metrics_stream(request: tiny_http::Request, metrics: &Arc<Metrics>) -> Result<()> {
+    let Some(mut writer) = start_sse_response(request) else {
+        return Ok(());
+    };
+    let subscriber = metrics.subscribe_stream();
+    let interval = metrics.stream_interval();
+
+    thread::spawn(move || {
+        let heartbeat_interval = Duration::from_secs(20);
+        let mut last_write = Instant::now();
+
+        // Tell the client how often snapshots arrive so it can size its
+        // staleness checks.
+        let connected = json!({"interval_secs": interval.as_secs()}).to_string();
+        if write_sse_event(&mut writer, "connected", &connected).is_err() {
+            return;
+        }
+
+        loop {
+            match subscriber.recv_timeout(Duration::from_secs(5)) {
+                Ok(snapshot) => {
+                    if write_sse_event(&mut writer, "metrics", &snapshot).is_err() {
+                        break;
+                    }
+                    last_write = Instant::now();
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    if last_write.elapsed() >= heartbeat_interval {
+                        if write_sse_heartbeat(&mut writer).is_err() {
+                            break;
+                        }
+                        last_write = Instant::now();
+                    }
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Take raw control of the connection and write the SSE response head.
+/// Returns `None` if the client went away before the headers were sent.
+fn start_sse_response(request: tiny_http::Request) -> Option<Box<dyn Write + Send + 'static>> {
+    // tiny_http's into_writer() takes ownership and returns a Write trait object
+    let mut writer = request.into_writer();
+
+    // Write HTTP response headers manually since we're taking raw control
+    let status_line = "HTTP/1.1 200 OK\r\n";
+    let headers_str = "Content-Type: text/event-stream\r\n\
+                       Cache-Control: no-cache\r\n\
+                       Connection: keep-alive\r\n\
+                       Access-Control-Allow-Origin: *\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n";
+
+    writer.write_all(status_line.as_bytes()).ok()?;
+    writer.write_all(headers_str.as_bytes()).ok()?;
+    writer.flush().ok()?;
+    Some(writer)
+}
+
 /// Write an SSE event to the stream using chunked encoding.
 fn write_sse_event<W: Write>(writer: &mut W, event_type: &str, data: &str) -> std::io::Result<()> {
     let message = format!("event: {}\ndata: {}\n\n", event_type, data);
@@ -1302,9 +2071,20 @@ fn write_sse_heartbeat<W: Write>(writer: &mut W) -> std::io::Result<()> {
     writer.flush()
 }
 
+fn context_stats_to_json(stats: &ContextStats) -> JsonValue {
+    json!({
+        "context_id": stats.context_id.to_string(),
+        "turn_count": stats.turn_count,
+        "head_depth": stats.head_depth,
+        "payload_bytes": stats.payload_bytes,
+        "fs_snapshot_bytes": stats.fs_snapshot_bytes,
+    })
+}
+
 fn context_to_json(
     store: &mut Store,
     session_tracker: &SessionTracker,
+    event_bus: &EventBus,
     context_id: u64,
     include_provenance: bool,
     include_lineage: bool,
@@ -1327,8 +2107,10 @@ fn context_to_json(
         "context_id": head.context_id.to_string(),
         "head_turn_id": head.head_turn_id.to_string(),
         "head_depth": head.head_depth,
+        "turn_count": head.turn_count,
         "created_at_unix_ms": head.created_at_unix_ms,
         "is_live": is_live,
+        "follower_count": event_bus.follower_count(context_id),
     });
 
     if let Some(tag) = client_tag {
@@ -1343,6 +2125,8 @@ fn context_to_json(
     if let Some(metadata) = &stored_metadata {
         if let Some(title) = &metadata.title {
             obj["title"] = JsonValue::String(title.clone());
+        } else if let Some(title) = &metadata.derived_title {
+            obj["derived_title"] = JsonValue::String(title.clone());
         }
         if let Some(labels) = &metadata.labels {
             obj["labels"] = serde_json::to_value(labels).unwrap_or(JsonValue::Null);
@@ -1391,6 +2175,7 @@ fn context_to_json(
         });
     }
 
+    make_js_safe(&mut obj);
     Ok(obj)
 }
 
@@ -1400,6 +2185,10 @@ fn parse_base_turn_id(
     required: bool,
 ) -> Result<u64> {
     let body = parse_json_body(request)?;
+    base_turn_id_from_body(&body, default, required)
+}
+
+fn base_turn_id_from_body(body: &JsonValue, default: u64, required: bool) -> Result<u64> {
     if let Some(value) = body.get("base_turn_id") {
         parse_json_u64(value, "base_turn_id")
     } else if required {
@@ -1465,6 +2254,33 @@ fn get_optional_u64(body: &JsonValue, key: &str) -> Result<Option<u64>> {
     }
 }
 
+fn get_optional_string(body: &JsonValue, key: &str) -> Result<Option<String>> {
+    match body.get(key) {
+        Some(JsonValue::String(s)) => Ok(Some(s.clone())),
+        Some(JsonValue::Null) | None => Ok(None),
+        Some(_) => Err(StoreError::InvalidInput(format!("invalid {key}"))),
+    }
+}
+
+fn get_optional_bool(body: &JsonValue, key: &str) -> Result<Option<bool>> {
+    match body.get(key) {
+        Some(JsonValue::Bool(b)) => Ok(Some(*b)),
+        Some(JsonValue::Null) | None => Ok(None),
+        Some(_) => Err(StoreError::InvalidInput(format!("invalid {key}"))),
+    }
+}
+
+/// The caller's `X-Correlation-Id`, recorded with any error the request
+/// causes.
+fn extract_correlation_id(request: &tiny_http::Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Correlation-Id"))
+        .map(|h| h.value.as_str().trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
 fn extract_http_client_tag(request: &tiny_http::Request) -> String {
     for name in ["X-CXDB-Client-Tag", "X-Client-Tag"] {
         if let Some(header) = request.headers().iter().find(|h| h.field.equiv(name)) {
@@ -1477,7 +2293,9 @@ fn extract_http_client_tag(request: &tiny_http::Request) -> String {
     "http".to_string()
 }
 
-fn encode_http_payload(
+/// Encode an HTTP append's JSON payload as msgpack: by field tag when the
+/// registry knows the type version, as plain JSON shape otherwise.
+pub fn encode_http_payload(
     payload_json: &JsonValue,
     type_id: &str,
     type_version: u32,
@@ -1556,13 +2374,23 @@ fn encode_field_value(
         if let Some(num) = parse_json_u64_opt(value) {
             return Ok(MsgpackValue::from(num));
         }
+        // Signed discriminants, e.g. a -1 sentinel.
+        if let Some(num) = value.as_i64() {
+            return Ok(MsgpackValue::from(num));
+        }
         if let Some(label) = value.as_str() {
             if let Some(enum_map) = registry.get_enum(enum_ref) {
                 if let Some((num_str, _)) = enum_map.iter().find(|(_, v)| v.as_str() == label) {
-                    let num = num_str.parse::<u64>().map_err(|_| {
-                        StoreError::InvalidInput(format!("invalid enum value for {}", field.name))
-                    })?;
-                    return Ok(MsgpackValue::from(num));
+                    return num_str
+                        .parse::<u64>()
+                        .map(MsgpackValue::from)
+                        .or_else(|_| num_str.parse::<i64>().map(MsgpackValue::from))
+                        .map_err(|_| {
+                            StoreError::InvalidInput(format!(
+                                "invalid enum value for {}",
+                                field.name
+                            ))
+                        });
                 }
             }
             return Err(StoreError::InvalidInput(format!(
@@ -1598,15 +2426,31 @@ fn encode_field_value(
             })?;
             let mut out = Vec::with_capacity(items.len());
             for item in items {
-                let encoded = match &field.items {
-                    Some(ItemsSpec::Simple(item_type)) => encode_value_for_type(item, item_type)?,
-                    Some(ItemsSpec::Ref(type_ref)) => encode_ref_value(item, type_ref, registry)?,
-                    None => json_to_msgpack_value(item)?,
-                };
-                out.push(encoded);
+                out.push(encode_item(item, field.items.as_ref(), registry)?);
             }
             Ok(MsgpackValue::Array(out))
         }
+        // A map with a `ref` is a nested type, as in projection.
+        "map" if field.type_ref.is_some() => encode_ref_value(
+            value,
+            field.type_ref.as_deref().unwrap_or_default(),
+            registry,
+        ),
+        "map" if field.key_type.is_some() || field.value_type.is_some() => {
+            let obj = value.as_object().ok_or_else(|| {
+                StoreError::InvalidInput(format!("expected object for {}", field.name))
+            })?;
+            let mut keys: Vec<&String> = obj.keys().collect();
+            keys.sort();
+            let mut out = Vec::with_capacity(obj.len());
+            for key in keys {
+                out.push((
+                    encode_map_key(key, field.key_type.as_deref())?,
+                    encode_item(&obj[key], field.value_type.as_ref(), registry)?,
+                ));
+            }
+            Ok(MsgpackValue::Map(out))
+        }
         "ref" => {
             if let Some(type_ref) = &field.type_ref {
                 encode_ref_value(value, type_ref, registry)
@@ -1628,10 +2472,54 @@ fn encode_ref_value(
         .ok_or_else(|| StoreError::InvalidInput(format!("expected object for ref {type_ref}")))?;
     let desc = registry
         .get_latest_type_version(type_ref)
-        .ok_or_else(|| StoreError::NotFound("type descriptor".into()))?;
+        .ok_or_else(|| StoreError::not_found(NotFoundKind::TypeDescriptor, "type descriptor"))?;
     encode_object_with_descriptor(obj, desc, registry)
 }
 
+/// Encode an array item or map value according to its spec.
+fn encode_item(
+    value: &JsonValue,
+    spec: Option<&ItemsSpec>,
+    registry: &Registry,
+) -> Result<MsgpackValue> {
+    match spec {
+        Some(ItemsSpec::Simple(item_type)) => encode_value_for_type(value, item_type),
+        Some(ItemsSpec::Ref(type_ref)) => encode_ref_value(value, type_ref, registry),
+        Some(ItemsSpec::Array(inner)) => {
+            let items = value
+                .as_array()
+                .ok_or_else(|| StoreError::InvalidInput("expected array".into()))?;
+            items
+                .iter()
+                .map(|item| encode_item(item, Some(inner), registry))
+                .collect::<Result<Vec<_>>>()
+                .map(MsgpackValue::Array)
+        }
+        None => json_to_msgpack_value(value),
+    }
+}
+
+/// JSON object keys are strings; an integer `key_type` stores them as
+/// msgpack integers. Without one, numeric keys become integers as in
+/// untyped objects.
+fn encode_map_key(key: &str, key_type: Option<&str>) -> Result<MsgpackValue> {
+    match key_type {
+        Some("string") => Ok(MsgpackValue::String(key.to_string().into())),
+        Some("u64" | "uint64" | "u32" | "uint32" | "u8" | "uint8") => key
+            .parse::<u64>()
+            .map(MsgpackValue::from)
+            .map_err(|_| StoreError::InvalidInput(format!("expected integer map key, got {key}"))),
+        Some("i64" | "int64" | "i32" | "int32") => key
+            .parse::<i64>()
+            .map(MsgpackValue::from)
+            .map_err(|_| StoreError::InvalidInput(format!("expected integer map key, got {key}"))),
+        _ => Ok(key
+            .parse::<u64>()
+            .map(MsgpackValue::from)
+            .unwrap_or_else(|_| MsgpackValue::String(key.to_string().into()))),
+    }
+}
+
 fn encode_value_for_type(value: &JsonValue, field_type: &str) -> Result<MsgpackValue> {
     match field_type {
         "string" => value
@@ -1763,6 +2651,221 @@ fn parse_query(query: &str) -> HashMap<String, String> {
         .collect()
 }
 
+/// Render a directory listing, sorted by `sort` (`name`, the default, or
+/// `size`) and paged with `limit` and an `after` name cursor.
+fn fs_listing_response(
+    turn_id: u64,
+    path: &str,
+    fs_root: &[u8; 32],
+    mut entries: Vec<crate::fs_store::TreeEntry>,
+    params: &HashMap<String, String>,
+) -> Result<HttpResponse> {
+    match params.get("sort").map(|s| s.as_str()).unwrap_or("name") {
+        "name" => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        "size" => entries.sort_by(|a, b| a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name))),
+        // Tree entries don't record modification times.
+        "mtime" => {
+            return Err(StoreError::InvalidInput(
+                "sort=mtime is not supported: fs snapshots carry no mtimes".into(),
+            ))
+        }
+        other => return Err(StoreError::InvalidInput(format!("invalid sort: {other}"))),
+    }
+    let limit = params
+        .get("limit")
+        .map(|v| {
+            v.parse::<usize>()
+                .map_err(|_| StoreError::InvalidInput("invalid limit".into()))
+        })
+        .transpose()?;
+    // Names are unique within a directory, so the cursor is the last name
+    // returned. A name no longer present resumes after where it would sort.
+    let start = match params.get("after") {
+        Some(after) => match entries.iter().position(|e| &e.name == after) {
+            Some(i) => i + 1,
+            None if params.get("sort").is_none_or(|s| s == "name") => {
+                entries.partition_point(|e| e.name.as_str() <= after.as_str())
+            }
+            None => return Err(StoreError::InvalidInput(format!("unknown cursor: {after}"))),
+        },
+        None => 0,
+    };
+    let mut page = entries.split_off(start.min(entries.len()));
+    let mut next_after = None;
+    if let Some(limit) = limit {
+        if page.len() > limit {
+            page.truncate(limit);
+            next_after = page.last().map(|e| e.name.clone());
+        }
+    }
+
+    let entries_json: Vec<JsonValue> = page
+        .iter()
+        .map(|e| {
+            let kind_str = match EntryKind::from(e.kind) {
+                EntryKind::File => "file",
+                EntryKind::Directory => "dir",
+                EntryKind::Symlink => "symlink",
+            };
+            json!({
+                "name": e.name,
+                "kind": kind_str,
+                "mode": format!("{:o}", e.mode),
+                "size": e.size,
+                "hash": hex::encode(&e.hash),
+            })
+        })
+        .collect();
+
+    let resp = json!({
+        "turn_id": turn_id.to_string(),
+        "path": path,
+        "fs_root_hash": hex::encode(fs_root),
+        "entries": entries_json,
+        "next_after": next_after,
+    });
+
+    let bytes = serde_json::to_vec(&resp)
+        .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+    Ok((
+        200,
+        Response::from_data(bytes)
+            .with_status_code(StatusCode(200))
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            ),
+    ))
+}
+
+fn fs_stat_response(
+    turn_id: u64,
+    path: &str,
+    stats: &crate::fs_store::FsTreeStats,
+) -> Result<HttpResponse> {
+    let resp = json!({
+        "turn_id": turn_id.to_string(),
+        "path": path,
+        "files": stats.files,
+        "dirs": stats.dirs,
+        "symlinks": stats.symlinks,
+        "total_bytes": stats.total_bytes,
+        "max_depth": stats.max_depth,
+    });
+    let bytes = serde_json::to_vec(&resp)
+        .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?;
+    Ok((
+        200,
+        Response::from_data(bytes)
+            .with_status_code(StatusCode(200))
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            ),
+    ))
+}
+
+/// The file path of a `/v1/turns/{id}/fs/{path}` request, undecoded and
+/// before dot-segment normalization.
+fn raw_fs_path(raw_url: &str) -> Option<&str> {
+    let path = raw_url.split('?').next().unwrap_or("");
+    let (_, rest) = path.strip_prefix("/v1/turns/")?.split_once('/')?;
+    rest.strip_prefix("fs/")
+}
+
+/// Reject fs paths that could walk out of the requested directory: `..`
+/// components (plain or percent-encoded), absolute paths, and encoded or
+/// backslash separators.
+fn check_fs_path(path: &str) -> std::result::Result<(), String> {
+    if path.starts_with('/') {
+        return Err(format!("fs path must be relative: {path}"));
+    }
+    for component in path.split('/') {
+        let lower = component.to_ascii_lowercase();
+        if lower.contains("%2f") || lower.contains("%5c") || component.contains('\\') {
+            return Err(format!("fs path contains an encoded separator: {path}"));
+        }
+        if lower.replace("%2e", ".") == ".." {
+            return Err(format!("fs path may not contain '..': {path}"));
+        }
+    }
+    Ok(())
+}
+
+/// Body encoding for read endpoints, chosen by the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    Json,
+    Msgpack,
+}
+
+impl BodyFormat {
+    /// Msgpack when `Accept` lists `application/msgpack` (or the older
+    /// `application/x-msgpack`); JSON otherwise.
+    fn from_request(request: &tiny_http::Request) -> Self {
+        let wants_msgpack = request
+            .headers()
+            .iter()
+            .filter(|h| h.field.equiv("Accept"))
+            .flat_map(|h| h.value.as_str().split(','))
+            .map(|media| media.split(';').next().unwrap_or("").trim())
+            .any(|media| {
+                media.eq_ignore_ascii_case("application/msgpack")
+                    || media.eq_ignore_ascii_case("application/x-msgpack")
+            });
+        if wants_msgpack {
+            BodyFormat::Msgpack
+        } else {
+            BodyFormat::Json
+        }
+    }
+}
+
+/// Serialize a response body as JSON or msgpack. Both carry the same
+/// structure; msgpack maps keep their field names.
+fn encode_response<T: serde::Serialize>(
+    status: u16,
+    value: &T,
+    format: BodyFormat,
+) -> Result<HttpResponse> {
+    let (bytes, content_type) = match format {
+        BodyFormat::Json => (
+            serde_json::to_vec(value)
+                .map_err(|e| StoreError::InvalidInput(format!("json encode error: {e}")))?,
+            "application/json",
+        ),
+        BodyFormat::Msgpack => (
+            rmp_serde::to_vec_named(value)
+                .map_err(|e| StoreError::InvalidInput(format!("msgpack encode error: {e}")))?,
+            "application/msgpack",
+        ),
+    };
+    Ok((
+        status,
+        Response::from_data(bytes)
+            .with_status_code(StatusCode(status))
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap(),
+            ),
+    ))
+}
+
+/// Insert `bytes` as `<prefix>_b64`, `<prefix>_hex` or `<prefix>_len`.
+fn insert_rendered_bytes(
+    obj: &mut Map<String, JsonValue>,
+    prefix: &str,
+    bytes: &[u8],
+    render: BytesRender,
+) {
+    let (suffix, value) = match render {
+        BytesRender::Base64 => (
+            "b64",
+            JsonValue::String(base64::engine::general_purpose::STANDARD.encode(bytes)),
+        ),
+        BytesRender::Hex => ("hex", JsonValue::String(hex::encode(bytes))),
+        BytesRender::LenOnly => ("len", JsonValue::Number((bytes.len() as u64).into())),
+    };
+    obj.insert(format!("{prefix}_{suffix}"), value);
+}
+
 fn unix_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -1770,20 +2873,24 @@ fn unix_ms() -> u64 {
         .as_millis() as u64
 }
 
+/// The binary protocol's status and detail; its codes already follow the
+/// HTTP taxonomy, so only the width changes.
 fn map_error(err: &StoreError) -> (u16, String) {
+    let (code, detail) = crate::protocol::map_store_error(err);
+    (code as u16, detail)
+}
+
+fn error_body(err: &StoreError, status: u16, message: &str) -> JsonValue {
     match err {
-        StoreError::NotFound(msg) => {
-            if msg.contains("type descriptor") {
-                (424, msg.clone())
-            } else if msg.contains("parent turn") || msg.contains("base turn") {
-                (409, msg.clone())
-            } else {
-                (404, msg.clone())
-            }
+        StoreError::NotFound(kind, _) => {
+            json!({"error": {"code": status, "kind": kind.as_str(), "message": message}})
         }
-        StoreError::InvalidInput(msg) => (422, msg.clone()),
-        StoreError::Corrupt(msg) => (500, msg.clone()),
-        StoreError::Io(msg) => (500, msg.to_string()),
+        StoreError::HeadMismatch { actual, .. } => json!({"error": {
+            "code": status,
+            "message": message,
+            "head_turn_id": actual.to_string(),
+        }}),
+        _ => json!({"error": {"code": status, "message": message}}),
     }
 }
 
@@ -1800,8 +2907,6 @@ fn renderer_spec_to_json(spec: &RendererSpec) -> JsonValue {
 }
 
 fn type_version_to_json(spec: &TypeVersionSpec) -> JsonValue {
-    use crate::registry::ItemsSpec;
-
     let mut fields = Map::new();
     for (tag, field) in spec.fields.iter() {
         let mut obj = Map::new();
@@ -1814,14 +2919,13 @@ fn type_version_to_json(spec: &TypeVersionSpec) -> JsonValue {
             obj.insert("ref".into(), JsonValue::String(type_ref.clone()));
         }
         if let Some(items) = &field.items {
-            match items {
-                ItemsSpec::Simple(s) => {
-                    obj.insert("items".into(), JsonValue::String(s.clone()));
-                }
-                ItemsSpec::Ref(r) => {
-                    obj.insert("items".into(), json!({"type": "ref", "ref": r}));
-                }
-            }
+            obj.insert("items".into(), items.to_json());
+        }
+        if let Some(key_type) = &field.key_type {
+            obj.insert("key_type".into(), JsonValue::String(key_type.clone()));
+        }
+        if let Some(value_type) = &field.value_type {
+            obj.insert("value_type".into(), value_type.to_json());
         }
         if field.optional {
             obj.insert("optional".into(), JsonValue::Bool(true));
@@ -1837,12 +2941,18 @@ fn type_version_to_json(spec: &TypeVersionSpec) -> JsonValue {
 }
 
 /// Guess content type from file extension.
-fn guess_content_type(path: &str) -> &'static str {
-    let ext = path.rsplit('.').next().unwrap_or("");
-    match ext.to_lowercase().as_str() {
+fn guess_content_type<'a>(path: &str, overrides: &'a HashMap<String, String>) -> &'a str {
+    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    if let Some(mime) = overrides.get(&ext) {
+        return mime;
+    }
+    match ext.as_str() {
         "html" | "htm" => "text/html",
         "css" => "text/css",
         "js" => "application/javascript",
+        "mjs" | "cjs" => "text/javascript",
+        "wasm" => "application/wasm",
+        "csv" => "text/csv",
         "json" => "application/json",
         "xml" => "application/xml",
         "txt" => "text/plain",
@@ -1865,6 +2975,13 @@ fn guess_content_type(path: &str) -> &'static str {
         "gif" => "image/gif",
         "svg" => "image/svg+xml",
         "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
         "pdf" => "application/pdf",
         "zip" => "application/zip",
         "tar" => "application/x-tar",
@@ -1951,4 +3068,1462 @@ mod tests {
             *k == MsgpackValue::from("text") && *v == MsgpackValue::from("hello")
         }));
     }
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        listener.local_addr().expect("addr").to_string()
+    }
+
+    /// An HTTP server over a fresh store and registry. The temp dir holding
+    /// them lives as long as this value.
+    struct TestServer {
+        addr: String,
+        store: Arc<Mutex<Store>>,
+        metrics: Arc<Metrics>,
+        session_tracker: Arc<SessionTracker>,
+        event_bus: Arc<EventBus>,
+        http: HttpServer,
+        _dir: tempfile::TempDir,
+    }
+
+    fn start_test_server() -> TestServer {
+        start_test_server_with(None, HttpOptions::default())
+    }
+
+    fn start_test_server_with(admin_addr: Option<String>, options: HttpOptions) -> TestServer {
+        let dir = tempdir().expect("tempdir");
+        let store = Arc::new(Mutex::new(
+            Store::open(&dir.path().join("store")).expect("open store"),
+        ));
+        let registry = Arc::new(Mutex::new(
+            Registry::open(&dir.path().join("registry")).expect("open registry"),
+        ));
+        let metrics = Arc::new(Metrics::new(dir.path().to_path_buf()));
+        let session_tracker = Arc::new(SessionTracker::new());
+        let event_bus = Arc::new(EventBus::new());
+        let addr = free_addr();
+        let http = start_http_with_options(
+            addr.clone(),
+            admin_addr,
+            Arc::clone(&store),
+            registry,
+            Arc::clone(&metrics),
+            Arc::clone(&session_tracker),
+            Arc::clone(&event_bus),
+            options,
+        )
+        .expect("start http");
+        TestServer {
+            addr,
+            store,
+            metrics,
+            session_tracker,
+            event_bus,
+            http,
+            _dir: dir,
+        }
+    }
+
+    fn http_status(addr: &str, path: &str) -> u16 {
+        http_request(addr, "GET", path, "").0
+    }
+
+    fn http_request(addr: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+        http_request_with_headers(addr, method, path, "", body)
+    }
+
+    /// Like `http_request`, with extra `Name: value\r\n` header lines.
+    fn http_request_with_headers(
+        addr: &str,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, String) {
+        use std::io::Read;
+
+        let mut stream = None;
+        for _ in 0..50 {
+            match std::net::TcpStream::connect(addr) {
+                Ok(s) => {
+                    stream = Some(s);
+                    break;
+                }
+                Err(_) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        let mut stream = stream.expect("connect");
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .expect("write request");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("read response");
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("status code");
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    /// GET `path` and return the status, raw header block and body bytes, for
+    /// responses that are not UTF-8.
+    fn http_get_bytes(addr: &str, path: &str) -> (u16, String, Vec<u8>) {
+        http_get_bytes_with_headers(addr, path, "")
+    }
+
+    /// Like `http_get_bytes`, with extra `Name: value\r\n` header lines.
+    fn http_get_bytes_with_headers(
+        addr: &str,
+        path: &str,
+        headers: &str,
+    ) -> (u16, String, Vec<u8>) {
+        use std::io::Read;
+
+        let mut stream = std::net::TcpStream::connect(addr).expect("connect");
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n"
+        )
+        .expect("write request");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).expect("read response");
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("header terminator");
+        let head = String::from_utf8_lossy(&response[..split]).to_string();
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("status code");
+        (status, head, response[split + 4..].to_vec())
+    }
+
+    #[test]
+    fn include_empty_controls_listing_of_contexts_without_turns() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let (empty, full) = {
+            let mut store = server.store.lock().unwrap();
+            let full = store.create_context(0).expect("create").context_id;
+            let payload = b"turn";
+            store
+                .append_turn(
+                    full,
+                    0,
+                    "com.example.Test".to_string(),
+                    1,
+                    1,
+                    0,
+                    payload.len() as u32,
+                    *blake3::hash(payload).as_bytes(),
+                    payload,
+                )
+                .expect("append");
+            let empty = store.create_context(0).expect("create").context_id;
+            (empty, full)
+        };
+
+        let listed = |query: &str| -> Vec<JsonValue> {
+            let (status, body) = http_request(addr, "GET", &format!("/v1/contexts{query}"), "");
+            assert_eq!(status, 200);
+            let listing: JsonValue = serde_json::from_str(&body).expect("json");
+            listing["contexts"].as_array().expect("contexts").clone()
+        };
+        // Both contexts can share a created_at millisecond, so compare ids
+        // without relying on listing order.
+        let ids = |contexts: &[JsonValue]| -> Vec<String> {
+            let mut ids: Vec<String> = contexts
+                .iter()
+                .map(|c| c["context_id"].as_str().unwrap_or("").to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        let empty_id = empty.to_string();
+        let with_empty = listed("?include_empty=1");
+        assert_eq!(ids(&with_empty), vec![full.to_string(), empty.to_string()]);
+        let entry = with_empty
+            .iter()
+            .find(|c| c["context_id"].as_str() == Some(empty_id.as_str()))
+            .expect("empty context listed");
+        assert_eq!(entry["head_turn_id"], "0");
+        assert_eq!(entry["head_depth"], 0);
+        assert!(entry.get("title").is_none());
+
+        assert_eq!(ids(&listed("")), ids(&with_empty));
+        assert_eq!(
+            ids(&listed("?include_empty=0&limit=1")),
+            vec![full.to_string()]
+        );
+    }
+
+    #[test]
+    fn requests_succeed_after_a_handler_panics_holding_the_store_lock() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let store = &server.store;
+
+        let poisoner = Arc::clone(store);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("handler panicked while holding the store lock");
+        })
+        .join();
+        assert!(store.is_poisoned());
+
+        let (status, body) = http_request(addr, "POST", "/v1/contexts", "");
+        assert_eq!(status, 201, "{body}");
+        let (status, body) = http_request(addr, "GET", "/v1/contexts", "");
+        assert_eq!(status, 200, "{body}");
+        assert!(!store.is_poisoned());
+    }
+
+    #[test]
+    fn panicking_handler_returns_500_and_server_keeps_serving() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let metrics = &server.metrics;
+
+        let (status, body) = http_request(addr, "GET", "/v1/test/panic", "");
+        assert_eq!(status, 500);
+        assert!(!body.contains("test hook"), "{body}");
+        let errors = metrics.recent_errors(1);
+        assert_eq!(errors[0].status_code, 500);
+        assert_eq!(errors[0].path.as_deref(), Some("/v1/test/panic"));
+        assert_eq!(errors[0].message, "handler panicked: test hook panic");
+        let correlation_id = errors[0].correlation_id.as_deref().expect("correlation id");
+        let body: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(
+            body["error"]["message"],
+            format!("internal error (correlation id {correlation_id})")
+        );
+
+        let (status, body) = http_request_with_headers(
+            addr,
+            "GET",
+            "/v1/test/panic",
+            "X-Correlation-Id: req-9\r\n",
+            "",
+        );
+        assert_eq!(status, 500);
+        let body: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(
+            body["error"]["message"],
+            "internal error (correlation id req-9)"
+        );
+
+        assert_eq!(http_status(addr, "/healthz"), 200);
+    }
+
+    #[test]
+    fn errors_record_the_request_correlation_id() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let events = server.event_bus.subscribe();
+
+        let (status, _) = http_request_with_headers(
+            addr,
+            "GET",
+            "/v1/contexts/999/turns",
+            "X-Correlation-Id: req-42\r\n",
+            "",
+        );
+        assert_eq!(status, 404);
+        let (status, _) = http_request(addr, "GET", "/v1/contexts/998/turns", "");
+        assert_eq!(status, 404);
+
+        let (status, body) = http_request(addr, "GET", "/v1/errors", "");
+        assert_eq!(status, 200, "{body}");
+        let result: JsonValue = serde_json::from_str(&body).expect("json");
+        let errors = result["errors"].as_array().expect("errors");
+        assert_eq!(errors[1]["path"], "/v1/contexts/999/turns");
+        assert_eq!(errors[1]["correlation_id"], "req-42");
+        assert!(errors[0].get("correlation_id").is_none(), "{body}");
+
+        let (_, data) = events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("event")
+            .to_sse();
+        let data: JsonValue = serde_json::from_str(&data).expect("json");
+        assert_eq!(data["correlation_id"], "req-42");
+    }
+
+    #[test]
+    fn malformed_requests_are_recorded_as_errors() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let (status, body) = http_request_with_headers(
+            addr,
+            "GET",
+            "/v1/events?context_id=abc",
+            "X-Correlation-Id: req-7\r\n",
+            "",
+        );
+        assert_eq!(status, 400, "{body}");
+        let body: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(body["error"]["code"], 400);
+
+        let (status, body) = http_request(addr, "GET", "/v1/errors", "");
+        assert_eq!(status, 200, "{body}");
+        let result: JsonValue = serde_json::from_str(&body).expect("json");
+        let errors = result["errors"].as_array().expect("errors");
+        assert_eq!(errors.len(), 1, "{body}");
+        assert_eq!(errors[0]["status_code"], 400);
+        assert_eq!(errors[0]["correlation_id"], "req-7");
+    }
+
+    #[test]
+    fn event_history_returns_buffered_events_after_an_id() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let event_bus = &server.event_bus;
+
+        for turn_id in 1..=4u64 {
+            event_bus.publish(StoreEvent::TurnAppended {
+                context_id: "1".to_string(),
+                turn_id: turn_id.to_string(),
+                parent_turn_id: (turn_id - 1).to_string(),
+                depth: turn_id as u32 - 1,
+                declared_type_id: None,
+                declared_type_version: None,
+            });
+        }
+        event_bus.publish(StoreEvent::ClientConnected {
+            session_id: "7".to_string(),
+            client_tag: "poller".to_string(),
+        });
+
+        let fetch = |query: &str| {
+            let (status, body) =
+                http_request(addr, "GET", &format!("/v1/events/history{query}"), "");
+            assert_eq!(status, 200, "{body}");
+            let events: Vec<JsonValue> = serde_json::from_str(&body).expect("json");
+            events
+        };
+        let ids = |events: &[JsonValue]| -> Vec<u64> {
+            events.iter().map(|e| e["id"].as_u64().unwrap()).collect()
+        };
+
+        let all = fetch("");
+        assert_eq!(ids(&all), vec![1, 2, 3, 4, 5]);
+        assert_eq!(all[0]["type"], "turn_appended");
+        assert_eq!(all[0]["data"]["turn_id"], "1");
+        assert_eq!(all[4]["type"], "client_connected");
+
+        assert_eq!(ids(&fetch("?since_id=2")), vec![3, 4, 5]);
+        assert_eq!(ids(&fetch("?since_id=2&limit=1")), vec![3]);
+        assert_eq!(ids(&fetch("?types=client_connected")), vec![5]);
+        assert!(fetch("?since_id=5").is_empty());
+
+        let (status, _) = http_request(addr, "GET", "/v1/events/history?since_id=x", "");
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn raw_turn_endpoint_returns_stored_payload_bytes() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"].as_str().expect("context_id");
+        let append = json!({
+            "type_id": "com.example.Note",
+            "type_version": 3,
+            "data": {"text": "héllo", "n": 7},
+        })
+        .to_string();
+        let (status, resp) =
+            http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &append);
+        assert_eq!(status, 201, "{resp}");
+        let appended: JsonValue = serde_json::from_str(&resp).expect("json");
+        let turn_id = appended["turn_id"].as_str().unwrap();
+        let content_hash = appended["content_hash"].as_str().unwrap();
+
+        let (status, head, bytes) = http_get_bytes(addr, &format!("/v1/turns/{turn_id}/raw"));
+        assert_eq!(status, 200, "{head}");
+        assert_eq!(blake3::hash(&bytes).to_hex().as_str(), content_hash);
+        let head = head.to_ascii_lowercase();
+        assert!(head.contains("content-type: application/msgpack"), "{head}");
+        assert!(
+            head.contains(&format!("etag: \"{content_hash}\"")),
+            "{head}"
+        );
+        assert!(head.contains("x-type-id: com.example.note"), "{head}");
+        assert!(head.contains("x-type-version: 3"), "{head}");
+
+        assert_eq!(http_status(addr, "/v1/turns/999/raw"), 404);
+    }
+
+    #[test]
+    fn read_endpoints_serve_msgpack_when_accepted() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"].as_str().expect("context_id");
+        let append = json!({
+            "type_id": "com.example.Note",
+            "type_version": 1,
+            "data": {"text": "hi", "n": 7},
+        })
+        .to_string();
+        let (status, resp) =
+            http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &append);
+        assert_eq!(status, 201, "{resp}");
+
+        for path in [
+            format!("/v1/contexts/{ctx}/turns?view=raw"),
+            format!("/v1/contexts/{ctx}"),
+        ] {
+            let (status, json_body) = http_request(addr, "GET", &path, "");
+            assert_eq!(status, 200, "{json_body}");
+            let (status, head, msgpack_body) =
+                http_get_bytes_with_headers(addr, &path, "Accept: application/msgpack\r\n");
+            assert_eq!(status, 200, "{head}");
+            assert!(
+                head.to_ascii_lowercase()
+                    .contains("content-type: application/msgpack"),
+                "{head}"
+            );
+            let from_json: JsonValue = serde_json::from_str(&json_body).expect("json");
+            let from_msgpack: JsonValue = rmp_serde::from_slice(&msgpack_body).expect("msgpack");
+            assert_eq!(from_msgpack, from_json, "{path}");
+        }
+    }
+
+    /// Store `bytes` as a blob and return its hash.
+    fn put_fs_blob(store: &Arc<Mutex<Store>>, bytes: &[u8]) -> [u8; 32] {
+        let hash = *blake3::hash(bytes).as_bytes();
+        let mut store = store.lock().unwrap();
+        store
+            .blob_store
+            .put_if_absent(hash, bytes)
+            .expect("put blob");
+        hash
+    }
+
+    /// Store a tree object of `(name, kind, hash, size)` entries.
+    fn put_fs_tree(
+        store: &Arc<Mutex<Store>>,
+        entries: &[(&str, EntryKind, [u8; 32], u64)],
+    ) -> [u8; 32] {
+        let tree = MsgpackValue::Array(
+            entries
+                .iter()
+                .map(|(name, kind, hash, size)| {
+                    MsgpackValue::Map(vec![
+                        (MsgpackValue::from(1), MsgpackValue::from(*name)),
+                        (MsgpackValue::from(2), MsgpackValue::from(*kind as u8)),
+                        (MsgpackValue::from(3), MsgpackValue::from(0o644)),
+                        (MsgpackValue::from(4), MsgpackValue::from(*size)),
+                        (MsgpackValue::from(5), MsgpackValue::Binary(hash.to_vec())),
+                    ])
+                })
+                .collect(),
+        );
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &tree).expect("encode tree");
+        put_fs_blob(store, &bytes)
+    }
+
+    /// A server with one turn whose fs snapshot holds `readme.md`,
+    /// `docs/guide.txt`, and the symlinks `link.md` -> `readme.md`,
+    /// `docs/up` -> `../readme.md`, `dangling` -> `missing.txt`, and
+    /// `loop` -> `loop`. Returns the server and the turn id.
+    fn start_fs_fixture() -> (TestServer, u64) {
+        let server = start_test_server();
+        let (addr, store) = (&server.addr, &server.store);
+
+        let readme = put_fs_blob(store, b"hello");
+        let guide = put_fs_blob(store, b"guide");
+        let up = put_fs_blob(store, b"../readme.md");
+        let docs = put_fs_tree(
+            store,
+            &[
+                ("guide.txt", EntryKind::File, guide, 5),
+                ("up", EntryKind::Symlink, up, 12),
+            ],
+        );
+        let link = put_fs_blob(store, b"readme.md");
+        let dangling = put_fs_blob(store, b"missing.txt");
+        let looped = put_fs_blob(store, b"loop");
+        let root = put_fs_tree(
+            store,
+            &[
+                ("readme.md", EntryKind::File, readme, 5),
+                ("docs", EntryKind::Directory, docs, 0),
+                ("link.md", EntryKind::Symlink, link, 9),
+                ("dangling", EntryKind::Symlink, dangling, 11),
+                ("loop", EntryKind::Symlink, looped, 4),
+            ],
+        );
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"].as_str().expect("context_id");
+        let append = json!({"type_id": "com.example.Note", "type_version": 1, "data": {}});
+        let (status, resp) = http_request(
+            addr,
+            "POST",
+            &format!("/v1/contexts/{ctx}/turns"),
+            &append.to_string(),
+        );
+        assert_eq!(status, 201, "{resp}");
+        let appended: JsonValue = serde_json::from_str(&resp).expect("json");
+        let turn_id: u64 = appended["turn_id"].as_str().unwrap().parse().unwrap();
+        store
+            .lock()
+            .unwrap()
+            .attach_fs(turn_id, root)
+            .expect("attach fs");
+        (server, turn_id)
+    }
+
+    #[test]
+    fn fs_symlinks_return_target_text_unless_followed() {
+        let (server, turn) = start_fs_fixture();
+        let addr = &server.addr;
+
+        let (status, head, body) = http_get_bytes(addr, &format!("/v1/turns/{turn}/fs/link.md"));
+        assert_eq!(status, 200, "{head}");
+        assert_eq!(body, b"readme.md");
+        let head = head.to_ascii_lowercase();
+        assert!(head.contains("x-fs-symlink: true"), "{head}");
+        assert!(head.contains("content-type: text/plain"), "{head}");
+
+        let (status, head, body) =
+            http_get_bytes(addr, &format!("/v1/turns/{turn}/fs/link.md?follow=1"));
+        assert_eq!(status, 200, "{head}");
+        assert_eq!(body, b"hello");
+        let head = head.to_ascii_lowercase();
+        assert!(!head.contains("x-fs-symlink"), "{head}");
+        assert!(head.contains("content-type: text/markdown"), "{head}");
+
+        // Relative targets resolve from the link's directory.
+        let (status, _, body) =
+            http_get_bytes(addr, &format!("/v1/turns/{turn}/fs/docs/up?follow=1"));
+        assert_eq!(status, 200);
+        assert_eq!(body, b"hello");
+
+        // A dangling link is readable as a link but 404s when followed.
+        let (status, _, body) = http_get_bytes(addr, &format!("/v1/turns/{turn}/fs/dangling"));
+        assert_eq!(status, 200);
+        assert_eq!(body, b"missing.txt");
+        assert_eq!(
+            http_status(addr, &format!("/v1/turns/{turn}/fs/dangling?follow=1")),
+            404
+        );
+        assert_eq!(
+            http_status(addr, &format!("/v1/turns/{turn}/fs/loop?follow=1")),
+            422
+        );
+    }
+
+    #[test]
+    fn fs_listings_sort_and_page_server_side() {
+        let (server, turn) = start_fs_fixture();
+        let (addr, store) = (&server.addr, &server.store);
+
+        // 30 files stored in reverse name order, with sizes descending by name.
+        let blob = put_fs_blob(store, b"x");
+        let names: Vec<String> = (0..30).rev().map(|i| format!("f{i:02}")).collect();
+        let entries: Vec<(&str, EntryKind, [u8; 32], u64)> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), EntryKind::File, blob, i as u64))
+            .collect();
+        let many = put_fs_tree(store, &entries);
+        let root = put_fs_tree(store, &[("many", EntryKind::Directory, many, 0)]);
+        store
+            .lock()
+            .unwrap()
+            .attach_fs(turn, root)
+            .expect("attach fs");
+
+        let list = |query: &str| -> JsonValue {
+            let (status, body) =
+                http_request(addr, "GET", &format!("/v1/turns/{turn}/fs?{query}"), "");
+            assert_eq!(status, 200, "{body}");
+            serde_json::from_str(&body).expect("json")
+        };
+        let names_of = |resp: &JsonValue| -> Vec<String> {
+            resp["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|e| e["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let page = list("path=many&limit=10");
+        let expected: Vec<String> = (0..10).map(|i| format!("f{i:02}")).collect();
+        assert_eq!(names_of(&page), expected);
+        assert_eq!(page["next_after"], "f09");
+
+        let page = list("path=many&limit=10&after=f09");
+        assert_eq!(names_of(&page)[0], "f10");
+        let last = list("path=many&limit=10&after=f19");
+        assert_eq!(names_of(&last).len(), 10);
+        assert!(last["next_after"].is_null());
+
+        // Size order is the reverse of name order here.
+        let page = list("path=many&sort=size&limit=3");
+        assert_eq!(names_of(&page), vec!["f29", "f28", "f27"]);
+        let page = list("path=many&sort=size&limit=3&after=f27");
+        assert_eq!(names_of(&page), vec!["f26", "f25", "f24"]);
+
+        // The directory fallback of the file route pages the same way.
+        let (status, body) = http_request(
+            addr,
+            "GET",
+            &format!("/v1/turns/{turn}/fs/many?limit=2"),
+            "",
+        );
+        assert_eq!(status, 200, "{body}");
+        let page: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(names_of(&page), vec!["f00", "f01"]);
+
+        assert_eq!(
+            http_status(addr, &format!("/v1/turns/{turn}/fs?path=many&sort=mtime")),
+            422
+        );
+    }
+
+    #[test]
+    fn fs_stat_aggregates_the_subtree() {
+        let (server, turn) = start_fs_fixture();
+        let addr = &server.addr;
+
+        let (status, body) = http_request(addr, "GET", &format!("/v1/turns/{turn}/fs?stat=1"), "");
+        assert_eq!(status, 200, "{body}");
+        let stat: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(
+            stat,
+            json!({
+                "turn_id": turn.to_string(),
+                "path": "",
+                "files": 2,
+                "dirs": 1,
+                "symlinks": 4,
+                "total_bytes": 10,
+                "max_depth": 2,
+            })
+        );
+
+        let (status, body) =
+            http_request(addr, "GET", &format!("/v1/turns/{turn}/fs/docs?stat=1"), "");
+        assert_eq!(status, 200, "{body}");
+        let stat: JsonValue = serde_json::from_str(&body).expect("json");
+        assert_eq!(stat["files"], 1);
+        assert_eq!(stat["symlinks"], 1);
+        assert_eq!(stat["max_depth"], 1);
+    }
+
+    #[test]
+    fn fs_paths_with_traversal_components_are_rejected() {
+        let (server, turn) = start_fs_fixture();
+        let addr = &server.addr;
+
+        assert_eq!(
+            http_status(addr, &format!("/v1/turns/{turn}/fs/docs/guide.txt")),
+            200
+        );
+        for path in [
+            "docs/../readme.md",
+            "../../../etc/passwd",
+            "docs/%2e%2e/readme.md",
+            "docs/%2E./readme.md",
+            "/readme.md",
+            "docs%2f..%2freadme.md",
+        ] {
+            let (status, body) =
+                http_request(addr, "GET", &format!("/v1/turns/{turn}/fs/{path}"), "");
+            assert_eq!(status, 400, "{path}: {body}");
+        }
+        for query in ["..", "docs/../docs", "%2Fdocs"] {
+            assert_eq!(
+                http_status(addr, &format!("/v1/turns/{turn}/fs?path={query}")),
+                400,
+                "{query}"
+            );
+        }
+        assert_eq!(
+            http_status(addr, &format!("/v1/turns/{turn}/fs?path=docs")),
+            200
+        );
+
+        // Rejections are recorded like any other failed request.
+        let (status, body) = http_request(addr, "GET", "/v1/errors", "");
+        assert_eq!(status, 200, "{body}");
+        let result: JsonValue = serde_json::from_str(&body).expect("json");
+        let errors = result["errors"].as_array().expect("errors");
+        assert_eq!(errors.len(), 9, "{body}");
+        assert!(errors.iter().all(|e| e["status_code"] == 400), "{body}");
+    }
+
+    #[test]
+    fn forked_context_turns_are_annotated_with_owning_context() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let create = |path: &str, body: &str| -> String {
+            let (status, resp) = http_request(addr, "POST", path, body);
+            assert_eq!(status, 201, "{resp}");
+            let created: JsonValue = serde_json::from_str(&resp).expect("json");
+            created["context_id"].as_str().unwrap().to_string()
+        };
+        let append = |ctx: &str, text: &str| -> String {
+            let body = json!({
+                "type_id": "com.example.Note",
+                "type_version": 1,
+                "data": {"text": text},
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            assert_eq!(status, 201, "{resp}");
+            let appended: JsonValue = serde_json::from_str(&resp).expect("json");
+            appended["turn_id"].as_str().unwrap().to_string()
+        };
+        let turns = |ctx: &str, query: &str| -> JsonValue {
+            let (status, resp) = http_request(
+                addr,
+                "GET",
+                &format!("/v1/contexts/{ctx}/turns?view=raw&{query}"),
+                "",
+            );
+            assert_eq!(status, 200, "{resp}");
+            serde_json::from_str(&resp).expect("json")
+        };
+
+        let base = create("/v1/contexts", "");
+        append(&base, "one");
+        let fork_point = append(&base, "two");
+        append(&base, "base only");
+        let fork = create(
+            "/v1/contexts/fork",
+            &json!({"base_turn_id": fork_point}).to_string(),
+        );
+        append(&fork, "three");
+
+        let full = turns(&fork, "include_inherited=1");
+        let owners: Vec<(&str, u64)> = full["turns"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| {
+                (
+                    t["context_id"].as_str().unwrap(),
+                    t["depth"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            owners,
+            vec![(base.as_str(), 0), (base.as_str(), 1), (fork.as_str(), 2)]
+        );
+
+        let own = turns(&fork, "include_inherited=0");
+        let own_turns = own["turns"].as_array().unwrap();
+        assert_eq!(own_turns.len(), 1);
+        assert_eq!(own_turns[0]["context_id"], fork.as_str());
+        assert!(own["next_before_turn_id"].is_null());
+    }
+
+    #[test]
+    fn pinned_context_projects_with_its_bundle() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let publish = |bundle_id: &str, types: JsonValue| {
+            let bundle = json!({
+                "registry_version": 1,
+                "bundle_id": bundle_id,
+                "types": types,
+                "enums": {},
+            })
+            .to_string();
+            let (status, resp) = http_request(
+                addr,
+                "PUT",
+                &format!("/v1/registry/bundles/{bundle_id}"),
+                &bundle,
+            );
+            assert_eq!(status, 201, "{resp}");
+        };
+        publish(
+            "v1",
+            json!({
+                "com.example.Note": {"versions": {"1": {"fields": {
+                    "1": {"name": "text", "type": "string"},
+                    "2": {"name": "author", "type": "ref", "ref": "com.example.Person"},
+                }}}},
+                "com.example.Person": {"versions": {"1": {"fields": {
+                    "1": {"name": "name", "type": "string"},
+                }}}},
+            }),
+        );
+
+        let create = |body: &str| -> String {
+            let (status, resp) = http_request(addr, "POST", "/v1/contexts", body);
+            assert_eq!(status, 201, "{resp}");
+            let created: JsonValue = serde_json::from_str(&resp).expect("json");
+            created["context_id"].as_str().unwrap().to_string()
+        };
+        let pinned = create(r#"{"metadata": {"bundle_id": "v1"}}"#);
+        let unpinned = create("");
+        for ctx in [&pinned, &unpinned] {
+            let body = json!({
+                "type_id": "com.example.Note",
+                "type_version": 1,
+                "data": {"text": "hello", "author": {"name": "ada"}},
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            assert_eq!(status, 201, "{resp}");
+        }
+        // v2 renames fields on both the outer and the nested type.
+        publish(
+            "v2",
+            json!({
+                "com.example.Note": {"versions": {"2": {"fields": {
+                    "1": {"name": "body", "type": "string"},
+                    "2": {"name": "author", "type": "ref", "ref": "com.example.Person"},
+                }}}},
+                "com.example.Person": {"versions": {"2": {"fields": {
+                    "1": {"name": "full_name", "type": "string"},
+                }}}},
+            }),
+        );
+
+        let latest = |ctx: &str, extra: &str| -> JsonValue {
+            let (status, resp) = http_request(
+                addr,
+                "GET",
+                &format!("/v1/contexts/{ctx}/turns?type_hint_mode=latest{extra}"),
+                "",
+            );
+            assert_eq!(status, 200, "{resp}");
+            serde_json::from_str(&resp).expect("json")
+        };
+
+        let resp = latest(&pinned, "");
+        assert_eq!(resp["meta"]["registry_bundle_id"], "v1");
+        assert_eq!(resp["turns"][0]["data"]["text"], "hello");
+        assert_eq!(resp["turns"][0]["data"]["author"]["name"], "ada");
+        assert_eq!(resp["turns"][0]["decoded_as"]["type_version"], 1);
+
+        let resp = latest(&unpinned, "");
+        assert_eq!(resp["meta"]["registry_bundle_id"], "v2");
+        assert_eq!(resp["turns"][0]["data"]["body"], "hello");
+        assert_eq!(resp["turns"][0]["data"]["author"]["full_name"], "ada");
+
+        let resp = latest(&unpinned, "&bundle_id=v1");
+        assert_eq!(resp["turns"][0]["data"]["text"], "hello");
+        assert_eq!(resp["turns"][0]["data"]["author"]["name"], "ada");
+
+        let (status, _) = http_request(
+            addr,
+            "POST",
+            "/v1/contexts",
+            r#"{"metadata": {"bundle_id": "missing"}}"#,
+        );
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn negative_enum_values_round_trip_between_label_and_number() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let bundle = json!({
+            "registry_version": 1,
+            "bundle_id": "signed",
+            "types": {"com.example.Status": {"versions": {"1": {"fields": {
+                "1": {"name": "state", "type": "i32", "enum": "com.example.State"}
+            }}}}},
+            "enums": {"com.example.State": {"-1": "unknown", "0": "idle", "1": "busy"}},
+        })
+        .to_string();
+        let (status, resp) = http_request(addr, "PUT", "/v1/registry/bundles/signed", &bundle);
+        assert_eq!(status, 201, "{resp}");
+
+        let (_, resp) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&resp).expect("json");
+        let ctx = created["context_id"].as_str().unwrap().to_string();
+        for state in [json!("unknown"), json!(-1)] {
+            let body = json!({
+                "type_id": "com.example.Status",
+                "type_version": 1,
+                "data": {"state": state},
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            assert_eq!(status, 201, "{resp}");
+        }
+
+        let (status, resp) = http_request(
+            addr,
+            "GET",
+            &format!("/v1/contexts/{ctx}/turns?enum_render=both"),
+            "",
+        );
+        assert_eq!(status, 200, "{resp}");
+        let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+        for turn in resp["turns"].as_array().unwrap() {
+            assert_eq!(
+                turn["data"]["state"],
+                json!({"label": "unknown", "value": -1})
+            );
+        }
+    }
+
+    #[test]
+    fn nested_array_and_typed_map_fields_round_trip() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let bundle = json!({
+            "registry_version": 1,
+            "bundle_id": "containers",
+            "types": {"com.example.Grid": {"versions": {"1": {"fields": {
+                "1": {"name": "rows", "type": "array", "items": {"type": "array", "items": "u32"}},
+                "2": {"name": "counts", "type": "map", "key_type": "u64", "value_type": "u32"}
+            }}}}},
+            "enums": {},
+        })
+        .to_string();
+        let (status, resp) = http_request(addr, "PUT", "/v1/registry/bundles/containers", &bundle);
+        assert_eq!(status, 201, "{resp}");
+
+        let (_, resp) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&resp).expect("json");
+        let ctx = created["context_id"].as_str().unwrap().to_string();
+        let data = json!({"rows": [[1, 2], [], [3]], "counts": {"7": 70, "12": 120}});
+        let body = json!({
+            "type_id": "com.example.Grid",
+            "type_version": 1,
+            "data": data,
+        })
+        .to_string();
+        let (status, resp) =
+            http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+        assert_eq!(status, 201, "{resp}");
+
+        // Map keys are stored as integers per key_type.
+        let (status, resp) = http_request(
+            addr,
+            "GET",
+            &format!("/v1/contexts/{ctx}/turns?view=raw"),
+            "",
+        );
+        assert_eq!(status, 200, "{resp}");
+        let turn_id = serde_json::from_str::<JsonValue>(&resp).expect("json")["turns"][0]
+            ["turn_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let (_, _, raw) = http_get_bytes(addr, &format!("/v1/turns/{turn_id}/raw"));
+        let stored = rmpv::decode::read_value(&mut raw.as_slice()).expect("msgpack");
+        let counts = stored
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_u64() == Some(2))
+            .map(|(_, v)| v.as_map().unwrap().clone())
+            .unwrap();
+        assert!(counts.iter().all(|(k, _)| k.is_u64()));
+
+        let (status, resp) = http_request(
+            addr,
+            "GET",
+            &format!("/v1/contexts/{ctx}/turns?u64_format=number"),
+            "",
+        );
+        assert_eq!(status, 200, "{resp}");
+        let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+        assert_eq!(resp["turns"][0]["data"], data);
+    }
+
+    #[test]
+    fn configured_render_defaults_apply_when_params_are_absent() {
+        let server = start_test_server_with(
+            None,
+            HttpOptions {
+                render_defaults: RenderOptions {
+                    u64_format: U64Format::String,
+                    ..RenderOptions::default()
+                },
+                ..HttpOptions::default()
+            },
+        );
+        let addr = &server.addr;
+
+        let bundle = json!({
+            "registry_version": 1,
+            "bundle_id": "counters",
+            "types": {"com.example.Counter": {"versions": {"1": {"fields": {
+                "1": {"name": "count", "type": "u64"}
+            }}}}},
+            "enums": {},
+        })
+        .to_string();
+        let (status, resp) = http_request(addr, "PUT", "/v1/registry/bundles/counters", &bundle);
+        assert_eq!(status, 201, "{resp}");
+
+        let (_, resp) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&resp).expect("json");
+        let ctx = created["context_id"].as_str().unwrap().to_string();
+        let body = json!({
+            "type_id": "com.example.Counter",
+            "type_version": 1,
+            "data": {"count": 42},
+        })
+        .to_string();
+        let (status, resp) =
+            http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+        assert_eq!(status, 201, "{resp}");
+
+        let (status, resp) = http_request(addr, "GET", &format!("/v1/contexts/{ctx}/turns"), "");
+        assert_eq!(status, 200, "{resp}");
+        let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+        assert_eq!(resp["turns"][0]["data"]["count"], json!("42"));
+
+        // An explicit param still wins over the configured default.
+        let (status, resp) = http_request(
+            addr,
+            "GET",
+            &format!("/v1/contexts/{ctx}/turns?u64_format=number"),
+            "",
+        );
+        assert_eq!(status, 200, "{resp}");
+        let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+        assert_eq!(resp["turns"][0]["data"]["count"], json!(42));
+    }
+
+    #[test]
+    fn max_depth_param_is_capped_at_the_configured_default() {
+        let defaults = RenderOptions {
+            max_depth: 4,
+            ..RenderOptions::default()
+        };
+        let depth = |query: &str| render_options(&parse_query(query), &defaults).max_depth;
+        assert_eq!(depth(""), 4);
+        assert_eq!(depth("max_depth=2"), 2);
+        assert_eq!(depth("max_depth=1000000"), 4);
+        assert_eq!(depth("max_depth=abc"), 4);
+    }
+
+    #[test]
+    fn search_order_ranks_by_activity_and_relevance() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let session_tracker = &server.session_tracker;
+
+        // Titled, so each context is indexed before it has turns.
+        let ids: Vec<u64> = (0..3)
+            .map(|i| {
+                let body = json!({"metadata": {"title": format!("ctx {i}")}}).to_string();
+                let (_, resp) = http_request(addr, "POST", "/v1/contexts", &body);
+                let created: JsonValue = serde_json::from_str(&resp).expect("json");
+                created["context_id"].as_str().unwrap().parse().unwrap()
+            })
+            .collect();
+        // The oldest context's session was active most recently; the newest
+        // has no session at all.
+        for (session_id, context_id) in [(1, ids[0]), (2, ids[1])] {
+            session_tracker.register(session_id, format!("s{session_id}"), None);
+            session_tracker.add_context(session_id, context_id);
+        }
+        thread::sleep(Duration::from_millis(5));
+        session_tracker.record_activity(1);
+
+        let search = |query: &str, order: &str| -> Vec<String> {
+            let q: String = url::form_urlencoded::byte_serialize(query.as_bytes()).collect();
+            let (status, resp) = http_request(
+                addr,
+                "GET",
+                &format!("/v1/contexts/search?q={q}&order={order}"),
+                "",
+            );
+            assert_eq!(status, 200, "{resp}");
+            let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+            resp["contexts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["context_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let all = format!("id IN ({}, {}, {})", ids[0], ids[1], ids[2]);
+        let expect = |order: [usize; 3]| order.map(|i| ids[i].to_string()).to_vec();
+
+        assert_eq!(search(&all, "recent"), expect([2, 1, 0]));
+        assert_eq!(search(&all, "activity"), expect([0, 1, 2]));
+        // The first context matches two comparisons, the others one.
+        let query = format!("id = {} OR {all}", ids[0]);
+        assert_eq!(search(&query, "relevance"), expect([0, 2, 1]));
+
+        let (status, _) = http_request(addr, "GET", "/v1/contexts/search?q=id%3D1&order=size", "");
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn set_head_promotes_continued_branch() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let store = &server.store;
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"]
+            .as_str()
+            .expect("context_id")
+            .to_string();
+        let append = |parent: &str, set_head: bool| -> JsonValue {
+            let body = json!({
+                "type_id": "com.example.Note",
+                "type_version": 1,
+                "data": {"text": "hi"},
+                "parent_turn_id": parent,
+                "set_head": set_head,
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            assert_eq!(status, 201, "{resp}");
+            serde_json::from_str(&resp).expect("json")
+        };
+        let turn_id = |resp: &JsonValue| resp["turn_id"].as_str().unwrap().to_string();
+
+        let root = turn_id(&append("0", false));
+        let main = turn_id(&append("0", false));
+        let branch_a = append(&root, false);
+        assert_eq!(branch_a["branched"], true);
+        let branch_b = turn_id(&append(&root, false));
+
+        let continued = append(&branch_b, true);
+        assert_eq!(continued["branched"], false);
+        assert_eq!(continued["head_turn_id"], continued["turn_id"]);
+
+        let context_id: u64 = ctx.parse().unwrap();
+        let store = store.lock().unwrap();
+        let head = store.get_head(context_id).expect("head");
+        assert_eq!(head.head_turn_id.to_string(), turn_id(&continued));
+        // The old head survives as a side-branch tip next to branch A.
+        let mut tips: Vec<String> = store
+            .branch_tips(context_id)
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        tips.sort();
+        let mut expected = vec![turn_id(&branch_a), main];
+        expected.sort();
+        assert_eq!(tips, expected);
+    }
+
+    #[test]
+    fn keyed_appends_replay_the_original_turn() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"]
+            .as_str()
+            .expect("context_id")
+            .to_string();
+        let append = |text: &str, key: &str| -> (u16, JsonValue) {
+            let body = json!({
+                "type_id": "com.example.Note",
+                "type_version": 1,
+                "data": {"text": text},
+                "idempotency_key": key,
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            (status, serde_json::from_str(&resp).expect("json"))
+        };
+
+        let (status, first) = append("hi", "k1");
+        assert_eq!(status, 201, "{first}");
+        let (status, retry) = append("hi", "k1");
+        assert_eq!(status, 201, "{retry}");
+        assert_eq!(retry["turn_id"], first["turn_id"]);
+        let (status, err) = append("bye", "k1");
+        assert_eq!(status, 409, "{err}");
+
+        let context_id: u64 = ctx.parse().unwrap();
+        let head = server
+            .store
+            .lock()
+            .unwrap()
+            .get_head(context_id)
+            .expect("head");
+        assert_eq!(head.turn_count, 1);
+    }
+
+    #[test]
+    fn head_digest_is_stable_until_the_head_moves() {
+        let server = start_test_server();
+        let addr = &server.addr;
+        let store = &server.store;
+
+        let (_, body) = http_request(addr, "POST", "/v1/contexts", "");
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+        let ctx = created["context_id"]
+            .as_str()
+            .expect("context_id")
+            .to_string();
+        let append = |text: &str, parent: &str| -> String {
+            let body = json!({
+                "type_id": "com.example.Note",
+                "type_version": 1,
+                "data": {"text": text},
+                "parent_turn_id": parent,
+            })
+            .to_string();
+            let (status, resp) =
+                http_request(addr, "POST", &format!("/v1/contexts/{ctx}/turns"), &body);
+            assert_eq!(status, 201, "{resp}");
+            let resp: JsonValue = serde_json::from_str(&resp).expect("json");
+            resp["turn_id"].as_str().unwrap().to_string()
+        };
+        let digest = || -> JsonValue {
+            let (status, body) =
+                http_request(addr, "GET", &format!("/v1/contexts/{ctx}/digest"), "");
+            assert_eq!(status, 200, "{body}");
+            serde_json::from_str(&body).expect("json")
+        };
+
+        let empty = digest();
+        assert_eq!(empty["head_turn_id"], "0");
+        assert_eq!(empty["digest"], hex::encode([0u8; 32]));
+
+        let root = append("a", "0");
+        let first = digest();
+        assert_eq!(first["head_turn_id"], root.as_str());
+        assert_eq!(first["head_depth"], 0);
+        assert_ne!(first["digest"], empty["digest"]);
+        assert_eq!(digest(), first);
+
+        append("b", "0");
+        let second = digest();
+        assert_ne!(second["digest"], first["digest"]);
+        assert_eq!(digest(), second);
+
+        // A side-branch append leaves the head, and so the digest, alone.
+        append("c", &root);
+        assert_eq!(digest(), second);
+
+        // The incrementally extended digest matches folding the chain.
+        let context_id: u64 = ctx.parse().unwrap();
+        let turns = store
+            .lock()
+            .unwrap()
+            .get_last(context_id, 10, false)
+            .expect("turns");
+        let expected = turns.iter().fold([0u8; 32], |d, turn| {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&d);
+            hasher.update(&turn.record.payload_hash);
+            *hasher.finalize().as_bytes()
+        });
+        assert_eq!(second["digest"], hex::encode(expected));
+    }
+
+    #[test]
+    fn create_with_metadata_lists_title_before_any_turn() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        let (status, body) = http_request(
+            addr,
+            "POST",
+            "/v1/contexts",
+            r#"{"metadata": {"title": "Onboarding", "labels": ["docs"]}}"#,
+        );
+        assert_eq!(status, 201);
+        let created: JsonValue = serde_json::from_str(&body).expect("json");
+
+        let (status, body) = http_request(addr, "GET", "/v1/contexts", "");
+        assert_eq!(status, 200);
+        let listing: JsonValue = serde_json::from_str(&body).expect("json");
+        let entry = &listing["contexts"][0];
+        assert_eq!(entry["context_id"], created["context_id"]);
+        assert_eq!(entry["title"], "Onboarding");
+        assert_eq!(entry["labels"], json!(["docs"]));
+        assert_eq!(entry["turn_count"], 0);
+
+        let (status, _) = http_request(
+            addr,
+            "POST",
+            "/v1/contexts",
+            r#"{"metadata": {"title": 7}}"#,
+        );
+        assert_eq!(status, 422);
+    }
+
+    #[test]
+    fn metrics_stream_announces_interval() {
+        use std::io::{BufRead, BufReader};
+
+        let server = start_test_server();
+        let addr = &server.addr;
+        let metrics = &server.metrics;
+
+        // Connection established via http_status's retry loop first.
+        assert_eq!(http_status(addr, "/healthz"), 200);
+        let mut stream = std::net::TcpStream::connect(addr).expect("connect");
+        write!(
+            stream,
+            "GET /v1/metrics/stream HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .expect("write request");
+        let reader = BufReader::new(stream);
+        let data = reader
+            .lines()
+            .map(|line| line.expect("read line"))
+            .find_map(|line| line.strip_prefix("data: ").map(str::to_string))
+            .expect("connected event");
+        let connected: JsonValue = serde_json::from_str(&data).expect("json");
+        assert_eq!(
+            connected["interval_secs"],
+            metrics.stream_interval().as_secs()
+        );
+        assert_eq!(metrics.stream_subscriber_count(), 1);
+    }
+
+    #[test]
+    fn shutdown_closes_the_listener_and_stops_the_ticker() {
+        let server = start_test_server();
+        assert_eq!(http_status(&server.addr, "/healthz"), 200);
+        // Returns only once the listener and metrics ticker threads exit.
+        server.http.shutdown();
+        // tiny_http's accept thread drops the socket shortly after.
+        let closed = (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+            std::net::TcpStream::connect(&server.addr).is_err()
+        });
+        assert!(closed, "listener still accepting after shutdown");
+    }
+
+    #[test]
+    fn admin_routes_move_to_admin_listener() {
+        let admin_addr = free_addr();
+        let server = start_test_server_with(Some(admin_addr.clone()), HttpOptions::default());
+        let main_addr = &server.addr;
+
+        assert_eq!(http_status(&admin_addr, "/v1/metrics"), 200);
+        assert_eq!(http_status(main_addr, "/v1/contexts"), 200);
+        assert_eq!(http_status(main_addr, "/v1/metrics"), 404);
+        assert_eq!(http_status(&admin_addr, "/v1/contexts"), 404);
+        assert_eq!(http_status(&admin_addr, "/healthz"), 200);
+    }
+
+    #[test]
+    fn mime_overrides_take_precedence_over_builtin_types() {
+        let dir = tempdir().expect("tempdir");
+        let path = dir.path().join("mime.json");
+        std::fs::write(
+            &path,
+            r#"{".MJS": "application/javascript", "wgsl": "text/wgsl"}"#,
+        )
+        .expect("write overrides");
+        let overrides = load_mime_overrides(&path).expect("load overrides");
+
+        assert_eq!(
+            guess_content_type("app/main.mjs", &overrides),
+            "application/javascript"
+        );
+        assert_eq!(guess_content_type("shader.WGSL", &overrides), "text/wgsl");
+        assert_eq!(
+            guess_content_type("mod.wasm", &overrides),
+            "application/wasm"
+        );
+        assert_eq!(
+            guess_content_type("data.unknownext", &overrides),
+            "application/octet-stream"
+        );
+
+        std::fs::write(&path, "not json").expect("write overrides");
+        assert!(load_mime_overrides(&path).is_err());
+    }
+
+    #[test]
+    fn errors_are_labelled_by_the_route_that_matched() {
+        let server = start_test_server();
+        let addr = &server.addr;
+
+        for (method, path) in [
+            ("POST", "/v1/contexts/42/append"),
+            ("GET", "/v1/contexts/7/turns?limit=5"),
+            ("GET", "/v1/turns/3/fs/src/main.rs"),
+            ("GET", "/v1/registry/types/com.example.Msg/versions/2"),
+            ("GET", "/v1/random-probe-1234"),
+            ("DELETE", "/v1/contexts/1"),
+            ("GET", "/v1/contexts/1/turns/extra"),
+        ] {
+            let (status, body) = http_request(addr, method, path, "{}");
+            assert!(status >= 400, "{method} {path}: {body}");
+        }
+
+        let (status, body) = http_request(addr, "GET", "/v1/errors", "");
+        assert_eq!(status, 200, "{body}");
+        let result: JsonValue = serde_json::from_str(&body).expect("json");
+        let mut ops: Vec<&str> = result["errors"]
+            .as_array()
+            .expect("errors")
+            .iter()
+            .map(|e| e["op"].as_str().expect("op"))
+            .collect();
+        ops.reverse();
+        assert_eq!(
+            ops,
+            [
+                "POST /v1/contexts/:id/append",
+                "GET /v1/contexts/:id/turns",
+                "GET /v1/turns/:id/fs/*",
+                "GET /v1/registry/types/:id/versions/:id",
+                "unmatched",
+                "unmatched",
+                "unmatched",
+            ]
+        );
+    }
+
+    #[test]
+    fn not_found_errors_carry_kind_in_body() {
+        let err = StoreError::not_found(NotFoundKind::Blob, "blob");
+        let (status, message) = map_error(&err);
+        assert_eq!(status, 404);
+        let body = error_body(&err, status, &message);
+        assert_eq!(body["error"]["kind"], "blob");
+        assert_eq!(body["error"]["message"], "blob: blob");
+
+        let err = StoreError::not_found(NotFoundKind::TypeDescriptor, "type descriptor");
+        assert_eq!(map_error(&err).0, 424);
+        let err = StoreError::not_found(NotFoundKind::ParentTurn, "parent turn");
+        assert_eq!(map_error(&err).0, 409);
+        // A missing turn that is only read stays a 404, whatever the wording.
+        let err = StoreError::not_found(NotFoundKind::Turn, "parent turn");
+        assert_eq!(map_error(&err).0, 404);
+    }
 }
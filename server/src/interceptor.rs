@@ -0,0 +1,59 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Synchronous hooks on the append path.
+//!
+//! An [`AppendInterceptor`] sees every turn payload before it is stored and
+//! can reject it (validation, PII scanning) or replace it (enrichment).
+//! Interceptors are registered with `Store::open_with_interceptors` and run
+//! in registration order under the store lock, so they should be fast.
+
+use crate::error::{Result, StoreError};
+
+/// The turn being appended, as an interceptor sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct AppendContext<'a> {
+    pub context_id: u64,
+    /// As requested; 0 means "append to the head".
+    pub parent_turn_id: u64,
+    pub declared_type_id: &'a str,
+    pub declared_type_version: u32,
+    pub encoding: u32,
+}
+
+pub trait AppendInterceptor: Send + Sync {
+    /// Inspect the uncompressed payload. `Err` rejects the append,
+    /// `Ok(Some(bytes))` replaces the payload (the store re-hashes it and later
+    /// interceptors see the new bytes), and `Ok(None)` keeps it.
+    fn before_append(&self, ctx: &AppendContext<'_>, payload: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+/// Rejects payloads larger than `max_bytes` once uncompressed.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxPayloadSize {
+    pub max_bytes: usize,
+}
+
+impl MaxPayloadSize {
+    /// `CXDB_APPEND_MAX_PAYLOAD_BYTES`, if set to a non-zero size.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("CXDB_APPEND_MAX_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|max_bytes| *max_bytes > 0)
+            .map(|max_bytes| Self { max_bytes })
+    }
+}
+
+impl AppendInterceptor for MaxPayloadSize {
+    fn before_append(&self, _ctx: &AppendContext<'_>, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        if payload.len() > self.max_bytes {
+            return Err(StoreError::InvalidInput(format!(
+                "payload is {} bytes, limit is {}",
+                payload.len(),
+                self.max_bytes
+            )));
+        }
+        Ok(None)
+    }
+}
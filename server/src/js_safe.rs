@@ -0,0 +1,43 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integers in JSON responses that browsers read.
+//!
+//! JavaScript parses every JSON number as a double, so integers past
+//! 2^53 - 1 silently lose precision. Responses read by dashboards (metrics,
+//! context listings, provenance) pass through [`make_js_safe`], which turns
+//! those integers into decimal strings and leaves smaller ones as numbers.
+//! Clients that need exact values accept both forms; [`parse_u64`] does
+//! that on the Rust side.
+
+use serde_json::Value as JsonValue;
+
+/// Largest integer a JavaScript number holds exactly (`Number.MAX_SAFE_INTEGER`).
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Replace every integer in `value` whose magnitude exceeds
+/// [`MAX_SAFE_INTEGER`] with its decimal string, recursively.
+pub fn make_js_safe(value: &mut JsonValue) {
+    match value {
+        JsonValue::Number(n) => {
+            let unsafe_int = n.as_u64().is_some_and(|v| v > MAX_SAFE_INTEGER)
+                || n.as_i64()
+                    .is_some_and(|v| v.unsigned_abs() > MAX_SAFE_INTEGER);
+            if unsafe_int {
+                *value = JsonValue::String(n.to_string());
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(make_js_safe),
+        JsonValue::Object(map) => map.values_mut().for_each(make_js_safe),
+        _ => {}
+    }
+}
+
+/// Read a u64 written either as a JSON number or as a decimal string.
+pub fn parse_u64(value: &JsonValue) -> Option<u64> {
+    match value {
+        JsonValue::Number(n) => n.as_u64(),
+        JsonValue::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
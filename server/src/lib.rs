@@ -4,16 +4,32 @@
 //! Library crate for the AI Context Store service.
 
 pub mod blob_store;
+pub mod canonical;
+pub mod cli;
+pub mod clock;
 pub mod config;
+pub mod context_meta;
 pub mod cql;
+pub mod data_mode;
 pub mod error;
 pub mod events;
 pub mod fs_store;
+pub mod handler;
 pub mod http;
+pub mod import;
+pub mod interceptor;
+pub mod js_safe;
+pub mod listener;
+pub mod lock;
+pub mod logging;
+pub mod lru;
 pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats_bridge;
 pub mod projection;
 pub mod protocol;
 pub mod registry;
 pub mod s3_sync;
 pub mod store;
 pub mod turn_store;
+pub mod webhook;
@@ -0,0 +1,336 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Binary protocol listener setup and the accept-loop readiness wait.
+//!
+//! The accept loop needs to notice the shutdown flag, so it cannot block in
+//! `accept()` forever. Instead it waits for the listener to become readable
+//! with a timeout: a new connection wakes it immediately, and the timeout
+//! only bounds how long shutdown takes to be noticed.
+//!
+//! The binary protocol is transport-agnostic: TCP and Unix domain socket
+//! listeners both hand out a [`Connection`] for the same client handler.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Bind a non-blocking TCP listener with an explicit listen backlog.
+pub fn bind_listener(addr: &str, backlog: i32) -> io::Result<TcpListener> {
+    let addr: SocketAddr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "bind address did not resolve")
+    })?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    // Keep `[::]:port` from claiming the IPv4 wildcard too, so it can be
+    // listed alongside `0.0.0.0:port`.
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Bind one listener per address, e.g. an IPv4 and an IPv6 loopback.
+pub fn bind_listeners(addrs: &[String], backlog: i32) -> io::Result<Vec<TcpListener>> {
+    addrs
+        .iter()
+        .map(|addr| bind_listener(addr, backlog))
+        .collect()
+}
+
+/// Bind a non-blocking Unix domain socket listener at `path`.
+///
+/// A stale socket file left by a previous run is replaced; one that still
+/// accepts connections is reported as `AddrInUse`. The socket file gets the
+/// data-dir file mode so only permitted users can connect.
+#[cfg(unix)]
+pub fn bind_unix_listener(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    if let Some(mode) = crate::data_mode::DataMode::from_env() {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.file_mode))?;
+    }
+    Ok(listener)
+}
+
+/// A binary protocol listener.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Accept one pending connection as a blocking stream, with a peer
+    /// description for session tracking.
+    pub fn accept(&self) -> io::Result<(Connection, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok((Connection::Tcp(stream), peer.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok((Connection::Unix(stream), "unix".to_string()))
+            }
+        }
+    }
+
+    /// Human-readable listen address for startup logs.
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|_| "tcp".to_string()),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| format!("unix:{}", p.display())))
+                .unwrap_or_else(|| "unix".to_string()),
+        }
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// An accepted client connection.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Closes a connection from another thread, waking a handler blocked in
+/// `read` with EOF.
+pub type Interrupter = Box<dyn Fn() + Send + Sync>;
+
+/// A stream the client handler can serve. Streams with an `interrupter` can
+/// be disconnected mid-read; others only notice a disconnect request between
+/// frames.
+pub trait ClientStream: Read + Write {
+    fn interrupter(&self) -> Option<Interrupter> {
+        None
+    }
+}
+
+impl<T: ClientStream + ?Sized> ClientStream for &mut T {
+    fn interrupter(&self) -> Option<Interrupter> {
+        (**self).interrupter()
+    }
+}
+
+impl ClientStream for Connection {
+    fn interrupter(&self) -> Option<Interrupter> {
+        match self {
+            Connection::Tcp(stream) => {
+                let stream = stream.try_clone().ok()?;
+                Some(Box::new(move || {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }))
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                let stream = stream.try_clone().ok()?;
+                Some(Box::new(move || {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }))
+            }
+        }
+    }
+}
+
+/// Wait up to `timeout` for a pending connection on any listener. Returns
+/// the indices of listeners where `accept()` should be attempted; empty on
+/// timeout.
+#[cfg(unix)]
+pub fn wait_for_connection(listeners: &[Listener], timeout: Duration) -> io::Result<Vec<usize>> {
+    let mut fds: Vec<libc::pollfd> = listeners
+        .iter()
+        .map(|l| libc::pollfd {
+            fd: l.raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+    // SAFETY: `fds` holds `fds.len()` valid pollfds for the duration of the call.
+    let rc = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+    if rc < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(Vec::new());
+        }
+        return Err(err);
+    }
+    Ok(fds
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+/// Without poll(2), fall back to sleeping for the interval and trying every
+/// listener.
+#[cfg(not(unix))]
+pub fn wait_for_connection(listeners: &[Listener], timeout: Duration) -> io::Result<Vec<usize>> {
+    std::thread::sleep(timeout);
+    Ok((0..listeners.len()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn bind_tcp(addrs: &[&str]) -> Vec<Listener> {
+        let addrs: Vec<String> = addrs.iter().map(|a| a.to_string()).collect();
+        bind_listeners(&addrs, 16)
+            .expect("bind")
+            .into_iter()
+            .map(Listener::Tcp)
+            .collect()
+    }
+
+    fn tcp_addr(listener: &Listener) -> SocketAddr {
+        match listener {
+            Listener::Tcp(l) => l.local_addr().unwrap(),
+            #[cfg(unix)]
+            Listener::Unix(_) => unreachable!(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_wakes_on_connect_and_times_out_when_idle() {
+        let listeners = bind_tcp(&["127.0.0.1:0"]);
+        let addr = tcp_addr(&listeners[0]);
+
+        let start = Instant::now();
+        assert!(wait_for_connection(&listeners, Duration::from_millis(20))
+            .unwrap()
+            .is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(15));
+
+        let _client = TcpStream::connect(addr).expect("connect");
+        let start = Instant::now();
+        assert_eq!(
+            wait_for_connection(&listeners, Duration::from_secs(5)).unwrap(),
+            vec![0]
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        listeners[0].accept().expect("accept");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn binds_ipv4_and_ipv6_loopback_together() {
+        let listeners = bind_tcp(&["127.0.0.1:0", "[::1]:0"]);
+
+        for (i, listener) in listeners.iter().enumerate() {
+            let addr = tcp_addr(listener);
+            assert_eq!(addr.is_ipv6(), i == 1);
+            let _client = TcpStream::connect(addr).expect("connect");
+            assert_eq!(
+                wait_for_connection(&listeners, Duration::from_secs(5)).unwrap(),
+                vec![i]
+            );
+            listener.accept().expect("accept");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_listener_round_trips_and_replaces_stale_socket() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("cxdb.sock");
+        // A socket file left behind by a dead server is replaced.
+        drop(UnixListener::bind(&path).expect("stale bind"));
+
+        let listeners = vec![Listener::Unix(bind_unix_listener(&path).expect("bind"))];
+
+        let mut client = UnixStream::connect(&path).expect("connect");
+        client.write_all(b"ping").unwrap();
+        assert_eq!(
+            wait_for_connection(&listeners, Duration::from_secs(5)).unwrap(),
+            vec![0]
+        );
+        let (mut conn, peer) = listeners[0].accept().expect("accept");
+        assert_eq!(peer, "unix");
+        let mut buf = [0u8; 4];
+        conn.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        conn.write_all(b"pong").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        assert!(bind_unix_listener(&path).is_err(), "live socket is in use");
+    }
+}
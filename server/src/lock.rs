@@ -0,0 +1,24 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mutex locking that survives a panicked holder.
+//!
+//! The store and registry are shared by every connection thread. With
+//! `lock().unwrap()`, one handler panicking while holding the lock would
+//! poison it and make every later request panic too. On-disk writes are
+//! append-then-flush with CRCs, so the in-memory state a panicking holder
+//! leaves behind is still usable; we log, clear the poison, and carry on.
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock `mutex`, recovering the guard if a previous holder panicked.
+pub fn lock_or_recover<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            tracing::warn!("recovering {name} lock poisoned by a panicked handler");
+            mutex.clear_poison();
+            poisoned.into_inner()
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Server log output.
+//!
+//! Everything logs through `tracing`; `init` installs the process-wide
+//! subscriber. The default is plain text on stderr, one line per event with
+//! just the message, as the server printed before; `CXDB_LOG_LEVEL_PREFIX=1`
+//! starts each line with its level. `CXDB_LOG_FORMAT=json` switches to one JSON object per line for log
+//! shippers, and `CXDB_LOG_FILE` appends to a file instead of stderr so logs
+//! can be kept and rotated apart from the data directory.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::{Dispatch, Level};
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `message fields...`, no timestamp (the supervisor adds one).
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level` and `fields`.
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Append to this file instead of writing to stderr.
+    pub file: Option<PathBuf>,
+    /// Most verbose level emitted.
+    pub level: Level,
+    /// Start text lines with the event's level.
+    pub level_prefix: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            file: None,
+            level: Level::INFO,
+            level_prefix: false,
+        }
+    }
+}
+
+impl LogOptions {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let format = match env::var("CXDB_LOG_FORMAT") {
+            Ok(v) if v.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => defaults.format,
+        };
+        Self {
+            format,
+            file: env::var("CXDB_LOG_FILE")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(PathBuf::from),
+            level: env::var("CXDB_LOG_LEVEL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.level),
+            level_prefix: env::var("CXDB_LOG_LEVEL_PREFIX")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(defaults.level_prefix),
+        }
+    }
+}
+
+/// Install the global log subscriber. Call once, early in `main`.
+pub fn init(options: &LogOptions) -> io::Result<()> {
+    let dispatch = match &options.file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            dispatch(options, Mutex::new(file))
+        }
+        None => dispatch(options, io::stderr),
+    };
+    tracing::dispatcher::set_global_default(dispatch).map_err(io::Error::other)
+}
+
+/// Build the subscriber `init` installs, writing to `writer`.
+pub fn dispatch<W>(options: &LogOptions, writer: W) -> Dispatch
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_max_level(options.level)
+        .with_target(false);
+    match options.format {
+        LogFormat::Text => Dispatch::new(
+            builder
+                .without_time()
+                .with_level(options.level_prefix)
+                .with_ansi(false)
+                .finish(),
+        ),
+        LogFormat::Json => Dispatch::new(builder.json().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture_text(options: &LogOptions) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        tracing::dispatcher::with_default(&dispatch(options, move || writer.clone()), || {
+            tracing::info!("cxdb listening on {}", "127.0.0.1:9009");
+        });
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn default_text_format_is_the_bare_message() {
+        assert_eq!(
+            capture_text(&LogOptions::default()),
+            "cxdb listening on 127.0.0.1:9009\n"
+        );
+        let prefixed = capture_text(&LogOptions {
+            level_prefix: true,
+            ..LogOptions::default()
+        });
+        assert_eq!(
+            prefixed.trim_start(),
+            "INFO cxdb listening on 127.0.0.1:9009\n"
+        );
+    }
+
+    #[test]
+    fn json_format_emits_one_parseable_object_per_line() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let options = LogOptions {
+            format: LogFormat::Json,
+            ..LogOptions::default()
+        };
+        tracing::dispatcher::with_default(&dispatch(&options, move || writer.clone()), || {
+            tracing::info!(session_id = 7, "cxdb listening on {}", "127.0.0.1:9009");
+            tracing::warn!("accept error: \"quoted\"\nsecond line");
+            tracing::debug!("below the default level");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json line"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(
+            lines[0]["fields"]["message"],
+            "cxdb listening on 127.0.0.1:9009"
+        );
+        assert_eq!(lines[0]["fields"]["session_id"], 7);
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(
+            lines[1]["fields"]["message"],
+            "accept error: \"quoted\"\nsecond line"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small least-recently-used cache.
+//!
+//! Entries are stamped with a tick on every use, and a tick-ordered index
+//! makes the least recently used entry the first key, so lookups, inserts
+//! and evictions are all O(log n) rather than a scan of the cache.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    /// Values and the tick they were last used at.
+    entries: HashMap<K, (V, u64)>,
+    /// The same keys ordered by last use, so the oldest is the first key.
+    by_tick: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// A cache holding up to `capacity` entries; 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            by_tick: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The cached value for `key`, marking it most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.by_tick.remove(last_used)?;
+        self.by_tick.insert(self.tick, key);
+        *last_used = self.tick;
+        Some(value)
+    }
+
+    /// Cache `value` under `key`. Re-inserting a cached key refreshes it;
+    /// otherwise a full cache first drops its least recently used entry.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.get(&key) {
+            self.by_tick.remove(last_used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.by_tick.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.by_tick.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        // Re-inserting a cached key refreshes it instead of evicting.
+        cache.insert("b", 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.get(&"c"), Some(&3));
+        assert_eq!(cache.by_tick.len(), cache.entries.len());
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cache = LruCache::new(0);
+        cache.insert("a", 1);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&"a"), None);
+    }
+}
@@ -1,16 +1,22 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use chrono::{SecondsFormat, Utc};
-use serde::Serialize;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
 use sysinfo::{Pid, System};
 
+use crate::clock::{system_clock, Clock};
+use crate::context_meta::TagNormalization;
+use crate::js_safe::make_js_safe;
+use crate::listener::Interrupter;
 use crate::registry::Registry;
 use crate::store::Store;
 
@@ -23,25 +29,102 @@ pub struct ClientSession {
     pub connected_at: u64,         // unix_ms
     pub last_activity_at: u64,     // unix_ms
     pub contexts_created: Vec<u64>, // context IDs created by this session
+    /// Contexts created past the tracker's per-session cap; counted but not
+    /// associated with the session (they don't show as live).
+    pub contexts_untracked: u64,
+    /// Frames received on the connection, including any sent before the
+    /// session was registered.
+    pub request_count: u64,
+    pub bytes_in: u64,  // frame bytes received, headers included
+    pub bytes_out: u64, // frame bytes sent, headers included
+}
+
+impl ClientSession {
+    /// Contexts this session has created, tracked or not.
+    pub fn context_count(&self) -> u64 {
+        self.contexts_created.len() as u64 + self.contexts_untracked
+    }
+}
+
+/// Prefix of the synthetic client tag given to sessions that never sent one.
+pub const ANON_CLIENT_TAG_PREFIX: &str = "anon:";
+
+/// Default `SessionTracker` per-session context cap.
+pub const DEFAULT_SESSION_MAX_CONTEXTS: usize = 10_000;
+
+/// Per-connection disconnect switch, attached for the life of the handler.
+struct SessionControl {
+    disconnect: Arc<AtomicBool>,
+    interrupter: Option<Interrupter>,
 }
 
 /// Tracks connected client sessions and their metadata.
-#[derive(Default)]
 pub struct SessionTracker {
     sessions: RwLock<HashMap<u64, ClientSession>>,
     context_to_session: RwLock<HashMap<u64, u64>>,
+    controls: Mutex<HashMap<u64, SessionControl>>,
+    /// Contexts associated with one session at most; later creations are
+    /// only counted, so a long-lived session can't grow without bound.
+    max_contexts_per_session: usize,
+    tag_normalization: TagNormalization,
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        Self::with_max_contexts(DEFAULT_SESSION_MAX_CONTEXTS)
+    }
 }
 
 impl SessionTracker {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_contexts(max_contexts_per_session: usize) -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
             context_to_session: RwLock::new(HashMap::new()),
+            controls: Mutex::new(HashMap::new()),
+            max_contexts_per_session,
+            tag_normalization: TagNormalization::default(),
         }
     }
 
+    /// Canonicalize client tags with `normalization` as sessions register.
+    pub fn with_tag_normalization(mut self, normalization: TagNormalization) -> Self {
+        self.tag_normalization = normalization;
+        self
+    }
+
+    /// Read the per-session cap from `CXDB_SESSION_MAX_CONTEXTS` and the tag
+    /// normalization from `CXDB_TAG_NORMALIZE`.
+    pub fn from_env() -> Self {
+        Self::with_max_contexts(env_u64(
+            "CXDB_SESSION_MAX_CONTEXTS",
+            DEFAULT_SESSION_MAX_CONTEXTS as u64,
+        ) as usize)
+        .with_tag_normalization(TagNormalization::from_env())
+    }
+
+    /// `tag` as sessions store it, for comparing against their tags.
+    pub fn normalize_tag(&self, tag: &str) -> String {
+        self.tag_normalization.apply(tag)
+    }
+
     /// Register a new session with the given client tag and optional peer address.
-    pub fn register(&self, session_id: u64, client_tag: String, peer_addr: Option<String>) {
+    /// An empty tag falls back to `anon:<peer_addr>` so untagged connections
+    /// can still be told apart. Returns the tag the session was registered with.
+    pub fn register(
+        &self,
+        session_id: u64,
+        client_tag: String,
+        peer_addr: Option<String>,
+    ) -> String {
+        let client_tag = self.tag_normalization.apply(&client_tag);
+        let client_tag = match &peer_addr {
+            Some(addr) if client_tag.is_empty() => format!("{ANON_CLIENT_TAG_PREFIX}{addr}"),
+            _ => client_tag,
+        };
         let now_ms = unix_ms();
         let session = ClientSession {
             session_id,
@@ -50,8 +133,16 @@ impl SessionTracker {
             connected_at: now_ms,
             last_activity_at: now_ms,
             contexts_created: Vec::new(),
+            contexts_untracked: 0,
+            request_count: 0,
+            bytes_in: 0,
+            bytes_out: 0,
         };
-        self.sessions.write().unwrap().insert(session_id, session);
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session_id, session.clone());
+        session.client_tag
     }
 
     /// Get the peer address for a session.
@@ -71,21 +162,80 @@ impl SessionTracker {
         }
     }
 
-    /// Associate a context with a session.
+    /// Update a session's running traffic totals. Unregistered sessions are
+    /// ignored; their totals land on the next call after registration.
+    pub fn record_traffic(
+        &self,
+        session_id: u64,
+        request_count: u64,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) {
+        if let Some(session) = self.sessions.write().unwrap().get_mut(&session_id) {
+            session.request_count = request_count;
+            session.bytes_in = bytes_in;
+            session.bytes_out = bytes_out;
+        }
+    }
+
+    /// Associate a context with a session. Once the session holds
+    /// `max_contexts_per_session` contexts, further ones are only counted in
+    /// `contexts_untracked`.
     pub fn add_context(&self, session_id: u64, context_id: u64) {
+        let mut sessions = self.sessions.write().unwrap();
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return;
+        };
+        if session.contexts_created.contains(&context_id) {
+            return;
+        }
+        if session.contexts_created.len() >= self.max_contexts_per_session {
+            session.contexts_untracked += 1;
+            return;
+        }
+        session.contexts_created.push(context_id);
         self.context_to_session
             .write()
             .unwrap()
             .insert(context_id, session_id);
-        if let Some(session) = self.sessions.write().unwrap().get_mut(&session_id) {
-            if !session.contexts_created.contains(&context_id) {
-                session.contexts_created.push(context_id);
-            }
+    }
+
+    /// Make a connection disconnectable through `request_disconnect`. The
+    /// returned flag is set once a disconnect is requested; the handler
+    /// checks it between frames.
+    pub fn attach_control(
+        &self,
+        session_id: u64,
+        interrupter: Option<Interrupter>,
+    ) -> Arc<AtomicBool> {
+        let disconnect = Arc::new(AtomicBool::new(false));
+        self.controls.lock().unwrap().insert(
+            session_id,
+            SessionControl {
+                disconnect: Arc::clone(&disconnect),
+                interrupter,
+            },
+        );
+        disconnect
+    }
+
+    /// Ask a connection's handler to close it, interrupting a blocked read
+    /// where the stream allows. Returns false for an unknown session.
+    pub fn request_disconnect(&self, session_id: u64) -> bool {
+        let controls = self.controls.lock().unwrap();
+        let Some(control) = controls.get(&session_id) else {
+            return false;
+        };
+        control.disconnect.store(true, Ordering::SeqCst);
+        if let Some(interrupt) = &control.interrupter {
+            interrupt();
         }
+        true
     }
 
     /// Unregister a session and return its orphaned contexts.
     pub fn unregister(&self, session_id: u64) -> Vec<u64> {
+        self.controls.lock().unwrap().remove(&session_id);
         let session = self.sessions.write().unwrap().remove(&session_id);
         if let Some(session) = session {
             let mut ctx_map = self.context_to_session.write().unwrap();
@@ -121,6 +271,20 @@ impl SessionTracker {
             .collect()
     }
 
+    /// Last activity time (unix ms) of each live context's session.
+    pub fn get_live_context_activity(&self) -> HashMap<u64, u64> {
+        let ctx_map = self.context_to_session.read().unwrap();
+        let sessions = self.sessions.read().unwrap();
+        ctx_map
+            .iter()
+            .filter_map(|(context_id, session_id)| {
+                sessions
+                    .get(session_id)
+                    .map(|s| (*context_id, s.last_activity_at))
+            })
+            .collect()
+    }
+
     /// Get the client tag for a session.
     pub fn get_client_tag(&self, session_id: u64) -> Option<String> {
         self.sessions
@@ -138,12 +302,15 @@ impl SessionTracker {
             .contains_key(&context_id)
     }
 
-    /// Get all unique client tags from active sessions.
+    /// Get all unique client tags from active sessions. Synthetic `anon:`
+    /// tags are per-connection and left out.
     pub fn get_active_tags(&self) -> Vec<String> {
         let sessions = self.sessions.read().unwrap();
         let mut tags: HashSet<String> = HashSet::new();
         for session in sessions.values() {
-            if !session.client_tag.is_empty() {
+            if !session.client_tag.is_empty()
+                && !session.client_tag.starts_with(ANON_CLIENT_TAG_PREFIX)
+            {
                 tags.insert(session.client_tag.clone());
             }
         }
@@ -151,8 +318,19 @@ impl SessionTracker {
     }
 }
 
-const MAX_LATENCY_SAMPLES: usize = 2048;
+const DEFAULT_LATENCY_SAMPLES: usize = 2048;
 const MAX_ERROR_ENTRIES: usize = 256;
+/// Buffered samples at which a thread tries to merge into the shared store.
+const LATENCY_FLUSH_AT: usize = 256;
+
+static NEXT_METRICS_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// This thread's latency buffer for each `Metrics` it has recorded into,
+    /// keyed by `Metrics::id`.
+    static LATENCY_BUFFERS: RefCell<Vec<(u64, Arc<LatencyBuffer>)>> =
+        const { RefCell::new(Vec::new()) };
+}
 
 #[derive(Debug, Clone)]
 pub struct MetricsConfig {
@@ -162,8 +340,27 @@ pub struct MetricsConfig {
     pub hot_ratio: f64,
     pub critical_ratio: f64,
     pub idle_seconds: u64,
+    /// Upper bounds (ms, ascending) of the latency histogram buckets; an
+    /// implicit +Inf bucket follows the last one.
+    pub latency_buckets_ms: Vec<f64>,
+    /// Max samples kept per operation for percentile estimates.
+    pub latency_samples: usize,
+    /// When set, samples older than this are dropped so percentiles cover a
+    /// fixed time span regardless of throughput.
+    pub latency_window_secs: Option<u64>,
+    /// Only every Nth timed operation is pushed into the latency reservoirs
+    /// and histograms, so the shared lock isn't taken on every request.
+    /// Operation counters are unaffected. 1 records everything.
+    pub latency_sample_every: u64,
+    /// Seconds between snapshots pushed to `/v1/metrics/stream` subscribers.
+    pub stream_interval_secs: u64,
+    /// Source of snapshot and error timestamps and of "now" for idle
+    /// session counts.
+    pub clock: Arc<dyn Clock>,
 }
 
+const DEFAULT_LATENCY_BUCKETS_MS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 1000.0];
+
 impl MetricsConfig {
     pub fn from_env() -> Self {
         let budget_pct = env_f64("CXDB_METRICS_BUDGET_PCT", 0.70).clamp(0.10, 0.85);
@@ -172,6 +369,19 @@ impl MetricsConfig {
         let hot_ratio = env_f64("CXDB_METRICS_HOT_RATIO", 0.80).clamp(0.10, 0.98);
         let critical_ratio = env_f64("CXDB_METRICS_CRITICAL_RATIO", 0.92).clamp(0.10, 0.999);
         let idle_seconds = env_u64("CXDB_METRICS_IDLE_SECONDS", 60);
+        let latency_buckets_ms = std::env::var("CXDB_METRICS_LATENCY_BUCKETS_MS")
+            .ok()
+            .and_then(|v| parse_buckets(&v))
+            .unwrap_or_else(|| DEFAULT_LATENCY_BUCKETS_MS.to_vec());
+        let latency_samples = env_u64(
+            "CXDB_METRICS_LATENCY_SAMPLES",
+            DEFAULT_LATENCY_SAMPLES as u64,
+        )
+        .clamp(16, 1_000_000) as usize;
+        let latency_window_secs =
+            Some(env_u64("CXDB_METRICS_LATENCY_WINDOW_SECS", 0)).filter(|secs| *secs > 0);
+        let latency_sample_every = env_u64("CXDB_METRICS_LATENCY_SAMPLE_EVERY", 1).max(1);
+        let stream_interval_secs = env_u64("CXDB_METRICS_STREAM_INTERVAL_SECS", 5).max(1);
         Self {
             budget_pct,
             hard_cap_bytes,
@@ -179,6 +389,12 @@ impl MetricsConfig {
             hot_ratio,
             critical_ratio,
             idle_seconds,
+            latency_buckets_ms,
+            latency_samples,
+            latency_window_secs,
+            latency_sample_every,
+            stream_interval_secs,
+            clock: system_clock(),
         }
     }
 }
@@ -203,18 +419,32 @@ pub struct Metrics {
     http_errors_total: AtomicU64,
     errors_total: AtomicU64,
     errors_by_type: Mutex<HashMap<String, u64>>,
+    errors_by_op: Mutex<HashMap<String, u64>>,
     recent_errors: Mutex<VecDeque<ErrorEntry>>,
+    webhook_delivered_total: AtomicU64,
+    webhook_dropped_total: AtomicU64,
+    nats_dropped_total: AtomicU64,
 
     rates: Mutex<RateStore>,
+    id: u64,
     latencies: Mutex<LatencyStore>,
+    /// Every thread's buffer, drained into `latencies` by `flush_latencies`.
+    latency_buffers: Mutex<Vec<Arc<LatencyBuffer>>>,
+    latency_ticks: AtomicU64,
     system: Mutex<System>,
+    stream_subscribers: Mutex<Vec<Sender<Arc<str>>>>,
 }
 
 impl Metrics {
     pub fn new(data_dir: PathBuf) -> Self {
+        Self::with_config(data_dir, MetricsConfig::from_env())
+    }
+
+    pub fn with_config(data_dir: PathBuf, config: MetricsConfig) -> Self {
         let pid = Pid::from_u32(std::process::id());
+        let latencies = LatencyStore::new(&config);
         Self {
-            config: MetricsConfig::from_env(),
+            config,
             start: Instant::now(),
             pid,
             data_dir,
@@ -231,18 +461,127 @@ impl Metrics {
             http_errors_total: AtomicU64::new(0),
             errors_total: AtomicU64::new(0),
             errors_by_type: Mutex::new(HashMap::new()),
+            errors_by_op: Mutex::new(HashMap::new()),
             recent_errors: Mutex::new(VecDeque::new()),
+            webhook_delivered_total: AtomicU64::new(0),
+            webhook_dropped_total: AtomicU64::new(0),
+            nats_dropped_total: AtomicU64::new(0),
             rates: Mutex::new(RateStore::new()),
-            latencies: Mutex::new(LatencyStore::new()),
+            id: NEXT_METRICS_ID.fetch_add(1, Ordering::Relaxed),
+            latencies: Mutex::new(latencies),
+            latency_buffers: Mutex::new(Vec::new()),
+            latency_ticks: AtomicU64::new(0),
             system: Mutex::new(System::new()),
+            stream_subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Like `new`, resuming the cumulative counters saved by the last
+    /// `persist_counters` in `data_dir`. Rates, latencies and recent errors
+    /// start empty. An unreadable counters file is logged and ignored.
+    pub fn open(data_dir: PathBuf) -> Self {
+        let metrics = Self::new(data_dir);
+        let path = metrics.data_dir.join(COUNTERS_FILE);
+        match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<PersistedCounters>(&bytes) {
+                Ok(counters) => metrics.restore_counters(&counters),
+                Err(e) => tracing::warn!("ignoring unreadable {}: {e}", path.display()),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("ignoring unreadable {}: {e}", path.display()),
+        }
+        metrics
+    }
+
+    /// Current values of the monotonic counters that survive a restart.
+    pub fn counters(&self) -> PersistedCounters {
+        PersistedCounters {
+            sessions_total: self.sessions_total.load(Ordering::Relaxed),
+            append_total: self.append_total.load(Ordering::Relaxed),
+            get_last_total: self.get_last_total.load(Ordering::Relaxed),
+            get_blob_total: self.get_blob_total.load(Ordering::Relaxed),
+            registry_ingest_total: self.registry_ingest_total.load(Ordering::Relaxed),
+            http_total: self.http_total.load(Ordering::Relaxed),
+            http_errors_total: self.http_errors_total.load(Ordering::Relaxed),
+            errors_total: self.errors_total.load(Ordering::Relaxed),
+            errors_by_type: self
+                .errors_by_type
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+            errors_by_op: self
+                .errors_by_op
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+        }
+    }
+
+    /// Write `counters()` to the data dir for the next `open`. Called on
+    /// graceful shutdown; a crash loses counts since the last call.
+    pub fn persist_counters(&self) -> std::io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.counters()).map_err(std::io::Error::other)?;
+        crate::data_mode::write_file_atomic(&self.data_dir.join(COUNTERS_FILE), &bytes)
+    }
+
+    fn restore_counters(&self, counters: &PersistedCounters) {
+        self.sessions_total
+            .fetch_add(counters.sessions_total, Ordering::Relaxed);
+        self.append_total
+            .fetch_add(counters.append_total, Ordering::Relaxed);
+        self.get_last_total
+            .fetch_add(counters.get_last_total, Ordering::Relaxed);
+        self.get_blob_total
+            .fetch_add(counters.get_blob_total, Ordering::Relaxed);
+        self.registry_ingest_total
+            .fetch_add(counters.registry_ingest_total, Ordering::Relaxed);
+        self.http_total
+            .fetch_add(counters.http_total, Ordering::Relaxed);
+        self.http_errors_total
+            .fetch_add(counters.http_errors_total, Ordering::Relaxed);
+        self.errors_total
+            .fetch_add(counters.errors_total, Ordering::Relaxed);
+        let mut by_type = self.errors_by_type.lock().unwrap();
+        for (kind, count) in &counters.errors_by_type {
+            *by_type.entry(kind.clone()).or_insert(0) += count;
+        }
+        let mut by_op = self.errors_by_op.lock().unwrap();
+        for (op, count) in &counters.errors_by_op {
+            *by_op.entry(op.clone()).or_insert(0) += count;
         }
     }
 
+    pub fn stream_interval(&self) -> Duration {
+        Duration::from_secs(self.config.stream_interval_secs)
+    }
+
+    /// Subscribe to serialized snapshots pushed by the metrics stream ticker.
+    pub fn subscribe_stream(&self) -> Receiver<Arc<str>> {
+        let (tx, rx) = mpsc::channel();
+        self.stream_subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn stream_subscriber_count(&self) -> usize {
+        self.stream_subscribers.lock().unwrap().len()
+    }
+
+    /// Fan one serialized snapshot out to every stream subscriber, dropping
+    /// the ones that have disconnected.
+    pub fn publish_stream(&self, snapshot: Arc<str>) {
+        let mut subs = self.stream_subscribers.lock().unwrap();
+        subs.retain(|tx| tx.send(Arc::clone(&snapshot)).is_ok());
+    }
+
     pub fn register_session(self: &Arc<Self>) -> SessionGuard {
         let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
         self.sessions_total.fetch_add(1, Ordering::Relaxed);
         self.sessions_active.fetch_add(1, Ordering::Relaxed);
-        let now_ms = unix_ms();
+        let now_ms = self.config.clock.now_unix_ms();
         self.last_session_activity_ms
             .store(now_ms, Ordering::Relaxed);
         self.session_activity
@@ -256,7 +595,7 @@ impl Metrics {
     }
 
     pub fn record_session_activity(&self, session_id: u64) {
-        let now_ms = unix_ms();
+        let now_ms = self.config.clock.now_unix_ms();
         self.last_session_activity_ms
             .store(now_ms, Ordering::Relaxed);
         if let Some(last) = self.session_activity.lock().unwrap().get_mut(&session_id) {
@@ -271,29 +610,45 @@ impl Metrics {
 
     pub fn record_append(&self, duration: Duration) {
         self.append_total.fetch_add(1, Ordering::Relaxed);
-        self.latencies
-            .lock()
-            .unwrap()
-            .append
-            .push(duration_to_ms(duration));
+        self.record_latency(LatencyOp::Append, duration);
     }
 
     pub fn record_get_last(&self, duration: Duration) {
         self.get_last_total.fetch_add(1, Ordering::Relaxed);
-        self.latencies
-            .lock()
-            .unwrap()
-            .get_last
-            .push(duration_to_ms(duration));
+        self.record_latency(LatencyOp::GetLast, duration);
     }
 
     pub fn record_get_blob(&self, duration: Duration) {
         self.get_blob_total.fetch_add(1, Ordering::Relaxed);
-        self.latencies
-            .lock()
-            .unwrap()
-            .get_blob
-            .push(duration_to_ms(duration));
+        self.record_latency(LatencyOp::GetBlob, duration);
+    }
+
+    /// One webhook event either delivered or given up on (queue full, or
+    /// every retry failed).
+    pub fn record_webhook_delivery(&self, delivered: bool) {
+        let counter = if delivered {
+            &self.webhook_delivered_total
+        } else {
+            &self.webhook_dropped_total
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `(delivered, dropped)` webhook event counts.
+    pub fn webhook_counts(&self) -> (u64, u64) {
+        (
+            self.webhook_delivered_total.load(Ordering::Relaxed),
+            self.webhook_dropped_total.load(Ordering::Relaxed),
+        )
+    }
+
+    /// One event the NATS bridge dropped because its queue was full.
+    pub fn record_nats_dropped(&self) {
+        self.nats_dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn nats_dropped(&self) -> u64 {
+        self.nats_dropped_total.load(Ordering::Relaxed)
     }
 
     pub fn record_registry_ingest(&self) {
@@ -305,27 +660,115 @@ impl Metrics {
         if status_code >= 400 {
             self.http_errors_total.fetch_add(1, Ordering::Relaxed);
         }
-        self.latencies
-            .lock()
-            .unwrap()
-            .http
-            .push(duration_to_ms(duration));
+        self.record_latency(LatencyOp::Http, duration);
+    }
+
+    /// Buffers a sample in this thread's own buffer, so concurrent handlers
+    /// don't serialize on the shared `latencies` lock. A full buffer is merged
+    /// opportunistically; if a snapshot holds the lock it just keeps growing
+    /// until the next attempt or snapshot.
+    fn record_latency(&self, op: LatencyOp, duration: Duration) {
+        if !self.sample_latency() {
+            return;
+        }
+        let sample = (op, Instant::now(), duration_to_ms(duration));
+        self.with_latency_buffer(|buffer| {
+            let mut pending = buffer.lock().unwrap();
+            pending.push(sample);
+            if pending.len() >= LATENCY_FLUSH_AT {
+                if let Ok(mut latencies) = self.latencies.try_lock() {
+                    latencies.merge(pending.drain(..));
+                }
+            }
+        });
+    }
+
+    fn with_latency_buffer(&self, f: impl FnOnce(&LatencyBuffer)) {
+        LATENCY_BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            if let Some((_, buffer)) = buffers.iter().find(|(id, _)| *id == self.id) {
+                return f(buffer);
+            }
+            // Buffers whose `Metrics` is gone are referenced only from here.
+            buffers.retain(|(_, buffer)| Arc::strong_count(buffer) > 1);
+            let buffer = Arc::new(LatencyBuffer::default());
+            self.latency_buffers.lock().unwrap().push(buffer.clone());
+            f(&buffer);
+            buffers.push((self.id, buffer));
+        })
+    }
+
+    /// Merges every thread's buffered samples into the shared store, oldest
+    /// first, and returns it locked. Buffers of exited threads are dropped
+    /// once drained.
+    fn flush_latencies(&self) -> MutexGuard<'_, LatencyStore> {
+        let mut latencies = self.latencies.lock().unwrap();
+        let mut pending = Vec::new();
+        self.latency_buffers.lock().unwrap().retain(|buffer| {
+            // Checked before draining: a buffer only we hold can't be refilled.
+            let alive = Arc::strong_count(buffer) > 1;
+            pending.append(&mut buffer.lock().unwrap());
+            alive
+        });
+        pending.sort_by_key(|(_, at, _)| *at);
+        latencies.merge(pending);
+        latencies
+    }
+
+    /// True for one in every `latency_sample_every` timed operations.
+    fn sample_latency(&self) -> bool {
+        let every = self.config.latency_sample_every;
+        every <= 1
+            || self
+                .latency_ticks
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(every)
+    }
+
+    /// Records a failed request. `kind` is the transport ("http"/"binary") and
+    /// `op` the operation within it: a binary msg type name such as
+    /// "append_turn", or an HTTP route such as "POST /v1/contexts/:id/append".
+    pub fn record_error(
+        &self,
+        kind: &str,
+        op: &str,
+        status_code: u16,
+        message: &str,
+        path: Option<&str>,
+    ) {
+        self.record_error_correlated(kind, op, status_code, message, path, None);
     }
 
-    pub fn record_error(&self, kind: &str, status_code: u16, message: &str, path: Option<&str>) {
+    /// [`Metrics::record_error`] tagged with the caller's correlation id, so
+    /// the entry can be matched to the upstream request that caused it.
+    pub fn record_error_correlated(
+        &self,
+        kind: &str,
+        op: &str,
+        status_code: u16,
+        message: &str,
+        path: Option<&str>,
+        correlation_id: Option<&str>,
+    ) {
         self.errors_total.fetch_add(1, Ordering::Relaxed);
         {
             let mut map = self.errors_by_type.lock().unwrap();
             let count = map.entry(kind.to_string()).or_insert(0);
             *count += 1;
         }
+        {
+            let mut map = self.errors_by_op.lock().unwrap();
+            *map.entry(op.to_string()).or_insert(0) += 1;
+        }
         {
             let entry = ErrorEntry {
-                timestamp_ms: unix_ms(),
+                timestamp_ms: self.config.clock.now_unix_ms(),
                 kind: kind.to_string(),
+                op: op.to_string(),
                 status_code,
                 message: message.to_string(),
                 path: path.map(|s| s.to_string()),
+                correlation_id: correlation_id.map(|s| s.to_string()),
             };
             let mut buf = self.recent_errors.lock().unwrap();
             if buf.len() >= MAX_ERROR_ENTRIES {
@@ -341,8 +784,30 @@ impl Metrics {
         buf.iter().rev().take(limit).cloned().collect()
     }
 
+    /// Returns the most recent errors matching `filter`, newest first, plus a
+    /// per-status count over every buffered match (not just the first `limit`).
+    pub fn query_errors(&self, filter: &ErrorFilter, limit: usize) -> ErrorQueryResult {
+        let buf = self.recent_errors.lock().unwrap();
+        let mut errors = Vec::new();
+        let mut counts_by_status = BTreeMap::new();
+        let mut matched = 0;
+        for entry in buf.iter().rev().filter(|e| filter.matches(e)) {
+            matched += 1;
+            *counts_by_status.entry(entry.status_code).or_insert(0) += 1;
+            if errors.len() < limit {
+                errors.push(entry.clone());
+            }
+        }
+        ErrorQueryResult {
+            errors,
+            matched,
+            counts_by_status,
+        }
+    }
+
     pub fn snapshot(&self, store: &mut Store, registry: &Registry) -> MetricsSnapshot {
-        let now = Utc::now();
+        let now = DateTime::<Utc>::from_timestamp_millis(self.config.clock.now_unix_ms() as i64)
+            .unwrap_or_default();
         let uptime_seconds = self.start.elapsed().as_secs_f64();
 
         let (memory, storage, objects) = self.collect_stats(store, registry);
@@ -362,16 +827,25 @@ impl Metrics {
         let http_rates = rates.update_http(http_total);
         let http_error_rates = rates.update_http_errors(http_errors);
 
-        let latencies = self.latencies.lock().unwrap();
-        let append_latency = LatencySummary::from_samples(&latencies.append);
-        let get_last_latency = LatencySummary::from_samples(&latencies.get_last);
-        let get_blob_latency = LatencySummary::from_samples(&latencies.get_blob);
-        let http_latency = LatencySummary::from_samples(&latencies.http);
+        let mut latencies = self.flush_latencies();
+        latencies.expire(Instant::now());
+        let append_latency = LatencySummary::from_reservoir(&latencies.append);
+        let get_last_latency = LatencySummary::from_reservoir(&latencies.get_last);
+        let get_blob_latency = LatencySummary::from_reservoir(&latencies.get_blob);
+        let http_latency = LatencySummary::from_reservoir(&latencies.http);
+        let latency_window = LatencyWindow {
+            max_samples: self.config.latency_samples,
+            max_age_secs: self.config.latency_window_secs,
+        };
+        let append_buckets = latencies.append_hist.snapshot();
+        let get_last_buckets = latencies.get_last_hist.snapshot();
+        let get_blob_buckets = latencies.get_blob_hist.snapshot();
+        let http_buckets = latencies.http_hist.snapshot();
 
         let sessions_active = self.sessions_active.load(Ordering::Relaxed);
         let sessions_total = self.sessions_total.load(Ordering::Relaxed);
         let last_activity_ms = self.last_session_activity_ms.load(Ordering::Relaxed);
-        let now_ms = unix_ms();
+        let now_ms = self.config.clock.now_unix_ms();
         let idle_sessions = {
             let idle_cutoff = now_ms.saturating_sub(self.config.idle_seconds.saturating_mul(1000));
             let map = self.session_activity.lock().unwrap();
@@ -379,6 +853,7 @@ impl Metrics {
         };
 
         let errors_by_type = self.errors_by_type.lock().unwrap().clone();
+        let errors_by_op = self.errors_by_op.lock().unwrap().clone();
         let errors_total = self.errors_total.load(Ordering::Relaxed);
 
         let store_stats = store.stats();
@@ -386,6 +861,14 @@ impl Metrics {
             snapshots_total: store_stats.fs_roots_total,
             index_bytes: store_stats.fs_roots_bytes,
             content_bytes: store_stats.fs_content_bytes,
+            tree_cache_hits: store_stats.fs_tree_cache_hits,
+            tree_cache_misses: store_stats.fs_tree_cache_misses,
+        };
+        let warmup = store.index_warmup_progress();
+        let index_warmup = IndexWarmupMetrics {
+            indexed: warmup.indexed,
+            total: warmup.total,
+            complete: warmup.is_complete(),
         };
 
         MetricsSnapshot {
@@ -401,6 +884,14 @@ impl Metrics {
             objects,
             storage,
             filesystem,
+            index_warmup,
+            webhook: WebhookMetrics {
+                delivered_total: self.webhook_delivered_total.load(Ordering::Relaxed),
+                dropped_total: self.webhook_dropped_total.load(Ordering::Relaxed),
+            },
+            nats: NatsMetrics {
+                dropped_total: self.nats_dropped_total.load(Ordering::Relaxed),
+            },
             perf: PerfMetrics {
                 append_tps_1m: append_rates.rate_1m,
                 append_tps_5m: append_rates.rate_5m,
@@ -422,10 +913,16 @@ impl Metrics {
                 get_last_latency_ms: get_last_latency,
                 get_blob_latency_ms: get_blob_latency,
                 http_latency_ms: http_latency,
+                append_latency_buckets: append_buckets,
+                get_last_latency_buckets: get_last_buckets,
+                get_blob_latency_buckets: get_blob_buckets,
+                http_latency_buckets: http_buckets,
+                latency_window,
             },
             errors: ErrorMetrics {
                 total: errors_total,
                 by_type: errors_by_type,
+                by_op: errors_by_op,
             },
         }
     }
@@ -488,8 +985,11 @@ impl Metrics {
             sys_swap_free_bytes: swap_free_bytes,
             process_rss_bytes,
             process_vmem_bytes,
+            // The system allocator exposes no cheap, portable "bytes in use"
+            // counter; this stays None unless an allocator with stats is
+            // swapped in.
             process_heap_bytes: None,
-            process_open_fds: None,
+            process_open_fds: process_open_fds(),
             budget_bytes,
             budget_pct: self.config.budget_pct,
             hard_cap_bytes: self.config.hard_cap_bytes,
@@ -503,8 +1003,10 @@ impl Metrics {
             contexts_total: store_stats.contexts_total,
             turns_total: store_stats.turns_total,
             blobs_total: store_stats.blobs_total,
+            blobs_compressed_total: store_stats.blobs_compressed_total,
             registry_types_total: registry_stats.types_total,
             registry_bundles_total: registry_stats.bundles_total,
+            registry_bundles_skipped: registry_stats.bundles_skipped,
             heads_total: store_stats.heads_total,
         };
 
@@ -515,6 +1017,7 @@ impl Metrics {
             heads_table_bytes: store_stats.heads_table_bytes,
             blobs_pack_bytes: store_stats.blobs_pack_bytes,
             blobs_index_bytes: store_stats.blobs_index_bytes,
+            compression_ratio: store_stats.blobs_compression_ratio,
             data_dir_total_bytes: disk_total,
             data_dir_free_bytes: disk_free,
         };
@@ -549,10 +1052,23 @@ pub struct MetricsSnapshot {
     pub objects: ObjectMetrics,
     pub storage: StorageMetrics,
     pub filesystem: FilesystemMetrics,
+    pub index_warmup: IndexWarmupMetrics,
+    pub webhook: WebhookMetrics,
+    pub nats: NatsMetrics,
     pub perf: PerfMetrics,
     pub errors: ErrorMetrics,
 }
 
+impl MetricsSnapshot {
+    /// The snapshot as served over HTTP, with counters too large for a
+    /// JavaScript number written as strings (see [`crate::js_safe`]).
+    pub fn to_json(&self) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(self)?;
+        make_js_safe(&mut value);
+        Ok(value)
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct MemoryMetrics {
     pub sys_total_bytes: u64,
@@ -587,8 +1103,11 @@ pub struct ObjectMetrics {
     pub contexts_total: usize,
     pub turns_total: usize,
     pub blobs_total: usize,
+    pub blobs_compressed_total: usize,
     pub registry_types_total: usize,
     pub registry_bundles_total: usize,
+    /// Bundle files skipped at startup (`CXDB_REGISTRY_SKIP_BAD_BUNDLES`).
+    pub registry_bundles_skipped: usize,
     pub heads_total: usize,
 }
 
@@ -600,6 +1119,8 @@ pub struct StorageMetrics {
     pub heads_table_bytes: u64,
     pub blobs_pack_bytes: u64,
     pub blobs_index_bytes: u64,
+    /// Raw blob bytes per stored byte; how much zstd is saving.
+    pub compression_ratio: f64,
     pub data_dir_total_bytes: u64,
     pub data_dir_free_bytes: u64,
 }
@@ -626,12 +1147,46 @@ pub struct PerfMetrics {
     pub get_last_latency_ms: LatencySummary,
     pub get_blob_latency_ms: LatencySummary,
     pub http_latency_ms: LatencySummary,
+    pub append_latency_buckets: LatencyBuckets,
+    pub get_last_latency_buckets: LatencyBuckets,
+    pub get_blob_latency_buckets: LatencyBuckets,
+    pub http_latency_buckets: LatencyBuckets,
+    pub latency_window: LatencyWindow,
+}
+
+/// Bounds on the sample reservoir behind the `*_latency_ms` percentiles.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyWindow {
+    pub max_samples: usize,
+    /// `None` means samples are only evicted by count.
+    pub max_age_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ErrorMetrics {
     pub total: u64,
     pub by_type: HashMap<String, u64>,
+    pub by_op: HashMap<String, u64>,
+}
+
+/// Name of the file under the data dir that carries counters across
+/// restarts.
+pub const COUNTERS_FILE: &str = "metrics_counters.json";
+
+/// Monotonic counters saved on shutdown and resumed by [`Metrics::open`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedCounters {
+    pub sessions_total: u64,
+    pub append_total: u64,
+    pub get_last_total: u64,
+    pub get_blob_total: u64,
+    pub registry_ingest_total: u64,
+    pub http_total: u64,
+    pub http_errors_total: u64,
+    pub errors_total: u64,
+    pub errors_by_type: BTreeMap<String, u64>,
+    pub errors_by_op: BTreeMap<String, u64>,
 }
 
 /// A single recorded error with context for debugging.
@@ -639,10 +1194,37 @@ pub struct ErrorMetrics {
 pub struct ErrorEntry {
     pub timestamp_ms: u64,
     pub kind: String,
+    pub op: String,
     pub status_code: u16,
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+/// Criteria for `Metrics::query_errors`; unset fields match every entry.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorFilter {
+    pub kind: Option<String>,
+    pub status_code: Option<u16>,
+    pub since_ms: Option<u64>,
+}
+
+impl ErrorFilter {
+    fn matches(&self, entry: &ErrorEntry) -> bool {
+        self.kind.as_ref().is_none_or(|k| *k == entry.kind)
+            && self.status_code.is_none_or(|s| s == entry.status_code)
+            && self.since_ms.is_none_or(|t| entry.timestamp_ms >= t)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorQueryResult {
+    pub errors: Vec<ErrorEntry>,
+    /// Buffered entries matching the filter, before `limit` applies.
+    pub matched: usize,
+    pub counts_by_status: BTreeMap<u16, usize>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -650,6 +1232,30 @@ pub struct FilesystemMetrics {
     pub snapshots_total: usize,
     pub index_bytes: u64,
     pub content_bytes: u64,
+    /// Fs tree lookups served from the parsed-tree cache.
+    pub tree_cache_hits: u64,
+    pub tree_cache_misses: u64,
+}
+
+/// Secondary-index warmup after a background-warmup open; `indexed ==
+/// total` once CQL sees every context.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexWarmupMetrics {
+    pub indexed: usize,
+    pub total: usize,
+    pub complete: bool,
+}
+
+/// Events pushed to `CXDB_WEBHOOK_URL`; both stay 0 without a webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookMetrics {
+    pub delivered_total: u64,
+    pub dropped_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NatsMetrics {
+    pub dropped_total: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -659,20 +1265,24 @@ pub struct LatencySummary {
     pub p99: Option<f64>,
     pub max: Option<f64>,
     pub count: usize,
+    /// Age of the oldest retained sample, i.e. the span the percentiles
+    /// actually cover.
+    pub window_secs: Option<f64>,
 }
 
 impl LatencySummary {
-    fn from_samples(samples: &VecDeque<f64>) -> Self {
-        if samples.is_empty() {
+    fn from_reservoir(reservoir: &LatencyReservoir) -> Self {
+        let Some((oldest, _)) = reservoir.samples.front() else {
             return Self {
                 p50: None,
                 p95: None,
                 p99: None,
                 max: None,
                 count: 0,
+                window_secs: None,
             };
-        }
-        let mut values: Vec<f64> = samples.iter().copied().collect();
+        };
+        let mut values: Vec<f64> = reservoir.samples.iter().map(|(_, ms)| *ms).collect();
         values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
         let p50 = percentile(&values, 0.50);
         let p95 = percentile(&values, 0.95);
@@ -684,38 +1294,166 @@ impl LatencySummary {
             p99,
             max,
             count: values.len(),
+            window_secs: Some(oldest.elapsed().as_secs_f64()),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum LatencyOp {
+    Append,
+    GetLast,
+    GetBlob,
+    Http,
+}
+
+type LatencySample = (LatencyOp, Instant, f64);
+
+/// Samples recorded by one thread and not yet merged into a `LatencyStore`.
+type LatencyBuffer = Mutex<Vec<LatencySample>>;
+
 struct LatencyStore {
-    append: VecDeque<f64>,
-    get_last: VecDeque<f64>,
-    get_blob: VecDeque<f64>,
-    http: VecDeque<f64>,
+    append: LatencyReservoir,
+    get_last: LatencyReservoir,
+    get_blob: LatencyReservoir,
+    http: LatencyReservoir,
+    append_hist: LatencyHistogram,
+    get_last_hist: LatencyHistogram,
+    get_blob_hist: LatencyHistogram,
+    http_hist: LatencyHistogram,
 }
 
 impl LatencyStore {
-    fn new() -> Self {
+    fn new(config: &MetricsConfig) -> Self {
+        let reservoir = || {
+            LatencyReservoir::new(
+                config.latency_samples,
+                config.latency_window_secs.map(Duration::from_secs),
+            )
+        };
+        let buckets_ms = &config.latency_buckets_ms;
+        Self {
+            append: reservoir(),
+            get_last: reservoir(),
+            get_blob: reservoir(),
+            http: reservoir(),
+            append_hist: LatencyHistogram::new(buckets_ms),
+            get_last_hist: LatencyHistogram::new(buckets_ms),
+            get_blob_hist: LatencyHistogram::new(buckets_ms),
+            http_hist: LatencyHistogram::new(buckets_ms),
+        }
+    }
+
+    fn merge(&mut self, samples: impl IntoIterator<Item = LatencySample>) {
+        for (op, at, ms) in samples {
+            let (reservoir, hist) = match op {
+                LatencyOp::Append => (&mut self.append, &mut self.append_hist),
+                LatencyOp::GetLast => (&mut self.get_last, &mut self.get_last_hist),
+                LatencyOp::GetBlob => (&mut self.get_blob, &mut self.get_blob_hist),
+                LatencyOp::Http => (&mut self.http, &mut self.http_hist),
+            };
+            reservoir.record(at, ms);
+            hist.record(ms);
+        }
+    }
+
+    /// Drop aged-out samples so an idle operation's percentiles empty out
+    /// instead of reporting stale values.
+    fn expire(&mut self, now: Instant) {
+        self.append.expire(now);
+        self.get_last.expire(now);
+        self.get_blob.expire(now);
+        self.http.expire(now);
+    }
+}
+
+/// Recent latency samples: FIFO bounded by count and, optionally, by age.
+struct LatencyReservoir {
+    samples: VecDeque<(Instant, f64)>,
+    capacity: usize,
+    max_age: Option<Duration>,
+}
+
+impl LatencyReservoir {
+    fn new(capacity: usize, max_age: Option<Duration>) -> Self {
         Self {
-            append: VecDeque::with_capacity(MAX_LATENCY_SAMPLES),
-            get_last: VecDeque::with_capacity(MAX_LATENCY_SAMPLES),
-            get_blob: VecDeque::with_capacity(MAX_LATENCY_SAMPLES),
-            http: VecDeque::with_capacity(MAX_LATENCY_SAMPLES),
+            samples: VecDeque::with_capacity(capacity.min(DEFAULT_LATENCY_SAMPLES)),
+            capacity,
+            max_age,
+        }
+    }
+
+    fn record(&mut self, now: Instant, ms: f64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
         }
+        self.samples.push_back((now, ms));
+        self.expire(now);
     }
+
+    fn expire(&mut self, now: Instant) {
+        let Some(max_age) = self.max_age else {
+            return;
+        };
+        while let Some((at, _)) = self.samples.front() {
+            if now.duration_since(*at) <= max_age {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Lifetime bucketed latency counts. Unlike the sample reservoir these never
+/// drop old observations, so they suit Prometheus-style `_bucket` series.
+struct LatencyHistogram {
+    bounds_ms: Vec<f64>,
+    /// One slot per bound plus a trailing +Inf slot; not cumulative.
+    counts: Vec<u64>,
 }
 
-trait SampleBuffer {
-    fn push(&mut self, value: f64);
+impl LatencyHistogram {
+    fn new(bounds_ms: &[f64]) -> Self {
+        Self {
+            bounds_ms: bounds_ms.to_vec(),
+            counts: vec![0; bounds_ms.len() + 1],
+        }
+    }
+
+    fn record(&mut self, ms: f64) {
+        let idx = self.bounds_ms.partition_point(|bound| *bound < ms);
+        self.counts[idx] += 1;
+    }
+
+    /// Cumulative counts per upper bound, as in Prometheus `le` buckets.
+    fn snapshot(&self) -> LatencyBuckets {
+        let mut running = 0;
+        let mut buckets = Vec::with_capacity(self.counts.len());
+        for (idx, count) in self.counts.iter().enumerate() {
+            running += count;
+            let label = match self.bounds_ms.get(idx) {
+                Some(bound) => format!("le_{bound}ms"),
+                None => "le_inf".to_string(),
+            };
+            buckets.push((label, running));
+        }
+        LatencyBuckets(buckets)
+    }
 }
 
-impl SampleBuffer for VecDeque<f64> {
-    fn push(&mut self, value: f64) {
-        if self.len() == MAX_LATENCY_SAMPLES {
-            self.pop_front();
+/// Cumulative latency bucket counts, serialized as an ordered JSON object
+/// such as `{"le_1ms": 3, "le_5ms": 7, ..., "le_inf": 9}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyBuckets(pub Vec<(String, u64)>);
+
+impl Serialize for LatencyBuckets {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (label, count) in &self.0 {
+            map.serialize_entry(label, count)?;
         }
-        self.push_back(value);
+        map.end()
     }
 }
 
@@ -837,6 +1575,21 @@ fn env_u64(key: &str, default: u64) -> u64 {
         .unwrap_or(default)
 }
 
+/// Parses a comma-separated list of positive millisecond bounds, returning
+/// them sorted and deduplicated, or `None` if any entry is invalid.
+fn parse_buckets(value: &str) -> Option<Vec<f64>> {
+    let mut bounds = value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if bounds.is_empty() || bounds.iter().any(|b| !b.is_finite() || *b <= 0.0) {
+        return None;
+    }
+    bounds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    bounds.dedup();
+    Some(bounds)
+}
+
 fn duration_to_ms(duration: Duration) -> f64 {
     duration.as_secs_f64() * 1000.0
 }
@@ -895,6 +1648,25 @@ fn disk_space_for_path(path: &Path) -> (u64, u64) {
     }
 }
 
+/// Number of open file descriptors, counted from the per-process fd
+/// directory (`/proc/self/fd` on Linux, `/dev/fd` on macOS). The handle used
+/// to read the directory is excluded.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn process_open_fds() -> Option<u64> {
+    let dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    let count = std::fs::read_dir(dir).ok()?.count() as u64;
+    Some(count.saturating_sub(1))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn process_open_fds() -> Option<u64> {
+    None
+}
+
 #[cfg(not(unix))]
 fn disk_space_for_path(path: &Path) -> (u64, u64) {
     use sysinfo::Disks;
@@ -923,6 +1695,78 @@ fn disk_space_for_path(path: &Path) -> (u64, u64) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn persisted_counters_resume_after_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let m = Metrics::open(dir.path().to_path_buf());
+        for _ in 0..3 {
+            m.record_append(Duration::from_millis(1));
+        }
+        m.record_get_last(Duration::from_millis(1));
+        m.record_http(200, Duration::from_millis(1));
+        m.record_http(404, Duration::from_millis(1));
+        m.record_error("http", "GET /v1/contexts/:id", 404, "context", None);
+        let before = m.counters();
+        m.persist_counters().expect("persist");
+        drop(m);
+
+        let m = Metrics::open(dir.path().to_path_buf());
+        assert_eq!(m.counters(), before);
+        assert_eq!(before.append_total, 3);
+        assert_eq!(before.errors_by_op["GET /v1/contexts/:id"], 1);
+        assert!(m.recent_errors(10).is_empty());
+
+        m.record_append(Duration::from_millis(1));
+        m.record_error("binary", "append_turn", 422, "bad hash", None);
+        let after = m.counters();
+        assert_eq!(after.append_total, 4);
+        assert_eq!(after.errors_total, 2);
+        assert_eq!(after.errors_by_type["http"], 1);
+        assert_eq!(after.errors_by_type["binary"], 1);
+
+        // A damaged file is ignored rather than failing startup.
+        std::fs::write(dir.path().join(COUNTERS_FILE), b"{not json").unwrap();
+        assert_eq!(
+            Metrics::open(dir.path().to_path_buf()).counters(),
+            PersistedCounters::default()
+        );
+    }
+
+    #[test]
+    fn counters_past_js_safe_range_serialize_as_strings() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let big = crate::js_safe::MAX_SAFE_INTEGER + 2;
+        let counters = PersistedCounters {
+            errors_total: big,
+            append_total: 5,
+            ..PersistedCounters::default()
+        };
+        std::fs::write(
+            dir.path().join(COUNTERS_FILE),
+            serde_json::to_vec(&counters).unwrap(),
+        )
+        .unwrap();
+        let m = Metrics::open(dir.path().to_path_buf());
+        let mut store = Store::open(&dir.path().join("store")).expect("open store");
+        let registry = Registry::open(&dir.path().join("registry")).expect("open registry");
+
+        let json = m.snapshot(&mut store, &registry).to_json().expect("json");
+        assert_eq!(json["errors"]["total"], serde_json::json!(big.to_string()));
+        assert_eq!(
+            crate::js_safe::parse_u64(&json["errors"]["total"]),
+            Some(big)
+        );
+        // Counters a double holds exactly stay numbers.
+        assert_eq!(json["sessions"]["total"], serde_json::json!(0));
+
+        let text = serde_json::to_string(&json).unwrap();
+        let reparsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            crate::js_safe::parse_u64(&reparsed["errors"]["total"]),
+            Some(big)
+        );
+    }
+
     #[test]
     fn disk_space_returns_sane_values() {
         let (total, free) = disk_space_for_path(Path::new("/tmp"));
@@ -952,11 +1796,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn session_contexts_past_the_cap_are_counted_not_tracked() {
+        let tracker = SessionTracker::with_max_contexts(2);
+        tracker.register(1, "client".into(), None);
+        for ctx in 10..15 {
+            tracker.add_context(1, ctx);
+        }
+        tracker.add_context(1, 10);
+
+        let session = tracker.get_session_for_context(10).expect("tracked");
+        assert_eq!(session.contexts_created, vec![10, 11]);
+        assert_eq!(session.contexts_untracked, 3);
+        assert_eq!(session.context_count(), 5);
+        assert!(!tracker.is_context_live(12));
+        assert_eq!(tracker.get_live_context_ids(), HashSet::from([10, 11]));
+
+        assert_eq!(tracker.unregister(1), vec![10, 11]);
+        assert!(tracker.get_live_context_ids().is_empty());
+    }
+
+    #[test]
+    fn untagged_sessions_get_a_peer_address_tag() {
+        let tracker = SessionTracker::new();
+        let tag = tracker.register(1, String::new(), Some("10.0.0.7:51234".into()));
+        assert_eq!(tag, "anon:10.0.0.7:51234");
+        assert_eq!(
+            tracker.get_client_tag(1).as_deref(),
+            Some("anon:10.0.0.7:51234")
+        );
+
+        let tag = tracker.register(2, "agent".into(), Some("10.0.0.8:40000".into()));
+        assert_eq!(tag, "agent");
+        assert_eq!(tracker.get_client_tag(2).as_deref(), Some("agent"));
+
+        assert_eq!(tracker.get_active_tags(), vec!["agent".to_string()]);
+    }
+
     #[test]
     fn error_ring_buffer_stores_entries() {
         let m = Metrics::new(PathBuf::from("/tmp"));
-        m.record_error("http", 404, "not found", Some("/v1/foo"));
-        m.record_error("binary", 500, "corrupt", None);
+        m.record_error("http", "test", 404, "not found", Some("/v1/foo"));
+        m.record_error("binary", "test", 500, "corrupt", None);
 
         let recent = m.recent_errors(10);
         assert_eq!(recent.len(), 2);
@@ -971,7 +1852,7 @@ mod tests {
     fn error_ring_buffer_evicts_oldest() {
         let m = Metrics::new(PathBuf::from("/tmp"));
         for i in 0..MAX_ERROR_ENTRIES + 10 {
-            m.record_error("http", 404, &format!("error-{i}"), None);
+            m.record_error("http", "test", 404, &format!("error-{i}"), None);
         }
         let recent = m.recent_errors(MAX_ERROR_ENTRIES);
         assert_eq!(recent.len(), MAX_ERROR_ENTRIES);
@@ -984,11 +1865,267 @@ mod tests {
             .contains(&format!("error-{}", MAX_ERROR_ENTRIES + 9)));
     }
 
+    #[test]
+    fn query_errors_filters_by_kind_status_and_time() {
+        let m = Metrics::new(PathBuf::from("/tmp"));
+        m.record_error(
+            "http",
+            "test",
+            422,
+            "bad field",
+            Some("/v1/contexts/1/append"),
+        );
+        m.record_error("binary", "test", 422, "bad hash", None);
+        m.record_error("http", "test", 404, "route", Some("/v1/nope"));
+        m.record_error("http", "test", 422, "bad json", Some("/v1/contexts/create"));
+
+        let filter = ErrorFilter {
+            kind: Some("http".into()),
+            status_code: Some(422),
+            since_ms: None,
+        };
+        let result = m.query_errors(&filter, 10);
+        let messages: Vec<&str> = result.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["bad json", "bad field"]);
+        assert_eq!(result.matched, 2);
+
+        let by_kind = m.query_errors(
+            &ErrorFilter {
+                kind: Some("http".into()),
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(by_kind.errors.len(), 1);
+        assert_eq!(by_kind.matched, 3);
+        assert_eq!(by_kind.counts_by_status.get(&422), Some(&2));
+        assert_eq!(by_kind.counts_by_status.get(&404), Some(&1));
+
+        let future = m.query_errors(
+            &ErrorFilter {
+                since_ms: Some(unix_ms() + 60_000),
+                ..Default::default()
+            },
+            10,
+        );
+        assert!(future.errors.is_empty());
+        assert!(future.counts_by_status.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_fds_tracks_opened_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let before = process_open_fds().expect("fd count");
+        assert!(before > 0);
+
+        // Other tests open files concurrently, so only check a lower bound.
+        let files: Vec<_> = (0..16)
+            .map(|i| std::fs::File::create(dir.path().join(format!("f{i}"))).expect("create"))
+            .collect();
+        let during = process_open_fds().expect("fd count");
+        assert!(
+            during >= before + 8,
+            "expected roughly 16 more fds, before={before} during={during}"
+        );
+        drop(files);
+    }
+
+    #[test]
+    fn time_windowed_reservoir_ages_out_old_samples() {
+        let mut reservoir = LatencyReservoir::new(100, Some(Duration::from_secs(10)));
+        let start = Instant::now();
+        reservoir.record(start, 500.0);
+        reservoir.record(start + Duration::from_secs(5), 1.0);
+        assert_eq!(reservoir.samples.len(), 2);
+
+        // The 500ms outlier is 11s old once the next sample lands.
+        reservoir.record(start + Duration::from_secs(11), 2.0);
+        let kept: Vec<f64> = reservoir.samples.iter().map(|(_, ms)| *ms).collect();
+        assert_eq!(kept, vec![1.0, 2.0]);
+
+        reservoir.expire(start + Duration::from_secs(30));
+        assert!(reservoir.samples.is_empty());
+        assert_eq!(LatencySummary::from_reservoir(&reservoir).count, 0);
+
+        // Count-bounded mode keeps the newest `capacity` samples regardless of age.
+        let mut fifo = LatencyReservoir::new(2, None);
+        for ms in [1.0, 2.0, 3.0] {
+            fifo.record(start, ms);
+        }
+        fifo.expire(start + Duration::from_secs(3600));
+        let kept: Vec<f64> = fifo.samples.iter().map(|(_, ms)| *ms).collect();
+        assert_eq!(kept, vec![2.0, 3.0]);
+    }
+
+    #[test]
+    fn stream_snapshot_is_shared_across_subscribers() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let metrics = Metrics::new(dir.path().to_path_buf());
+        let first = metrics.subscribe_stream();
+        let second = metrics.subscribe_stream();
+        assert_eq!(metrics.stream_subscriber_count(), 2);
+
+        metrics.publish_stream(Arc::from("{\"tick\":1}"));
+        let a = first.recv().expect("first");
+        let b = second.recv().expect("second");
+        assert!(Arc::ptr_eq(&a, &b));
+
+        drop(second);
+        metrics.publish_stream(Arc::from("{\"tick\":2}"));
+        assert_eq!(metrics.stream_subscriber_count(), 1);
+        assert_eq!(&*first.recv().expect("first"), "{\"tick\":2}");
+    }
+
+    #[test]
+    fn latency_histogram_counts_land_in_le_buckets() {
+        let m = Metrics::new(PathBuf::from("/tmp"));
+        for micros in [200, 1_000, 3_000, 7_000, 40_000, 2_000_000] {
+            m.record_append(Duration::from_micros(micros));
+        }
+        m.record_get_last(Duration::from_millis(20));
+
+        // CXDB_METRICS_LATENCY_BUCKETS_MS is unset, so the defaults apply.
+        let latencies = m.flush_latencies();
+        assert_eq!(
+            latencies.append_hist.snapshot().0,
+            vec![
+                ("le_1ms".to_string(), 2),
+                ("le_5ms".to_string(), 3),
+                ("le_10ms".to_string(), 4),
+                ("le_25ms".to_string(), 4),
+                ("le_50ms".to_string(), 5),
+                ("le_100ms".to_string(), 5),
+                ("le_250ms".to_string(), 5),
+                ("le_1000ms".to_string(), 5),
+                ("le_inf".to_string(), 6),
+            ]
+        );
+        assert_eq!(
+            latencies.get_last_hist.snapshot().0[3],
+            ("le_25ms".to_string(), 1)
+        );
+
+        let mut hist = LatencyHistogram::new(&[0.5, 2.5]);
+        for ms in [0.1, 0.5, 1.0, 2.5, 3.0] {
+            hist.record(ms);
+        }
+        let json = serde_json::to_string(&hist.snapshot()).unwrap();
+        assert_eq!(json, r#"{"le_0.5ms":2,"le_2.5ms":4,"le_inf":5}"#);
+
+        assert_eq!(parse_buckets("10, 1,5,5"), Some(vec![1.0, 5.0, 10.0]));
+        assert_eq!(parse_buckets("1,-2"), None);
+        assert_eq!(parse_buckets("fast"), None);
+    }
+
+    #[test]
+    fn latency_sampling_records_one_in_n_operations() {
+        let mut config = MetricsConfig::from_env();
+        config.latency_sample_every = 10;
+        let m = Metrics::with_config(PathBuf::from("/tmp"), config);
+        for _ in 0..1_000 {
+            m.record_append(Duration::from_millis(2));
+        }
+
+        assert_eq!(m.append_total.load(Ordering::Relaxed), 1_000);
+        let latencies = m.flush_latencies();
+        let recorded = latencies.append_hist.snapshot().0.last().unwrap().1;
+        assert!((90..=110).contains(&recorded), "recorded {recorded}");
+        assert_eq!(latencies.append.samples.len() as u64, recorded);
+    }
+
+    #[test]
+    fn threads_record_latency_without_the_shared_lock() {
+        let mut config = MetricsConfig::from_env();
+        config.latency_sample_every = 1;
+        config.latency_samples = 8_000;
+        config.latency_window_secs = None;
+        let m = Arc::new(Metrics::with_config(PathBuf::from("/tmp"), config));
+
+        // Hold the shared store as a long snapshot would; recorders must not
+        // block on it.
+        let held = m.latencies.lock().unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let m = Arc::clone(&m);
+                std::thread::spawn(move || {
+                    for ms in 1..=1_000u64 {
+                        m.record_append(Duration::from_millis(ms));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("recorder thread");
+        }
+        assert!(held.append.samples.is_empty());
+        drop(held);
+
+        let latencies = m.flush_latencies();
+        let summary = LatencySummary::from_reservoir(&latencies.append);
+        assert_eq!(summary.count, 8_000);
+        assert_eq!(summary.p50, Some(500.5));
+        assert_eq!(summary.max, Some(1_000.0));
+        assert!((990.0..=991.0).contains(&summary.p99.unwrap()));
+        assert_eq!(latencies.append_hist.snapshot().0.last().unwrap().1, 8_000);
+        drop(latencies);
+        // The recorder threads have exited, so their buffers are released.
+        assert!(m.latency_buffers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_append_counts_against_append_op() {
+        use crate::protocol::{map_store_error, MsgType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(&dir.path().join("store")).unwrap();
+        let registry = Registry::open(&dir.path().join("registry")).unwrap();
+        let m = Metrics::new(dir.path().to_path_buf());
+
+        let ctx = store.create_context(0).unwrap().context_id;
+        let payload = b"payload";
+        let err = store
+            .append_turn(
+                ctx,
+                0,
+                "com.example.Test".into(),
+                1,
+                1,
+                0,
+                payload.len() as u32,
+                [0u8; 32],
+                payload,
+            )
+            .expect_err("hash mismatch");
+        let (code, detail) = map_store_error(&err);
+        m.record_error(
+            "binary",
+            MsgType::op_name(MsgType::AppendTurn as u16),
+            code as u16,
+            &detail,
+            None,
+        );
+        m.record_error(
+            "binary",
+            MsgType::op_name(999),
+            422,
+            "unknown msg_type",
+            None,
+        );
+
+        let snapshot = m.snapshot(&mut store, &registry);
+        assert_eq!(snapshot.errors.by_op.get("append_turn"), Some(&1));
+        assert_eq!(snapshot.errors.by_op.get("get_last"), None);
+        assert_eq!(snapshot.errors.by_op.get("unknown"), Some(&1));
+        assert_eq!(snapshot.errors.by_type.get("binary"), Some(&2));
+        assert_eq!(m.recent_errors(1)[0].op, "unknown");
+    }
+
     #[test]
     fn error_ring_buffer_respects_limit() {
         let m = Metrics::new(PathBuf::from("/tmp"));
         for i in 0..20 {
-            m.record_error("http", 400, &format!("err-{i}"), None);
+            m.record_error("http", "test", 400, &format!("err-{i}"), None);
         }
         let recent = m.recent_errors(5);
         assert_eq!(recent.len(), 5);
@@ -1000,9 +2137,9 @@ mod tests {
     #[test]
     fn record_error_increments_counters() {
         let m = Metrics::new(PathBuf::from("/tmp"));
-        m.record_error("http", 404, "not found", None);
-        m.record_error("http", 500, "internal", None);
-        m.record_error("binary", 422, "bad input", None);
+        m.record_error("http", "test", 404, "not found", None);
+        m.record_error("http", "test", 500, "internal", None);
+        m.record_error("binary", "test", 422, "bad input", None);
 
         assert_eq!(m.errors_total.load(Ordering::Relaxed), 3);
         let by_type = m.errors_by_type.lock().unwrap();
@@ -0,0 +1,196 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwards store events to NATS (`--features nats`).
+//!
+//! Each event is published to `<prefix>.<event_type>` (e.g.
+//! `cxdb.events.turn_appended`) with the SSE `data` JSON as its payload, so
+//! consumers can subscribe to one type or to `<prefix>.>` for everything.
+//! The client reconnects on its own; while the broker is unreachable,
+//! publishes buffer in the client until its queue fills, then the publisher
+//! waits for the connection to come back. Events wait for the publisher in
+//! a bounded queue; when it is full the oldest event is dropped (counted in
+//! `nats.dropped_total`), so a stalled broker costs old events rather than
+//! unbounded memory.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::events::EventBus;
+use crate::lock::lock_or_recover;
+use crate::metrics::Metrics;
+
+pub const DEFAULT_SUBJECT_PREFIX: &str = "cxdb.events";
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct NatsBridgeOptions {
+    pub url: String,
+    pub subject_prefix: String,
+    /// Events waiting for the publisher before the oldest are dropped.
+    pub queue_capacity: usize,
+}
+
+impl NatsBridgeOptions {
+    /// `None` unless `CXDB_NATS_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("CXDB_NATS_URL")
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        let subject_prefix = std::env::var("CXDB_NATS_SUBJECT_PREFIX")
+            .ok()
+            .map(|v| v.trim_end_matches('.').to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_SUBJECT_PREFIX.to_string());
+        let queue_capacity = std::env::var("CXDB_NATS_QUEUE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_QUEUE_CAPACITY)
+            .max(1);
+        Some(Self {
+            url,
+            subject_prefix,
+            queue_capacity,
+        })
+    }
+
+    pub fn subject(&self, event_type: &str) -> String {
+        format!("{}.{}", self.subject_prefix, event_type)
+    }
+}
+
+/// FIFO of at most `capacity` items that drops its oldest item to make room.
+struct DropOldestQueue<T> {
+    state: Mutex<(VecDeque<T>, bool)>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl<T> DropOldestQueue<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new((VecDeque::with_capacity(capacity), false)),
+            ready: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Queue `item`; returns whether the oldest item was dropped for it.
+    fn push(&self, item: T) -> bool {
+        let mut state = lock_or_recover(&self.state, "nats queue");
+        let dropped = state.0.len() >= self.capacity && state.0.pop_front().is_some();
+        state.0.push_back(item);
+        self.ready.notify_one();
+        dropped
+    }
+
+    /// No more pushes; `pop` drains what is queued and then returns `None`.
+    fn close(&self) {
+        lock_or_recover(&self.state, "nats queue").1 = true;
+        self.ready.notify_all();
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut state = lock_or_recover(&self.state, "nats queue");
+        loop {
+            if let Some(item) = state.0.pop_front() {
+                return Some(item);
+            }
+            if state.1 {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Subscribe to `event_bus` and publish every event to NATS until the bus is
+/// dropped. Connecting happens in the background, so a broker that is down
+/// at startup doesn't block the server. Returns the publisher thread.
+pub fn spawn_nats_bridge(
+    event_bus: &EventBus,
+    options: NatsBridgeOptions,
+    metrics: Arc<Metrics>,
+) -> JoinHandle<()> {
+    let subscriber = event_bus.subscribe();
+    let queue = Arc::new(DropOldestQueue::new(options.queue_capacity));
+
+    let intake = Arc::clone(&queue);
+    thread::spawn(move || {
+        while let Some(event) = subscriber.recv() {
+            if intake.push(event.to_sse()) {
+                metrics.record_nats_dropped();
+            }
+        }
+        intake.close();
+    });
+
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                tracing::error!("nats bridge disabled: failed to start runtime: {e}");
+                return;
+            }
+        };
+        let connect = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .event_callback(|event| async move {
+                match event {
+                    async_nats::Event::Connected => tracing::info!("nats bridge connected"),
+                    other => tracing::warn!("nats bridge: {other}"),
+                }
+            })
+            .connect(options.url.as_str());
+        let client = match runtime.block_on(connect) {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!(url = %options.url, "nats bridge disabled: {e}");
+                return;
+            }
+        };
+
+        while let Some((event_type, data)) = queue.pop() {
+            let subject = options.subject(event_type);
+            if let Err(e) = runtime.block_on(client.publish(subject, data.into())) {
+                tracing::warn!(event_type, "nats publish failed: {e}");
+            }
+        }
+        let _ = runtime.block_on(client.flush());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subjects_nest_event_types_under_the_prefix() {
+        let options = NatsBridgeOptions {
+            url: "nats://localhost:4222".to_string(),
+            subject_prefix: DEFAULT_SUBJECT_PREFIX.to_string(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        };
+        assert_eq!(
+            options.subject("turn_appended"),
+            "cxdb.events.turn_appended"
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest_event() {
+        let queue = DropOldestQueue::new(2);
+        assert!(!queue.push(1));
+        assert!(!queue.push(2));
+        assert!(queue.push(3));
+        queue.close();
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+}
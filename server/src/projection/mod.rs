@@ -11,6 +11,10 @@ use serde_json::{Map, Number, Value as JsonValue};
 use crate::error::{Result, StoreError};
 use crate::registry::{ItemsSpec, Registry, TypeVersionSpec};
 
+mod protobuf;
+
+pub use protobuf::{project_protobuf, proto_schema};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BytesRender {
     Base64,
@@ -37,6 +41,55 @@ pub enum TimeRender {
     UnixMs,
 }
 
+impl BytesRender {
+    /// Parse a `bytes_render` value: `base64`, `hex` or `len_only`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "base64" => Some(Self::Base64),
+            "hex" => Some(Self::Hex),
+            "len_only" => Some(Self::LenOnly),
+            _ => None,
+        }
+    }
+}
+
+impl U64Format {
+    /// Parse a `u64_format` value: `number` or `string`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "number" => Some(Self::Number),
+            "string" => Some(Self::String),
+            _ => None,
+        }
+    }
+}
+
+impl EnumRender {
+    /// Parse an `enum_render` value: `label`, `number` or `both`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "label" => Some(Self::Label),
+            "number" => Some(Self::Number),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+}
+
+impl TimeRender {
+    /// Parse a `time_render` value: `iso` or `unix_ms`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "iso" => Some(Self::Iso),
+            "unix_ms" => Some(Self::UnixMs),
+            _ => None,
+        }
+    }
+}
+
+/// Default cap on nested type-ref projection; see `RenderOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
 #[derive(Debug, Clone)]
 pub struct RenderOptions {
     pub bytes_render: BytesRender,
@@ -44,11 +97,59 @@ pub struct RenderOptions {
     pub enum_render: EnumRender,
     pub time_render: TimeRender,
     pub include_unknown: bool,
+    /// How many type refs deep projection follows. A cyclic registry (A refs
+    /// B refs A) would otherwise recurse as deep as the payload nests; past
+    /// this depth values are rendered raw and marked `"$max_depth_exceeded"`.
+    pub max_depth: usize,
+    /// Record values that don't match their declared field type in
+    /// `ProjectionResult::type_errors`. They still render as null.
+    pub strict: bool,
+    /// Bundle the context is pinned to. Nested refs to types it defines
+    /// decode with its latest version of them rather than the registry's.
+    pub bundle_id: Option<String>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            bytes_render: BytesRender::Base64,
+            u64_format: U64Format::Number,
+            enum_render: EnumRender::Label,
+            time_render: TimeRender::Iso,
+            include_unknown: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            strict: false,
+            bundle_id: None,
+        }
+    }
 }
 
 pub struct ProjectionResult {
     pub data: JsonValue,
     pub unknown: Option<JsonValue>,
+    /// Type mismatches found in strict mode; always empty otherwise.
+    pub type_errors: Vec<TypeMismatch>,
+}
+
+/// A value whose msgpack type doesn't fit its declared field type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMismatch {
+    /// Dotted field path, with `[i]` for array items, e.g. `items[2].name`.
+    pub path: String,
+    /// The declared type (or referenced type id).
+    pub expected: String,
+    /// The msgpack kind actually stored, e.g. "string" or "map".
+    pub found: &'static str,
+}
+
+impl std::fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, found {}",
+            self.path, self.expected, self.found
+        )
+    }
 }
 
 pub fn project_msgpack(
@@ -64,13 +165,24 @@ pub fn project_msgpack(
     let map = normalize_tags(&value)?;
     let mut data = Map::new();
     let mut unknown = Map::new();
+    let mut type_errors = Vec::new();
 
     for (tag, field) in descriptor.fields.iter() {
         if let Some(val) = map.get(tag) {
-            let rendered = render_field_value(val, field, registry, options);
+            let rendered = render_field_value(
+                val,
+                field,
+                registry,
+                options,
+                0,
+                &field.name,
+                &mut type_errors,
+            );
             data.insert(field.name.clone(), rendered);
         }
     }
+    // Fields iterate in hash order; report mismatches deterministically.
+    type_errors.sort_by(|a, b| a.path.cmp(&b.path));
 
     if options.include_unknown {
         for (tag, val) in map.iter() {
@@ -88,6 +200,7 @@ pub fn project_msgpack(
         } else {
             None
         },
+        type_errors,
     })
 }
 
@@ -123,18 +236,21 @@ fn render_field_value(
     field: &crate::registry::FieldSpec,
     registry: &Registry,
     options: &RenderOptions,
+    depth: usize,
+    path: &str,
+    errors: &mut Vec<TypeMismatch>,
 ) -> JsonValue {
     if let Some(enum_ref) = &field.enum_ref {
-        if let Some(num) = value_to_u64(value) {
+        if let Some(num) = value_to_enum_number(value) {
             if let Some(map) = registry.get_enum(enum_ref) {
                 if let Some(label) = map.get(&num.to_string()) {
                     return match options.enum_render {
                         EnumRender::Label => JsonValue::String(label.clone()),
-                        EnumRender::Number => JsonValue::Number(Number::from(num)),
+                        EnumRender::Number => JsonValue::Number(num),
                         EnumRender::Both => {
                             let mut obj = Map::new();
                             obj.insert("label".into(), JsonValue::String(label.clone()));
-                            obj.insert("value".into(), JsonValue::Number(Number::from(num)));
+                            obj.insert("value".into(), JsonValue::Number(num));
                             JsonValue::Object(obj)
                         }
                     };
@@ -149,38 +265,108 @@ fn render_field_value(
     // `type_ref` that should trigger recursive projection.
     if field.type_ref.is_some() && (field.field_type == "ref" || field.field_type == "map") {
         if let Some(type_ref) = &field.type_ref {
-            return render_type_ref(value, type_ref, registry, options);
+            return render_type_ref(value, type_ref, registry, options, depth, path, errors);
         }
     }
 
     let field_type = field.field_type.as_str();
-    match field_type {
+    let rendered = match field_type {
         "u64" | "uint64" | "i64" | "int64" => render_u64(value, options),
         "u32" | "uint32" | "u8" | "uint8" | "int32" => render_int(value),
         "string" => render_string(value),
         "bool" => render_bool(value),
         "bytes" | "typed_blob" => render_bytes(value, options),
-        "array" => render_array(value, field.items.as_ref(), registry, options),
+        "array" => render_array(
+            value,
+            field.items.as_ref(),
+            registry,
+            options,
+            depth,
+            path,
+            errors,
+        ),
+        "map" => render_map(
+            value,
+            field.value_type.as_ref(),
+            registry,
+            options,
+            depth,
+            path,
+            errors,
+        ),
         "unix_ms" | "time_ms" | "timestamp_ms" => render_time(value, options),
-        _ => render_value(value, options),
+        _ => return render_value(value, options),
+    };
+    // Typed renderers return null for anything they can't represent; only
+    // an explicit nil is a legitimate null.
+    if options.strict && rendered.is_null() && !value.is_nil() {
+        errors.push(TypeMismatch {
+            path: path.to_string(),
+            expected: field.field_type.clone(),
+            found: value_kind(value),
+        });
+    }
+    rendered
+}
+
+fn value_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "bool",
+        Value::Integer(int) if int.as_u64().is_some() => "integer",
+        Value::Integer(_) => "negative integer",
+        Value::F32(_) | Value::F64(_) => "float",
+        Value::String(_) => "string",
+        Value::Binary(_) => "bytes",
+        Value::Array(_) => "array",
+        Value::Map(_) => "map",
+        Value::Ext(..) => "ext",
     }
 }
 
-/// Recursively project a value using a referenced type's descriptor
+/// Recursively project a value using a referenced type's descriptor.
+/// `depth` counts the type refs already followed to reach `value`.
 fn render_type_ref(
     value: &Value,
     type_ref: &str,
     registry: &Registry,
     options: &RenderOptions,
+    depth: usize,
+    path: &str,
+    errors: &mut Vec<TypeMismatch>,
 ) -> JsonValue {
+    if depth >= options.max_depth {
+        return match render_value(value, options) {
+            JsonValue::Object(mut obj) => {
+                obj.insert("$max_depth_exceeded".into(), JsonValue::Bool(true));
+                JsonValue::Object(obj)
+            }
+            other => {
+                let mut obj = Map::new();
+                obj.insert("$max_depth_exceeded".into(), JsonValue::Bool(true));
+                obj.insert("value".into(), other);
+                JsonValue::Object(obj)
+            }
+        };
+    }
+
     // Get the latest version of the referenced type
-    let Some(type_spec) = registry.get_latest_type_version(type_ref) else {
+    let Some(type_spec) =
+        registry.get_latest_type_version_pinned(options.bundle_id.as_deref(), type_ref)
+    else {
         // Fall back to raw rendering if type not found
         return render_value(value, options);
     };
 
     // Normalize the value to a tag map
     let Ok(map) = normalize_tags(value) else {
+        if options.strict && !value.is_nil() {
+            errors.push(TypeMismatch {
+                path: path.to_string(),
+                expected: type_ref.to_string(),
+                found: value_kind(value),
+            });
+        }
         return render_value(value, options);
     };
 
@@ -188,7 +374,16 @@ fn render_type_ref(
     let mut data = Map::new();
     for (tag, field) in type_spec.fields.iter() {
         if let Some(val) = map.get(tag) {
-            let rendered = render_field_value(val, field, registry, options);
+            let field_path = format!("{path}.{}", field.name);
+            let rendered = render_field_value(
+                val,
+                field,
+                registry,
+                options,
+                depth + 1,
+                &field_path,
+                errors,
+            );
             data.insert(field.name.clone(), rendered);
         }
     }
@@ -291,36 +486,90 @@ fn render_array(
     items_spec: Option<&ItemsSpec>,
     registry: &Registry,
     options: &RenderOptions,
+    depth: usize,
+    path: &str,
+    errors: &mut Vec<TypeMismatch>,
 ) -> JsonValue {
     let arr = match value {
         Value::Array(arr) => arr,
         _ => return JsonValue::Null,
     };
 
-    let mut out = Vec::with_capacity(arr.len());
-    for item in arr.iter() {
-        let rendered = match items_spec {
-            Some(ItemsSpec::Simple(item_type)) => {
-                let dummy_field = crate::registry::FieldSpec {
-                    name: "".into(),
-                    field_type: item_type.clone(),
-                    enum_ref: None,
-                    type_ref: None,
-                    optional: false,
-                    items: None,
-                };
-                render_field_value(item, &dummy_field, registry, options)
-            }
-            Some(ItemsSpec::Ref(type_ref)) => {
-                // Recursively project array items using the referenced type
-                render_type_ref(item, type_ref, registry, options)
-            }
-            None => render_value(item, options),
+    let out = arr
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let item_path = format!("{path}[{i}]");
+            render_item(
+                item, items_spec, registry, options, depth, &item_path, errors,
+            )
+        })
+        .collect();
+    JsonValue::Array(out)
+}
+
+/// Render a map field's entries as a JSON object, values per `value_spec`.
+fn render_map(
+    value: &Value,
+    value_spec: Option<&ItemsSpec>,
+    registry: &Registry,
+    options: &RenderOptions,
+    depth: usize,
+    path: &str,
+    errors: &mut Vec<TypeMismatch>,
+) -> JsonValue {
+    let entries = match value {
+        Value::Map(entries) => entries,
+        _ => return JsonValue::Null,
+    };
+
+    let mut obj = Map::new();
+    for (k, v) in entries.iter() {
+        let key = match k {
+            Value::String(s) => s.as_str().unwrap_or("").to_string(),
+            Value::Integer(int) => int.to_string(),
+            other => other.to_string(),
         };
-        out.push(rendered);
+        let entry_path = format!("{path}.{key}");
+        let rendered = render_item(v, value_spec, registry, options, depth, &entry_path, errors);
+        obj.insert(key, rendered);
     }
+    JsonValue::Object(obj)
+}
 
-    JsonValue::Array(out)
+/// Render one array item or map value according to its spec.
+fn render_item(
+    item: &Value,
+    spec: Option<&ItemsSpec>,
+    registry: &Registry,
+    options: &RenderOptions,
+    depth: usize,
+    path: &str,
+    errors: &mut Vec<TypeMismatch>,
+) -> JsonValue {
+    match spec {
+        Some(ItemsSpec::Simple(item_type)) => {
+            let dummy_field = crate::registry::FieldSpec {
+                name: "".into(),
+                field_type: item_type.clone(),
+                enum_ref: None,
+                type_ref: None,
+                optional: false,
+                items: None,
+                key_type: None,
+                value_type: None,
+            };
+            render_field_value(item, &dummy_field, registry, options, depth, path, errors)
+        }
+        Some(ItemsSpec::Ref(type_ref)) => {
+            // Recursively project items using the referenced type
+            render_type_ref(item, type_ref, registry, options, depth, path, errors)
+        }
+        Some(ItemsSpec::Array(inner)) => {
+            render_array(item, Some(inner), registry, options, depth, path, errors)
+        }
+        None => render_value(item, options),
+    }
 }
 
 fn render_time(value: &Value, options: &RenderOptions) -> JsonValue {
@@ -351,6 +600,18 @@ fn value_to_u64(value: &Value) -> Option<u64> {
     }
 }
 
+/// An enum discriminant of either sign. Enum map keys are its decimal form,
+/// e.g. "-1" or "18446744073709551615".
+fn value_to_enum_number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Integer(int) => int
+            .as_u64()
+            .map(Number::from)
+            .or_else(|| int.as_i64().map(Number::from)),
+        _ => None,
+    }
+}
+
 fn value_to_i64(value: &Value) -> Option<i64> {
     match value {
         Value::Integer(int) => int.as_i64().or_else(|| {
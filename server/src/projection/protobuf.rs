@@ -0,0 +1,312 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Protobuf output for typed payloads.
+//!
+//! Registry tags become protobuf field numbers, so a payload re-encodes field
+//! for field and [`proto_schema`] describes the result. Supported field types
+//! are the integer scalars, `bool`, `string`, `bytes`, arrays of those, and
+//! refs (and arrays of refs) as nested messages. Anything else, such as maps,
+//! nested arrays or untyped fields, is left out of both the message and the
+//! schema; the schema notes each omission in a comment.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use rmpv::Value;
+
+use super::{normalize_tags, value_to_i64, value_to_u64, DEFAULT_MAX_DEPTH};
+use crate::error::{Result, StoreError};
+use crate::registry::{FieldSpec, ItemsSpec, Registry, TypeVersionSpec};
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    Uint64,
+    Int64,
+    Uint32,
+    Int32,
+    Bool,
+    String,
+    Bytes,
+}
+
+impl Scalar {
+    fn from_type(field_type: &str) -> Option<Self> {
+        Some(match field_type {
+            "u64" | "uint64" => Scalar::Uint64,
+            "i64" | "int64" | "unix_ms" | "time_ms" | "timestamp_ms" => Scalar::Int64,
+            "u32" | "uint32" | "u8" | "uint8" => Scalar::Uint32,
+            "int32" => Scalar::Int32,
+            "bool" => Scalar::Bool,
+            "string" => Scalar::String,
+            "bytes" | "typed_blob" => Scalar::Bytes,
+            _ => return None,
+        })
+    }
+
+    fn proto_name(self) -> &'static str {
+        match self {
+            Scalar::Uint64 => "uint64",
+            Scalar::Int64 => "int64",
+            Scalar::Uint32 => "uint32",
+            Scalar::Int32 => "int32",
+            Scalar::Bool => "bool",
+            Scalar::String => "string",
+            Scalar::Bytes => "bytes",
+        }
+    }
+
+    fn is_length_delimited(self) -> bool {
+        matches!(self, Scalar::String | Scalar::Bytes)
+    }
+
+    /// The varint for a numeric or bool value; `None` if it doesn't fit.
+    fn varint(self, value: &Value) -> Option<u64> {
+        match self {
+            Scalar::Uint64 => value_to_u64(value),
+            Scalar::Uint32 => value_to_u64(value).filter(|v| *v <= u32::MAX as u64),
+            // Negative values are sign-extended to ten bytes, as protobuf
+            // encodes int32 and int64 alike.
+            Scalar::Int64 => value_to_i64(value).map(|v| v as u64),
+            Scalar::Int32 => value_to_i64(value)
+                .filter(|v| i32::try_from(*v).is_ok())
+                .map(|v| v as u64),
+            Scalar::Bool => match value {
+                Value::Boolean(b) => Some(*b as u64),
+                _ => None,
+            },
+            Scalar::String | Scalar::Bytes => None,
+        }
+    }
+
+    fn bytes(self, value: &Value) -> Option<&[u8]> {
+        match (self, value) {
+            (Scalar::String, Value::String(s)) => Some(s.as_bytes()),
+            (Scalar::Bytes, Value::Binary(b)) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+/// How a registry field maps onto a protobuf field.
+enum ProtoField<'a> {
+    Scalar(Scalar),
+    Repeated(Scalar),
+    Message(&'a str),
+    RepeatedMessage(&'a str),
+}
+
+/// How `field` maps onto protobuf, or why it can't (for the schema comment).
+fn proto_field<'a>(tag: u64, field: &'a FieldSpec) -> std::result::Result<ProtoField<'a>, String> {
+    // 19000-19999 are reserved by protobuf; 2^29-1 is the largest field number.
+    if tag == 0 || tag > 536_870_911 || (19_000..=19_999).contains(&tag) {
+        return Err(format!("tag {tag} is not a valid protobuf field number"));
+    }
+    if let Some(type_ref) = &field.type_ref {
+        if field.field_type == "ref" || field.field_type == "map" {
+            return Ok(ProtoField::Message(type_ref));
+        }
+    }
+    if field.field_type == "array" {
+        return match &field.items {
+            Some(ItemsSpec::Simple(item_type)) => Scalar::from_type(item_type)
+                .map(ProtoField::Repeated)
+                .ok_or_else(|| format!("array of {item_type} is not supported")),
+            Some(ItemsSpec::Ref(type_ref)) => Ok(ProtoField::RepeatedMessage(type_ref)),
+            Some(ItemsSpec::Array(_)) => Err("nested arrays are not supported".to_string()),
+            None => Err("array without items is not supported".to_string()),
+        };
+    }
+    Scalar::from_type(&field.field_type)
+        .map(ProtoField::Scalar)
+        .ok_or_else(|| format!("{} is not supported", field.field_type))
+}
+
+/// Re-encode a msgpack payload as the protobuf message `proto_schema`
+/// describes for `descriptor`. Values that don't fit their field type are
+/// left out, as projection renders them null.
+pub fn project_protobuf(
+    payload: &[u8],
+    descriptor: &TypeVersionSpec,
+    registry: &Registry,
+) -> Result<Vec<u8>> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let value = rmpv::decode::read_value(&mut cursor)
+        .map_err(|e| StoreError::InvalidInput(format!("msgpack decode error: {e}")))?;
+    let map = normalize_tags(&value)?;
+    let mut out = Vec::new();
+    encode_message(&map, descriptor, registry, 0, &mut out)?;
+    Ok(out)
+}
+
+fn encode_message(
+    map: &HashMap<u64, Value>,
+    descriptor: &TypeVersionSpec,
+    registry: &Registry,
+    depth: usize,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    let mut tags: Vec<u64> = descriptor.fields.keys().copied().collect();
+    tags.sort_unstable();
+    for tag in tags {
+        let field = &descriptor.fields[&tag];
+        let (Ok(kind), Some(value)) = (proto_field(tag, field), map.get(&tag)) else {
+            continue;
+        };
+        match kind {
+            ProtoField::Scalar(scalar) => put_scalar(out, tag, scalar, value),
+            ProtoField::Repeated(scalar) => {
+                let Value::Array(items) = value else {
+                    continue;
+                };
+                if scalar.is_length_delimited() {
+                    for item in items {
+                        put_scalar(out, tag, scalar, item);
+                    }
+                } else {
+                    let mut packed = Vec::new();
+                    for v in items.iter().filter_map(|item| scalar.varint(item)) {
+                        put_varint(&mut packed, v);
+                    }
+                    put_len(out, tag, &packed);
+                }
+            }
+            ProtoField::Message(type_ref) => {
+                if let Some(message) = encode_nested(value, type_ref, registry, depth)? {
+                    put_len(out, tag, &message);
+                }
+            }
+            ProtoField::RepeatedMessage(type_ref) => {
+                let Value::Array(items) = value else {
+                    continue;
+                };
+                for item in items {
+                    if let Some(message) = encode_nested(item, type_ref, registry, depth)? {
+                        put_len(out, tag, &message);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode `value` as the latest version of `type_ref`; `None` when the type
+/// is unknown or the value isn't a tag map.
+fn encode_nested(
+    value: &Value,
+    type_ref: &str,
+    registry: &Registry,
+    depth: usize,
+) -> Result<Option<Vec<u8>>> {
+    if depth >= DEFAULT_MAX_DEPTH {
+        return Err(StoreError::InvalidInput(format!(
+            "protobuf: type refs nest deeper than {DEFAULT_MAX_DEPTH}"
+        )));
+    }
+    let Some(descriptor) = registry.get_latest_type_version(type_ref) else {
+        return Ok(None);
+    };
+    let Ok(map) = normalize_tags(value) else {
+        return Ok(None);
+    };
+    let mut out = Vec::new();
+    encode_message(&map, descriptor, registry, depth + 1, &mut out)?;
+    Ok(Some(out))
+}
+
+fn put_scalar(out: &mut Vec<u8>, tag: u64, scalar: Scalar, value: &Value) {
+    if let Some(bytes) = scalar.bytes(value) {
+        put_len(out, tag, bytes);
+    } else if let Some(v) = scalar.varint(value) {
+        put_varint(out, (tag << 3) | WIRE_VARINT);
+        put_varint(out, v);
+    }
+}
+
+fn put_len(out: &mut Vec<u8>, tag: u64, bytes: &[u8]) {
+    put_varint(out, (tag << 3) | WIRE_LEN);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// A protobuf identifier for a type id or field name, e.g. `com.example.Note`
+/// becomes `com_example_Note`.
+fn proto_identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        ident.insert(0, 'T');
+    }
+    ident
+}
+
+/// A proto3 schema for `descriptor` (as `type_id`), with one message per
+/// type it refers to, transitively. Referenced types use their latest
+/// version, as projection does.
+pub fn proto_schema(type_id: &str, descriptor: &TypeVersionSpec, registry: &Registry) -> String {
+    let mut out = format!(
+        "// Generated from {type_id} v{}.\nsyntax = \"proto3\";\n",
+        descriptor.version
+    );
+    let mut seen = BTreeSet::from([type_id.to_string()]);
+    let mut queue = VecDeque::from([(type_id.to_string(), descriptor)]);
+    while let Some((id, descriptor)) = queue.pop_front() {
+        out.push_str(&format!("\nmessage {} {{\n", proto_identifier(&id)));
+        let mut tags: Vec<u64> = descriptor.fields.keys().copied().collect();
+        tags.sort_unstable();
+        for tag in tags {
+            let field = &descriptor.fields[&tag];
+            let name = proto_identifier(&field.name);
+            let (label, type_name, type_ref) = match proto_field(tag, field) {
+                Ok(ProtoField::Scalar(scalar)) => {
+                    let label = if field.optional { "optional " } else { "" };
+                    (label, scalar.proto_name().to_string(), None)
+                }
+                Ok(ProtoField::Repeated(scalar)) => {
+                    ("repeated ", scalar.proto_name().to_string(), None)
+                }
+                Ok(ProtoField::Message(type_ref)) => {
+                    ("", proto_identifier(type_ref), Some(type_ref))
+                }
+                Ok(ProtoField::RepeatedMessage(type_ref)) => {
+                    ("repeated ", proto_identifier(type_ref), Some(type_ref))
+                }
+                Err(reason) => {
+                    out.push_str(&format!("  // {name} = {tag} omitted: {reason}\n"));
+                    continue;
+                }
+            };
+            if let Some(type_ref) = type_ref {
+                let Some(referenced) = registry.get_latest_type_version(type_ref) else {
+                    out.push_str(&format!(
+                        "  // {name} = {tag} omitted: unknown type {type_ref}\n"
+                    ));
+                    continue;
+                };
+                if seen.insert(type_ref.to_string()) {
+                    queue.push_back((type_ref.to_string(), referenced));
+                }
+            }
+            out.push_str(&format!("  {label}{type_name} {name} = {tag};"));
+            if let Some(enum_ref) = &field.enum_ref {
+                out.push_str(&format!(" // enum {enum_ref}"));
+            }
+            out.push('\n');
+        }
+        out.push_str("}\n");
+    }
+    out
+}
@@ -7,12 +7,21 @@ use std::io::{Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::error::{Result, StoreError};
+use crate::context_meta::MetadataOverlay;
+use crate::error::{NotFoundKind, Result, StoreError};
+use crate::store::TurnWithMeta;
+use crate::turn_store::ContextHead;
 
 /// Maximum frame payload size (64 MB). Frames larger than this are rejected
 /// to prevent memory exhaustion from malicious or corrupted clients.
 const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
 
+/// Maximum number of contexts in a single GET_LAST_BATCH request.
+pub const MAX_GET_LAST_BATCH: usize = 1024;
+/// Maximum number of turns, across all contexts, in one GET_LAST_BATCH
+/// response.
+pub const MAX_GET_LAST_BATCH_TURNS: usize = 65_536;
+
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MsgType {
@@ -27,9 +36,76 @@ pub enum MsgType {
     GetBlob = 9,
     AttachFs = 10,
     PutBlob = 11,
+    GetLastBatch = 12,
     Error = 255,
 }
 
+impl MsgType {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        Some(match value {
+            1 => MsgType::Hello,
+            2 => MsgType::CtxCreate,
+            3 => MsgType::CtxFork,
+            4 => MsgType::GetHead,
+            5 => MsgType::AppendTurn,
+            6 => MsgType::GetLast,
+            7 => MsgType::GetBefore,
+            8 => MsgType::GetRangeByDepth,
+            9 => MsgType::GetBlob,
+            10 => MsgType::AttachFs,
+            11 => MsgType::PutBlob,
+            12 => MsgType::GetLastBatch,
+            255 => MsgType::Error,
+            _ => return None,
+        })
+    }
+
+    /// Snake-case operation name used in metrics and logs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MsgType::Hello => "hello",
+            MsgType::CtxCreate => "ctx_create",
+            MsgType::CtxFork => "ctx_fork",
+            MsgType::GetHead => "get_head",
+            MsgType::AppendTurn => "append_turn",
+            MsgType::GetLast => "get_last",
+            MsgType::GetBefore => "get_before",
+            MsgType::GetRangeByDepth => "get_range_by_depth",
+            MsgType::GetBlob => "get_blob",
+            MsgType::AttachFs => "attach_fs",
+            MsgType::PutBlob => "put_blob",
+            MsgType::GetLastBatch => "get_last_batch",
+            MsgType::Error => "error",
+        }
+    }
+
+    /// Lowest negotiated protocol version that may use this message type.
+    /// New message types raise this so older clients get a clear error
+    /// instead of a frame they cannot parse.
+    pub fn min_version(&self) -> u16 {
+        match self {
+            MsgType::Hello
+            | MsgType::CtxCreate
+            | MsgType::CtxFork
+            | MsgType::GetHead
+            | MsgType::AppendTurn
+            | MsgType::GetLast
+            | MsgType::GetBefore
+            | MsgType::GetRangeByDepth
+            | MsgType::GetBlob
+            | MsgType::AttachFs
+            | MsgType::PutBlob
+            | MsgType::Error => 1,
+            MsgType::GetLastBatch => 2,
+        }
+    }
+
+    /// Operation name for a raw frame type; unknown types map to "unknown".
+    pub fn op_name(value: u16) -> &'static str {
+        MsgType::from_u16(value).map_or("unknown", |t| t.as_str())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameHeader {
     pub len: u32,
@@ -53,6 +129,9 @@ pub struct AppendTurnRequest {
     /// Optional filesystem snapshot root hash to attach to this turn.
     /// Present if flags bit 0 is set.
     pub fs_root_hash: Option<[u8; 32]>,
+    /// Append only if the context head is still this turn. Present if flags
+    /// bit 1 is set.
+    pub expected_head_turn_id: Option<u64>,
 }
 
 /// Request to attach a filesystem snapshot to an existing turn.
@@ -69,11 +148,32 @@ pub struct PutBlobRequest {
     pub data: Vec<u8>,
 }
 
+#[derive(Debug, Clone)]
+pub struct CtxCreateRequest {
+    pub base_turn_id: u64,
+    pub metadata: MetadataOverlay,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct GetLastRequest {
     pub context_id: u64,
     pub limit: u32,
     pub include_payload: u32,
+    /// Optional trailing field; requests that omit it get hashes.
+    pub include_hash: bool,
+}
+
+/// GET_LAST response flag: items carry no `content_hash_b3_256`.
+pub const FLAG_GET_LAST_NO_HASH: u16 = 1 << 0;
+/// GET_LAST request and response flag: integers in the response body are
+/// LEB128 varints rather than fixed-width little-endian.
+pub const FLAG_GET_LAST_VARINT: u16 = 1 << 1;
+
+#[derive(Debug, Clone)]
+pub struct GetLastBatchRequest {
+    pub include_payload: u32,
+    /// `(context_id, limit)` pairs, answered in request order.
+    pub entries: Vec<(u64, u32)>,
 }
 
 pub fn read_frame<R: Read>(reader: &mut R) -> Result<(FrameHeader, Vec<u8>)> {
@@ -126,6 +226,32 @@ pub fn parse_ctx_create(payload: &[u8]) -> Result<u64> {
     Ok(cursor.read_u64::<LittleEndian>()?)
 }
 
+/// Parse a CTX_CREATE payload: `base_turn_id: u64`, optionally followed by
+/// `meta_json_len: u32` and a JSON object with `title`, `labels`, and
+/// `client_tag`. An 8-byte payload is the original form with no metadata.
+pub fn parse_ctx_create_request(payload: &[u8]) -> Result<CtxCreateRequest> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let base_turn_id = cursor.read_u64::<LittleEndian>()?;
+    let mut metadata = MetadataOverlay::default();
+    if payload.len() > 8 {
+        let meta_len = cursor.read_u32::<LittleEndian>()? as usize;
+        if payload.len() < 12 + meta_len {
+            return Err(StoreError::InvalidInput(
+                "ctx_create metadata truncated".into(),
+            ));
+        }
+        if meta_len > 0 {
+            let json: serde_json::Value = serde_json::from_slice(&payload[12..12 + meta_len])
+                .map_err(|e| StoreError::InvalidInput(format!("invalid metadata json: {e}")))?;
+            metadata = MetadataOverlay::from_json(&json)?;
+        }
+    }
+    Ok(CtxCreateRequest {
+        base_turn_id,
+        metadata,
+    })
+}
+
 pub fn parse_ctx_fork(payload: &[u8]) -> Result<u64> {
     parse_ctx_create(payload)
 }
@@ -136,10 +262,45 @@ pub fn parse_get_head(payload: &[u8]) -> Result<u64> {
 
 pub fn parse_get_last(payload: &[u8]) -> Result<GetLastRequest> {
     let mut cursor = std::io::Cursor::new(payload);
+    let context_id = cursor.read_u64::<LittleEndian>()?;
+    let limit = cursor.read_u32::<LittleEndian>()?;
+    let include_payload = cursor.read_u32::<LittleEndian>()?;
+    let include_hash = if payload.len() >= 20 {
+        cursor.read_u32::<LittleEndian>()? != 0
+    } else {
+        true
+    };
     Ok(GetLastRequest {
-        context_id: cursor.read_u64::<LittleEndian>()?,
-        limit: cursor.read_u32::<LittleEndian>()?,
-        include_payload: cursor.read_u32::<LittleEndian>()?,
+        context_id,
+        limit,
+        include_payload,
+        include_hash,
+    })
+}
+
+pub fn parse_get_last_batch(payload: &[u8]) -> Result<GetLastBatchRequest> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let include_payload = cursor.read_u32::<LittleEndian>()?;
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    if count > MAX_GET_LAST_BATCH {
+        return Err(StoreError::InvalidInput(format!(
+            "batch of {count} contexts exceeds maximum {MAX_GET_LAST_BATCH}"
+        )));
+    }
+    if payload.len() != 8 + count * 12 {
+        return Err(StoreError::InvalidInput(
+            "batch length does not match entry count".into(),
+        ));
+    }
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let context_id = cursor.read_u64::<LittleEndian>()?;
+        let limit = cursor.read_u32::<LittleEndian>()?;
+        entries.push((context_id, limit));
+    }
+    Ok(GetLastBatchRequest {
+        include_payload,
+        entries,
     })
 }
 
@@ -189,6 +350,13 @@ pub fn parse_append_turn(payload: &[u8], flags: u16) -> Result<AppendTurnRequest
         None
     };
 
+    // Check for optional expected head (flags bit 1)
+    let expected_head_turn_id = if flags & 2 != 0 {
+        Some(cursor.read_u64::<LittleEndian>()?)
+    } else {
+        None
+    };
+
     Ok(AppendTurnRequest {
         context_id,
         parent_turn_id,
@@ -201,6 +369,7 @@ pub fn parse_append_turn(payload: &[u8], flags: u16) -> Result<AppendTurnRequest
         payload_bytes,
         idempotency_key,
         fs_root_hash,
+        expected_head_turn_id,
     })
 }
 
@@ -265,20 +434,148 @@ pub fn encode_ctx_create_resp(
     Ok(buf)
 }
 
+/// GET_HEAD response: the CTX_CREATE layout followed by the turn count, so
+/// clients that only read the first 20 bytes keep working.
+pub fn encode_get_head_resp(head: &ContextHead) -> Result<Vec<u8>> {
+    let mut buf = encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+    buf.write_u64::<LittleEndian>(head.turn_count)?;
+    Ok(buf)
+}
+
+/// Encodes the APPEND_TURN ack. `head_turn_id` trails the original 52-byte
+/// layout: it differs from `new_turn_id` when the append started a side
+/// branch instead of moving the head.
+/// Encodes an APPEND_TURN ack. `head_turn_id` is only sent from protocol
+/// version 2; version 1 acks end at the hash.
 pub fn encode_append_ack(
+    protocol_version: u16,
     context_id: u64,
     new_turn_id: u64,
     new_depth: u32,
     hash: &[u8; 32],
+    head_turn_id: u64,
 ) -> Result<Vec<u8>> {
-    let mut buf = Vec::with_capacity(8 + 8 + 4 + 32);
+    let mut buf = Vec::with_capacity(8 + 8 + 4 + 32 + 8);
     buf.write_u64::<LittleEndian>(context_id)?;
     buf.write_u64::<LittleEndian>(new_turn_id)?;
     buf.write_u32::<LittleEndian>(new_depth)?;
     buf.extend_from_slice(hash);
+    if protocol_version >= 2 {
+        buf.write_u64::<LittleEndian>(head_turn_id)?;
+    }
     Ok(buf)
 }
 
+/// Encodes turns in the GET_LAST response layout: count, then per turn the
+/// record, declared type and hash, plus the payload when it was loaded.
+pub fn encode_turns(items: &[TurnWithMeta]) -> Result<Vec<u8>> {
+    encode_turns_with(items, 0)
+}
+
+/// Like [`encode_turns`], shaped by GET_LAST response `flags`:
+/// [`FLAG_GET_LAST_NO_HASH`] leaves out hashes and [`FLAG_GET_LAST_VARINT`]
+/// writes every integer as LEB128 instead of fixed width. The caller sends
+/// the same flags in the response frame header.
+pub fn encode_turns_with(items: &[TurnWithMeta], flags: u16) -> Result<Vec<u8>> {
+    let varint = flags & FLAG_GET_LAST_VARINT != 0;
+    let put_u32 = |buf: &mut Vec<u8>, v: u32| -> Result<()> {
+        if varint {
+            write_uvarint(buf, v as u64);
+        } else {
+            buf.write_u32::<LittleEndian>(v)?;
+        }
+        Ok(())
+    };
+    let put_u64 = |buf: &mut Vec<u8>, v: u64| -> Result<()> {
+        if varint {
+            write_uvarint(buf, v);
+        } else {
+            buf.write_u64::<LittleEndian>(v)?;
+        }
+        Ok(())
+    };
+
+    let mut resp = Vec::new();
+    put_u32(&mut resp, items.len() as u32)?;
+    for item in items {
+        put_u64(&mut resp, item.record.turn_id)?;
+        put_u64(&mut resp, item.record.parent_turn_id)?;
+        put_u32(&mut resp, item.record.depth)?;
+        put_u32(&mut resp, item.meta.declared_type_id.len() as u32)?;
+        resp.extend_from_slice(item.meta.declared_type_id.as_bytes());
+        put_u32(&mut resp, item.meta.declared_type_version)?;
+        put_u32(&mut resp, item.meta.encoding)?;
+        // Included payloads are always sent raw; otherwise report how the
+        // blob store holds it, not the codec it was appended with.
+        let compression = if item.payload.is_some() {
+            0
+        } else {
+            item.stored_codec as u32
+        };
+        put_u32(&mut resp, compression)?;
+        let uncompressed_len = item
+            .payload
+            .as_ref()
+            .map(|p| p.len() as u32)
+            .unwrap_or(item.meta.uncompressed_len);
+        put_u32(&mut resp, uncompressed_len)?;
+        if flags & FLAG_GET_LAST_NO_HASH == 0 {
+            resp.extend_from_slice(&item.record.payload_hash);
+        }
+        if let Some(payload) = &item.payload {
+            put_u32(&mut resp, payload.len() as u32)?;
+            resp.extend_from_slice(payload);
+        }
+    }
+    Ok(resp)
+}
+
+/// Appends `v` as unsigned LEB128: seven bits per byte, low bits first, high
+/// bit set on every byte but the last.
+pub fn write_uvarint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Reads an unsigned LEB128 value written by [`write_uvarint`].
+pub fn read_uvarint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(StoreError::InvalidInput(
+        "varint longer than 10 bytes".into(),
+    ))
+}
+
+/// Encodes a GET_LAST_BATCH response: count, then per context its id and a
+/// length-prefixed GET_LAST body. Fails if the response would not fit in one
+/// frame.
+pub fn encode_get_last_batch_resp(groups: &[(u64, Vec<TurnWithMeta>)]) -> Result<Vec<u8>> {
+    let mut resp = Vec::new();
+    resp.write_u32::<LittleEndian>(groups.len() as u32)?;
+    for (context_id, items) in groups {
+        let body = encode_turns(items)?;
+        resp.write_u64::<LittleEndian>(*context_id)?;
+        resp.write_u32::<LittleEndian>(body.len() as u32)?;
+        resp.extend_from_slice(&body);
+        if resp.len() > MAX_FRAME_SIZE as usize {
+            return Err(StoreError::InvalidInput(format!(
+                "batch response exceeds the {MAX_FRAME_SIZE}-byte frame limit; \
+                 split it or omit payloads"
+            )));
+        }
+    }
+    Ok(resp)
+}
+
 pub fn encode_error(code: u32, detail: &str) -> Result<Vec<u8>> {
     let mut buf = Vec::new();
     buf.write_u32::<LittleEndian>(code)?;
@@ -287,12 +584,62 @@ pub fn encode_error(code: u32, detail: &str) -> Result<Vec<u8>> {
     Ok(buf)
 }
 
+/// Map a store error to the binary error code and detail.
+///
+/// Codes follow the HTTP taxonomy. Not-found details are prefixed with the
+/// stable kind name (e.g. `blob: ...`) so clients can tell what was missing.
+pub fn map_store_error(err: &StoreError) -> (u32, String) {
+    match err {
+        StoreError::NotFound(kind, msg) => {
+            let code = match kind {
+                NotFoundKind::TypeDescriptor => 424,
+                NotFoundKind::ParentTurn => 409,
+                _ => 404,
+            };
+            (code, format!("{kind}: {msg}"))
+        }
+        StoreError::InvalidInput(msg) => (422, msg.clone()),
+        StoreError::BadRequest(msg) => (400, msg.clone()),
+        StoreError::Conflict(msg) => (409, msg.clone()),
+        StoreError::HeadMismatch { .. } => (409, err.to_string()),
+        StoreError::Corrupt(msg) => (500, msg.clone()),
+        StoreError::Io(msg) => (500, msg.to_string()),
+        StoreError::Internal(msg) => (500, msg.clone()),
+        StoreError::Panicked(_) => (500, err.to_string()),
+    }
+}
+
+/// Oldest wire protocol version this server speaks.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+/// Newest wire protocol version this server speaks. Version 2 adds
+/// GET_LAST_BATCH, the HELLO capability block, the append ack's
+/// `head_turn_id`, and the GET_LAST hash-omission and varint options.
+pub const MAX_PROTOCOL_VERSION: u16 = 2;
+
 /// Parsed HELLO request with optional client metadata.
 #[derive(Debug, Clone, Default)]
 pub struct HelloRequest {
+    /// Highest version the client supports; 0 for a legacy empty HELLO.
     pub protocol_version: u16,
     pub client_tag: String,
     pub client_meta_json: Option<String>,
+    /// Lowest version the client accepts, when it sent one.
+    pub min_protocol_version: Option<u16>,
+}
+
+/// Pick the protocol version for a connection: the highest version both
+/// sides support. Legacy clients that advertise nothing get version 1.
+pub fn negotiate_protocol_version(hello: &HelloRequest) -> Result<u16> {
+    let client_max = hello.protocol_version.max(1);
+    let client_min = hello.min_protocol_version.unwrap_or(1);
+    let version = client_max.min(MAX_PROTOCOL_VERSION);
+    if client_min > client_max || version < client_min || version < MIN_PROTOCOL_VERSION {
+        return Err(StoreError::InvalidInput(format!(
+            "unsupported protocol version: client accepts {client_min}..={client_max}, \
+             server supports {MIN_PROTOCOL_VERSION}..={MAX_PROTOCOL_VERSION}"
+        )));
+    }
+    Ok(version)
 }
 
 /// Parse HELLO payload. Supports both old (empty) and new (with metadata) formats.
@@ -334,17 +681,77 @@ pub fn parse_hello(payload: &[u8]) -> Result<HelloRequest> {
         None
     };
 
+    // Optional trailing min_protocol_version(u16)
+    let min_protocol_version = if (cursor.position() as usize) + 2 <= payload.len() {
+        Some(cursor.read_u16::<LittleEndian>()?)
+    } else {
+        None
+    };
+
     Ok(HelloRequest {
         protocol_version,
         client_tag,
         client_meta_json,
+        min_protocol_version,
     })
 }
 
-/// Encode HELLO response with session_id and protocol_version.
-pub fn encode_hello_resp(session_id: u64, protocol_version: u16) -> Result<Vec<u8>> {
-    let mut buf = Vec::with_capacity(10);
+/// Capability bits advertised in the HELLO response.
+pub const CAP_COMPRESSION_ZSTD: u32 = 1 << 0;
+pub const CAP_FS_SNAPSHOTS: u32 = 1 << 1;
+pub const CAP_GET_LAST_BATCH: u32 = 1 << 2;
+pub const CAP_CQL: u32 = 1 << 3;
+pub const CAP_S3_SYNC: u32 = 1 << 4;
+pub const CAP_CONDITIONAL_APPEND: u32 = 1 << 5;
+
+/// Optional features and limits a client can adapt to without trial and error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub flags: u32,
+    pub max_frame_size: u32,
+    pub max_batch_contexts: u32,
+    /// Most turns one GET_LAST_BATCH request may return in total.
+    pub max_batch_turns: u32,
+}
+
+impl ServerCapabilities {
+    /// Capabilities of this build; `s3_sync` reflects runtime configuration.
+    pub fn new(s3_sync: bool) -> Self {
+        let mut flags = CAP_COMPRESSION_ZSTD
+            | CAP_FS_SNAPSHOTS
+            | CAP_GET_LAST_BATCH
+            | CAP_CQL
+            | CAP_CONDITIONAL_APPEND;
+        if s3_sync {
+            flags |= CAP_S3_SYNC;
+        }
+        Self {
+            flags,
+            max_frame_size: MAX_FRAME_SIZE,
+            max_batch_contexts: MAX_GET_LAST_BATCH as u32,
+            max_batch_turns: MAX_GET_LAST_BATCH_TURNS as u32,
+        }
+    }
+}
+
+/// Encode HELLO response: session_id, negotiated protocol_version, then
+/// capabilities (flags, max_frame_size, max_batch_contexts, max_batch_turns).
+/// The capability block is only sent from version 2, so version 1 clients get
+/// the 10-byte reply they expect.
+pub fn encode_hello_resp(
+    session_id: u64,
+    protocol_version: u16,
+    capabilities: &ServerCapabilities,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(26);
     buf.write_u64::<LittleEndian>(session_id)?;
     buf.write_u16::<LittleEndian>(protocol_version)?;
+    if protocol_version < 2 {
+        return Ok(buf);
+    }
+    buf.write_u32::<LittleEndian>(capabilities.flags)?;
+    buf.write_u32::<LittleEndian>(capabilities.max_frame_size)?;
+    buf.write_u32::<LittleEndian>(capabilities.max_batch_contexts)?;
+    buf.write_u32::<LittleEndian>(capabilities.max_batch_turns)?;
     Ok(buf)
 }
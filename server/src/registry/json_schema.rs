@@ -0,0 +1,155 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSON Schema for the typed JSON form of a type version.
+//!
+//! The schema describes what the typed view renders with default options and
+//! what JSON appends accept: named fields, enum labels, base64 bytes, and
+//! RFC 3339 times. Referenced types go in `$defs` at their latest version, as
+//! projection resolves them.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use serde_json::{json, Map, Value};
+
+use super::{FieldSpec, ItemsSpec, Registry, TypeVersionSpec};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A JSON Schema (draft 2020-12) document for `spec`, titled `type_id`.
+pub fn json_schema(type_id: &str, spec: &TypeVersionSpec, registry: &Registry) -> Value {
+    let mut refs = RefCollector::default();
+    let mut schema = object_schema(spec, registry, &mut refs);
+
+    let mut defs = Map::new();
+    while let Some(type_ref) = refs.queue.pop_front() {
+        if let Some(referenced) = registry.get_latest_type_version(&type_ref) {
+            let def = object_schema(referenced, registry, &mut refs);
+            defs.insert(type_ref, def);
+        }
+    }
+
+    let obj = schema.as_object_mut().expect("object schema");
+    obj.insert("$schema".into(), json!(DRAFT));
+    obj.insert("title".into(), json!(type_id));
+    obj.insert("x-cxdb-type-version".into(), json!(spec.version));
+    if !defs.is_empty() {
+        obj.insert("$defs".into(), Value::Object(defs));
+    }
+    schema
+}
+
+/// Types referenced so far, for `$defs`.
+#[derive(Default)]
+struct RefCollector {
+    seen: BTreeSet<String>,
+    queue: VecDeque<String>,
+}
+
+impl RefCollector {
+    /// A `$ref` to `type_ref`'s definition, or an unconstrained schema if
+    /// the registry doesn't know the type.
+    fn reference(&mut self, type_ref: &str, registry: &Registry) -> Value {
+        if registry.get_latest_type_version(type_ref).is_none() {
+            return json!({});
+        }
+        if self.seen.insert(type_ref.to_string()) {
+            self.queue.push_back(type_ref.to_string());
+        }
+        // JSON Pointer escaping: `~` and `/` are special in a reference token.
+        let token = type_ref.replace('~', "~0").replace('/', "~1");
+        json!({ "$ref": format!("#/$defs/{token}") })
+    }
+}
+
+fn object_schema(spec: &TypeVersionSpec, registry: &Registry, refs: &mut RefCollector) -> Value {
+    let mut tags: Vec<u64> = spec.fields.keys().copied().collect();
+    tags.sort_unstable();
+
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for tag in tags {
+        let field = &spec.fields[&tag];
+        properties.insert(field.name.clone(), field_schema(field, registry, refs));
+        if !field.optional {
+            required.push(json!(field.name));
+        }
+    }
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = Value::Array(required);
+    }
+    schema
+}
+
+fn field_schema(field: &FieldSpec, registry: &Registry, refs: &mut RefCollector) -> Value {
+    if let Some(labels) = field
+        .enum_ref
+        .as_deref()
+        .and_then(|enum_ref| enum_labels(enum_ref, registry))
+    {
+        return json!({ "enum": labels });
+    }
+    if let Some(type_ref) = &field.type_ref {
+        if field.field_type == "ref" || field.field_type == "map" {
+            return refs.reference(type_ref, registry);
+        }
+    }
+    match field.field_type.as_str() {
+        "array" => match &field.items {
+            Some(items) => json!({ "type": "array", "items": items_schema(items, registry, refs) }),
+            None => json!({ "type": "array" }),
+        },
+        "map" => match &field.value_type {
+            Some(value_type) => json!({
+                "type": "object",
+                "additionalProperties": items_schema(value_type, registry, refs),
+            }),
+            None => json!({ "type": "object" }),
+        },
+        other => scalar_schema(other),
+    }
+}
+
+fn items_schema(items: &ItemsSpec, registry: &Registry, refs: &mut RefCollector) -> Value {
+    match items {
+        ItemsSpec::Simple(item_type) => scalar_schema(item_type),
+        ItemsSpec::Ref(type_ref) => refs.reference(type_ref, registry),
+        ItemsSpec::Array(inner) => {
+            json!({ "type": "array", "items": items_schema(inner, registry, refs) })
+        }
+    }
+}
+
+fn scalar_schema(field_type: &str) -> Value {
+    match field_type {
+        "string" => json!({ "type": "string" }),
+        "bool" => json!({ "type": "boolean" }),
+        "u64" | "uint64" => json!({ "type": "integer", "minimum": 0 }),
+        "i64" | "int64" => json!({ "type": "integer" }),
+        "u32" | "uint32" => json!({ "type": "integer", "minimum": 0, "maximum": u32::MAX }),
+        "u8" | "uint8" => json!({ "type": "integer", "minimum": 0, "maximum": u8::MAX }),
+        "int32" => json!({ "type": "integer", "minimum": i32::MIN, "maximum": i32::MAX }),
+        "bytes" | "typed_blob" => json!({ "type": "string", "contentEncoding": "base64" }),
+        "unix_ms" | "time_ms" | "timestamp_ms" => {
+            json!({ "type": "string", "format": "date-time" })
+        }
+        _ => json!({}),
+    }
+}
+
+/// An enum's labels in discriminant order.
+fn enum_labels(enum_ref: &str, registry: &Registry) -> Option<Vec<String>> {
+    let values = registry.get_enum(enum_ref)?;
+    let mut entries: Vec<(i128, &String)> = values
+        .iter()
+        .filter_map(|(num, label)| num.parse::<i128>().ok().map(|n| (n, label)))
+        .collect();
+    entries.sort();
+    Some(
+        entries
+            .into_iter()
+            .map(|(_, label)| label.clone())
+            .collect(),
+    )
+}
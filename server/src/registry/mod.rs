@@ -4,14 +4,25 @@
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+use crate::data_mode;
 use crate::error::{Result, StoreError};
+use crate::lock::lock_or_recover;
+use crate::lru::LruCache;
+
+mod json_schema;
+
+pub use json_schema::json_schema;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistryBundle {
     pub registry_version: u32,
+    /// May be omitted, in which case the bundle takes the id it is put
+    /// under. Bodies without one can be shared by several ids.
+    #[serde(default)]
     pub bundle_id: String,
     #[serde(default)]
     pub types: HashMap<String, TypeEntry>,
@@ -38,6 +49,22 @@ pub struct RendererSpec {
     pub integrity: Option<String>,
 }
 
+impl RendererSpec {
+    /// Bundled with the frontend rather than loaded from a remote URL.
+    pub fn is_builtin(&self) -> bool {
+        self.esm_url.starts_with("builtin:")
+    }
+}
+
+/// Narrows `Registry::get_renderers`. The default matches every renderer.
+#[derive(Debug, Clone, Default)]
+pub struct RendererFilter {
+    /// Only types whose id starts with this prefix.
+    pub type_prefix: Option<String>,
+    /// Only renderers with a `builtin:` ESM URL.
+    pub builtin_only: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypeVersion {
     #[serde(default)]
@@ -60,6 +87,12 @@ pub struct FieldDef {
     pub optional: Option<bool>,
     #[serde(default)]
     pub items: Option<serde_json::Value>,
+    /// Key type of a `map` field, e.g. "string" or "u64".
+    #[serde(default)]
+    pub key_type: Option<String>,
+    /// Value spec of a `map` field, in the same forms as `items`.
+    #[serde(default)]
+    pub value_type: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -70,6 +103,10 @@ pub struct FieldSpec {
     pub type_ref: Option<String>,
     pub optional: bool,
     pub items: Option<ItemsSpec>,
+    /// For `map` fields without a `ref`: how keys are encoded from JSON.
+    pub key_type: Option<String>,
+    /// For `map` fields without a `ref`: the type of each value.
+    pub value_type: Option<ItemsSpec>,
 }
 
 /// Specifies array item type - either a simple type string or a type reference
@@ -79,6 +116,49 @@ pub enum ItemsSpec {
     Simple(String),
     /// Reference to another type like "cxdb:ToolCallItem"
     Ref(String),
+    /// Nested array whose items follow the inner spec, e.g. `[[1, 2], [3]]`
+    Array(Box<ItemsSpec>),
+}
+
+impl ItemsSpec {
+    /// Parse an `items` (or map `value_type`) spec: a type name, a ref as
+    /// `{ "type": "ref", "ref": "T" }` or the shorthand `{ "ref": "T" }`, or
+    /// a nested array as `{ "type": "array", "items": <spec> }`.
+    pub fn parse(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(s) => Some(ItemsSpec::Simple(s.clone())),
+            serde_json::Value::Object(obj) => match obj.get("type") {
+                Some(serde_json::Value::String(t)) if t == "ref" => match obj.get("ref") {
+                    Some(serde_json::Value::String(r)) => Some(ItemsSpec::Ref(r.clone())),
+                    _ => None,
+                },
+                Some(serde_json::Value::String(t)) if t == "array" => {
+                    match obj.get("items").and_then(Self::parse) {
+                        Some(inner) => Some(ItemsSpec::Array(Box::new(inner))),
+                        None => Some(ItemsSpec::Simple(t.clone())),
+                    }
+                }
+                Some(serde_json::Value::String(t)) => Some(ItemsSpec::Simple(t.clone())),
+                _ => match obj.get("ref") {
+                    // Shorthand: { "ref": "cxdb.ToolCallItem" } without "type"
+                    Some(serde_json::Value::String(r)) => Some(ItemsSpec::Ref(r.clone())),
+                    _ => None,
+                },
+            },
+            _ => None,
+        }
+    }
+
+    /// The descriptor form `parse` accepts.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ItemsSpec::Simple(s) => serde_json::Value::String(s.clone()),
+            ItemsSpec::Ref(r) => serde_json::json!({"type": "ref", "ref": r}),
+            ItemsSpec::Array(inner) => {
+                serde_json::json!({"type": "array", "items": inner.to_json()})
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -104,10 +184,105 @@ pub struct FieldSignature {
 #[derive(Debug, Clone)]
 pub struct Registry {
     dir: PathBuf,
-    bundles: HashMap<String, Vec<u8>>,
+    /// Content hash of each bundle id's body.
+    bundles: HashMap<String, blake3::Hash>,
+    /// Bundle bodies by content hash; ids with identical bodies share one.
+    bundle_bodies: HashMap<blake3::Hash, BundleBody>,
+    /// Recently read bodies of bundles held compressed in memory.
+    bundle_cache: Arc<Mutex<BundleCache>>,
     types: HashMap<String, TypeSpec>,
     enums: HashMap<String, HashMap<String, String>>,
     last_bundle_id: Option<String>,
+    /// Highest version of each type defined in each bundle, for projecting
+    /// a context pinned to that bundle.
+    bundle_latest: HashMap<String, HashMap<String, u32>>,
+    /// Bundle files passed over at open because they didn't parse.
+    skipped_bundles: Vec<SkippedBundle>,
+    options: RegistryOptions,
+}
+
+/// A bundle file `open` couldn't parse and skipped.
+#[derive(Debug, Clone)]
+pub struct SkippedBundle {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Registry ingest policy.
+#[derive(Debug, Clone)]
+pub struct RegistryOptions {
+    /// Reject new bundles with a non-`builtin:` renderer that has no
+    /// `integrity` hash. Bundles already on disk still load.
+    pub require_renderer_integrity: bool,
+    /// Log and skip bundle files that fail to parse at open instead of
+    /// failing. They are listed in `Registry::skipped_bundles`.
+    pub skip_bad_bundles: bool,
+    /// Most bundles `put_bundle` will hold; 0 means no limit. Bundles
+    /// already on disk still load past it.
+    pub max_bundles: usize,
+    /// Most distinct type ids the registry will hold; 0 means no limit.
+    pub max_types: usize,
+    /// Write new bundle bodies to `objects/` zstd-compressed. Uncompressed
+    /// bodies already there still load.
+    pub compress_on_disk: bool,
+    /// Hold bundle bodies zstd-compressed in memory, decompressing them on
+    /// read through an LRU of `bundle_cache_entries` bodies.
+    pub compress_in_memory: bool,
+    /// Decompressed bodies kept by `compress_in_memory`; 0 disables caching.
+    pub bundle_cache_entries: usize,
+}
+
+/// Default `RegistryOptions::max_bundles`.
+pub const DEFAULT_MAX_BUNDLES: usize = 10_000;
+/// Default `RegistryOptions::max_types`.
+pub const DEFAULT_MAX_TYPES: usize = 100_000;
+/// Default `RegistryOptions::bundle_cache_entries`.
+pub const DEFAULT_BUNDLE_CACHE_ENTRIES: usize = 64;
+
+impl Default for RegistryOptions {
+    fn default() -> Self {
+        Self {
+            require_renderer_integrity: false,
+            skip_bad_bundles: false,
+            max_bundles: DEFAULT_MAX_BUNDLES,
+            max_types: DEFAULT_MAX_TYPES,
+            compress_on_disk: false,
+            compress_in_memory: false,
+            bundle_cache_entries: DEFAULT_BUNDLE_CACHE_ENTRIES,
+        }
+    }
+}
+
+impl RegistryOptions {
+    pub fn from_env() -> Self {
+        let env_usize = |key: &str, default: usize| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        let env_bool = |key: &str| {
+            std::env::var(key)
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false)
+        };
+        Self {
+            max_bundles: env_usize("CXDB_MAX_BUNDLES", DEFAULT_MAX_BUNDLES),
+            max_types: env_usize("CXDB_MAX_TYPES", DEFAULT_MAX_TYPES),
+            compress_on_disk: env_bool("CXDB_REGISTRY_COMPRESS"),
+            compress_in_memory: env_bool("CXDB_REGISTRY_COMPRESS_IN_MEMORY"),
+            bundle_cache_entries: env_usize(
+                "CXDB_REGISTRY_BUNDLE_CACHE_ENTRIES",
+                DEFAULT_BUNDLE_CACHE_ENTRIES,
+            ),
+            require_renderer_integrity: std::env::var("CXDB_REQUIRE_RENDERER_INTEGRITY")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            skip_bad_bundles: std::env::var("CXDB_REGISTRY_SKIP_BAD_BUNDLES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -118,44 +293,166 @@ pub enum PutOutcome {
 
 impl Registry {
     pub fn open(dir: &Path) -> Result<Self> {
-        fs::create_dir_all(dir)?;
+        Self::open_with_options(dir, RegistryOptions::default())
+    }
+
+    pub fn open_with_options(dir: &Path, options: RegistryOptions) -> Result<Self> {
+        data_mode::create_dir_all(dir)?;
         let mut registry = Self {
             dir: dir.to_path_buf(),
             bundles: HashMap::new(),
+            bundle_bodies: HashMap::new(),
+            bundle_cache: Arc::new(Mutex::new(BundleCache::new(options.bundle_cache_entries))),
             types: HashMap::new(),
             enums: HashMap::new(),
             last_bundle_id: None,
+            bundle_latest: HashMap::new(),
+            skipped_bundles: Vec::new(),
+            options,
         };
 
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
+            // Also skips `*.json.tmp` left by a `put_bundle` interrupted
+            // before its rename.
             if path.extension().and_then(|s| s.to_str()) != Some("json") {
                 continue;
             }
             let bytes = fs::read(&path)?;
-            let bundle: RegistryBundle = serde_json::from_slice(&bytes)
-                .map_err(|e| StoreError::Corrupt(format!("invalid bundle json: {e}")))?;
+            let (hash, raw, bundle) = match registry.read_bundle_file(bytes) {
+                Ok(loaded) => loaded,
+                Err(e) if registry.options.skip_bad_bundles => {
+                    tracing::warn!(
+                        "skipping unreadable registry bundle {}: {e}",
+                        path.display()
+                    );
+                    registry
+                        .skipped_bundles
+                        .push(SkippedBundle { path, error: e });
+                    continue;
+                }
+                Err(e) => {
+                    return Err(StoreError::Corrupt(format!(
+                        "bad bundle file {}: {e}",
+                        path.display()
+                    )))
+                }
+            };
             let bundle_id = bundle.bundle_id.clone();
-            registry.ingest_bundle(bundle, &bytes, true)?;
-            registry.bundles.insert(bundle_id.clone(), bytes);
+            registry.ingest_bundle(bundle, &raw, true)?;
+            registry.bundles.insert(bundle_id.clone(), hash);
+            let body = registry.hold_body(raw)?;
+            registry.bundle_bodies.insert(hash, body);
             registry.last_bundle_id = Some(bundle_id);
         }
 
         Ok(registry)
     }
 
+    pub fn skipped_bundles(&self) -> &[SkippedBundle] {
+        &self.skipped_bundles
+    }
+
     pub fn last_bundle_id(&self) -> Option<String> {
         self.last_bundle_id.clone()
     }
 
-    pub fn get_bundle(&self, bundle_id: &str) -> Option<&[u8]> {
-        self.bundles.get(bundle_id).map(|b| b.as_slice())
+    pub fn has_bundle(&self, bundle_id: &str) -> bool {
+        self.bundles.contains_key(bundle_id)
+    }
+
+    /// A bundle's body exactly as it was put, decompressed if it is held
+    /// compressed.
+    pub fn get_bundle(&self, bundle_id: &str) -> Option<Arc<[u8]>> {
+        let hash = self.bundles.get(bundle_id)?;
+        match self.bundle_bodies.get(hash)? {
+            BundleBody::Plain(raw) => Some(Arc::clone(raw)),
+            BundleBody::Compressed(compressed) => {
+                let mut cache = lock_or_recover(&self.bundle_cache, "bundle cache");
+                if let Some(raw) = cache.get(hash) {
+                    return Some(Arc::clone(raw));
+                }
+                match zstd::decode_all(&compressed[..]) {
+                    Ok(raw) => {
+                        let raw: Arc<[u8]> = raw.into();
+                        cache.insert(*hash, Arc::clone(&raw));
+                        Some(raw)
+                    }
+                    Err(e) => {
+                        tracing::error!("registry bundle {bundle_id} failed to decompress: {e}");
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// `raw` in the form `compress_in_memory` asks for.
+    fn hold_body(&self, raw: Vec<u8>) -> Result<BundleBody> {
+        if !self.options.compress_in_memory {
+            return Ok(BundleBody::Plain(raw.into()));
+        }
+        let compressed = zstd::encode_all(&raw[..], BUNDLE_ZSTD_LEVEL)
+            .map_err(|e| StoreError::Internal(format!("bundle compress: {e}")))?;
+        Ok(BundleBody::Compressed(compressed))
+    }
+
+    /// The blake3 hash of a bundle's body, which is also its storage key.
+    pub fn bundle_hash(&self, bundle_id: &str) -> Option<blake3::Hash> {
+        self.bundles.get(bundle_id).copied()
     }
 
+    /// A bundle file from `dir`: either a reference written by
+    /// `put_bundle`, resolved against `objects/`, or a whole bundle as
+    /// stored before bodies were content-addressed.
+    fn read_bundle_file(
+        &self,
+        bytes: Vec<u8>,
+    ) -> std::result::Result<(blake3::Hash, Vec<u8>, RegistryBundle), String> {
+        let (bundle_id, raw) = match serde_json::from_slice::<BundleRef>(&bytes) {
+            Ok(bundle_ref) => {
+                let objects = self.objects_dir();
+                let compressed = objects.join(format!("{}.json.zst", bundle_ref.content_hash));
+                let (path, raw) = if compressed.exists() {
+                    let raw = fs::read(&compressed)
+                        .and_then(|stored| zstd::decode_all(&stored[..]))
+                        .map_err(|e| format!("{}: {e}", compressed.display()))?;
+                    (compressed, raw)
+                } else {
+                    let path = objects.join(format!("{}.json", bundle_ref.content_hash));
+                    let raw = fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+                    (path, raw)
+                };
+                if blake3::hash(&raw).to_hex().as_str() != bundle_ref.content_hash {
+                    return Err(format!("{} does not match its hash", path.display()));
+                }
+                (Some(bundle_ref.bundle_id), raw)
+            }
+            Err(_) => (None, bytes),
+        };
+        let mut bundle: RegistryBundle =
+            serde_json::from_slice(&raw).map_err(|e| format!("invalid bundle json: {e}"))?;
+        if let Some(bundle_id) = bundle_id {
+            bundle.bundle_id = bundle_id;
+        }
+        if bundle.bundle_id.is_empty() {
+            return Err("bundle has no bundle_id".to_string());
+        }
+        Ok((blake3::hash(&raw), raw, bundle))
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.dir.join(BUNDLE_OBJECTS_DIR)
+    }
+
+    /// Store `raw` under `bundle_id`. The body is kept once per distinct
+    /// content, so putting the same bytes under another id costs only a
+    /// reference.
     pub fn put_bundle(&mut self, bundle_id: &str, raw: &[u8]) -> Result<PutOutcome> {
+        let hash = blake3::hash(raw);
         if let Some(existing) = self.bundles.get(bundle_id) {
-            if existing == raw {
+            if *existing == hash {
                 return Ok(PutOutcome::AlreadyExists);
             }
             return Err(StoreError::InvalidInput(
@@ -163,26 +460,74 @@ impl Registry {
             ));
         }
 
-        let bundle: RegistryBundle = serde_json::from_slice(raw)
+        let mut bundle: RegistryBundle = serde_json::from_slice(raw)
             .map_err(|e| StoreError::InvalidInput(format!("invalid json: {e}")))?;
-        if bundle.bundle_id != bundle_id {
+        if bundle.bundle_id.is_empty() {
+            bundle.bundle_id = bundle_id.to_string();
+        } else if bundle.bundle_id != bundle_id {
             return Err(StoreError::InvalidInput(
                 "bundle_id does not match path".into(),
             ));
         }
 
-        self.ingest_bundle(bundle.clone(), raw, false)?;
+        self.ingest_bundle(bundle, raw, false)?;
 
-        let filename = bundle_filename(bundle_id);
-        let path = self.dir.join(filename);
-        fs::write(&path, raw)?;
-
-        self.bundles.insert(bundle_id.to_string(), raw.to_vec());
+        if !self.bundle_bodies.contains_key(&hash) {
+            let objects = self.objects_dir();
+            data_mode::create_dir_all(&objects)?;
+            if self.options.compress_on_disk {
+                let compressed = zstd::encode_all(raw, BUNDLE_ZSTD_LEVEL)
+                    .map_err(|e| StoreError::Internal(format!("bundle compress: {e}")))?;
+                let path = objects.join(format!("{}.json.zst", hash.to_hex()));
+                data_mode::write_file_atomic(&path, &compressed)?;
+            } else {
+                let path = objects.join(format!("{}.json", hash.to_hex()));
+                data_mode::write_file_atomic(&path, raw)?;
+            }
+        }
+        let bundle_ref = BundleRef {
+            bundle_id: bundle_id.to_string(),
+            content_hash: hash.to_hex().to_string(),
+        };
+        let ref_bytes = serde_json::to_vec(&bundle_ref)
+            .map_err(|e| StoreError::Internal(format!("bundle ref encode: {e}")))?;
+        data_mode::write_file_atomic(&self.dir.join(bundle_filename(bundle_id)), &ref_bytes)?;
+
+        self.bundles.insert(bundle_id.to_string(), hash);
+        if !self.bundle_bodies.contains_key(&hash) {
+            let body = self.hold_body(raw.to_vec())?;
+            self.bundle_bodies.insert(hash, body);
+        }
         self.last_bundle_id = Some(bundle_id.to_string());
 
         Ok(PutOutcome::Created)
     }
 
+    /// Refuse a new bundle that would take the registry past
+    /// `max_bundles` or `max_types`, before anything is merged.
+    fn check_capacity(&self, bundle: &RegistryBundle) -> Result<()> {
+        let max_bundles = self.options.max_bundles;
+        if max_bundles > 0 && self.bundles.len() >= max_bundles {
+            return Err(StoreError::InvalidInput(format!(
+                "registry is full: {} bundles stored, limit is {max_bundles} (CXDB_MAX_BUNDLES)",
+                self.bundles.len()
+            )));
+        }
+        let max_types = self.options.max_types;
+        let new_types = bundle
+            .types
+            .keys()
+            .filter(|type_id| !self.types.contains_key(*type_id))
+            .count();
+        if max_types > 0 && self.types.len() + new_types > max_types {
+            return Err(StoreError::InvalidInput(format!(
+                "bundle adds {new_types} types to the {} already registered, limit is {max_types} (CXDB_MAX_TYPES)",
+                self.types.len()
+            )));
+        }
+        Ok(())
+    }
+
     pub fn get_type_version(&self, type_id: &str, version: u32) -> Option<&TypeVersionSpec> {
         self.types.get(type_id)?.versions.get(&version)
     }
@@ -203,19 +548,59 @@ impl Registry {
     pub fn stats(&self) -> RegistryStats {
         RegistryStats {
             bundles_total: self.bundles.len(),
+            bundle_bodies_total: self.bundle_bodies.len(),
             types_total: self.types.len(),
             enums_total: self.enums.len(),
+            bundles_skipped: self.skipped_bundles.len(),
         }
     }
 
+    /// The highest version of `type_id` defined by `bundle_id`, or `None`
+    /// if the bundle doesn't define the type.
+    pub fn get_latest_type_version_in_bundle(
+        &self,
+        bundle_id: &str,
+        type_id: &str,
+    ) -> Option<&TypeVersionSpec> {
+        let version = *self.bundle_latest.get(bundle_id)?.get(type_id)?;
+        self.get_type_version(type_id, version)
+    }
+
+    /// The latest version of `type_id` as seen from a context pinned to
+    /// `bundle_id`: the bundle's own latest if it defines the type, else the
+    /// registry-wide latest.
+    pub fn get_latest_type_version_pinned(
+        &self,
+        bundle_id: Option<&str>,
+        type_id: &str,
+    ) -> Option<&TypeVersionSpec> {
+        bundle_id
+            .and_then(|bundle_id| self.get_latest_type_version_in_bundle(bundle_id, type_id))
+            .or_else(|| self.get_latest_type_version(type_id))
+    }
+
     /// Returns a mapping of type_id -> RendererSpec for all types with renderers.
     /// Uses the latest version's renderer for each type.
-    pub fn get_all_renderers(&self) -> HashMap<String, RendererSpec> {
-        let mut result = HashMap::new();
+    pub fn get_all_renderers(&self) -> BTreeMap<String, RendererSpec> {
+        self.get_renderers(&RendererFilter::default())
+    }
+
+    /// Latest-version renderers of the types matching `filter`, keyed and
+    /// ordered by type id.
+    pub fn get_renderers(&self, filter: &RendererFilter) -> BTreeMap<String, RendererSpec> {
+        let mut result = BTreeMap::new();
         for (type_id, type_spec) in &self.types {
+            if let Some(prefix) = &filter.type_prefix {
+                if !type_id.starts_with(prefix.as_str()) {
+                    continue;
+                }
+            }
             // Get the latest version's renderer (BTreeMap is ordered, last = highest version)
             if let Some((_, version_spec)) = type_spec.versions.iter().next_back() {
                 if let Some(renderer) = &version_spec.renderer {
+                    if filter.builtin_only && !renderer.is_builtin() {
+                        continue;
+                    }
                     result.insert(type_id.clone(), renderer.clone());
                 }
             }
@@ -230,6 +615,15 @@ impl Registry {
             ));
         }
 
+        if self.options.require_renderer_integrity && !loading {
+            check_renderer_integrity(&bundle)?;
+        }
+        // Bundles stored before these checks still load.
+        if !loading {
+            check_unique_enum_labels(&bundle)?;
+            self.check_capacity(&bundle)?;
+        }
+
         // Merge enums
         for (enum_id, mapping) in bundle.enums.iter() {
             if let Some(existing) = self.enums.get(enum_id) {
@@ -289,7 +683,6 @@ impl Registry {
                 type_spec.versions.insert(version, normalized);
             }
         }
-
         // Validate enum references after merge
         for (type_id, type_spec) in self.types.iter() {
             for (version, version_spec) in type_spec.versions.iter() {
@@ -305,6 +698,19 @@ impl Registry {
             }
         }
 
+        // Bundles load in directory order, so only what this bundle itself
+        // defines is a stable snapshot. Recorded only once the bundle is
+        // accepted, so a rejected one leaves nothing behind.
+        let mut latest = HashMap::new();
+        for (type_id, type_entry) in bundle.types.iter() {
+            for version_str in type_entry.versions.keys() {
+                let version = parse_version(version_str)?;
+                let entry = latest.entry(type_id.clone()).or_insert(version);
+                *entry = (*entry).max(version);
+            }
+        }
+        self.bundle_latest.insert(bundle.bundle_id.clone(), latest);
+
         if !loading {
             let _ = raw;
         }
@@ -316,8 +722,55 @@ impl Registry {
 #[derive(Debug, Clone)]
 pub struct RegistryStats {
     pub bundles_total: usize,
+    /// Distinct bundle bodies; lower than `bundles_total` when ids share
+    /// content.
+    pub bundle_bodies_total: usize,
     pub types_total: usize,
     pub enums_total: usize,
+    pub bundles_skipped: usize,
+}
+
+/// Every remote renderer in `bundle` must pin its module with an SRI hash.
+fn check_renderer_integrity(bundle: &RegistryBundle) -> Result<()> {
+    for (type_id, entry) in &bundle.types {
+        for (version, def) in &entry.versions {
+            let Some(renderer) = &def.renderer else {
+                continue;
+            };
+            let has_integrity = renderer
+                .integrity
+                .as_deref()
+                .is_some_and(|i| !i.trim().is_empty());
+            if !renderer.is_builtin() && !has_integrity {
+                return Err(StoreError::InvalidInput(format!(
+                    "renderer for type {type_id} version {version} loads {} without an integrity hash",
+                    renderer.esm_url
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encoding maps an enum label back to its number, so a label shared by two
+/// numbers would encode non-deterministically.
+fn check_unique_enum_labels(bundle: &RegistryBundle) -> Result<()> {
+    for (enum_id, mapping) in &bundle.enums {
+        let mut seen: HashMap<&str, &str> = HashMap::new();
+        for (number, label) in mapping {
+            if let Some(other) = seen.insert(label.as_str(), number.as_str()) {
+                let (a, b) = if other < number.as_str() {
+                    (other, number.as_str())
+                } else {
+                    (number.as_str(), other)
+                };
+                return Err(StoreError::InvalidInput(format!(
+                    "enum {enum_id} maps both {a} and {b} to label {label:?}"
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
 fn parse_version(version: &str) -> Result<u32> {
@@ -333,31 +786,10 @@ fn normalize_version(version: u32, def: &TypeVersion) -> Result<TypeVersionSpec>
             .parse()
             .map_err(|_| StoreError::InvalidInput("invalid field tag".into()))?;
 
-        // Parse items spec - can be a simple string or an object with type/ref.
         // Supports both long form `{ "type": "ref", "ref": "T" }` and shorthand
         // `{ "ref": "T" }` (as used in conversation-bundle.json).
-        let items = match &field_def.items {
-            Some(serde_json::Value::String(s)) => Some(ItemsSpec::Simple(s.clone())),
-            Some(serde_json::Value::Object(obj)) => {
-                if let Some(serde_json::Value::String(t)) = obj.get("type") {
-                    if t == "ref" {
-                        if let Some(serde_json::Value::String(r)) = obj.get("ref") {
-                            Some(ItemsSpec::Ref(r.clone()))
-                        } else {
-                            None
-                        }
-                    } else {
-                        Some(ItemsSpec::Simple(t.clone()))
-                    }
-                } else if let Some(serde_json::Value::String(r)) = obj.get("ref") {
-                    // Shorthand: { "ref": "cxdb.ToolCallItem" } without "type"
-                    Some(ItemsSpec::Ref(r.clone()))
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        };
+        let items = field_def.items.as_ref().and_then(ItemsSpec::parse);
+        let value_type = field_def.value_type.as_ref().and_then(ItemsSpec::parse);
 
         fields.insert(
             tag,
@@ -368,6 +800,8 @@ fn normalize_version(version: u32, def: &TypeVersion) -> Result<TypeVersionSpec>
                 type_ref: field_def.type_ref.clone(),
                 optional: field_def.optional.unwrap_or(false),
                 items,
+                key_type: field_def.key_type.clone(),
+                value_type,
             },
         );
     }
@@ -378,9 +812,40 @@ fn normalize_version(version: u32, def: &TypeVersion) -> Result<TypeVersionSpec>
     })
 }
 
+/// Subdirectory of the registry dir holding bundle bodies, one file per
+/// content hash: `<hash>.json`, or `<hash>.json.zst` when compressed. The
+/// hash is always of the uncompressed body.
+const BUNDLE_OBJECTS_DIR: &str = "objects";
+
+const BUNDLE_ZSTD_LEVEL: i32 = 3;
+
+/// A bundle body as `Registry` holds it in memory.
+#[derive(Debug, Clone)]
+enum BundleBody {
+    Plain(Arc<[u8]>),
+    /// zstd-compressed; see `RegistryOptions::compress_in_memory`.
+    Compressed(Vec<u8>),
+}
+
+/// LRU cache of decompressed bundle bodies keyed by content hash, so a
+/// cached entry never goes stale.
+type BundleCache = LruCache<blake3::Hash, Arc<[u8]>>;
+
+/// What `put_bundle` writes to a bundle id's file: the hash of its body in
+/// `objects/`.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleRef {
+    bundle_id: String,
+    content_hash: String,
+}
+
+/// On-disk name for a bundle. Sanitizing alone maps `a/b` and `a_b` to the
+/// same name, so a hash of the full id keeps names distinct; `open` reads the
+/// id back from the file contents.
 fn bundle_filename(bundle_id: &str) -> String {
     let mut safe = bundle_id.replace('/', "_");
     safe = safe.replace(':', "_");
     safe = safe.replace('#', "_");
-    format!("bundle_{safe}.json")
+    let hash = blake3::hash(bundle_id.as_bytes()).to_hex();
+    format!("bundle_{safe}_{}.json", &hash[..12])
 }
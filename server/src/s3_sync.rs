@@ -29,6 +29,7 @@
 //!   sync_manifest.json    # metadata about last sync
 //! ```
 
+use crate::data_mode;
 use crate::error::{Result, StoreError};
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client as S3Client;
@@ -107,7 +108,7 @@ impl SyncState {
         let path = data_dir.join("sync_state.json");
         let json = serde_json::to_string_pretty(self)
             .map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
-        fs::write(&path, json)?;
+        data_mode::write_file(&path, json.as_bytes())?;
         Ok(())
     }
 }
@@ -166,26 +167,26 @@ impl S3Sync {
         let has_local_data = SYNC_FILES.iter().any(|f| self.data_dir.join(f).exists());
 
         if has_local_data {
-            eprintln!("[s3_sync] Local data exists, skipping restore");
+            tracing::info!("[s3_sync] Local data exists, skipping restore");
             return Ok(false);
         }
 
-        eprintln!("[s3_sync] No local data found, attempting S3 restore...");
+        tracing::info!("[s3_sync] No local data found, attempting S3 restore...");
 
         // Try to fetch manifest from S3
         let manifest = match self.fetch_manifest().await {
             Ok(Some(m)) => m,
             Ok(None) => {
-                eprintln!("[s3_sync] No S3 manifest found, starting fresh");
+                tracing::info!("[s3_sync] No S3 manifest found, starting fresh");
                 return Ok(false);
             }
             Err(e) => {
-                eprintln!("[s3_sync] Failed to fetch manifest: {e}");
+                tracing::warn!("[s3_sync] Failed to fetch manifest: {e}");
                 return Ok(false);
             }
         };
 
-        eprintln!(
+        tracing::info!(
             "[s3_sync] Found S3 manifest with {} files from {}",
             manifest.files.len(),
             manifest.created_at
@@ -197,30 +198,30 @@ impl S3Sync {
 
             // Create parent directories
             if let Some(parent) = local_path.parent() {
-                fs::create_dir_all(parent)?;
+                data_mode::create_dir_all(parent)?;
             }
 
             match self.download_file(relative_path, &local_path).await {
                 Ok(size) => {
                     if size != *expected_size {
-                        eprintln!(
-                            "[s3_sync] Warning: {relative_path} size mismatch (expected {expected_size}, got {size})"
+                        tracing::warn!(
+                            "[s3_sync] {relative_path} size mismatch (expected {expected_size}, got {size})"
                         );
                     }
-                    eprintln!("[s3_sync] Restored {relative_path} ({size} bytes)");
+                    tracing::info!("[s3_sync] Restored {relative_path} ({size} bytes)");
                 }
                 Err(e) => {
-                    eprintln!("[s3_sync] Failed to restore {relative_path}: {e}");
+                    tracing::warn!("[s3_sync] Failed to restore {relative_path}: {e}");
                 }
             }
         }
 
         // Restore registry files
         if let Err(e) = self.restore_registry().await {
-            eprintln!("[s3_sync] Registry restore failed: {e}");
+            tracing::warn!("[s3_sync] Registry restore failed: {e}");
         }
 
-        eprintln!("[s3_sync] Restore complete");
+        tracing::info!("[s3_sync] Restore complete");
         Ok(true)
     }
 
@@ -240,7 +241,7 @@ impl S3Sync {
 
     async fn sync_loop(self, mut shutdown_rx: watch::Receiver<bool>) {
         let mut ticker = interval(Duration::from_secs(self.config.sync_interval_secs));
-        eprintln!(
+        tracing::info!(
             "[s3_sync] Starting background sync (interval: {}s)",
             self.config.sync_interval_secs
         );
@@ -249,7 +250,7 @@ impl S3Sync {
             tokio::select! {
                 _ = ticker.tick() => {
                     if let Err(e) = self.do_sync().await {
-                        eprintln!("[s3_sync] Sync failed: {e}");
+                        tracing::warn!("[s3_sync] Sync failed: {e}");
                     }
                 }
                 _ = shutdown_rx.changed() => {
@@ -261,11 +262,11 @@ impl S3Sync {
         }
 
         // Final sync on shutdown
-        eprintln!("[s3_sync] Performing final sync before shutdown...");
+        tracing::info!("[s3_sync] Performing final sync before shutdown...");
         if let Err(e) = self.do_sync().await {
-            eprintln!("[s3_sync] Final sync failed: {e}");
+            tracing::warn!("[s3_sync] Final sync failed: {e}");
         }
-        eprintln!("[s3_sync] Shutdown complete");
+        tracing::info!("[s3_sync] Shutdown complete");
     }
 
     async fn do_sync(&self) -> Result<()> {
@@ -294,7 +295,7 @@ impl S3Sync {
                         bytes_synced += current_size - last_size;
                     }
                     Err(e) => {
-                        eprintln!("[s3_sync] Failed to upload {relative_path}: {e}");
+                        tracing::warn!("[s3_sync] Failed to upload {relative_path}: {e}");
                     }
                 }
             }
@@ -313,9 +314,11 @@ impl S3Sync {
                 .as_secs();
             state.save(&self.data_dir)?;
 
-            eprintln!(
+            tracing::info!(
                 "[s3_sync] Synced {} files ({} bytes) + {} registry bundles",
-                files_synced, bytes_synced, registry_synced
+                files_synced,
+                bytes_synced,
+                registry_synced
             );
         }
 
@@ -351,7 +354,7 @@ impl S3Sync {
 
     async fn restore_registry(&self) -> Result<()> {
         let registry_dir = self.data_dir.join("registry");
-        fs::create_dir_all(&registry_dir)?;
+        data_mode::create_dir_all(&registry_dir)?;
 
         // List objects with registry/ prefix and download each
         let prefix = self.s3_key("registry/");
@@ -379,7 +382,7 @@ impl S3Sync {
 
                     let local_path = self.data_dir.join(&relative_path);
                     if let Err(e) = self.download_file(&relative_path, &local_path).await {
-                        eprintln!("[s3_sync] Failed to restore {relative_path}: {e}");
+                        tracing::warn!("[s3_sync] Failed to restore {relative_path}: {e}");
                     }
                 }
             }
@@ -517,7 +520,7 @@ impl S3Sync {
             .into_bytes();
 
         let size = bytes.len() as u64;
-        fs::write(local_path, &bytes)?;
+        data_mode::write_file(local_path, &bytes)?;
 
         Ok(size)
     }
@@ -532,7 +535,7 @@ pub struct S3SyncHandle {
 impl S3SyncHandle {
     /// Signal shutdown and wait for the sync task to finish
     pub async fn shutdown(self) {
-        eprintln!("[s3_sync] Shutdown requested, waiting for final sync...");
+        tracing::info!("[s3_sync] Shutdown requested, waiting for final sync...");
         let _ = self.shutdown_tx.send(true);
         let _ = self.handle.await;
     }
@@ -3,21 +3,35 @@
 
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use blake3::Hasher;
 use rmpv::Value;
 
-use crate::blob_store::BlobStore;
-use crate::cql::{self, CqlError, CqlQuery, IndexStats, SecondaryIndexes};
-use crate::error::{Result, StoreError};
-use crate::fs_store::{FsRootsIndex, TreeEntry};
-use crate::turn_store::{ContextHead, TurnMeta, TurnRecord, TurnStore};
+use crate::blob_store::{BlobCodec, BlobKey, BlobStore, BlobStoreOptions};
+use crate::canonical::canonicalize_msgpack;
+use crate::clock::{system_clock, Clock};
+use crate::context_meta::{MetadataOverlay, MetadataOverlayLog, TagNormalization};
+use crate::cql::{self, CqlError, CqlLimits, CqlQuery, FieldName, IndexStats, SecondaryIndexes};
+use crate::data_mode;
+use crate::error::{NotFoundKind, Result, StoreError};
+use crate::fs_store::{FsRootsIndex, TreeCache, TreeEntry, DEFAULT_TREE_CACHE_ENTRIES};
+use crate::interceptor::{AppendContext, AppendInterceptor};
+use crate::lock::lock_or_recover;
+use crate::turn_store::{
+    BranchTip, ContextHead, TurnMeta, TurnRecord, TurnStore, TURN_FLAG_INLINE_PAYLOAD,
+};
 
 #[derive(Debug, Clone)]
 pub struct TurnWithMeta {
     pub record: TurnRecord,
     pub meta: TurnMeta,
     pub payload: Option<Vec<u8>>,
+    /// How the payload blob is actually stored, which may differ from the
+    /// codec it was appended with (`meta.append_compression`).
+    pub stored_codec: BlobCodec,
 }
 
 /// Provenance captures the origin story of a context.
@@ -75,6 +89,9 @@ pub struct ContextMetadata {
     pub title: Option<String>,
     pub labels: Option<Vec<String>>,
     pub provenance: Option<Provenance>,
+    /// Title guessed from the first turn's text when `title` is unset; see
+    /// `StoreOptions::derived_title_chars`. Never overrides an explicit title.
+    pub derived_title: Option<String>,
 }
 
 /// Result of a CQL search query.
@@ -84,6 +101,55 @@ pub struct SearchResult {
     pub total_count: usize,
     pub query: CqlQuery,
     pub elapsed_ms: u64,
+    /// The background index warmup hadn't finished, so contexts not yet
+    /// indexed are missing from the results.
+    pub partial: bool,
+    /// Per returned context, the fields it matched on and its indexed value
+    /// for each; only filled in with `SearchOptions::explain_matches`.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub matched_on: HashMap<u64, serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Order of CQL search results.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SearchOrder {
+    /// Newest context (highest id) first.
+    #[default]
+    Recent,
+    /// Contexts satisfying more of the query's comparisons first.
+    Relevance,
+    /// Most recently active first, by last-activity time (unix ms) per
+    /// context id. Contexts without one follow.
+    Activity(HashMap<u64, u64>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub order: SearchOrder,
+    /// Report which fields each returned context matched on.
+    pub explain_matches: bool,
+}
+
+/// How far the secondary-index warmup has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexWarmupProgress {
+    pub indexed: usize,
+    pub total: usize,
+}
+
+impl IndexWarmupProgress {
+    pub fn is_complete(&self) -> bool {
+        self.indexed >= self.total
+    }
+}
+
+/// Contexts that existed at open and haven't been indexed yet.
+#[derive(Debug, Default)]
+struct IndexWarmup {
+    /// Oldest first, so `pop` warms the most recent contexts first.
+    pending: Vec<u64>,
+    pending_set: HashSet<u64>,
+    total: usize,
 }
 
 pub struct Store {
@@ -93,22 +159,270 @@ pub struct Store {
     /// Cache of context metadata, populated lazily from first turn.
     /// None value means we checked but found no metadata.
     pub context_metadata_cache: HashMap<u64, Option<ContextMetadata>>,
+    /// Caller-supplied metadata merged over the first-turn values.
+    metadata_overlays: MetadataOverlayLog,
+    /// Contexts whose cache entry holds only overlay metadata, so the first
+    /// append still needs to extract and merge its payload's metadata.
+    overlay_pending: HashSet<u64>,
     /// Secondary indexes for CQL queries.
     secondary_indexes: SecondaryIndexes,
+    /// Per-context stats for every context, reused by [`top_contexts`] until
+    /// `TOP_CONTEXTS_TTL` elapses since computing it walks every chain.
+    top_contexts_cache: Option<(Instant, TopContexts)>,
+    /// Parsed fs tree objects, shared by snapshot listings and lookups.
+    fs_tree_cache: TreeCache,
+    index_warmup: IndexWarmup,
+    /// Chain digest per context, keyed by the head it was computed for.
+    /// Appends to the head extend it in place; anything else (set_head, a
+    /// fork) leaves a stale entry that `head_digest` recomputes.
+    head_digests: HashMap<u64, (u64, [u8; 32])>,
+    /// Unix ms of each context's last read through the store since open. With
+    /// the head's last append time this decides when a context is idle
+    /// enough for `archive_idle_contexts`.
+    last_access: HashMap<u64, u64>,
+    /// Run in order on every append; see `open_with_interceptors`.
+    interceptors: Vec<Box<dyn AppendInterceptor>>,
+    options: StoreOptions,
+}
+
+/// Store behavior toggles and tuning.
+#[derive(Debug, Clone)]
+pub struct StoreOptions {
+    /// Re-encode msgpack turn payloads with sorted map keys before hashing
+    /// so logically identical payloads share one blob. The stored bytes and
+    /// content hash then differ from what the client sent.
+    pub canonicalize_msgpack: bool,
+    /// When non-zero, a context without an explicit title gets a derived
+    /// title: the first string field of its first turn, cut to this many
+    /// characters.
+    pub derived_title_chars: usize,
+    /// How many parsed fs tree objects to keep in memory; 0 disables the
+    /// cache.
+    pub fs_tree_cache_entries: usize,
+    /// Payloads shorter than this many bytes skip compression; see
+    /// `BlobStoreOptions::compress_min_len`.
+    pub blob_compress_min_len: usize,
+    /// Encrypt payload blobs at rest; see `BlobStoreOptions::encryption_key`.
+    pub blob_encryption_key: Option<BlobKey>,
+    /// Payloads shorter than this many bytes are kept in the turn's meta
+    /// record instead of the blob store, skipping the blob header and index
+    /// entry; 0 disables inlining. Only turn reads return inlined payloads:
+    /// they are not blobs, so fetching one by hash finds nothing. Ignored
+    /// when `blob_encryption_key` is set: turns.meta is not encrypted.
+    pub inline_payload_threshold: usize,
+    /// Open without reading every context's first turn; secondary indexes
+    /// are filled in afterwards by `warm_indexes` (see
+    /// `spawn_index_warmup`). Until then CQL results may be partial.
+    pub background_index_warmup: bool,
+    /// Contexts neither appended to nor read for this many seconds have their
+    /// turns evicted from memory (see `spawn_archiver`); 0 disables archival.
+    pub archive_idle_secs: u64,
+    /// Retention for contexts without a `retain:` label.
+    pub retention: RetentionPolicy,
+    /// Seconds between `spawn_retention_sweeper` passes; 0 disables the
+    /// sweeper.
+    pub retention_sweep_secs: u64,
+    /// Source of created-at times and of "now" for idle and retention
+    /// cutoffs.
+    pub clock: Arc<dyn Clock>,
+    /// Applied to client tags from payloads and overlays before they are
+    /// cached or indexed, and to tags in CQL lookups.
+    pub tag_normalization: TagNormalization,
+    /// Bounds on CQL search cost.
+    pub cql_limits: CqlLimits,
+}
+
+/// Which of a context's turns to keep. `Store::apply_retention` tombstones
+/// turns outside either limit, except the first turn (it carries the
+/// context's metadata) and the head, so depths and the head never change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keep only this many of the most recent turns.
+    pub max_turns: Option<u64>,
+    /// Keep only turns appended within this long.
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> Self {
+        let env_u64 = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+        };
+        Self {
+            max_turns: env_u64("CXDB_RETAIN_TURNS"),
+            max_age: env_u64("CXDB_RETAIN_SECS").map(Duration::from_secs),
+        }
+    }
+
+    /// The policy set by a context's labels: `retain:<turns>` and/or
+    /// `retain:<n>{s,m,h,d}` for an age. `None` if no label sets one.
+    pub fn from_labels(labels: &[String]) -> Option<Self> {
+        let mut policy = Self::default();
+        for label in labels {
+            let Some(value) = label.strip_prefix("retain:") else {
+                continue;
+            };
+            if let Ok(turns) = value.parse::<u64>() {
+                policy.max_turns = Some(turns);
+                continue;
+            }
+            let (digits, unit) = value.split_at(value.len().saturating_sub(1));
+            let secs_per_unit = match unit {
+                "s" => 1,
+                "m" => 60,
+                "h" => 3600,
+                "d" => 86_400,
+                _ => continue,
+            };
+            if let Ok(n) = digits.parse::<u64>() {
+                policy.max_age = Some(Duration::from_secs(n.saturating_mul(secs_per_unit)));
+            }
+        }
+        (!policy.is_unbounded()).then_some(policy)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.max_turns.is_none() && self.max_age.is_none()
+    }
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            canonicalize_msgpack: false,
+            derived_title_chars: 0,
+            fs_tree_cache_entries: DEFAULT_TREE_CACHE_ENTRIES,
+            blob_compress_min_len: 0,
+            blob_encryption_key: None,
+            inline_payload_threshold: 0,
+            background_index_warmup: false,
+            archive_idle_secs: 0,
+            retention: RetentionPolicy::default(),
+            retention_sweep_secs: 0,
+            clock: system_clock(),
+            tag_normalization: TagNormalization::default(),
+            cql_limits: CqlLimits::default(),
+        }
+    }
+}
+
+impl StoreOptions {
+    /// Options from `CXDB_*` variables. Fails only on a malformed
+    /// `CXDB_ENCRYPTION_KEY`.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            canonicalize_msgpack: std::env::var("CXDB_CANONICALIZE_MSGPACK")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            derived_title_chars: std::env::var("CXDB_DERIVED_TITLE_CHARS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            fs_tree_cache_entries: std::env::var("CXDB_FS_TREE_CACHE_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TREE_CACHE_ENTRIES),
+            blob_compress_min_len: std::env::var("CXDB_BLOB_COMPRESS_MIN_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            blob_encryption_key: BlobKey::from_env()?,
+            inline_payload_threshold: std::env::var("CXDB_INLINE_PAYLOAD_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            background_index_warmup: std::env::var("CXDB_BACKGROUND_INDEX_WARMUP")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            archive_idle_secs: std::env::var("CXDB_ARCHIVE_IDLE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            retention: RetentionPolicy::from_env(),
+            retention_sweep_secs: std::env::var("CXDB_RETENTION_SWEEP_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            clock: system_clock(),
+            tag_normalization: TagNormalization::from_env(),
+            cql_limits: CqlLimits::from_env(),
+        })
+    }
 }
 
+/// Turn payload encoding id for msgpack.
+const ENCODING_MSGPACK: u32 = 1;
+
+/// Contexts `spawn_index_warmup` indexes per store lock, so requests
+/// interleave with the warmup.
+const INDEX_WARMUP_BATCH: usize = 256;
+
+/// Pause between warmup batches. `Mutex` isn't fair, so without it the
+/// warmup thread can retake the lock before a waiting request gets it.
+const INDEX_WARMUP_PAUSE: Duration = Duration::from_millis(1);
+
+/// How long `top_contexts` serves a previously computed ranking.
+const TOP_CONTEXTS_TTL: Duration = Duration::from_secs(30);
+
+/// Longest pause between `spawn_archiver` sweeps.
+const ARCHIVE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
 impl Store {
     pub fn open(dir: &Path) -> Result<Self> {
+        Self::open_with_options(dir, StoreOptions::default())
+    }
+
+    pub fn open_with_options(dir: &Path, options: StoreOptions) -> Result<Self> {
+        Self::open_with_interceptors(dir, options, Vec::new())
+    }
+
+    /// Like `open_with_options`, running `interceptors` in order on every
+    /// append before the turn is stored.
+    pub fn open_with_interceptors(
+        dir: &Path,
+        options: StoreOptions,
+        interceptors: Vec<Box<dyn AppendInterceptor>>,
+    ) -> Result<Self> {
+        let mut options = options;
+        if options.blob_encryption_key.is_some() && options.inline_payload_threshold > 0 {
+            tracing::warn!(
+                "payload inlining is disabled while blob encryption is on; turns.meta is not encrypted"
+            );
+            options.inline_payload_threshold = 0;
+        }
+        data_mode::create_dir_all(dir)?;
         let mut store = Self {
-            blob_store: BlobStore::open(&dir.join("blobs"))?,
-            turn_store: TurnStore::open(&dir.join("turns"))?,
+            blob_store: BlobStore::open_with_options(
+                &dir.join("blobs"),
+                BlobStoreOptions {
+                    compress_min_len: options.blob_compress_min_len,
+                    encryption_key: options.blob_encryption_key.clone(),
+                },
+            )?,
+            turn_store: TurnStore::open_with_clock(&dir.join("turns"), Arc::clone(&options.clock))?,
             fs_roots: FsRootsIndex::open(&dir.join("fs"))?,
             context_metadata_cache: HashMap::new(),
-            secondary_indexes: SecondaryIndexes::new(),
+            metadata_overlays: MetadataOverlayLog::open(&dir.join("meta"))?,
+            overlay_pending: HashSet::new(),
+            secondary_indexes: SecondaryIndexes::with_tag_normalization(options.tag_normalization),
+            top_contexts_cache: None,
+            head_digests: HashMap::new(),
+            last_access: HashMap::new(),
+            interceptors,
+            fs_tree_cache: TreeCache::new(options.fs_tree_cache_entries),
+            index_warmup: IndexWarmup::default(),
+            options,
         };
 
-        // Pre-populate metadata cache and build secondary indexes
-        store.build_indexes();
+        if store.options.background_index_warmup {
+            store.queue_index_warmup();
+        } else {
+            // Pre-populate metadata cache and build secondary indexes
+            store.build_indexes();
+        }
 
         Ok(store)
     }
@@ -117,6 +431,7 @@ impl Store {
     fn build_indexes(&mut self) {
         // Get all context heads
         let heads = self.turn_store.list_recent_contexts(u32::MAX);
+        self.index_warmup.total = heads.len();
 
         // Pre-populate metadata cache for all contexts
         for head in &heads {
@@ -128,6 +443,63 @@ impl Store {
             .build_from_cache(&self.context_metadata_cache, &heads);
     }
 
+    /// Queue every existing context for `warm_indexes` instead of indexing
+    /// them now. Only reads the heads table.
+    fn queue_index_warmup(&mut self) {
+        let heads = self.turn_store.list_recent_contexts(u32::MAX);
+        let pending: Vec<u64> = heads.iter().rev().map(|h| h.context_id).collect();
+        self.index_warmup = IndexWarmup {
+            pending_set: pending.iter().copied().collect(),
+            total: pending.len(),
+            pending,
+        };
+    }
+
+    /// Index up to `batch` contexts still waiting on the background warmup.
+    /// Returns true once every context is indexed.
+    pub fn warm_indexes(&mut self, batch: usize) -> bool {
+        let mut warmed = Vec::new();
+        while warmed.len() < batch {
+            let Some(context_id) = self.index_warmup.pending.pop() else {
+                break;
+            };
+            if let Some(entry) = self.take_pending_context(context_id) {
+                warmed.push(entry);
+            }
+        }
+        if !warmed.is_empty() {
+            self.secondary_indexes.add_contexts(&warmed);
+        }
+        self.index_warmup.pending.is_empty()
+    }
+
+    /// Index one context ahead of the warmup, e.g. before appending to it,
+    /// so the append doesn't mistake it for a context's first turn.
+    fn warm_context(&mut self, context_id: u64) {
+        if let Some(entry) = self.take_pending_context(context_id) {
+            self.secondary_indexes.add_contexts(&[entry]);
+        }
+    }
+
+    fn take_pending_context(
+        &mut self,
+        context_id: u64,
+    ) -> Option<(Option<ContextMetadata>, ContextHead)> {
+        if !self.index_warmup.pending_set.remove(&context_id) {
+            return None;
+        }
+        let head = self.turn_store.get_head(context_id).ok()?;
+        let metadata = self.get_context_metadata(context_id);
+        Some((metadata, head))
+    }
+
+    pub fn index_warmup_progress(&self) -> IndexWarmupProgress {
+        IndexWarmupProgress {
+            indexed: self.index_warmup.total - self.index_warmup.pending_set.len(),
+            total: self.index_warmup.total,
+        }
+    }
+
     /// Get cached context metadata, loading from first turn if not cached.
     pub fn get_context_metadata(&mut self, context_id: u64) -> Option<ContextMetadata> {
         // Check cache first
@@ -137,17 +509,73 @@ impl Store {
 
         // Try to load from first turn (depth=0)
         let metadata = self.load_context_metadata(context_id);
+
+        // An empty context has no first turn yet. Caching a miss would stop
+        // its first append from extracting metadata, so only overlay-backed
+        // entries are cached, and they stay pending until that append.
+        let is_empty = self
+            .turn_store
+            .get_head(context_id)
+            .is_ok_and(|head| head.head_turn_id == 0);
+        if is_empty {
+            metadata.as_ref()?;
+            self.overlay_pending.insert(context_id);
+        }
+
         self.context_metadata_cache
             .insert(context_id, metadata.clone());
         metadata
     }
 
-    /// Load context metadata from the first turn of a context.
+    /// Load context metadata from the first turn of a context, with any
+    /// overlay applied on top.
     fn load_context_metadata(&mut self, context_id: u64) -> Option<ContextMetadata> {
+        let metadata = self.load_first_turn_metadata(context_id);
+        self.with_overlay(context_id, metadata)
+    }
+
+    fn load_first_turn_metadata(&mut self, context_id: u64) -> Option<ContextMetadata> {
+        self.turn_store.restore_context(context_id).ok()?;
         // Get the first turn (depth=0) for this context
         let first_turn = self.turn_store.get_first_turn(context_id).ok()?;
-        let payload = self.blob_store.get(&first_turn.payload_hash).ok()?;
-        extract_context_metadata(&payload)
+        let payload = self.turn_payload(&first_turn).ok()?;
+        self.first_turn_metadata(&payload)
+    }
+
+    /// Metadata from a first-turn payload, with a derived title when enabled
+    /// and the payload has no explicit one.
+    fn first_turn_metadata(&self, payload: &[u8]) -> Option<ContextMetadata> {
+        let metadata = extract_context_metadata(payload);
+        let max_chars = self.options.derived_title_chars;
+        if max_chars == 0 || metadata.as_ref().is_some_and(|m| m.title.is_some()) {
+            return metadata;
+        }
+        match derive_title(payload, max_chars) {
+            Some(title) => {
+                let mut metadata = metadata.unwrap_or_default();
+                metadata.derived_title = Some(title);
+                Some(metadata)
+            }
+            None => metadata,
+        }
+    }
+
+    /// `metadata` with any overlay applied on top and its client tag
+    /// normalized; every path that caches or indexes metadata goes through
+    /// here.
+    fn with_overlay(
+        &self,
+        context_id: u64,
+        metadata: Option<ContextMetadata>,
+    ) -> Option<ContextMetadata> {
+        let mut metadata = match self.metadata_overlays.get(context_id) {
+            Some(overlay) => overlay.apply(metadata),
+            None => metadata,
+        };
+        if let Some(tag) = metadata.as_mut().and_then(|m| m.client_tag.as_mut()) {
+            *tag = self.options.tag_normalization.apply(tag);
+        }
+        metadata
     }
 
     /// Update the metadata cache when the first turn for a context is appended.
@@ -162,11 +590,13 @@ impl Store {
         // Only extract once: on the first append to this context.
         // The cache starts empty, so the first append always triggers extraction.
         // For new contexts this is depth=0; for forked contexts this is depth=N+1.
-        if let std::collections::hash_map::Entry::Vacant(e) =
-            self.context_metadata_cache.entry(context_id)
-        {
-            let metadata = extract_context_metadata(payload);
-            e.insert(metadata.clone());
+        // Contexts created with an overlay already have a cache entry, so
+        // they are tracked in `overlay_pending` instead.
+        let pending = self.overlay_pending.remove(&context_id);
+        if pending || !self.context_metadata_cache.contains_key(&context_id) {
+            let metadata = self.with_overlay(context_id, self.first_turn_metadata(payload));
+            self.context_metadata_cache
+                .insert(context_id, metadata.clone());
             metadata
         } else {
             None
@@ -177,6 +607,35 @@ impl Store {
         self.turn_store.create_context(base_turn_id)
     }
 
+    /// Create a context and record `overlay` as its metadata before any turn
+    /// exists. The overlay is indexed immediately, so the context shows up in
+    /// listings and CQL searches by title, tag, or label right away.
+    pub fn create_context_with_metadata(
+        &mut self,
+        base_turn_id: u64,
+        overlay: MetadataOverlay,
+    ) -> Result<ContextHead> {
+        let head = self.turn_store.create_context(base_turn_id)?;
+        if overlay.is_empty() {
+            return Ok(head);
+        }
+        let context_id = head.context_id;
+        self.metadata_overlays.put(context_id, overlay)?;
+        let metadata = self.load_context_metadata(context_id);
+        self.context_metadata_cache
+            .insert(context_id, metadata.clone());
+        self.overlay_pending.insert(context_id);
+        self.secondary_indexes.add_context(
+            context_id,
+            metadata.as_ref(),
+            head.created_at_unix_ms,
+            head.head_depth,
+        );
+        self.secondary_indexes
+            .update_turn_count(context_id, head.turn_count);
+        Ok(head)
+    }
+
     pub fn fork_context(&mut self, base_turn_id: u64) -> Result<ContextHead> {
         self.turn_store.fork_context(base_turn_id)
     }
@@ -185,6 +644,37 @@ impl Store {
         self.turn_store.get_head(context_id)
     }
 
+    /// Fails with `HeadMismatch` unless the context head is
+    /// `expected_head_turn_id`. Conditional appends run this and the append
+    /// under one store lock so no other writer can slip in between.
+    pub fn check_head(&self, context_id: u64, expected_head_turn_id: u64) -> Result<ContextHead> {
+        let head = self.get_head(context_id)?;
+        if head.head_turn_id != expected_head_turn_id {
+            return Err(StoreError::HeadMismatch {
+                expected: expected_head_turn_id,
+                actual: head.head_turn_id,
+            });
+        }
+        Ok(head)
+    }
+
+    /// Tips of the context's side branches (not including the head).
+    pub fn branch_tips(&self, context_id: u64) -> Vec<u64> {
+        self.turn_store.branch_tips(context_id)
+    }
+
+    /// Promote a side-branch tip to be the context head; the old head is kept
+    /// as a side-branch tip.
+    pub fn set_head(&mut self, context_id: u64, tip_turn_id: u64) -> Result<ContextHead> {
+        self.turn_store.set_head(context_id, tip_turn_id)
+    }
+
+    /// The head and each side-branch tip with its fork point.
+    pub fn branches(&mut self, context_id: u64) -> Result<Vec<BranchTip>> {
+        self.resident(context_id)?;
+        self.turn_store.branches(context_id)
+    }
+
     /// Append a turn to a context.
     ///
     /// Returns the turn record and, if this is the first turn (depth=0), the extracted metadata.
@@ -225,7 +715,40 @@ impl Store {
             return Err(StoreError::InvalidInput("content hash mismatch".into()));
         }
 
-        self.blob_store.put_if_absent(content_hash, &raw_bytes)?;
+        let ctx = AppendContext {
+            context_id,
+            parent_turn_id,
+            declared_type_id: &declared_type_id,
+            declared_type_version,
+            encoding,
+        };
+        let (raw_bytes, content_hash, uncompressed_len) = match self.intercept(&ctx, raw_bytes)? {
+            (bytes, true) => {
+                let hash = *blake3::hash(&bytes).as_bytes();
+                let len = bytes.len() as u32;
+                (bytes, hash, len)
+            }
+            (bytes, false) => (bytes, content_hash, uncompressed_len),
+        };
+
+        // The client's hash covers the bytes it sent; with canonicalization
+        // the turn is stored under the hash of the canonical bytes instead.
+        let (raw_bytes, content_hash, uncompressed_len) =
+            if self.options.canonicalize_msgpack && encoding == ENCODING_MSGPACK {
+                let canonical = canonicalize_msgpack(&raw_bytes)?;
+                let hash = *blake3::hash(&canonical).as_bytes();
+                let len = canonical.len() as u32;
+                (canonical, hash, len)
+            } else {
+                (raw_bytes, content_hash, uncompressed_len)
+            };
+
+        let inline_payload = if raw_bytes.len() < self.options.inline_payload_threshold {
+            Some(raw_bytes.clone())
+        } else {
+            self.blob_store.put_if_absent(content_hash, &raw_bytes)?;
+            None
+        };
 
         let record = self.turn_store.append_turn(
             context_id,
@@ -236,8 +759,14 @@ impl Store {
             declared_type_version,
             compression,
             uncompressed_len,
+            inline_payload,
         )?;
 
+        self.warm_context(context_id);
+        self.extend_head_digest(context_id, &record);
+        self.last_access
+            .insert(context_id, record.created_at_unix_ms);
+
         // Cache metadata if this is the first turn, and return it for event publishing
         let metadata = self.maybe_cache_metadata(context_id, record.depth, &raw_bytes);
 
@@ -251,32 +780,94 @@ impl Store {
                 record.depth,
             );
         }
+        let turn_count = self.turn_store.get_head(context_id)?.turn_count;
+        self.secondary_indexes
+            .update_turn_count(context_id, turn_count);
 
         Ok((record, metadata))
     }
 
+    /// The turn an earlier append to `context_id` with idempotency key
+    /// `key` created, if the key is still live and the turn still visible.
+    /// A live key first used for a different `content_hash` is a
+    /// `StoreError::Conflict`.
+    pub fn idempotent_turn(
+        &mut self,
+        context_id: u64,
+        key: &[u8],
+        content_hash: &[u8; 32],
+    ) -> Result<Option<TurnRecord>> {
+        let Some(turn_id) = self
+            .turn_store
+            .idempotent_turn(context_id, key, content_hash)?
+        else {
+            return Ok(None);
+        };
+        self.turn_store.load_ancestry(turn_id)?;
+        Ok(self.turn_store.get_turn(turn_id).ok())
+    }
+
+    /// Record that the append to `context_id` keyed `key` created
+    /// `turn_id`; see `idempotent_turn`.
+    pub fn record_idempotency_key(
+        &mut self,
+        context_id: u64,
+        key: Vec<u8>,
+        content_hash: [u8; 32],
+        turn_id: u64,
+    ) -> Result<()> {
+        self.turn_store
+            .record_idempotency_key(context_id, key, content_hash, turn_id)
+    }
+
+    /// Runs the interceptors over `payload`, returning the bytes to store and
+    /// whether any interceptor replaced them.
+    fn intercept(&self, ctx: &AppendContext<'_>, payload: Vec<u8>) -> Result<(Vec<u8>, bool)> {
+        let mut payload = payload;
+        let mut replaced = false;
+        for interceptor in &self.interceptors {
+            if let Some(bytes) = interceptor.before_append(ctx, &payload)? {
+                payload = bytes;
+                replaced = true;
+            }
+        }
+        Ok((payload, replaced))
+    }
+
     pub fn get_last(
         &mut self,
         context_id: u64,
         limit: u32,
         include_payload: bool,
     ) -> Result<Vec<TurnWithMeta>> {
+        self.resident(context_id)?;
         let turns = self.turn_store.get_last(context_id, limit)?;
-        let mut out = Vec::with_capacity(turns.len());
-        for record in turns {
-            let meta = self.turn_store.get_turn_meta(record.turn_id)?;
-            let payload = if include_payload {
-                Some(self.blob_store.get(&record.payload_hash)?)
-            } else {
-                None
-            };
-            out.push(TurnWithMeta {
-                record,
-                meta,
-                payload,
-            });
-        }
-        Ok(out)
+        self.with_meta(turns, include_payload)
+    }
+
+    /// Runs `get_last` for each `(context_id, limit)` pair in order. Fails on
+    /// the first missing context, or once the groups hold more than
+    /// `max_turns` turns in total.
+    pub fn get_last_batch(
+        &mut self,
+        entries: &[(u64, u32)],
+        include_payload: bool,
+        max_turns: usize,
+    ) -> Result<Vec<(u64, Vec<TurnWithMeta>)>> {
+        let mut total = 0usize;
+        entries
+            .iter()
+            .map(|&(context_id, limit)| {
+                let turns = self.get_last(context_id, limit, include_payload)?;
+                total += turns.len();
+                if total > max_turns {
+                    return Err(StoreError::InvalidInput(format!(
+                        "batch returns more than {max_turns} turns; split it or lower the limits"
+                    )));
+                }
+                Ok((context_id, turns))
+            })
+            .collect()
     }
 
     pub fn get_before(
@@ -286,21 +877,71 @@ impl Store {
         limit: u32,
         include_payload: bool,
     ) -> Result<Vec<TurnWithMeta>> {
+        self.resident(context_id)?;
         let turns = self
             .turn_store
             .get_before(context_id, before_turn_id, limit)?;
+        self.with_meta(turns, include_payload)
+    }
+
+    /// Up to `limit` turns ending at `depth`, oldest first. A depth past the
+    /// head is clamped to the head; see `TurnStore::get_at_depth`.
+    pub fn get_at_depth(
+        &mut self,
+        context_id: u64,
+        depth: u32,
+        limit: u32,
+        include_payload: bool,
+    ) -> Result<Vec<TurnWithMeta>> {
+        self.resident(context_id)?;
+        let turns = self.turn_store.get_at_depth(context_id, depth, limit)?;
+        self.with_meta(turns, include_payload)
+    }
+
+    /// The context `turn_id` was appended in; see `TurnStore::turn_context`.
+    pub fn turn_context(&self, turn_id: u64) -> Option<u64> {
+        self.turn_store.turn_context(turn_id)
+    }
+
+    /// The registry bundle the context was pinned to at create time, if any.
+    pub fn pinned_bundle_id(&self, context_id: u64) -> Option<String> {
+        self.metadata_overlays
+            .get(context_id)
+            .and_then(|overlay| overlay.bundle_id.clone())
+    }
+
+    /// A single turn by id, with its payload bytes as stored.
+    pub fn get_turn(&mut self, turn_id: u64) -> Result<TurnWithMeta> {
+        self.turn_store.load_ancestry(turn_id)?;
+        let record = self.turn_store.get_turn(turn_id)?;
+        let mut turns = self.with_meta(vec![record], true)?;
+        Ok(turns.remove(0))
+    }
+
+    fn with_meta(
+        &mut self,
+        turns: Vec<TurnRecord>,
+        include_payload: bool,
+    ) -> Result<Vec<TurnWithMeta>> {
         let mut out = Vec::with_capacity(turns.len());
         for record in turns {
-            let meta = self.turn_store.get_turn_meta(record.turn_id)?;
-            let payload = if include_payload {
-                Some(self.blob_store.get(&record.payload_hash)?)
-            } else {
-                None
+            let mut meta = self.turn_store.get_turn_meta(record.turn_id)?;
+            let inline_payload = meta.inline_payload.take();
+            let payload = match inline_payload {
+                _ if !include_payload => None,
+                Some(bytes) => Some(bytes),
+                None => Some(self.blob_store.get(&record.payload_hash)?),
             };
+            let stored_codec = self
+                .blob_store
+                .codec(&record.payload_hash)
+                .filter(|_| record.flags & TURN_FLAG_INLINE_PAYLOAD == 0)
+                .unwrap_or(BlobCodec::None);
             out.push(TurnWithMeta {
                 record,
                 meta,
                 payload,
+                stored_codec,
             });
         }
         Ok(out)
@@ -310,6 +951,33 @@ impl Store {
         self.blob_store.get(hash)
     }
 
+    /// A turn's payload, from its meta record if it was inlined or else
+    /// from the blob store.
+    fn turn_payload(&mut self, record: &TurnRecord) -> Result<Vec<u8>> {
+        if record.flags & TURN_FLAG_INLINE_PAYLOAD != 0 {
+            if let Some(payload) = self
+                .turn_store
+                .get_turn_meta(record.turn_id)?
+                .inline_payload
+            {
+                return Ok(payload);
+            }
+        }
+        self.blob_store.get(&record.payload_hash)
+    }
+
+    /// A turn's payload length without reading the payload.
+    fn payload_len(&self, record: &TurnRecord) -> u64 {
+        if record.flags & TURN_FLAG_INLINE_PAYLOAD != 0 {
+            return self
+                .turn_store
+                .get_turn_meta(record.turn_id)
+                .map(|meta| meta.uncompressed_len as u64)
+                .unwrap_or(0);
+        }
+        self.blob_store.raw_len(&record.payload_hash).unwrap_or(0) as u64
+    }
+
     pub fn list_recent_contexts(&self, limit: u32) -> Vec<ContextHead> {
         self.turn_store.list_recent_contexts(limit)
     }
@@ -363,41 +1031,31 @@ impl Store {
     // CQL Search Methods
     // =========================================================================
 
-    /// Search contexts using a CQL query string.
+    /// Search contexts using a CQL query string, newest first.
     pub fn search_contexts(
         &self,
         query: &str,
         live_contexts: &HashSet<u64>,
         limit: Option<u32>,
     ) -> std::result::Result<SearchResult, CqlError> {
-        let start = std::time::Instant::now();
+        self.search_contexts_with_options(query, live_contexts, limit, &SearchOptions::default())
+    }
 
-        // Parse the query
+    /// Search contexts using a CQL query string, sorted by `options.order`.
+    /// Ties go to the newer context.
+    pub fn search_contexts_with_options(
+        &self,
+        query: &str,
+        live_contexts: &HashSet<u64>,
+        limit: Option<u32>,
+        options: &SearchOptions,
+    ) -> std::result::Result<SearchResult, CqlError> {
+        let start = std::time::Instant::now();
         let parsed = cql::parse(query)?;
-
-        // Execute the query
-        let matching_ids = cql::execute(&parsed.ast, &self.secondary_indexes, live_contexts)?;
-
-        // Sort by context_id descending (most recent first) and apply limit
-        let mut sorted_ids: Vec<u64> = matching_ids.into_iter().collect();
-        sorted_ids.sort_by(|a, b| b.cmp(a));
-
-        let total_count = sorted_ids.len();
-        if let Some(limit) = limit {
-            sorted_ids.truncate(limit as usize);
-        }
-
-        let elapsed = start.elapsed();
-
-        Ok(SearchResult {
-            context_ids: sorted_ids,
-            total_count,
-            query: parsed,
-            elapsed_ms: elapsed.as_millis() as u64,
-        })
+        self.run_search(parsed, live_contexts, limit, options, start)
     }
 
-    /// Search contexts using a pre-parsed CQL query.
+    /// Search contexts using a pre-parsed CQL query, newest first.
     pub fn search_contexts_parsed(
         &self,
         query: &CqlQuery,
@@ -405,29 +1063,134 @@ impl Store {
         limit: Option<u32>,
     ) -> std::result::Result<SearchResult, CqlError> {
         let start = std::time::Instant::now();
+        self.run_search(
+            query.clone(),
+            live_contexts,
+            limit,
+            &SearchOptions::default(),
+            start,
+        )
+    }
 
-        // Execute the query
-        let matching_ids = cql::execute(&query.ast, &self.secondary_indexes, live_contexts)?;
+    fn run_search(
+        &self,
+        query: CqlQuery,
+        live_contexts: &HashSet<u64>,
+        limit: Option<u32>,
+        options: &SearchOptions,
+        start: std::time::Instant,
+    ) -> std::result::Result<SearchResult, CqlError> {
+        let matching_ids = cql::execute_with_limits(
+            &query.ast,
+            &self.secondary_indexes,
+            live_contexts,
+            &self.options.cql_limits,
+        )?;
 
-        // Sort by context_id descending (most recent first) and apply limit
-        let mut sorted_ids: Vec<u64> = matching_ids.into_iter().collect();
+        let mut sorted_ids: Vec<u64> = matching_ids.iter().copied().collect();
         sorted_ids.sort_by(|a, b| b.cmp(a));
+        match &options.order {
+            SearchOrder::Recent => {}
+            SearchOrder::Relevance => {
+                let counts = cql::match_counts(
+                    &query.ast,
+                    &self.secondary_indexes,
+                    live_contexts,
+                    &matching_ids,
+                )?;
+                // Stable, so equal counts keep the newest-first order.
+                sorted_ids.sort_by_key(|id| std::cmp::Reverse(counts.get(id).copied()));
+            }
+            SearchOrder::Activity(last_activity) => {
+                sorted_ids.sort_by_key(|id| std::cmp::Reverse(last_activity.get(id).copied()));
+            }
+        }
 
         let total_count = sorted_ids.len();
         if let Some(limit) = limit {
             sorted_ids.truncate(limit as usize);
         }
 
-        let elapsed = start.elapsed();
+        let mut matched_on = HashMap::new();
+        if options.explain_matches {
+            let returned: HashSet<u64> = sorted_ids.iter().copied().collect();
+            let fields = cql::matched_fields(
+                &query.ast,
+                &self.secondary_indexes,
+                live_contexts,
+                &returned,
+            )?;
+            for (context_id, fields) in fields {
+                let values = fields
+                    .into_iter()
+                    .filter_map(|field| {
+                        let value = self.indexed_value(context_id, field, live_contexts)?;
+                        Some((field.as_str().to_string(), value))
+                    })
+                    .collect();
+                matched_on.insert(context_id, values);
+            }
+        }
 
         Ok(SearchResult {
             context_ids: sorted_ids,
             total_count,
-            query: query.clone(),
-            elapsed_ms: elapsed.as_millis() as u64,
+            query,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            partial: !self.index_warmup_progress().is_complete(),
+            matched_on,
         })
     }
 
+    /// A context's value for a CQL field, as the secondary indexes see it.
+    /// Ids are strings, as elsewhere in JSON.
+    fn indexed_value(
+        &self,
+        context_id: u64,
+        field: FieldName,
+        live_contexts: &HashSet<u64>,
+    ) -> Option<serde_json::Value> {
+        use serde_json::Value as Json;
+        let metadata = self
+            .context_metadata_cache
+            .get(&context_id)
+            .and_then(|m| m.as_ref());
+        let provenance = metadata.and_then(|m| m.provenance.as_ref());
+        let string = |s: Option<&String>| s.map(|s| Json::String(s.clone()));
+        let id = |id: Option<u64>| id.map(|id| Json::String(id.to_string()));
+        match field {
+            FieldName::Id => id(Some(context_id)),
+            FieldName::Tag => string(metadata?.client_tag.as_ref()),
+            FieldName::Title => {
+                let metadata = metadata?;
+                string(metadata.title.as_ref().or(metadata.derived_title.as_ref()))
+            }
+            FieldName::Label => metadata?
+                .labels
+                .as_ref()
+                .map(|labels| Json::from(labels.clone())),
+            FieldName::User => string(provenance?.on_behalf_of.as_ref()),
+            FieldName::Service => string(provenance?.service_name.as_ref()),
+            FieldName::Host => string(provenance?.host_name.as_ref()),
+            FieldName::TraceId => string(provenance?.trace_id.as_ref()),
+            FieldName::Parent => id(provenance?.parent_context_id),
+            FieldName::Root => id(provenance?.root_context_id),
+            FieldName::Created => {
+                let head = self.turn_store.get_head(context_id).ok()?;
+                Some(Json::from(head.created_at_unix_ms))
+            }
+            FieldName::Depth => {
+                let head = self.turn_store.get_head(context_id).ok()?;
+                Some(Json::from(head.head_depth))
+            }
+            FieldName::TurnCount => {
+                let head = self.turn_store.get_head(context_id).ok()?;
+                Some(Json::from(head.turn_count))
+            }
+            FieldName::IsLive => Some(Json::Bool(live_contexts.contains(&context_id))),
+        }
+    }
+
     /// Get secondary index statistics.
     pub fn index_stats(&self) -> IndexStats {
         self.secondary_indexes.stats()
@@ -441,11 +1204,15 @@ impl Store {
     /// The tree objects and file blobs must already exist in the blob store.
     pub fn attach_fs(&mut self, turn_id: u64, fs_root_hash: [u8; 32]) -> Result<()> {
         // Verify the turn exists
+        self.turn_store.load_ancestry(turn_id)?;
         let _ = self.turn_store.get_turn(turn_id)?;
 
         // Verify the root tree exists in blob store
         if !self.blob_store.contains(&fs_root_hash) {
-            return Err(StoreError::NotFound("fs root tree blob".into()));
+            return Err(StoreError::not_found(
+                NotFoundKind::FsSnapshot,
+                "fs root tree blob",
+            ));
         }
 
         self.fs_roots.attach(turn_id, fs_root_hash)
@@ -466,10 +1233,16 @@ impl Store {
         let fs_root = self
             .fs_roots
             .get_inherited(turn_id, &self.turn_store)
-            .ok_or_else(|| StoreError::NotFound("no fs snapshot for turn".into()))?;
-
-        let (tree_hash, is_dir) =
-            crate::fs_store::resolve_path(&mut self.blob_store, &fs_root, path)?;
+            .ok_or_else(|| {
+                StoreError::not_found(NotFoundKind::FsSnapshot, "no fs snapshot for turn")
+            })?;
+
+        let (tree_hash, is_dir) = crate::fs_store::resolve_path(
+            &mut self.blob_store,
+            &mut self.fs_tree_cache,
+            &fs_root,
+            path,
+        )?;
 
         if !is_dir {
             return Err(StoreError::InvalidInput(format!(
@@ -477,7 +1250,8 @@ impl Store {
             )));
         }
 
-        crate::fs_store::load_tree_entries(&mut self.blob_store, &tree_hash)
+        let entries = self.fs_tree_cache.load(&mut self.blob_store, &tree_hash)?;
+        Ok(entries.as_ref().clone())
     }
 
     /// Get file content at a path in the filesystem snapshot for a turn.
@@ -485,9 +1259,55 @@ impl Store {
         let fs_root = self
             .fs_roots
             .get_inherited(turn_id, &self.turn_store)
-            .ok_or_else(|| StoreError::NotFound("no fs snapshot for turn".into()))?;
+            .ok_or_else(|| {
+                StoreError::not_found(NotFoundKind::FsSnapshot, "no fs snapshot for turn")
+            })?;
+
+        crate::fs_store::get_file_at_path(
+            &mut self.blob_store,
+            &mut self.fs_tree_cache,
+            &fs_root,
+            path,
+        )
+    }
 
-        crate::fs_store::get_file_at_path(&mut self.blob_store, &fs_root, path)
+    /// Aggregate file/dir/symlink counts, bytes, and depth under a path.
+    pub fn fs_stat(&mut self, turn_id: u64, path: &str) -> Result<crate::fs_store::FsTreeStats> {
+        let fs_root = self
+            .fs_roots
+            .get_inherited(turn_id, &self.turn_store)
+            .ok_or_else(|| {
+                StoreError::not_found(NotFoundKind::FsSnapshot, "no fs snapshot for turn")
+            })?;
+
+        crate::fs_store::stat_path(
+            &mut self.blob_store,
+            &mut self.fs_tree_cache,
+            &fs_root,
+            path,
+        )
+    }
+
+    /// Get file content at a path, following a symlink at the final
+    /// component. Also returns the path the symlinks resolved to.
+    pub fn get_fs_file_following(
+        &mut self,
+        turn_id: u64,
+        path: &str,
+    ) -> Result<(Vec<u8>, TreeEntry, String)> {
+        let fs_root = self
+            .fs_roots
+            .get_inherited(turn_id, &self.turn_store)
+            .ok_or_else(|| {
+                StoreError::not_found(NotFoundKind::FsSnapshot, "no fs snapshot for turn")
+            })?;
+
+        crate::fs_store::get_file_following_symlinks(
+            &mut self.blob_store,
+            &mut self.fs_tree_cache,
+            &fs_root,
+            path,
+        )
     }
 
     pub fn stats(&mut self) -> StoreStats {
@@ -500,6 +1320,8 @@ impl Store {
             contexts_total: turn_stats.contexts_total,
             heads_total: turn_stats.heads_total,
             blobs_total: blob_stats.blobs_total,
+            blobs_compressed_total: blob_stats.blobs_compressed_total,
+            blobs_compression_ratio: blob_stats.compression_ratio,
             turns_log_bytes: turn_stats.turns_log_bytes,
             turns_index_bytes: turn_stats.turns_index_bytes,
             turns_meta_bytes: turn_stats.turns_meta_bytes,
@@ -509,7 +1331,372 @@ impl Store {
             fs_roots_total: fs_stats.entries_total,
             fs_roots_bytes: fs_stats.file_bytes,
             fs_content_bytes,
+            fs_tree_cache_hits: self.fs_tree_cache.hits(),
+            fs_tree_cache_misses: self.fs_tree_cache.misses(),
+        }
+    }
+
+    /// Storage consumed by one context, computed by walking its chain from head.
+    ///
+    /// Payload bytes count every turn in the chain, including turns inherited
+    /// from a fork base, so shared turns are attributed to each context that
+    /// sees them. Filesystem bytes are deduplicated within the context.
+    pub fn context_stats(&mut self, context_id: u64) -> Result<ContextStats> {
+        // Not a read of the context: reloads it without resetting its idle
+        // clock, so the next archive sweep evicts it again.
+        self.turn_store.restore_context(context_id)?;
+        let head = self.turn_store.get_head(context_id)?;
+        let turns = self.turn_store.get_last(context_id, u32::MAX)?;
+
+        let mut payload_bytes = 0u64;
+        let mut visited: HashSet<[u8; 32]> = HashSet::new();
+        let mut fs_snapshot_bytes = 0u64;
+        for turn in &turns {
+            payload_bytes += self.payload_len(turn);
+            if let Some(root) = self.fs_roots.get(turn.turn_id) {
+                fs_snapshot_bytes += self.compute_tree_size(&root, &mut visited);
+            }
         }
+
+        Ok(ContextStats {
+            context_id,
+            // Includes side-branch appends and the fork base, unlike `turns`.
+            turn_count: head.turn_count,
+            head_depth: head.head_depth,
+            payload_bytes,
+            fs_snapshot_bytes,
+        })
+    }
+
+    /// The context head plus a digest of its whole chain, cheap enough for
+    /// clients to poll when validating a cached copy of the turns.
+    ///
+    /// The digest folds the payload hashes from the root:
+    /// `d = blake3(d_parent || payload_hash)`, starting from 32 zero bytes,
+    /// so an empty context digests to zeros and forks share their base's
+    /// digests. It is cached per context and extended on append.
+    pub fn head_digest(&mut self, context_id: u64) -> Result<HeadDigest> {
+        let head = self.turn_store.get_head(context_id)?;
+        let digest = match self.head_digests.get(&context_id) {
+            Some((turn_id, digest)) if *turn_id == head.head_turn_id => *digest,
+            _ => {
+                self.resident(context_id)?;
+                let turns = self.turn_store.get_last(context_id, u32::MAX)?;
+                let digest = turns
+                    .iter()
+                    .fold([0u8; 32], |d, turn| chain_digest(&d, &turn.payload_hash));
+                self.head_digests
+                    .insert(context_id, (head.head_turn_id, digest));
+                digest
+            }
+        };
+        Ok(HeadDigest {
+            context_id,
+            head_turn_id: head.head_turn_id,
+            head_depth: head.head_depth,
+            digest,
+        })
+    }
+
+    /// Reloads the context if it was archived and restarts its idle clock.
+    fn resident(&mut self, context_id: u64) -> Result<()> {
+        if self.turn_store.get_head(context_id).is_ok() {
+            self.turn_store.restore_context(context_id)?;
+            self.last_access
+                .insert(context_id, self.options.clock.now_unix_ms());
+        }
+        Ok(())
+    }
+
+    /// Evicts one context's turns from memory; see
+    /// `TurnStore::archive_contexts`. Returns the number of turns evicted.
+    pub fn archive_context(&mut self, context_id: u64) -> Result<usize> {
+        self.turn_store.archive_contexts(&[context_id])
+    }
+
+    /// Archives every context neither appended to nor read within `idle`.
+    /// Returns the number of contexts archived.
+    pub fn archive_idle_contexts(&mut self, idle: Duration) -> Result<usize> {
+        let cutoff = self
+            .options
+            .clock
+            .now_unix_ms()
+            .saturating_sub(idle.as_millis() as u64);
+        let idle_ids: Vec<u64> = self
+            .turn_store
+            .list_recent_contexts(u32::MAX)
+            .into_iter()
+            .filter(|head| !self.turn_store.is_archived(head.context_id))
+            .filter(|head| {
+                let accessed = self.last_access.get(&head.context_id).copied();
+                accessed.unwrap_or(0).max(head.created_at_unix_ms) < cutoff
+            })
+            .map(|head| head.context_id)
+            .collect();
+        if !idle_ids.is_empty() {
+            self.turn_store.archive_contexts(&idle_ids)?;
+            for context_id in &idle_ids {
+                self.last_access.remove(context_id);
+            }
+        }
+        Ok(idle_ids.len())
+    }
+
+    /// The retention policy for a context: its `retain:` labels, else the
+    /// global default.
+    pub fn retention_policy(&mut self, context_id: u64) -> RetentionPolicy {
+        self.get_context_metadata(context_id)
+            .and_then(|metadata| metadata.labels)
+            .and_then(|labels| RetentionPolicy::from_labels(&labels))
+            .unwrap_or(self.options.retention)
+    }
+
+    /// Tombstones the turns every resident context's retention policy no
+    /// longer keeps; see `apply_retention_to`. A context that fails is logged
+    /// and skipped. Returns the number of turns tombstoned.
+    pub fn apply_retention(&mut self) -> Result<usize> {
+        let mut tombstoned = 0;
+        for context_id in self.retention_candidates() {
+            match self.apply_retention_to(context_id) {
+                Ok(count) => tombstoned += count,
+                Err(e) => tracing::warn!(context_id, "retention sweep failed: {e}"),
+            }
+        }
+        Ok(tombstoned)
+    }
+
+    /// Whether a retention sweep could tombstone anything: the default
+    /// policy is bounded or some context has a `retain:` label. Answers
+    /// `true` while a background index warmup is still running, since labels
+    /// aren't all indexed yet.
+    pub fn has_retention_policies(&self) -> bool {
+        !self.options.retention.is_unbounded()
+            || !self.index_warmup_progress().is_complete()
+            || self.secondary_indexes.has_label_prefix("retain:")
+    }
+
+    /// Contexts a retention sweep looks at: every context not archived.
+    /// Archived contexts are left alone rather than reloaded; they are swept
+    /// once something restores them.
+    pub fn retention_candidates(&self) -> Vec<u64> {
+        self.turn_store
+            .list_recent_contexts(u32::MAX)
+            .into_iter()
+            .map(|head| head.context_id)
+            .filter(|context_id| !self.turn_store.is_archived(*context_id))
+            .collect()
+    }
+
+    /// Tombstones the turns one context's retention policy no longer keeps.
+    /// Only the context's own head-chain turns are considered, so side
+    /// branches and turns inherited from a fork base follow their owner's
+    /// policy. Does nothing to an archived context. Returns the number of
+    /// turns tombstoned.
+    pub fn apply_retention_to(&mut self, context_id: u64) -> Result<usize> {
+        if self.turn_store.is_archived(context_id) {
+            return Ok(0);
+        }
+        let policy = self.retention_policy(context_id);
+        if policy.is_unbounded() {
+            return Ok(0);
+        }
+        let now_ms = self.options.clock.now_unix_ms();
+        let turns = self.turn_store.get_last(context_id, u32::MAX)?;
+        let keep_from = policy
+            .max_turns
+            .map_or(0, |n| turns.len().saturating_sub(n as usize));
+        let expired_before = policy
+            .max_age
+            .map_or(0, |age| now_ms.saturating_sub(age.as_millis() as u64));
+        let doomed: Vec<u64> = turns
+            .iter()
+            .enumerate()
+            .take(turns.len().saturating_sub(1))
+            .filter(|(i, turn)| *i < keep_from || turn.created_at_unix_ms < expired_before)
+            .map(|(_, turn)| turn)
+            .filter(|turn| turn.depth > 0)
+            .filter(|turn| self.turn_store.turn_context(turn.turn_id) == Some(context_id))
+            .map(|turn| turn.turn_id)
+            .collect();
+        let count = self.turn_store.tombstone_turns(&doomed)?;
+        if count > 0 {
+            self.head_digests.remove(&context_id);
+        }
+        Ok(count)
+    }
+
+    fn extend_head_digest(&mut self, context_id: u64, record: &TurnRecord) {
+        if let Some(entry) = self.head_digests.get_mut(&context_id) {
+            if entry.0 == record.parent_turn_id {
+                *entry = (record.turn_id, chain_digest(&entry.1, &record.payload_hash));
+            }
+        }
+    }
+
+    /// Check the turn graph and that every live turn's payload and every blob
+    /// under an fs snapshot is present and hashes to its address. Reads every
+    /// referenced blob, so it takes as long as the pack is large.
+    pub fn verify(&mut self) -> Result<VerifyReport> {
+        self.turn_store.load_all()?;
+        let mut problems = self.turn_store.verify();
+        let turns_checked = self.turn_store.iter_turns().count();
+
+        for turn in self.turn_store.iter_turns() {
+            if turn.flags & TURN_FLAG_INLINE_PAYLOAD == 0
+                || self.turn_store.is_tombstoned(turn.turn_id)
+            {
+                continue;
+            }
+            let matches = self
+                .turn_store
+                .get_turn_meta(turn.turn_id)
+                .ok()
+                .and_then(|meta| meta.inline_payload)
+                .is_some_and(|payload| blake3::hash(&payload).as_bytes() == &turn.payload_hash);
+            if !matches {
+                problems.push(format!(
+                    "turn {}: inline payload {}: missing or does not match its hash",
+                    turn.turn_id,
+                    hex::encode(turn.payload_hash)
+                ));
+            }
+        }
+
+        let mut checked = HashSet::new();
+        for (turn_id, hash) in self.live_payloads() {
+            if checked.insert(hash) {
+                if let Err(problem) = self.check_blob(&hash) {
+                    problems.push(format!(
+                        "turn {turn_id}: payload {}: {problem}",
+                        hex::encode(hash)
+                    ));
+                }
+            }
+        }
+        let mut fs_blobs: Vec<[u8; 32]> = self
+            .mark_fs_blobs(&mut problems)
+            .into_iter()
+            .filter(|hash| !checked.contains(hash))
+            .collect();
+        fs_blobs.sort_unstable();
+        for hash in fs_blobs {
+            checked.insert(hash);
+            if let Err(problem) = self.check_blob(&hash) {
+                problems.push(format!("fs blob {}: {problem}", hex::encode(hash)));
+            }
+        }
+
+        let blobs_unreferenced = self
+            .blob_store
+            .hashes()
+            .iter()
+            .filter(|hash| !checked.contains(*hash))
+            .count();
+        Ok(VerifyReport {
+            turns_checked,
+            blobs_checked: checked.len(),
+            blobs_unreferenced,
+            problems,
+        })
+    }
+
+    /// Drop blobs that no live turn or fs snapshot references, such as the
+    /// payloads of tombstoned turns, from the blob index. `compact` then
+    /// reclaims their space. Refuses to run if an fs tree can't be read,
+    /// since the blobs under it couldn't be marked.
+    pub fn gc(&mut self) -> Result<GcReport> {
+        self.turn_store.load_all()?;
+        let mut keep: HashSet<[u8; 32]> = self
+            .live_payloads()
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+        let mut problems = Vec::new();
+        keep.extend(self.mark_fs_blobs(&mut problems));
+        if let Some(problem) = problems.first() {
+            return Err(StoreError::Corrupt(format!("gc stopped: {problem}")));
+        }
+        let blobs_removed = self.blob_store.retain(&keep)?;
+        Ok(GcReport {
+            blobs_kept: self.blob_store.stats().blobs_total,
+            blobs_removed,
+        })
+    }
+
+    /// Rewrite the blob pack with only the blobs still indexed.
+    pub fn compact(&mut self) -> Result<CompactReport> {
+        let (pack_bytes_before, pack_bytes_after) = self.blob_store.compact()?;
+        Ok(CompactReport {
+            pack_bytes_before,
+            pack_bytes_after,
+        })
+    }
+
+    /// Rebuild turns.idx and blobs.idx from their logs. Blobs that `gc`
+    /// dropped but `compact` hasn't yet removed are indexed again.
+    pub fn reindex(&mut self) -> Result<ReindexReport> {
+        let turns_indexed = self.turn_store.reindex()?;
+        let (blobs_indexed, pack_bytes_skipped) = self.blob_store.reindex()?;
+        Ok(ReindexReport {
+            turns_indexed,
+            blobs_indexed,
+            pack_bytes_skipped,
+        })
+    }
+
+    /// `(turn_id, payload_hash)` for every resident turn not tombstoned
+    /// whose payload is in the blob store, by turn id.
+    fn live_payloads(&self) -> Vec<(u64, [u8; 32])> {
+        let mut payloads: Vec<(u64, [u8; 32])> = self
+            .turn_store
+            .iter_turns()
+            .filter(|turn| !self.turn_store.is_tombstoned(turn.turn_id))
+            .filter(|turn| turn.flags & TURN_FLAG_INLINE_PAYLOAD == 0)
+            .map(|turn| (turn.turn_id, turn.payload_hash))
+            .collect();
+        payloads.sort_unstable();
+        payloads
+    }
+
+    fn check_blob(&mut self, hash: &[u8; 32]) -> std::result::Result<(), String> {
+        let bytes = self.blob_store.get(hash).map_err(|e| e.to_string())?;
+        if blake3::hash(&bytes).as_bytes() != hash {
+            return Err("content does not match its hash".to_string());
+        }
+        Ok(())
+    }
+
+    /// Every blob reachable from an fs snapshot root, trees included. Trees
+    /// that are present but can't be parsed are added to `problems`; missing
+    /// ones are left for the caller to notice.
+    fn mark_fs_blobs(&mut self, problems: &mut Vec<String>) -> HashSet<[u8; 32]> {
+        let mut marked = HashSet::new();
+        let mut pending = self.fs_roots.unique_roots();
+        pending.sort_unstable();
+        while let Some(tree_hash) = pending.pop() {
+            if !marked.insert(tree_hash) || !self.blob_store.contains(&tree_hash) {
+                continue;
+            }
+            let entries = match crate::fs_store::load_tree_entries(&mut self.blob_store, &tree_hash)
+            {
+                Ok(entries) => entries,
+                Err(e) => {
+                    problems.push(format!("fs tree {}: {e}", hex::encode(tree_hash)));
+                    continue;
+                }
+            };
+            for entry in entries {
+                let Ok(hash) = entry.hash_array() else {
+                    continue;
+                };
+                if entry.kind == 1 {
+                    pending.push(hash);
+                } else {
+                    marked.insert(hash);
+                }
+            }
+        }
+        marked
     }
 
     /// Compute the total size of all blobs referenced by filesystem snapshots.
@@ -578,6 +1765,8 @@ pub struct StoreStats {
     pub contexts_total: usize,
     pub heads_total: usize,
     pub blobs_total: usize,
+    pub blobs_compressed_total: usize,
+    pub blobs_compression_ratio: f64,
     pub turns_log_bytes: u64,
     pub turns_index_bytes: u64,
     pub turns_meta_bytes: u64,
@@ -587,6 +1776,194 @@ pub struct StoreStats {
     pub fs_roots_total: usize,
     pub fs_roots_bytes: u64,
     pub fs_content_bytes: u64,
+    pub fs_tree_cache_hits: u64,
+    pub fs_tree_cache_misses: u64,
+}
+
+/// Outcome of `Store::verify`; the store is sound when `problems` is empty.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub turns_checked: usize,
+    pub blobs_checked: usize,
+    /// Indexed blobs nothing live refers to; `gc` would drop them.
+    pub blobs_unreferenced: usize,
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GcReport {
+    pub blobs_kept: usize,
+    pub blobs_removed: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactReport {
+    pub pack_bytes_before: u64,
+    pub pack_bytes_after: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReindexReport {
+    pub turns_indexed: usize,
+    pub blobs_indexed: usize,
+    /// Unreadable pack bytes passed over while scanning.
+    pub pack_bytes_skipped: u64,
+}
+
+/// Run `Store::warm_indexes` to completion on a background thread, taking
+/// the store lock one batch at a time and pausing between batches.
+pub fn spawn_index_warmup(store: Arc<Mutex<Store>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let start = Instant::now();
+        while !lock_or_recover(&store, "store").warm_indexes(INDEX_WARMUP_BATCH) {
+            thread::sleep(INDEX_WARMUP_PAUSE);
+        }
+        let progress = lock_or_recover(&store, "store").index_warmup_progress();
+        tracing::info!(
+            contexts = progress.total,
+            elapsed_ms = start.elapsed().as_millis(),
+            "Warmed secondary indexes"
+        );
+    })
+}
+
+/// Run `Store::archive_idle_contexts` periodically for the life of the
+/// process.
+pub fn spawn_archiver(store: Arc<Mutex<Store>>, idle: Duration) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(idle.min(ARCHIVE_SWEEP_INTERVAL));
+        match lock_or_recover(&store, "store").archive_idle_contexts(idle) {
+            Ok(0) => {}
+            Ok(archived) => tracing::info!(contexts = archived, "Archived idle contexts"),
+            Err(e) => tracing::warn!("archive sweep failed: {e}"),
+        }
+    })
+}
+
+/// Sweep retention every `interval` for the life of the process. The store
+/// lock is taken once per context, so requests interleave with a sweep.
+pub fn spawn_retention_sweeper(store: Arc<Mutex<Store>>, interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let contexts = lock_or_recover(&store, "store").retention_candidates();
+        let mut turns = 0;
+        for context_id in contexts {
+            match lock_or_recover(&store, "store").apply_retention_to(context_id) {
+                Ok(count) => turns += count,
+                Err(e) => tracing::warn!(context_id, "retention sweep failed: {e}"),
+            }
+        }
+        if turns > 0 {
+            tracing::info!(turns, "Tombstoned turns past retention");
+        }
+    })
+}
+
+/// The `limit` largest contexts ranked by payload bytes or turn count.
+///
+/// Stats walk every chain, so like the retention sweeper this takes the store
+/// lock once per context rather than for the whole pass. The result is cached
+/// for `TOP_CONTEXTS_TTL` and may lag recent appends by that much. Contexts
+/// whose stats can't be read are left out and listed in `skipped`.
+pub fn top_contexts(store: &Mutex<Store>, by: TopContextsBy, limit: usize) -> TopContexts {
+    let cached = lock_or_recover(store, "store")
+        .top_contexts_cache
+        .as_ref()
+        .filter(|(at, _)| at.elapsed() < TOP_CONTEXTS_TTL)
+        .map(|(_, top)| top.clone());
+    let mut top = match cached {
+        Some(top) => top,
+        None => {
+            let contexts = lock_or_recover(store, "store").list_recent_contexts(u32::MAX);
+            let mut top = TopContexts::default();
+            for head in contexts {
+                match lock_or_recover(store, "store").context_stats(head.context_id) {
+                    Ok(stats) => top.contexts.push(stats),
+                    Err(e) => {
+                        tracing::warn!(context_id = head.context_id, "context stats failed: {e}");
+                        top.skipped.push(head.context_id);
+                    }
+                }
+            }
+            lock_or_recover(store, "store").top_contexts_cache =
+                Some((Instant::now(), top.clone()));
+            top
+        }
+    };
+
+    top.contexts.sort_by(|a, b| {
+        let (a_key, b_key) = match by {
+            TopContextsBy::Bytes => (a.payload_bytes, b.payload_bytes),
+            TopContextsBy::Turns => (a.turn_count, b.turn_count),
+        };
+        b_key.cmp(&a_key).then(a.context_id.cmp(&b.context_id))
+    });
+    top.contexts.truncate(limit);
+    top
+}
+
+/// See [`top_contexts`].
+#[derive(Debug, Clone, Default)]
+pub struct TopContexts {
+    pub contexts: Vec<ContextStats>,
+    /// Contexts left out because their stats failed to load.
+    pub skipped: Vec<u64>,
+}
+
+/// Ranking key for [`top_contexts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopContextsBy {
+    Bytes,
+    Turns,
+}
+
+impl TopContextsBy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "bytes" => Some(Self::Bytes),
+            "turns" => Some(Self::Turns),
+            _ => None,
+        }
+    }
+}
+
+/// See [`Store::head_digest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadDigest {
+    pub context_id: u64,
+    pub head_turn_id: u64,
+    pub head_depth: u32,
+    pub digest: [u8; 32],
+}
+
+fn chain_digest(parent: &[u8; 32], payload_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Hasher::new();
+    hasher.update(parent);
+    hasher.update(payload_hash);
+    *hasher.finalize().as_bytes()
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextStats {
+    pub context_id: u64,
+    pub turn_count: u64,
+    pub head_depth: u32,
+    pub payload_bytes: u64,
+    pub fs_snapshot_bytes: u64,
+}
+
+/// The provenance `correlation_id` embedded in an uncompressed msgpack turn
+/// payload, if any. Used to tag errors from appends that never got stored.
+pub fn payload_correlation_id(payload: &[u8]) -> Option<String> {
+    extract_context_metadata(payload)?
+        .provenance?
+        .correlation_id
 }
 
 /// Extract context metadata from a msgpack-encoded ConversationItem payload.
@@ -677,6 +2054,41 @@ fn extract_context_metadata(payload: &[u8]) -> Option<ContextMetadata> {
     }
 }
 
+/// The first non-empty string in a turn payload, fields taken in tag order
+/// (as projection lists them) and nested values depth-first. The context
+/// metadata block (key 30) is skipped. Only the first line is kept, cut to
+/// `max_chars` characters.
+fn derive_title(payload: &[u8], max_chars: usize) -> Option<String> {
+    fn first_string(value: &Value) -> Option<&str> {
+        match value {
+            Value::String(s) => s.as_str().map(str::trim).filter(|s| !s.is_empty()),
+            Value::Array(items) => items.iter().find_map(first_string),
+            Value::Map(entries) => {
+                let mut entries: Vec<&(Value, Value)> = entries.iter().collect();
+                entries.sort_by_key(|(k, _)| key_to_tag(k).unwrap_or(u64::MAX));
+                entries.iter().find_map(|(_, v)| first_string(v))
+            }
+            _ => None,
+        }
+    }
+
+    let mut cursor = std::io::Cursor::new(payload);
+    let value = rmpv::decode::read_value(&mut cursor).ok()?;
+    let Value::Map(entries) = &value else {
+        return None;
+    };
+    let fields = Value::Map(
+        entries
+            .iter()
+            .filter(|(k, _)| key_to_tag(k) != Some(30))
+            .cloned()
+            .collect(),
+    );
+    let text = first_string(&fields)?;
+    let line = text.lines().next().unwrap_or(text).trim_end();
+    Some(line.chars().take(max_chars).collect())
+}
+
 /// Extract provenance from a msgpack map.
 fn extract_provenance(prov_map: &[(Value, Value)]) -> Provenance {
     let mut prov = Provenance::default();
@@ -809,6 +2221,30 @@ mod tests {
         Value::Integer(rmpv::Integer::from(n))
     }
 
+    #[test]
+    fn retention_labels_set_turn_and_age_limits() {
+        let labels = |ls: &[&str]| ls.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            RetentionPolicy::from_labels(&labels(&["team:a", "retain:100"])),
+            Some(RetentionPolicy {
+                max_turns: Some(100),
+                max_age: None,
+            })
+        );
+        assert_eq!(
+            RetentionPolicy::from_labels(&labels(&["retain:7d", "retain:5"])),
+            Some(RetentionPolicy {
+                max_turns: Some(5),
+                max_age: Some(Duration::from_secs(7 * 86_400)),
+            })
+        );
+        assert_eq!(
+            RetentionPolicy::from_labels(&labels(&["retain:soon"])),
+            None
+        );
+        assert_eq!(RetentionPolicy::from_labels(&labels(&["team:a"])), None);
+    }
+
     #[test]
     fn key_to_tag_accepts_integer_keys() {
         assert_eq!(key_to_tag(&int_val(30)), Some(30));
@@ -1,16 +1,19 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::Duration;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher;
 
-use crate::error::{Result, StoreError};
+use crate::clock::{system_clock, Clock};
+use crate::data_mode;
+use crate::error::{NotFoundKind, Result, StoreError};
 
 #[derive(Debug, Clone)]
 pub struct TurnRecord {
@@ -29,8 +32,14 @@ pub struct TurnMeta {
     pub declared_type_id: String,
     pub declared_type_version: u32,
     pub encoding: u32,
-    pub compression: u32,
+    /// Codec the client sent the payload with on append. Informational
+    /// only: the blob store picks its own codec, so reads should report
+    /// `BlobStore::codec` instead.
+    pub append_compression: u32,
     pub uncompressed_len: u32,
+    /// The payload itself, for a turn appended with it inlined here instead
+    /// of in the blob store (`TURN_FLAG_INLINE_PAYLOAD`).
+    pub inline_payload: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,6 +49,156 @@ pub struct ContextHead {
     pub head_depth: u32,
     pub created_at_unix_ms: u64,
     pub flags: u32,
+    /// Turns reachable from this context: the base turn's chain at fork time
+    /// plus every turn appended since. Unlike `head_depth + 1` this keeps
+    /// counting when appends branch from a non-head parent.
+    pub turn_count: u64,
+}
+
+/// One tip of a context: the head or a side branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchTip {
+    pub tip_turn_id: u64,
+    pub depth: u32,
+    pub is_head: bool,
+    /// Deepest turn this branch shares with the head's chain; for the head
+    /// itself that is the head turn. 0 if the branch shares no turn with it.
+    pub fork_turn_id: u64,
+}
+
+/// Leading marker of a versioned turns.log record. Like heads.tbl, legacy (v1)
+/// records start with the id itself and carry no version, and a v1 record
+/// whose id collides with the marker is retried as v1.
+const TURN_RECORD_MAGIC: u32 = 0x52545843; // 'C''X''T''R'
+/// Current turns.log record version. v2 adds no fields yet, only the marker.
+const TURN_RECORD_VERSION: u8 = 2;
+
+/// `TurnRecord::flags` bit: the payload is in the turn's turns.meta record,
+/// not the blob store.
+pub const TURN_FLAG_INLINE_PAYLOAD: u32 = 1;
+
+/// High bit of a turns.meta record's type id length: a length-prefixed
+/// inline payload follows the fixed fields. Type ids never come near 2 GiB,
+/// so records written before inlining never set it.
+const META_INLINE_PAYLOAD: u32 = 1 << 31;
+
+/// branches.tbl record: context_id, tip_turn_id, replaced_turn_id, crc32.
+const BRANCH_RECORD_LEN: usize = 8 + 8 + 8 + 4;
+
+/// tombstones.tbl record: turn_id, crc32.
+const TOMBSTONE_RECORD_LEN: usize = 8 + 4;
+
+/// How long an append's idempotency key keeps answering retries.
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Leading marker of a versioned heads.tbl record. Legacy (v1) records start
+/// directly with the context id, so one whose low half equals this value is
+/// read as v1 after it fails to parse as a versioned record.
+const HEAD_RECORD_MAGIC: u32 = 0x44485843; // 'C''X''H''D'
+/// Current heads.tbl record version. v1 records carry no marker at all.
+const HEAD_RECORD_VERSION: u8 = 2;
+
+/// Nesting past which `Chain::prefix` copies instead of sharing, so lookups
+/// stay a bounded number of hops when a context is forked at its head over
+/// and over.
+const CHAIN_MAX_LEVELS: usize = 32;
+
+/// Turn ids from a context's root to its head, indexed by depth. A fork
+/// shares the first `len` ids of its base's chain instead of copying them;
+/// only the ids after that prefix are owned.
+#[derive(Debug, Clone, Default)]
+struct Chain {
+    base: Option<(Arc<Chain>, usize)>,
+    own: Vec<u64>,
+    /// Number of shared segments under this one.
+    levels: usize,
+}
+
+impl Chain {
+    fn from_ids(own: Vec<u64>) -> Self {
+        Chain {
+            own,
+            ..Chain::default()
+        }
+    }
+
+    fn base_len(&self) -> usize {
+        self.base.as_ref().map_or(0, |(_, len)| *len)
+    }
+
+    fn len(&self) -> usize {
+        self.base_len() + self.own.len()
+    }
+
+    fn get(&self, depth: usize) -> Option<u64> {
+        let mut chain = self;
+        loop {
+            let base_len = chain.base_len();
+            if depth >= base_len {
+                return chain.own.get(depth - base_len).copied();
+            }
+            chain = &chain.base.as_ref()?.0;
+        }
+    }
+
+    fn first(&self) -> Option<u64> {
+        self.get(0)
+    }
+
+    /// Ids at depths `start..end`, oldest first.
+    fn ids(&self, start: usize, end: usize) -> Vec<u64> {
+        let mut parts = Vec::new();
+        let mut chain = self;
+        let mut end = end;
+        while start < end {
+            let base_len = chain.base_len();
+            if end > base_len {
+                let from = start.max(base_len);
+                parts.push(&chain.own[from - base_len..end - base_len]);
+                end = from;
+            }
+            match &chain.base {
+                Some((base, _)) if start < end => chain = base,
+                _ => break,
+            }
+        }
+        parts
+            .iter()
+            .rev()
+            .flat_map(|ids| ids.iter().copied())
+            .collect()
+    }
+
+    fn push(&mut self, turn_id: u64) {
+        self.own.push(turn_id);
+    }
+
+    /// A chain of this one's first `len` ids. Freezes the owned ids into a
+    /// shared segment first if the prefix reaches into them.
+    fn prefix(&mut self, len: usize) -> Chain {
+        if len == 0 {
+            return Chain::default();
+        }
+        if len > self.base_len() {
+            let mut frozen = std::mem::take(self);
+            if frozen.levels >= CHAIN_MAX_LEVELS {
+                frozen = Chain::from_ids(frozen.ids(0, frozen.len()));
+            }
+            let full = frozen.len();
+            let levels = frozen.levels + 1;
+            *self = Chain {
+                base: Some((Arc::new(frozen), full)),
+                own: Vec::new(),
+                levels,
+            };
+        }
+        let (base, _) = self.base.as_ref().expect("prefix is within the base");
+        Chain {
+            base: Some((base.clone(), len)),
+            own: Vec::new(),
+            levels: self.levels,
+        }
+    }
 }
 
 pub struct TurnStore {
@@ -47,75 +206,163 @@ pub struct TurnStore {
     turns_idx_path: std::path::PathBuf,
     turns_meta_path: std::path::PathBuf,
     heads_tbl_path: std::path::PathBuf,
+    idempotency_tbl_path: std::path::PathBuf,
 
     turns_log: File,
     turns_idx: File,
     turns_meta: File,
     heads_tbl: File,
+    branches_tbl: File,
+    tombstones_tbl: File,
+    idempotency_tbl: File,
 
     turns: HashMap<u64, TurnRecord>,
     turn_index: HashMap<u64, u64>,
     turn_meta: HashMap<u64, TurnMeta>,
     heads: HashMap<u64, ContextHead>,
+    /// Each context's chain from root to head, indexed by depth, so depth and
+    /// window lookups skip the parent walk. Costs 8 bytes per turn a context
+    /// appended; a fork shares its base's ids. A context whose chain has a
+    /// missing turn is left out and served by walking parents instead.
+    chains: HashMap<u64, Chain>,
+    /// Tips of branches other than the head, oldest first. An append whose
+    /// parent is not the head starts (or extends) one of these instead of
+    /// moving the head.
+    branch_tips: HashMap<u64, Vec<u64>>,
+    /// The context each turn was appended in. Not stored on disk; rebuilt
+    /// from the order of heads.tbl and branches.tbl records.
+    turn_contexts: HashMap<u64, u64>,
+    /// turns.meta offset of each turn's metadata record.
+    meta_index: HashMap<u64, u64>,
+    /// Contexts whose turns were evicted by `archive_contexts`. Only turns
+    /// reachable from a non-archived context stay in `turns`/`turn_meta`;
+    /// the rest are read back from disk through `turn_index`/`meta_index`.
+    archived: HashSet<u64>,
+    /// Turns removed by a retention policy. Their records stay so parent
+    /// links and depths hold, but reads skip them.
+    tombstoned: HashSet<u64>,
+    /// `(context_id, key)` of keyed appends to the turn they created, so a
+    /// retried append returns that turn instead of a duplicate.
+    idempotency_keys: HashMap<(u64, Vec<u8>), IdempotencyEntry>,
 
     next_turn_id: u64,
     next_context_id: u64,
+    clock: Arc<dyn Clock>,
 }
 
 impl TurnStore {
     pub fn open(dir: &Path) -> Result<Self> {
-        std::fs::create_dir_all(dir)?;
+        Self::open_with_clock(dir, system_clock())
+    }
+
+    /// Like `open`, stamping new turns and contexts with `clock`'s time.
+    pub fn open_with_clock(dir: &Path, clock: Arc<dyn Clock>) -> Result<Self> {
+        Self::open_inner(dir, clock, 1, 1)
+    }
+
+    /// Like `open`, but new turn and context ids start no lower than
+    /// `turn_base` and `ctx_base`, so stores opened with disjoint bases never
+    /// hand out the same id. Ids already on disk are kept; allocation resumes
+    /// past the highest of them as usual.
+    pub fn open_with_id_base(dir: &Path, turn_base: u64, ctx_base: u64) -> Result<Self> {
+        Self::open_inner(dir, system_clock(), turn_base, ctx_base)
+    }
+
+    fn open_inner(
+        dir: &Path,
+        clock: Arc<dyn Clock>,
+        turn_base: u64,
+        ctx_base: u64,
+    ) -> Result<Self> {
+        data_mode::create_dir_all(dir)?;
         let turns_log_path = dir.join("turns.log");
         let turns_idx_path = dir.join("turns.idx");
         let turns_meta_path = dir.join("turns.meta");
         let heads_tbl_path = dir.join("heads.tbl");
 
-        let turns_log = OpenOptions::new()
+        let turns_log = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_log_path)?;
-        let turns_idx = OpenOptions::new()
+        let turns_idx = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_idx_path)?;
-        let turns_meta = OpenOptions::new()
+        let turns_meta = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_meta_path)?;
-        let heads_tbl = OpenOptions::new()
+        let heads_tbl = data_mode::open_options()
             .create(true)
             .truncate(false)
             .read(true)
             .write(true)
             .open(&heads_tbl_path)?;
+        let branches_tbl = data_mode::open_options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("branches.tbl"))?;
+        let tombstones_tbl = data_mode::open_options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("tombstones.tbl"))?;
+        let idempotency_tbl_path = dir.join("idempotency.tbl");
+        let idempotency_tbl = data_mode::open_options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&idempotency_tbl_path)?;
 
         let mut store = Self {
             turns_log_path,
             turns_idx_path,
             turns_meta_path,
             heads_tbl_path,
+            idempotency_tbl_path,
             turns_log,
             turns_idx,
             turns_meta,
             heads_tbl,
+            branches_tbl,
+            tombstones_tbl,
+            idempotency_tbl,
             turns: HashMap::new(),
             turn_index: HashMap::new(),
             turn_meta: HashMap::new(),
             heads: HashMap::new(),
-            next_turn_id: 1,
-            next_context_id: 1,
+            chains: HashMap::new(),
+            branch_tips: HashMap::new(),
+            turn_contexts: HashMap::new(),
+            meta_index: HashMap::new(),
+            archived: HashSet::new(),
+            tombstoned: HashSet::new(),
+            idempotency_keys: HashMap::new(),
+            // 0 means "no turn" / "no context" throughout.
+            next_turn_id: turn_base.max(1),
+            next_context_id: ctx_base.max(1),
+            clock,
         };
 
         store.load_turns()?;
         store.load_meta()?;
-        store.load_heads()?;
+        let previous_heads = store.load_heads()?;
+        store.load_branches()?;
+        store.reconcile_branch_tips(&previous_heads)?;
+        store.load_tombstones()?;
+        store.load_idempotency_keys()?;
         store.rebuild_index()?;
+        store.rebuild_chains();
         store.update_counters();
 
         Ok(store)
@@ -123,7 +370,7 @@ impl TurnStore {
 
     pub fn stats(&self) -> TurnStoreStats {
         TurnStoreStats {
-            turns_total: self.turns.len(),
+            turns_total: self.turn_index.len(),
             contexts_total: self.heads.len(),
             heads_total: self.heads.len(),
             turns_log_bytes: file_len(&self.turns_log_path),
@@ -165,144 +412,267 @@ impl TurnStore {
 
     fn load_meta(&mut self) -> Result<()> {
         self.turn_meta.clear();
+        self.meta_index.clear();
         self.turns_meta.seek(SeekFrom::Start(0))?;
 
         loop {
             let start = self.turns_meta.stream_position()?;
-            let turn_id = match self.turns_meta.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(StoreError::Io(e)),
-            };
-            let len = match self.turns_meta.read_u32::<LittleEndian>() {
-                Ok(v) => v as usize,
-                Err(_) => {
-                    self.turns_meta.set_len(start)?;
-                    break;
-                }
-            };
-            let mut buf = vec![0u8; len];
-            if self.turns_meta.read_exact(&mut buf).is_err() {
-                self.turns_meta.set_len(start)?;
-                break;
-            }
-            let declared_type_id = String::from_utf8(buf)
-                .map_err(|_| StoreError::Corrupt("invalid type id utf8".into()))?;
-            let declared_type_version = match self.turns_meta.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.turns_meta.set_len(start)?;
-                    break;
-                }
-            };
-            let encoding = match self.turns_meta.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.turns_meta.set_len(start)?;
-                    break;
+            match read_meta_record(&mut self.turns_meta) {
+                Ok(Some((turn_id, meta))) => {
+                    self.turn_meta.insert(turn_id, meta);
+                    self.meta_index.insert(turn_id, start);
                 }
-            };
-            let compression = match self.turns_meta.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.turns_meta.set_len(start)?;
-                    break;
-                }
-            };
-            let uncompressed_len = match self.turns_meta.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
+                Ok(None) => break,
+                Err(StoreError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
                     self.turns_meta.set_len(start)?;
                     break;
                 }
-            };
-
-            self.turn_meta.insert(
-                turn_id,
-                TurnMeta {
-                    declared_type_id,
-                    declared_type_version,
-                    encoding,
-                    compression,
-                    uncompressed_len,
-                },
-            );
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(())
     }
 
-    fn load_heads(&mut self) -> Result<()> {
+    /// Replay heads.tbl. A record that moves an existing context's head to a
+    /// new turn is an append in that context, so it claims the turn; a
+    /// context's first record only points at its fork base and claims nothing.
+    /// Returns each context's head before its last move.
+    fn load_heads(&mut self) -> Result<HashMap<u64, u64>> {
+        let mut previous_heads = HashMap::new();
         self.heads.clear();
+        self.turn_contexts.clear();
         self.heads_tbl.seek(SeekFrom::Start(0))?;
         loop {
             let start = self.heads_tbl.stream_position()?;
-            let context_id = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(StoreError::Io(e)),
-            };
-            let head_turn_id = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
+            let head = match read_head_record(&mut self.heads_tbl) {
+                Ok(Some(head)) => head,
+                Ok(None) => break,
+                Err(StoreError::Corrupt(_)) => {
+                    // truncate partial/corrupt tail
                     self.heads_tbl.set_len(start)?;
                     break;
                 }
-            };
-            let head_depth = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
+                Err(StoreError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
                     self.heads_tbl.set_len(start)?;
                     break;
                 }
+                Err(e) => return Err(e),
             };
-            let flags = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
-                    break;
+            let moved = self
+                .heads
+                .get(&head.context_id)
+                .is_some_and(|prev| prev.head_turn_id != head.head_turn_id);
+            if moved && head.head_turn_id != 0 {
+                self.turn_contexts
+                    .entry(head.head_turn_id)
+                    .or_insert(head.context_id);
+            }
+            if let Some(prev) = self.heads.insert(head.context_id, head) {
+                if moved {
+                    previous_heads.insert(prev.context_id, prev.head_turn_id);
                 }
-            };
-            let created_at_unix_ms = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
+            }
+        }
+        Ok(previous_heads)
+    }
+
+    /// Replay branches.tbl: each record adds a tip and retires the tip it
+    /// grew from, if any.
+    fn load_branches(&mut self) -> Result<()> {
+        self.branch_tips.clear();
+        self.branches_tbl.seek(SeekFrom::Start(0))?;
+        loop {
+            let start = self.branches_tbl.stream_position()?;
+            let mut buf = [0u8; BRANCH_RECORD_LEN];
+            match self.branches_tbl.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    self.branches_tbl.set_len(start)?;
                     break;
                 }
+                Err(e) => return Err(StoreError::Io(e)),
+            }
+            let mut cursor = &buf[..];
+            let context_id = cursor.read_u64::<LittleEndian>()?;
+            let tip_turn_id = cursor.read_u64::<LittleEndian>()?;
+            let replaced_turn_id = cursor.read_u64::<LittleEndian>()?;
+            let crc = cursor.read_u32::<LittleEndian>()?;
+            let mut hasher = Hasher::new();
+            hasher.update(&buf[..24]);
+            if crc != hasher.finalize() {
+                self.branches_tbl.set_len(start)?;
+                break;
+            }
+            let tips = self.branch_tips.entry(context_id).or_default();
+            tips.retain(|t| *t != replaced_turn_id);
+            if tip_turn_id != 0 {
+                tips.push(tip_turn_id);
+                // Side-branch appends only show up here.
+                self.turn_contexts.entry(tip_turn_id).or_insert(context_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// `set_head` writes the new head before the branch record that swaps
+    /// it out of the tips, so a crash in between leaves the head listed as a
+    /// tip and the old head listed nowhere. Finish that swap here.
+    fn reconcile_branch_tips(&mut self, previous_heads: &HashMap<u64, u64>) -> Result<()> {
+        let mut torn = Vec::new();
+        for (context_id, tips) in &self.branch_tips {
+            let Some(head) = self.heads.get(context_id) else {
+                continue;
             };
-            let crc = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
+            if head.head_turn_id != 0 && tips.contains(&head.head_turn_id) {
+                let previous = previous_heads.get(context_id).copied().unwrap_or(0);
+                torn.push((*context_id, head.head_turn_id, previous));
+            }
+        }
+        for (context_id, head_turn_id, previous) in torn {
+            let tips = self.branch_tips.entry(context_id).or_default();
+            tips.retain(|t| *t != head_turn_id);
+            if previous != 0 && !tips.contains(&previous) {
+                tips.push(previous);
+            }
+            self.write_branch(context_id, previous, head_turn_id)?;
+        }
+        Ok(())
+    }
+
+    fn load_tombstones(&mut self) -> Result<()> {
+        self.tombstoned.clear();
+        self.tombstones_tbl.seek(SeekFrom::Start(0))?;
+        loop {
+            let start = self.tombstones_tbl.stream_position()?;
+            let mut buf = [0u8; TOMBSTONE_RECORD_LEN];
+            match self.tombstones_tbl.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    self.tombstones_tbl.set_len(start)?;
                     break;
                 }
-            };
-
-            let mut buf = Vec::with_capacity(8 + 8 + 4 + 4 + 8);
-            buf.write_u64::<LittleEndian>(context_id)?;
-            buf.write_u64::<LittleEndian>(head_turn_id)?;
-            buf.write_u32::<LittleEndian>(head_depth)?;
-            buf.write_u32::<LittleEndian>(flags)?;
-            buf.write_u64::<LittleEndian>(created_at_unix_ms)?;
+                Err(e) => return Err(StoreError::Io(e)),
+            }
+            let mut cursor = &buf[..];
+            let turn_id = cursor.read_u64::<LittleEndian>()?;
+            let crc = cursor.read_u32::<LittleEndian>()?;
             let mut hasher = Hasher::new();
-            hasher.update(&buf);
-            let actual_crc = hasher.finalize();
-            if crc != actual_crc {
-                self.heads_tbl.set_len(start)?;
+            hasher.update(&buf[..8]);
+            if crc != hasher.finalize() {
+                self.tombstones_tbl.set_len(start)?;
                 break;
             }
+            self.tombstoned.insert(turn_id);
+        }
+        Ok(())
+    }
 
-            self.heads.insert(
-                context_id,
-                ContextHead {
-                    context_id,
-                    head_turn_id,
-                    head_depth,
-                    created_at_unix_ms,
-                    flags,
-                },
-            );
+    /// Replay idempotency.tbl: context_id, turn_id, recorded_at_unix_ms,
+    /// content hash, key length (u32), key, crc32. Expired keys are dropped,
+    /// and the table is rewritten without them so it doesn't grow forever.
+    fn load_idempotency_keys(&mut self) -> Result<()> {
+        self.idempotency_keys.clear();
+        let mut buf = Vec::new();
+        self.idempotency_tbl.seek(SeekFrom::Start(0))?;
+        self.idempotency_tbl.read_to_end(&mut buf)?;
+        let expired_before = self.idempotency_expired_before();
+        let mut offset = 0usize;
+        let mut dropped = false;
+        while offset < buf.len() {
+            // A torn or corrupt tail is dropped with the expired keys.
+            let Some((len, context_id, key, entry)) = parse_idempotency_record(&buf[offset..])
+            else {
+                dropped = true;
+                break;
+            };
+            if entry.recorded_at >= expired_before {
+                self.idempotency_keys.insert((context_id, key), entry);
+            } else {
+                dropped = true;
+            }
+            offset += len;
+        }
+        if dropped {
+            let mut live = Vec::new();
+            for ((context_id, key), entry) in &self.idempotency_keys {
+                live.extend(encode_idempotency_record(*context_id, key, entry)?);
+            }
+            data_mode::write_file_atomic(&self.idempotency_tbl_path, &live)?;
+            self.idempotency_tbl = data_mode::open_options()
+                .read(true)
+                .write(true)
+                .open(&self.idempotency_tbl_path)?;
+        }
+        Ok(())
+    }
+
+    fn idempotency_expired_before(&self) -> u64 {
+        self.now_unix_ms()
+            .saturating_sub(IDEMPOTENCY_KEY_TTL.as_millis() as u64)
+    }
+
+    /// The turn a keyed append to `context_id` already created, if the key
+    /// was used within `IDEMPOTENCY_KEY_TTL`. Reusing a live key for a
+    /// different payload is a conflict.
+    pub fn idempotent_turn(
+        &self,
+        context_id: u64,
+        key: &[u8],
+        content_hash: &[u8; 32],
+    ) -> Result<Option<u64>> {
+        let Some(entry) = self.idempotency_keys.get(&(context_id, key.to_vec())) else {
+            return Ok(None);
+        };
+        if entry.recorded_at < self.idempotency_expired_before() {
+            return Ok(None);
         }
+        if entry.content_hash != *content_hash {
+            return Err(StoreError::Conflict(format!(
+                "idempotency key already used for turn {} with a different payload",
+                entry.turn_id
+            )));
+        }
+        Ok(Some(entry.turn_id))
+    }
+
+    /// Remember that the append keyed `key` in `context_id`, with payload
+    /// hash `content_hash`, created `turn_id`. Expired keys are pruned from
+    /// memory here; the table sheds them on the next open.
+    pub fn record_idempotency_key(
+        &mut self,
+        context_id: u64,
+        key: Vec<u8>,
+        content_hash: [u8; 32],
+        turn_id: u64,
+    ) -> Result<()> {
+        let entry = IdempotencyEntry {
+            turn_id,
+            recorded_at: self.now_unix_ms(),
+            content_hash,
+        };
+        let buf = encode_idempotency_record(context_id, &key, &entry)?;
+        self.idempotency_tbl.seek(SeekFrom::End(0))?;
+        self.idempotency_tbl.write_all(&buf)?;
+        self.idempotency_tbl.flush()?;
+        let expired_before = self.idempotency_expired_before();
+        self.idempotency_keys
+            .retain(|_, entry| entry.recorded_at >= expired_before);
+        self.idempotency_keys.insert((context_id, key), entry);
+        Ok(())
+    }
+
+    fn write_branch(&mut self, context_id: u64, tip: u64, replaced: u64) -> Result<()> {
+        let mut buf = Vec::with_capacity(BRANCH_RECORD_LEN);
+        buf.write_u64::<LittleEndian>(context_id)?;
+        buf.write_u64::<LittleEndian>(tip)?;
+        buf.write_u64::<LittleEndian>(replaced)?;
+        let mut hasher = Hasher::new();
+        hasher.update(&buf);
+        buf.write_u32::<LittleEndian>(hasher.finalize())?;
+        self.branches_tbl.seek(SeekFrom::End(0))?;
+        self.branches_tbl.write_all(&buf)?;
+        self.branches_tbl.flush()?;
         Ok(())
     }
 
@@ -317,31 +687,114 @@ impl TurnStore {
         Ok(())
     }
 
+    fn rebuild_chains(&mut self) {
+        self.chains.clear();
+        let mut heads: Vec<(u64, u64)> = self
+            .heads
+            .values()
+            .map(|h| (h.context_id, h.head_turn_id))
+            .collect();
+        // Bases before their forks, so forks find a chain to share.
+        heads.sort_unstable();
+        for (context_id, head_turn_id) in heads {
+            self.set_chain(context_id, head_turn_id);
+        }
+    }
+
+    /// Index `context_id`'s chain ending at `turn_id`, sharing the prefix of
+    /// whichever context's chain the nearest ancestor was appended on. A
+    /// missing ancestor leaves the context unindexed.
+    fn set_chain(&mut self, context_id: u64, turn_id: u64) {
+        let mut own = Vec::new();
+        let mut current = turn_id;
+        let base = loop {
+            if current == 0 {
+                break None;
+            }
+            let Some(rec) = self.turns.get(&current) else {
+                self.chains.remove(&context_id);
+                return;
+            };
+            let depth = rec.depth as usize;
+            let shared = self.turn_contexts.get(&current).and_then(|owner| {
+                let chain = self.chains.get(owner)?;
+                (chain.get(depth) == Some(current)).then_some(*owner)
+            });
+            if let Some(owner) = shared {
+                break Some((owner, depth + 1));
+            }
+            own.push(current);
+            current = rec.parent_turn_id;
+        };
+        own.reverse();
+        let mut chain = match base {
+            Some((owner, len)) => self
+                .chains
+                .get_mut(&owner)
+                .expect("owner chain checked above")
+                .prefix(len),
+            None => Chain::default(),
+        };
+        chain.own = own;
+        let depth_ok = turn_id == 0 || self.turns[&turn_id].depth as usize + 1 == chain.len();
+        if depth_ok {
+            self.chains.insert(context_id, chain);
+        } else {
+            self.chains.remove(&context_id);
+        }
+    }
+
+    /// Turn ids from the root to `turn_id`, or `None` if an ancestor is missing
+    /// or depths are not contiguous.
+    fn resolve_chain(&self, turn_id: u64) -> Option<Vec<u64>> {
+        let mut chain = Vec::new();
+        let mut current = turn_id;
+        while current != 0 {
+            let rec = self.turns.get(&current)?;
+            chain.push(current);
+            current = rec.parent_turn_id;
+        }
+        chain.reverse();
+        let depth_ok = chain
+            .last()
+            .is_none_or(|id| self.turns[id].depth as usize + 1 == chain.len());
+        depth_ok.then_some(chain)
+    }
+
+    fn records(&self, ids: &[u64]) -> Result<Vec<TurnRecord>> {
+        ids.iter()
+            .map(|id| {
+                self.turns
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))
+            })
+            .collect()
+    }
+
     fn update_counters(&mut self) {
         if let Some(max_id) = self.turns.keys().max().cloned() {
-            self.next_turn_id = max_id + 1;
+            self.next_turn_id = self.next_turn_id.max(max_id + 1);
         }
         if let Some(max_ctx) = self.heads.keys().max().cloned() {
-            self.next_context_id = max_ctx + 1;
+            self.next_context_id = self.next_context_id.max(max_ctx + 1);
         }
     }
 
-    fn now_unix_ms() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+    fn now_unix_ms(&self) -> u64 {
+        self.clock.now_unix_ms()
     }
 
     pub fn create_context(&mut self, base_turn_id: u64) -> Result<ContextHead> {
-        let (head_turn_id, head_depth) = if base_turn_id == 0 {
-            (0, 0)
+        self.load_ancestry(base_turn_id)?;
+        let (head_turn_id, head_depth, turn_count) = if base_turn_id == 0 {
+            (0, 0, 0)
         } else {
             let turn = self
                 .turns
                 .get(&base_turn_id)
-                .ok_or_else(|| StoreError::NotFound("base turn".into()))?;
-            (turn.turn_id, turn.depth)
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::ParentTurn, "base turn"))?;
+            (turn.turn_id, turn.depth, turn.depth as u64 + 1)
         };
 
         let context_id = self.next_context_id;
@@ -351,12 +804,14 @@ impl TurnStore {
             context_id,
             head_turn_id,
             head_depth,
-            created_at_unix_ms: Self::now_unix_ms(),
+            created_at_unix_ms: self.now_unix_ms(),
             flags: 0,
+            turn_count,
         };
 
         self.write_head(&head)?;
         self.heads.insert(context_id, head.clone());
+        self.set_chain(context_id, head_turn_id);
         Ok(head)
     }
 
@@ -368,7 +823,7 @@ impl TurnStore {
         self.heads
             .get(&context_id)
             .cloned()
-            .ok_or_else(|| StoreError::NotFound("context".into()))
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -380,30 +835,35 @@ impl TurnStore {
         encoding: u32,
         declared_type_id: String,
         declared_type_version: u32,
-        compression: u32,
+        append_compression: u32,
         uncompressed_len: u32,
+        inline_payload: Option<Vec<u8>>,
     ) -> Result<TurnRecord> {
+        self.restore_context(context_id)?;
+        self.load_ancestry(parent_turn_id)?;
+        let head = self
+            .heads
+            .get(&context_id)
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?
+            .clone();
         let (parent_id, depth) = if parent_turn_id != 0 {
             let parent = self
                 .turns
                 .get(&parent_turn_id)
-                .ok_or_else(|| StoreError::NotFound("parent turn".into()))?;
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::ParentTurn, "parent turn"))?;
             (parent.turn_id, parent.depth + 1)
+        } else if head.head_turn_id == 0 {
+            (0, 0)
         } else {
-            let head = self
-                .heads
-                .get(&context_id)
-                .ok_or_else(|| StoreError::NotFound("context".into()))?;
-            if head.head_turn_id == 0 {
-                (0, 0)
-            } else {
-                let parent = self
-                    .turns
-                    .get(&head.head_turn_id)
-                    .ok_or_else(|| StoreError::NotFound("head turn".into()))?;
-                (parent.turn_id, parent.depth + 1)
-            }
+            let parent = self
+                .turns
+                .get(&head.head_turn_id)
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "head turn"))?;
+            (parent.turn_id, parent.depth + 1)
         };
+        // Only appends onto the head (or into an empty context) move it;
+        // anything else grows a side branch and leaves the head alone.
+        let extends_head = head.head_turn_id == 0 || parent_id == head.head_turn_id;
 
         let turn_id = self.next_turn_id;
         self.next_turn_id += 1;
@@ -415,8 +875,12 @@ impl TurnStore {
             codec: encoding,
             type_tag: 0,
             payload_hash,
-            flags: 0,
-            created_at_unix_ms: Self::now_unix_ms(),
+            flags: if inline_payload.is_some() {
+                TURN_FLAG_INLINE_PAYLOAD
+            } else {
+                0
+            },
+            created_at_unix_ms: self.now_unix_ms(),
         };
 
         let offset = self.turns_log.seek(SeekFrom::End(0))?;
@@ -432,15 +896,24 @@ impl TurnStore {
         // store meta
         let mut meta_bytes = Vec::new();
         meta_bytes.write_u64::<LittleEndian>(turn_id)?;
-        meta_bytes.write_u32::<LittleEndian>(declared_type_id.len() as u32)?;
+        let mut type_id_len = declared_type_id.len() as u32;
+        if inline_payload.is_some() {
+            type_id_len |= META_INLINE_PAYLOAD;
+        }
+        meta_bytes.write_u32::<LittleEndian>(type_id_len)?;
         meta_bytes.extend_from_slice(declared_type_id.as_bytes());
         meta_bytes.write_u32::<LittleEndian>(declared_type_version)?;
         meta_bytes.write_u32::<LittleEndian>(encoding)?;
-        meta_bytes.write_u32::<LittleEndian>(compression)?;
+        meta_bytes.write_u32::<LittleEndian>(append_compression)?;
         meta_bytes.write_u32::<LittleEndian>(uncompressed_len)?;
-        self.turns_meta.seek(SeekFrom::End(0))?;
+        if let Some(payload) = &inline_payload {
+            meta_bytes.write_u32::<LittleEndian>(payload.len() as u32)?;
+            meta_bytes.extend_from_slice(payload);
+        }
+        let meta_offset = self.turns_meta.seek(SeekFrom::End(0))?;
         self.turns_meta.write_all(&meta_bytes)?;
         self.turns_meta.flush()?;
+        self.meta_index.insert(turn_id, meta_offset);
 
         self.turn_meta.insert(
             turn_id,
@@ -448,20 +921,47 @@ impl TurnStore {
                 declared_type_id,
                 declared_type_version,
                 encoding,
-                compression,
+                append_compression,
                 uncompressed_len,
+                inline_payload,
             },
         );
         self.turns.insert(turn_id, record.clone());
         self.turn_index.insert(turn_id, offset);
+        self.turn_contexts.insert(turn_id, context_id);
 
-        // update head
-        let head = ContextHead {
-            context_id,
-            head_turn_id: turn_id,
-            head_depth: depth,
-            created_at_unix_ms: record.created_at_unix_ms,
-            flags: 0,
+        let head = if extends_head {
+            let depth_idx = depth as usize;
+            match self.chains.get_mut(&context_id) {
+                Some(chain)
+                    if depth_idx == chain.len()
+                        && (depth_idx == 0 || chain.get(depth_idx - 1) == Some(parent_id)) =>
+                {
+                    chain.push(turn_id);
+                }
+                _ => self.set_chain(context_id, turn_id),
+            }
+            ContextHead {
+                context_id,
+                head_turn_id: turn_id,
+                head_depth: depth,
+                created_at_unix_ms: record.created_at_unix_ms,
+                flags: 0,
+                turn_count: head.turn_count + 1,
+            }
+        } else {
+            let tips = self.branch_tips.entry(context_id).or_default();
+            let replaced = match tips.iter().position(|t| *t == parent_id) {
+                Some(pos) => tips.remove(pos),
+                None => 0,
+            };
+            tips.push(turn_id);
+            self.write_branch(context_id, turn_id, replaced)?;
+            ContextHead {
+                created_at_unix_ms: record.created_at_unix_ms,
+                turn_count: head.turn_count + 1,
+                ..head
+            }
         };
         self.write_head(&head)?;
         self.heads.insert(context_id, head);
@@ -469,17 +969,103 @@ impl TurnStore {
         Ok(record)
     }
 
+    /// Tips of the context's side branches, oldest first. The head is not
+    /// included.
+    pub fn branch_tips(&self, context_id: u64) -> Vec<u64> {
+        self.branch_tips
+            .get(&context_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Make a side-branch tip the context head. The previous head becomes a
+    /// side-branch tip, so no branch is lost. Promoting the current head is a
+    /// no-op.
+    pub fn set_head(&mut self, context_id: u64, tip_turn_id: u64) -> Result<ContextHead> {
+        self.restore_context(context_id)?;
+        let head = self
+            .heads
+            .get(&context_id)
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?
+            .clone();
+        if head.head_turn_id == tip_turn_id {
+            return Ok(head);
+        }
+        let tips = self.branch_tips.entry(context_id).or_default();
+        let pos = tips.iter().position(|t| *t == tip_turn_id).ok_or_else(|| {
+            StoreError::InvalidInput("turn is not a branch tip of this context".into())
+        })?;
+        let tip = self.get_turn(tip_turn_id)?;
+        let old_head_turn_id = head.head_turn_id;
+        let head = ContextHead {
+            head_turn_id: tip_turn_id,
+            head_depth: tip.depth,
+            ..head
+        };
+        // Head first: if the branch record is lost, reopening finishes the
+        // swap from heads.tbl (see `reconcile_branch_tips`).
+        self.write_head(&head)?;
+        self.write_branch(context_id, old_head_turn_id, tip_turn_id)?;
+
+        let tips = self.branch_tips.entry(context_id).or_default();
+        tips.remove(pos);
+        tips.push(old_head_turn_id);
+        self.set_chain(context_id, tip_turn_id);
+        self.heads.insert(context_id, head.clone());
+        Ok(head)
+    }
+
+    /// The head followed by every side-branch tip, each with the point where
+    /// it diverges from the head's chain.
+    pub fn branches(&self, context_id: u64) -> Result<Vec<BranchTip>> {
+        let head = self
+            .heads
+            .get(&context_id)
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?;
+        let resolved;
+        let main_line = match self.chains.get(&context_id) {
+            Some(chain) => chain,
+            None => {
+                resolved =
+                    Chain::from_ids(self.resolve_chain(head.head_turn_id).unwrap_or_default());
+                &resolved
+            }
+        };
+        let on_main_line =
+            |turn: &TurnRecord| main_line.get(turn.depth as usize) == Some(turn.turn_id);
+
+        let mut branches = Vec::new();
+        if head.head_turn_id != 0 {
+            branches.push(BranchTip {
+                tip_turn_id: head.head_turn_id,
+                depth: head.head_depth,
+                is_head: true,
+                fork_turn_id: head.head_turn_id,
+            });
+        }
+        for tip in self.branch_tips(context_id) {
+            let tip_record = self.get_turn(tip)?;
+            let mut fork_turn_id = 0;
+            let mut current = tip_record.parent_turn_id;
+            while let Some(rec) = self.turns.get(&current) {
+                if on_main_line(rec) {
+                    fork_turn_id = rec.turn_id;
+                    break;
+                }
+                current = rec.parent_turn_id;
+            }
+            branches.push(BranchTip {
+                tip_turn_id: tip,
+                depth: tip_record.depth,
+                is_head: false,
+                fork_turn_id,
+            });
+        }
+        Ok(branches)
+    }
+
     fn write_head(&mut self, head: &ContextHead) -> Result<()> {
-        let mut buf = Vec::with_capacity(8 + 8 + 4 + 4 + 8 + 4);
-        buf.write_u64::<LittleEndian>(head.context_id)?;
-        buf.write_u64::<LittleEndian>(head.head_turn_id)?;
-        buf.write_u32::<LittleEndian>(head.head_depth)?;
-        buf.write_u32::<LittleEndian>(head.flags)?;
-        buf.write_u64::<LittleEndian>(head.created_at_unix_ms)?;
-        let mut hasher = Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
-        buf.write_u32::<LittleEndian>(crc)?;
+        let buf = encode_head_record(head)?;
         self.heads_tbl.seek(SeekFrom::End(0))?;
         self.heads_tbl.write_all(&buf)?;
         self.heads_tbl.flush()?;
@@ -489,22 +1075,36 @@ impl TurnStore {
     pub fn get_turn(&self, turn_id: u64) -> Result<TurnRecord> {
         self.turns
             .get(&turn_id)
+            .filter(|rec| !self.tombstoned.contains(&rec.turn_id))
             .cloned()
-            .ok_or_else(|| StoreError::NotFound("turn".into()))
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))
+    }
+
+    /// The context `turn_id` was appended in. A fork shares its base's turns,
+    /// so this can differ from the context the turn was read through.
+    pub fn turn_context(&self, turn_id: u64) -> Option<u64> {
+        self.turn_contexts.get(&turn_id).copied()
     }
 
     pub fn get_turn_meta(&self, turn_id: u64) -> Result<TurnMeta> {
         self.turn_meta
             .get(&turn_id)
             .cloned()
-            .ok_or_else(|| StoreError::NotFound("turn meta".into()))
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn meta"))
     }
 
     pub fn get_last(&self, context_id: u64, limit: u32) -> Result<Vec<TurnRecord>> {
         let head = self
             .heads
             .get(&context_id)
-            .ok_or_else(|| StoreError::NotFound("context".into()))?;
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?;
+
+        if let Some(chain) = self.chains.get(&context_id) {
+            let start = chain.len().saturating_sub(limit as usize);
+            return self
+                .records(&chain.ids(start, chain.len()))
+                .map(|recs| self.visible(recs));
+        }
 
         let mut results = Vec::new();
         let mut current = head.head_turn_id;
@@ -512,13 +1112,52 @@ impl TurnStore {
             let rec = self
                 .turns
                 .get(&current)
-                .ok_or_else(|| StoreError::NotFound("turn".into()))?
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))?
                 .clone();
             results.push(rec.clone());
             current = rec.parent_turn_id;
         }
         results.reverse();
-        Ok(results)
+        Ok(self.visible(results))
+    }
+
+    /// Drops tombstoned turns from a read's results. Retention only
+    /// tombstones a context's oldest turns, so a window just comes back
+    /// shorter rather than reaching further back.
+    fn visible(&self, mut records: Vec<TurnRecord>) -> Vec<TurnRecord> {
+        if !self.tombstoned.is_empty() {
+            records.retain(|rec| !self.tombstoned.contains(&rec.turn_id));
+        }
+        records
+    }
+
+    pub fn is_tombstoned(&self, turn_id: u64) -> bool {
+        self.tombstoned.contains(&turn_id)
+    }
+
+    /// Tombstones turns so reads skip them, persisting the marks. Records
+    /// stay for the chain structure; nothing references the payload blobs
+    /// any more. Returns how many turns were newly tombstoned.
+    pub fn tombstone_turns(&mut self, turn_ids: &[u64]) -> Result<usize> {
+        let mut buf = Vec::new();
+        let mut added = 0;
+        for turn_id in turn_ids {
+            if !self.turn_index.contains_key(turn_id) || !self.tombstoned.insert(*turn_id) {
+                continue;
+            }
+            let start = buf.len();
+            buf.write_u64::<LittleEndian>(*turn_id)?;
+            let mut hasher = Hasher::new();
+            hasher.update(&buf[start..]);
+            buf.write_u32::<LittleEndian>(hasher.finalize())?;
+            added += 1;
+        }
+        if !buf.is_empty() {
+            self.tombstones_tbl.seek(SeekFrom::End(0))?;
+            self.tombstones_tbl.write_all(&buf)?;
+            self.tombstones_tbl.flush()?;
+        }
+        Ok(added)
     }
 
     pub fn get_before(
@@ -530,7 +1169,7 @@ impl TurnStore {
         let head = self
             .heads
             .get(&context_id)
-            .ok_or_else(|| StoreError::NotFound("context".into()))?;
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?;
 
         if before_turn_id == 0 || head.head_turn_id == 0 {
             return self.get_last(context_id, limit);
@@ -539,20 +1178,76 @@ impl TurnStore {
         let before = self
             .turns
             .get(&before_turn_id)
-            .ok_or_else(|| StoreError::NotFound("before turn".into()))?;
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "before turn"))?;
+        if let Some(chain) = self.chains.get(&context_id) {
+            let end = before.depth as usize;
+            if chain.get(end) == Some(before_turn_id) {
+                let start = end.saturating_sub(limit as usize);
+                return self
+                    .records(&chain.ids(start, end))
+                    .map(|recs| self.visible(recs));
+            }
+        }
+        // Not on the context's current chain (e.g. an abandoned branch).
         let mut current = before.parent_turn_id;
         let mut results = Vec::new();
         while current != 0 && results.len() < limit as usize {
             let rec = self
                 .turns
                 .get(&current)
-                .ok_or_else(|| StoreError::NotFound("turn".into()))?
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))?
                 .clone();
             results.push(rec.clone());
             current = rec.parent_turn_id;
         }
         results.reverse();
-        Ok(results)
+        Ok(self.visible(results))
+    }
+
+    /// Up to `limit` turns ending at the turn at `depth` in the context's
+    /// chain, oldest first.
+    ///
+    /// A chain holds every depth from 0 to `head_depth` (a fork inherits its
+    /// base's ancestors), so the only depth that can be missing is one past the
+    /// head; that case returns the window ending at the head, the nearest turn.
+    pub fn get_at_depth(&self, context_id: u64, depth: u32, limit: u32) -> Result<Vec<TurnRecord>> {
+        let head = self
+            .heads
+            .get(&context_id)
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?;
+
+        if let Some(chain) = self.chains.get(&context_id) {
+            let end = (depth as usize + 1).min(chain.len());
+            let start = end.saturating_sub(limit as usize);
+            return self
+                .records(&chain.ids(start, end))
+                .map(|recs| self.visible(recs));
+        }
+
+        let mut current = head.head_turn_id;
+        while current != 0 {
+            let rec = self
+                .turns
+                .get(&current)
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))?;
+            if rec.depth <= depth {
+                break;
+            }
+            current = rec.parent_turn_id;
+        }
+
+        let mut results = Vec::new();
+        while current != 0 && results.len() < limit as usize {
+            let rec = self
+                .turns
+                .get(&current)
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))?
+                .clone();
+            current = rec.parent_turn_id;
+            results.push(rec);
+        }
+        results.reverse();
+        Ok(self.visible(results))
     }
 
     /// Get the first turn (depth=0) of a context, if it exists.
@@ -560,7 +1255,14 @@ impl TurnStore {
         let head = self
             .heads
             .get(&context_id)
-            .ok_or_else(|| StoreError::NotFound("context".into()))?;
+            .ok_or_else(|| StoreError::not_found(NotFoundKind::Context, "context"))?;
+
+        if let Some(chain) = self.chains.get(&context_id) {
+            return match chain.first() {
+                Some(id) => self.get_turn(id),
+                None => Err(StoreError::not_found(NotFoundKind::Turn, "first turn")),
+            };
+        }
 
         // Walk back from head to find the turn with depth=0
         let mut current = head.head_turn_id;
@@ -568,25 +1270,260 @@ impl TurnStore {
             let rec = self
                 .turns
                 .get(&current)
-                .ok_or_else(|| StoreError::NotFound("turn".into()))?;
+                .ok_or_else(|| StoreError::not_found(NotFoundKind::Turn, "turn"))?;
             if rec.depth == 0 {
                 return Ok(rec.clone());
             }
             current = rec.parent_turn_id;
         }
 
-        Err(StoreError::NotFound("first turn".into()))
+        Err(StoreError::not_found(NotFoundKind::Turn, "first turn"))
+    }
+
+    pub fn is_archived(&self, context_id: u64) -> bool {
+        self.archived.contains(&context_id)
+    }
+
+    /// Marks contexts archived and evicts every in-memory turn no longer
+    /// reachable from a non-archived context's head or branch tips. Heads and
+    /// the on-disk index stay, so archived contexts still list; reading one
+    /// needs `restore_context` first. Returns the number of turns evicted.
+    pub fn archive_contexts(&mut self, context_ids: &[u64]) -> Result<usize> {
+        if let Some(missing) = context_ids.iter().find(|id| !self.heads.contains_key(id)) {
+            return Err(StoreError::not_found(
+                NotFoundKind::Context,
+                format!("context {missing}"),
+            ));
+        }
+        self.archived.extend(context_ids);
+        for context_id in context_ids {
+            self.chains.remove(context_id);
+        }
+
+        let mut keep = HashSet::new();
+        for context_id in self.heads.keys() {
+            if !self.archived.contains(context_id) {
+                self.collect_reachable(*context_id, &mut keep);
+            }
+        }
+        let before = self.turns.len();
+        self.turns.retain(|turn_id, _| keep.contains(turn_id));
+        self.turn_meta.retain(|turn_id, _| keep.contains(turn_id));
+        Ok(before - self.turns.len())
+    }
+
+    /// Reloads an archived context's turns from disk. Returns false if the
+    /// context wasn't archived.
+    pub fn restore_context(&mut self, context_id: u64) -> Result<bool> {
+        if !self.archived.remove(&context_id) {
+            return Ok(false);
+        }
+        let head_turn_id = self.heads[&context_id].head_turn_id;
+        self.load_ancestry(head_turn_id)?;
+        for tip in self.branch_tips(context_id) {
+            self.load_ancestry(tip)?;
+        }
+        self.set_chain(context_id, head_turn_id);
+        Ok(true)
+    }
+
+    /// Reads `turn_id` and its evicted ancestors back into memory. Resident
+    /// turns always have resident ancestors, so the walk stops at the first
+    /// one. Unknown ids are left for the caller's lookup to report.
+    pub fn load_ancestry(&mut self, turn_id: u64) -> Result<()> {
+        let mut current = turn_id;
+        while current != 0 && !self.turns.contains_key(&current) {
+            let Some(offset) = self.turn_index.get(&current).copied() else {
+                break;
+            };
+            self.turns_log.seek(SeekFrom::Start(offset))?;
+            let record = read_turn_record(&mut self.turns_log)?;
+            if let Some(offset) = self.meta_index.get(&current).copied() {
+                self.turns_meta.seek(SeekFrom::Start(offset))?;
+                if let Some((_, meta)) = read_meta_record(&mut self.turns_meta)? {
+                    self.turn_meta.insert(current, meta);
+                }
+            }
+            current = record.parent_turn_id;
+            self.turns.insert(record.turn_id, record);
+        }
+        Ok(())
+    }
+
+    /// Adds every resident turn reachable from the context's head or branch
+    /// tips to `into`.
+    fn collect_reachable(&self, context_id: u64, into: &mut HashSet<u64>) {
+        let head_turn_id = self.heads.get(&context_id).map_or(0, |h| h.head_turn_id);
+        let tips = self.branch_tips.get(&context_id).into_iter().flatten();
+        for tip in std::iter::once(&head_turn_id).chain(tips) {
+            let mut current = *tip;
+            while current != 0 && into.insert(current) {
+                match self.turns.get(&current) {
+                    Some(rec) => current = rec.parent_turn_id,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Restores every archived context and reads back any other evicted
+    /// turn, so every turn on disk is resident.
+    pub fn load_all(&mut self) -> Result<()> {
+        let archived: Vec<u64> = self.archived.iter().copied().collect();
+        for context_id in archived {
+            self.restore_context(context_id)?;
+        }
+        let evicted: Vec<u64> = self
+            .turn_index
+            .keys()
+            .filter(|turn_id| !self.turns.contains_key(turn_id))
+            .copied()
+            .collect();
+        for turn_id in evicted {
+            self.load_ancestry(turn_id)?;
+        }
+        Ok(())
+    }
+
+    /// Resident turns, in no particular order.
+    pub fn iter_turns(&self) -> impl Iterator<Item = &TurnRecord> + '_ {
+        self.turns.values()
+    }
+
+    /// Structural problems among resident turns: parents that don't exist,
+    /// depths that don't follow the parent's, and heads or branch tips that
+    /// point at missing turns. Run `load_all` first to cover evicted turns.
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut turn_ids: Vec<u64> = self.turns.keys().copied().collect();
+        turn_ids.sort_unstable();
+        for turn_id in turn_ids {
+            let record = &self.turns[&turn_id];
+            let expected_depth = match record.parent_turn_id {
+                0 => 0,
+                parent_id => match self.turns.get(&parent_id) {
+                    Some(parent) => parent.depth + 1,
+                    None => {
+                        problems.push(format!("turn {turn_id}: parent {parent_id} is missing"));
+                        continue;
+                    }
+                },
+            };
+            if record.depth != expected_depth {
+                problems.push(format!(
+                    "turn {turn_id}: depth {} should be {expected_depth}",
+                    record.depth
+                ));
+            }
+        }
+
+        let mut context_ids: Vec<u64> = self.heads.keys().copied().collect();
+        context_ids.sort_unstable();
+        for context_id in context_ids {
+            let head_turn_id = self.heads[&context_id].head_turn_id;
+            if head_turn_id != 0 && !self.turns.contains_key(&head_turn_id) {
+                problems.push(format!(
+                    "context {context_id}: head turn {head_turn_id} is missing"
+                ));
+            }
+            for tip in self.branch_tips(context_id) {
+                if !self.turns.contains_key(&tip) {
+                    problems.push(format!("context {context_id}: branch tip {tip} is missing"));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Rewrites turns.idx from the offsets found scanning turns.log on open.
+    /// Returns the number of turns indexed.
+    pub fn reindex(&mut self) -> Result<usize> {
+        self.rebuild_index()?;
+        Ok(self.turn_index.len())
     }
 
     pub fn list_recent_contexts(&self, limit: u32) -> Vec<ContextHead> {
         let mut contexts: Vec<ContextHead> = self.heads.values().cloned().collect();
         // Sort by created_at descending (most recent first)
-        contexts.sort_by(|a, b| b.created_at_unix_ms.cmp(&a.created_at_unix_ms));
+        contexts.sort_by_key(|c| std::cmp::Reverse(c.created_at_unix_ms));
         contexts.truncate(limit as usize);
         contexts
     }
 }
 
+/// The records in a turns directory's heads.tbl and turns.log, read without
+/// opening the store, so a corrupt tail is reported instead of truncated.
+#[derive(Debug, Clone, Default)]
+pub struct RecordDump {
+    /// `(file offset, record)` in file order, superseded heads included.
+    pub heads: Vec<(u64, ContextHead)>,
+    pub turns: Vec<(u64, TurnRecord)>,
+    pub heads_tail: Option<UnreadableTail>,
+    pub turns_tail: Option<UnreadableTail>,
+}
+
+/// Where reading a file stopped short of its end, and why.
+#[derive(Debug, Clone)]
+pub struct UnreadableTail {
+    pub offset: u64,
+    pub len: u64,
+    pub reason: String,
+}
+
+/// Read every heads.tbl and turns.log record under `dir` (a store's `turns`
+/// directory) without modifying either file.
+pub fn dump_records(dir: &Path) -> Result<RecordDump> {
+    let (heads, heads_tail) = read_records(&dir.join("heads.tbl"), read_head_record)?;
+    let (turns, turns_tail) = read_records(&dir.join("turns.log"), |reader| {
+        read_turn_record(reader).map(Some)
+    })?;
+    Ok(RecordDump {
+        heads,
+        turns,
+        heads_tail,
+        turns_tail,
+    })
+}
+
+type Records<T> = (Vec<(u64, T)>, Option<UnreadableTail>);
+
+fn read_records<T>(
+    path: &Path,
+    mut read: impl FnMut(&mut BufReader<File>) -> Result<Option<T>>,
+) -> Result<Records<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), None)),
+        Err(e) => return Err(StoreError::Io(e)),
+    };
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut offset = 0u64;
+    while offset < len {
+        let reason = match read(&mut reader) {
+            Ok(Some(record)) => {
+                records.push((offset, record));
+                offset = reader.stream_position()?;
+                continue;
+            }
+            Ok(None) => break,
+            Err(StoreError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                "partial record".to_string()
+            }
+            Err(StoreError::Io(e)) => return Err(StoreError::Io(e)),
+            Err(e) => e.to_string(),
+        };
+        let tail = UnreadableTail {
+            offset,
+            len: len - offset,
+            reason,
+        };
+        return Ok((records, Some(tail)));
+    }
+    Ok((records, None))
+}
+
 #[derive(Debug, Clone)]
 pub struct TurnStoreStats {
     pub turns_total: usize,
@@ -602,8 +1539,63 @@ fn file_len(path: &std::path::PathBuf) -> u64 {
     std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
 }
 
+/// What a keyed append created; see `TurnStore::idempotent_turn`.
+#[derive(Debug, Clone, Copy)]
+struct IdempotencyEntry {
+    turn_id: u64,
+    recorded_at: u64,
+    content_hash: [u8; 32],
+}
+
+fn encode_idempotency_record(
+    context_id: u64,
+    key: &[u8],
+    entry: &IdempotencyEntry,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(8 + 8 + 8 + 32 + 4 + key.len() + 4);
+    buf.write_u64::<LittleEndian>(context_id)?;
+    buf.write_u64::<LittleEndian>(entry.turn_id)?;
+    buf.write_u64::<LittleEndian>(entry.recorded_at)?;
+    buf.extend_from_slice(&entry.content_hash);
+    buf.write_u32::<LittleEndian>(key.len() as u32)?;
+    buf.extend_from_slice(key);
+    let mut hasher = Hasher::new();
+    hasher.update(&buf);
+    buf.write_u32::<LittleEndian>(hasher.finalize())?;
+    Ok(buf)
+}
+
+/// One idempotency.tbl record from the front of `buf`: its length, context
+/// id, key and entry. `None` if it is incomplete or fails its CRC.
+fn parse_idempotency_record(buf: &[u8]) -> Option<(usize, u64, Vec<u8>, IdempotencyEntry)> {
+    let mut cursor = buf;
+    let context_id = cursor.read_u64::<LittleEndian>().ok()?;
+    let turn_id = cursor.read_u64::<LittleEndian>().ok()?;
+    let recorded_at = cursor.read_u64::<LittleEndian>().ok()?;
+    let mut content_hash = [0u8; 32];
+    cursor.read_exact(&mut content_hash).ok()?;
+    let key_len = cursor.read_u32::<LittleEndian>().ok()? as usize;
+    let body_len = 8 + 8 + 8 + 32 + 4 + key_len;
+    if buf.len() < body_len + 4 {
+        return None;
+    }
+    let crc = (&buf[body_len..]).read_u32::<LittleEndian>().ok()?;
+    if crc != crc32fast::hash(&buf[..body_len]) {
+        return None;
+    }
+    let key = buf[body_len - key_len..body_len].to_vec();
+    let entry = IdempotencyEntry {
+        turn_id,
+        recorded_at,
+        content_hash,
+    };
+    Some((body_len + 4, context_id, key, entry))
+}
+
 fn encode_turn_record(record: &TurnRecord) -> Result<Vec<u8>> {
-    let mut buf = Vec::with_capacity(80);
+    let mut buf = Vec::with_capacity(4 + 1 + 80);
+    buf.write_u32::<LittleEndian>(TURN_RECORD_MAGIC)?;
+    buf.write_u8(TURN_RECORD_VERSION)?;
     buf.write_u64::<LittleEndian>(record.turn_id)?;
     buf.write_u64::<LittleEndian>(record.parent_turn_id)?;
     buf.write_u32::<LittleEndian>(record.depth)?;
@@ -613,14 +1605,96 @@ fn encode_turn_record(record: &TurnRecord) -> Result<Vec<u8>> {
     buf.write_u32::<LittleEndian>(record.flags)?;
     buf.write_u64::<LittleEndian>(record.created_at_unix_ms)?;
     let mut hasher = Hasher::new();
-    hasher.update(&buf);
+    hasher.update(&buf[4..]);
     let crc = hasher.finalize();
     buf.write_u32::<LittleEndian>(crc)?;
     Ok(buf)
 }
 
-fn read_turn_record(reader: &mut File) -> Result<TurnRecord> {
-    let turn_id = reader.read_u64::<LittleEndian>()?;
+/// Reads one turns.meta record: turn id, length-prefixed declared type id,
+/// then the type version, encoding, append compression and uncompressed
+/// length, and a length-prefixed inline payload if the length's
+/// `META_INLINE_PAYLOAD` bit is set. `None` at a clean end of file; a partial
+/// record is an `UnexpectedEof` error.
+fn read_meta_record<R: Read>(reader: &mut R) -> Result<Option<(u64, TurnMeta)>> {
+    let turn_id = match reader.read_u64::<LittleEndian>() {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(StoreError::Io(e)),
+    };
+    let len = reader.read_u32::<LittleEndian>()?;
+    let mut buf = vec![0u8; (len & !META_INLINE_PAYLOAD) as usize];
+    reader.read_exact(&mut buf)?;
+    let declared_type_version = reader.read_u32::<LittleEndian>()?;
+    let encoding = reader.read_u32::<LittleEndian>()?;
+    let append_compression = reader.read_u32::<LittleEndian>()?;
+    let uncompressed_len = reader.read_u32::<LittleEndian>()?;
+    let inline_payload = if len & META_INLINE_PAYLOAD != 0 {
+        let payload_len = reader.read_u32::<LittleEndian>()?;
+        let mut payload = Vec::new();
+        reader.take(payload_len as u64).read_to_end(&mut payload)?;
+        if payload.len() != payload_len as usize {
+            return Err(StoreError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        Some(payload)
+    } else {
+        None
+    };
+    let declared_type_id =
+        String::from_utf8(buf).map_err(|_| StoreError::Corrupt("invalid type id utf8".into()))?;
+    Ok(Some((
+        turn_id,
+        TurnMeta {
+            declared_type_id,
+            declared_type_version,
+            encoding,
+            append_compression,
+            uncompressed_len,
+            inline_payload,
+        },
+    )))
+}
+
+/// Reads one turns.log record of either version.
+///
+/// Versioned records start with `TURN_RECORD_MAGIC` and a version byte that
+/// the CRC covers; v1 records start directly with the turn id. As with
+/// heads.tbl, a record that starts with the magic but does not parse as
+/// versioned is retried as v1 before the error is reported.
+fn read_turn_record<R: Read + Seek>(reader: &mut R) -> Result<TurnRecord> {
+    let start = reader.stream_position()?;
+    let lead = reader.read_u32::<LittleEndian>()?;
+    if lead != TURN_RECORD_MAGIC {
+        return read_turn_fields(reader, lead, false);
+    }
+    match read_turn_fields(reader, lead, true) {
+        Ok(record) => Ok(record),
+        Err(err) => {
+            reader.seek(SeekFrom::Start(start + 4))?;
+            read_turn_fields(reader, lead, false).map_err(|_| err)
+        }
+    }
+}
+
+/// Reads the rest of a turns.log record after its `lead` word, as a
+/// versioned record or as v1.
+fn read_turn_fields<R: Read>(reader: &mut R, lead: u32, versioned: bool) -> Result<TurnRecord> {
+    let mut body = Vec::with_capacity(1 + 76);
+    let turn_id = if versioned {
+        let version = reader.read_u8()?;
+        if version != TURN_RECORD_VERSION {
+            // Written by a newer build; refuse to load rather than truncate it.
+            return Err(StoreError::InvalidInput(format!(
+                "unsupported turn record version {version}"
+            )));
+        }
+        body.write_u8(version)?;
+        reader.read_u64::<LittleEndian>()?
+    } else {
+        // v1: the lead word is the low half of the turn id.
+        let high = reader.read_u32::<LittleEndian>()?;
+        (high as u64) << 32 | lead as u64
+    };
     let parent_turn_id = reader.read_u64::<LittleEndian>()?;
     let depth = reader.read_u32::<LittleEndian>()?;
     let codec = reader.read_u32::<LittleEndian>()?;
@@ -631,17 +1705,16 @@ fn read_turn_record(reader: &mut File) -> Result<TurnRecord> {
     let created_at_unix_ms = reader.read_u64::<LittleEndian>()?;
     let crc = reader.read_u32::<LittleEndian>()?;
 
-    let mut buf = Vec::with_capacity(80);
-    buf.write_u64::<LittleEndian>(turn_id)?;
-    buf.write_u64::<LittleEndian>(parent_turn_id)?;
-    buf.write_u32::<LittleEndian>(depth)?;
-    buf.write_u32::<LittleEndian>(codec)?;
-    buf.write_u64::<LittleEndian>(type_tag)?;
-    buf.extend_from_slice(&payload_hash);
-    buf.write_u32::<LittleEndian>(flags)?;
-    buf.write_u64::<LittleEndian>(created_at_unix_ms)?;
+    body.write_u64::<LittleEndian>(turn_id)?;
+    body.write_u64::<LittleEndian>(parent_turn_id)?;
+    body.write_u32::<LittleEndian>(depth)?;
+    body.write_u32::<LittleEndian>(codec)?;
+    body.write_u64::<LittleEndian>(type_tag)?;
+    body.extend_from_slice(&payload_hash);
+    body.write_u32::<LittleEndian>(flags)?;
+    body.write_u64::<LittleEndian>(created_at_unix_ms)?;
     let mut hasher = Hasher::new();
-    hasher.update(&buf);
+    hasher.update(&body);
     let actual_crc = hasher.finalize();
 
     if crc != actual_crc {
@@ -659,3 +1732,442 @@ fn read_turn_record(reader: &mut File) -> Result<TurnRecord> {
         created_at_unix_ms,
     })
 }
+
+fn encode_head_record(head: &ContextHead) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + 1 + 8 + 8 + 4 + 4 + 8 + 8 + 4);
+    buf.write_u32::<LittleEndian>(HEAD_RECORD_MAGIC)?;
+    buf.write_u8(HEAD_RECORD_VERSION)?;
+    buf.write_u64::<LittleEndian>(head.context_id)?;
+    buf.write_u64::<LittleEndian>(head.head_turn_id)?;
+    buf.write_u32::<LittleEndian>(head.head_depth)?;
+    buf.write_u32::<LittleEndian>(head.flags)?;
+    buf.write_u64::<LittleEndian>(head.created_at_unix_ms)?;
+    buf.write_u64::<LittleEndian>(head.turn_count)?;
+    let mut hasher = Hasher::new();
+    hasher.update(&buf[4..]);
+    let crc = hasher.finalize();
+    buf.write_u32::<LittleEndian>(crc)?;
+    Ok(buf)
+}
+
+/// Reads one heads.tbl record, returning `None` at a clean end of file.
+///
+/// Versioned records start with `HEAD_RECORD_MAGIC` and a version byte; v1
+/// records have neither and lack `turn_count`, which is then approximated as
+/// `head_depth + 1` (exact for contexts that never branched). A record that
+/// starts with the magic but does not parse as versioned is retried as v1
+/// before the error is reported, since a v1 context id can collide with it.
+fn read_head_record<R: Read + Seek>(reader: &mut R) -> Result<Option<ContextHead>> {
+    let start = reader.stream_position()?;
+    let lead = match reader.read_u32::<LittleEndian>() {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(StoreError::Io(e)),
+    };
+    if lead != HEAD_RECORD_MAGIC {
+        return read_head_fields(reader, lead, false).map(Some);
+    }
+    match read_head_fields(reader, lead, true) {
+        Ok(head) => Ok(Some(head)),
+        Err(err) => {
+            reader.seek(SeekFrom::Start(start + 4))?;
+            read_head_fields(reader, lead, false)
+                .map(Some)
+                .map_err(|_| err)
+        }
+    }
+}
+
+/// Reads the rest of a heads.tbl record after its `lead` word, as a
+/// versioned record or as v1.
+fn read_head_fields<R: Read>(reader: &mut R, lead: u32, versioned: bool) -> Result<ContextHead> {
+    let mut body = Vec::with_capacity(1 + 8 + 8 + 4 + 4 + 8 + 8);
+    let version = if versioned {
+        let version = reader.read_u8()?;
+        body.write_u8(version)?;
+        let context_id = reader.read_u64::<LittleEndian>()?;
+        body.write_u64::<LittleEndian>(context_id)?;
+        version
+    } else {
+        // v1: the lead word is the low half of the context id.
+        let high = reader.read_u32::<LittleEndian>()?;
+        body.write_u32::<LittleEndian>(lead)?;
+        body.write_u32::<LittleEndian>(high)?;
+        1
+    };
+    if version != 1 && version != HEAD_RECORD_VERSION {
+        // Written by a newer build; refuse to load rather than truncate it.
+        return Err(StoreError::InvalidInput(format!(
+            "unsupported head record version {version}"
+        )));
+    }
+
+    let mut fixed = [0u8; 8 + 4 + 4 + 8];
+    reader.read_exact(&mut fixed)?;
+    body.extend_from_slice(&fixed);
+    let turn_count = if version >= 2 {
+        let turn_count = reader.read_u64::<LittleEndian>()?;
+        body.write_u64::<LittleEndian>(turn_count)?;
+        Some(turn_count)
+    } else {
+        None
+    };
+    let crc = reader.read_u32::<LittleEndian>()?;
+
+    let mut hasher = Hasher::new();
+    hasher.update(&body);
+    if crc != hasher.finalize() {
+        return Err(StoreError::Corrupt("head crc mismatch".into()));
+    }
+
+    let mut cursor = &body[if version >= 2 { 1 } else { 0 }..];
+    let context_id = cursor.read_u64::<LittleEndian>()?;
+    let head_turn_id = cursor.read_u64::<LittleEndian>()?;
+    let head_depth = cursor.read_u32::<LittleEndian>()?;
+    let flags = cursor.read_u32::<LittleEndian>()?;
+    let created_at_unix_ms = cursor.read_u64::<LittleEndian>()?;
+    let turn_count = turn_count.unwrap_or(if head_turn_id == 0 {
+        0
+    } else {
+        head_depth as u64 + 1
+    });
+
+    Ok(ContextHead {
+        context_id,
+        head_turn_id,
+        head_depth,
+        created_at_unix_ms,
+        flags,
+        turn_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_turn() -> TurnRecord {
+        TurnRecord {
+            turn_id: 42,
+            parent_turn_id: 41,
+            depth: 3,
+            codec: 1,
+            type_tag: 0,
+            payload_hash: [9u8; 32],
+            flags: 0,
+            created_at_unix_ms: 1_700_000_000_000,
+        }
+    }
+
+    fn with_crc(mut buf: Vec<u8>) -> Vec<u8> {
+        let crc = crc32fast::hash(&buf);
+        buf.write_u32::<LittleEndian>(crc).unwrap();
+        buf
+    }
+
+    fn encode_turn_record_v1(record: &TurnRecord) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u64::<LittleEndian>(record.turn_id).unwrap();
+        buf.write_u64::<LittleEndian>(record.parent_turn_id)
+            .unwrap();
+        buf.write_u32::<LittleEndian>(record.depth).unwrap();
+        buf.write_u32::<LittleEndian>(record.codec).unwrap();
+        buf.write_u64::<LittleEndian>(record.type_tag).unwrap();
+        buf.extend_from_slice(&record.payload_hash);
+        buf.write_u32::<LittleEndian>(record.flags).unwrap();
+        buf.write_u64::<LittleEndian>(record.created_at_unix_ms)
+            .unwrap();
+        with_crc(buf)
+    }
+
+    fn append(store: &mut TurnStore, context_id: u64, parent_turn_id: u64) -> TurnRecord {
+        store
+            .append_turn(
+                context_id,
+                parent_turn_id,
+                [0u8; 32],
+                1,
+                "com.example.Test".into(),
+                1,
+                0,
+                0,
+                None,
+            )
+            .unwrap()
+    }
+
+    fn chain_depths(store: &TurnStore, context_id: u64) -> Vec<u32> {
+        store
+            .get_last(context_id, u32::MAX)
+            .unwrap()
+            .iter()
+            .map(|t| t.depth)
+            .collect()
+    }
+
+    #[test]
+    fn depth_lookups_use_the_chain_index_not_a_parent_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = TurnStore::open(dir.path()).unwrap();
+        let ctx = store.create_context(0).unwrap().context_id;
+        let turns: Vec<u64> = (0..10_000)
+            .map(|_| append(&mut store, ctx, 0).turn_id)
+            .collect();
+
+        // Punch a hole mid-chain: walking parents from the head to any
+        // shallower depth would hit it and fail.
+        store.turns.remove(&turns[5_000]);
+        let ids =
+            |records: Vec<TurnRecord>| -> Vec<u64> { records.iter().map(|t| t.turn_id).collect() };
+        assert_eq!(store.get_first_turn(ctx).unwrap().turn_id, turns[0]);
+        assert_eq!(
+            ids(store.get_at_depth(ctx, 2_000, 2).unwrap()),
+            vec![turns[1_999], turns[2_000]]
+        );
+        assert_eq!(
+            ids(store.get_before(ctx, turns[3_000], 2).unwrap()),
+            vec![turns[2_998], turns[2_999]]
+        );
+    }
+
+    #[test]
+    fn forks_share_their_base_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, forks) = {
+            let mut store = TurnStore::open(dir.path()).unwrap();
+            let base = store.create_context(0).unwrap().context_id;
+            for _ in 0..1_000 {
+                append(&mut store, base, 0);
+            }
+            // Fork at the head while the base keeps growing, the pattern that
+            // re-shares the base's newest ids on every fork.
+            let mut forks = Vec::new();
+            for _ in 0..100 {
+                let head = store.get_head(base).unwrap().head_turn_id;
+                let fork = store.fork_context(head).unwrap().context_id;
+                append(&mut store, fork, 0);
+                append(&mut store, base, 0);
+                forks.push(fork);
+            }
+            for (i, fork) in forks.iter().enumerate() {
+                assert_eq!(store.chains[fork].own.len(), 1);
+                assert_eq!(chain_depths(&store, *fork).len(), 1_000 + i + 1);
+            }
+            assert_eq!(chain_depths(&store, base).len(), 1_100);
+            assert!(store.chains[&base].levels <= CHAIN_MAX_LEVELS);
+            (base, forks)
+        };
+
+        let store = TurnStore::open(dir.path()).unwrap();
+        for (i, fork) in forks.iter().enumerate() {
+            assert_eq!(store.chains[fork].own.len(), 1);
+            let depths = chain_depths(&store, *fork);
+            assert_eq!(depths, (0..(1_000 + i as u32 + 1)).collect::<Vec<_>>());
+            assert_eq!(
+                store.get_at_depth(*fork, 500, 1).unwrap()[0].turn_id,
+                store.get_at_depth(base, 500, 1).unwrap()[0].turn_id
+            );
+        }
+        assert_eq!(chain_depths(&store, base).len(), 1_100);
+    }
+
+    #[test]
+    fn depth_index_follows_branches_and_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ctx, fork, turns) = {
+            let mut store = TurnStore::open(dir.path()).unwrap();
+            let ctx = store.create_context(0).unwrap().context_id;
+            let turns: Vec<TurnRecord> = (0..5).map(|_| append(&mut store, ctx, 0)).collect();
+
+            // Branch from depth 1: a side branch leaves the head chain alone.
+            let branch = append(&mut store, ctx, turns[1].turn_id);
+            assert_eq!(chain_depths(&store, ctx), vec![0, 1, 2, 3, 4]);
+            assert_eq!(
+                store.get_at_depth(ctx, 2, 1).unwrap()[0].turn_id,
+                turns[2].turn_id
+            );
+
+            // Paging from the branch tip, which is off the chain, walks parents.
+            let before = store.get_before(ctx, branch.turn_id, 10).unwrap();
+            let ids: Vec<u64> = before.iter().map(|t| t.turn_id).collect();
+            assert_eq!(ids, vec![turns[0].turn_id, turns[1].turn_id]);
+
+            let fork = store.fork_context(turns[3].turn_id).unwrap().context_id;
+            append(&mut store, fork, 0);
+            assert_eq!(chain_depths(&store, fork), vec![0, 1, 2, 3, 4]);
+            (ctx, fork, turns)
+        };
+
+        let store = TurnStore::open(dir.path()).unwrap();
+        assert_eq!(chain_depths(&store, ctx), vec![0, 1, 2, 3, 4]);
+        assert_eq!(chain_depths(&store, fork), vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            store.get_first_turn(fork).unwrap().turn_id,
+            turns[0].turn_id
+        );
+    }
+
+    #[test]
+    fn id_bases_keep_two_stores_disjoint() {
+        let (a_dir, b_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut a = TurnStore::open(a_dir.path()).unwrap();
+        let mut b = TurnStore::open_with_id_base(b_dir.path(), 1 << 40, 1 << 20).unwrap();
+
+        let mut turn_ids = HashSet::new();
+        let mut context_ids = HashSet::new();
+        for store in [&mut a, &mut b] {
+            for _ in 0..3 {
+                let ctx = store.create_context(0).unwrap().context_id;
+                assert!(context_ids.insert(ctx), "context {ctx} allocated twice");
+                for _ in 0..4 {
+                    let turn = append(store, ctx, 0);
+                    assert!(
+                        turn_ids.insert(turn.turn_id),
+                        "turn {} allocated twice",
+                        turn.turn_id
+                    );
+                }
+            }
+        }
+        assert_eq!(turn_ids.len(), 24);
+        assert!(context_ids.contains(&(1 << 20)) && turn_ids.contains(&(1 << 40)));
+
+        // A reopened store resumes after its own ids, whatever base it gets.
+        drop(b);
+        let mut b = TurnStore::open(b_dir.path()).unwrap();
+        let ctx = b.create_context(0).unwrap().context_id;
+        assert_eq!(ctx, (1 << 20) + 3);
+        assert_eq!(append(&mut b, ctx, 0).turn_id, (1 << 40) + 12);
+    }
+
+    #[test]
+    fn reads_v1_and_v2_turn_records() {
+        let turn = sample_turn();
+        let v1 = encode_turn_record_v1(&turn);
+        assert_eq!(v1.len(), 80);
+        let v2 = encode_turn_record(&turn).unwrap();
+        assert_eq!(v2.len(), 85);
+        assert_eq!(v2[4], TURN_RECORD_VERSION);
+
+        // A log written by an older build and appended to by a newer one.
+        let mut log = Cursor::new([v1, v2].concat());
+        for _ in 0..2 {
+            let read = read_turn_record(&mut log).unwrap();
+            assert_eq!(read.turn_id, 42);
+            assert_eq!(read.parent_turn_id, 41);
+            assert_eq!(read.depth, 3);
+            assert_eq!(read.payload_hash, [9u8; 32]);
+            assert_eq!(read.created_at_unix_ms, 1_700_000_000_000);
+        }
+    }
+
+    #[test]
+    fn reads_v1_turn_record_whose_id_collides_with_magic() {
+        // High half 2: the byte after the magic reads as the current version,
+        // so the versioned parse gets as far as the CRC check.
+        let turn = TurnRecord {
+            turn_id: 2u64 << 32 | TURN_RECORD_MAGIC as u64,
+            ..sample_turn()
+        };
+        let v1 = encode_turn_record_v1(&turn);
+        let v2 = encode_turn_record(&sample_turn()).unwrap();
+
+        let mut log = Cursor::new([v1.clone(), v2].concat());
+        assert_eq!(read_turn_record(&mut log).unwrap().turn_id, turn.turn_id);
+        assert_eq!(read_turn_record(&mut log).unwrap().turn_id, 42);
+
+        // Also when the colliding record is the last one in the log.
+        let read = read_turn_record(&mut Cursor::new(v1)).unwrap();
+        assert_eq!(read.turn_id, turn.turn_id);
+        assert_eq!(read.parent_turn_id, 41);
+
+        // And through the store's own load, which truncates what it can't read.
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut store = TurnStore::open(dir.path()).unwrap();
+            let ctx = store.create_context(0).unwrap().context_id;
+            append(&mut store, ctx, 0);
+        }
+        let log_path = dir.path().join("turns.log");
+        let mut bytes = encode_turn_record_v1(&turn);
+        bytes.extend(std::fs::read(&log_path).unwrap());
+        std::fs::write(&log_path, &bytes).unwrap();
+        let store = TurnStore::open(dir.path()).unwrap();
+        assert!(store.get_turn(turn.turn_id).is_ok());
+        assert_eq!(
+            std::fs::metadata(&log_path).unwrap().len(),
+            bytes.len() as u64
+        );
+    }
+
+    #[test]
+    fn reads_v1_head_record_with_derived_turn_count() {
+        let mut buf = Vec::new();
+        buf.write_u64::<LittleEndian>(5).unwrap();
+        buf.write_u64::<LittleEndian>(42).unwrap();
+        buf.write_u32::<LittleEndian>(3).unwrap();
+        buf.write_u32::<LittleEndian>(0).unwrap();
+        buf.write_u64::<LittleEndian>(1_700_000_000_000).unwrap();
+        let v1 = with_crc(buf);
+
+        let head = read_head_record(&mut Cursor::new(v1)).unwrap().unwrap();
+        assert_eq!(head.context_id, 5);
+        assert_eq!(head.head_turn_id, 42);
+        assert_eq!(head.head_depth, 3);
+        assert_eq!(head.created_at_unix_ms, 1_700_000_000_000);
+        assert_eq!(head.turn_count, 4);
+    }
+
+    #[test]
+    fn reads_v1_head_record_whose_context_id_collides_with_magic() {
+        // High half 2: the byte after the magic reads as the current version,
+        // so the versioned parse gets as far as the CRC check.
+        let context_id = 2u64 << 32 | HEAD_RECORD_MAGIC as u64;
+        let mut buf = Vec::new();
+        buf.write_u64::<LittleEndian>(context_id).unwrap();
+        buf.write_u64::<LittleEndian>(42).unwrap();
+        buf.write_u32::<LittleEndian>(3).unwrap();
+        buf.write_u32::<LittleEndian>(0).unwrap();
+        buf.write_u64::<LittleEndian>(1_700_000_000_000).unwrap();
+        let v1 = with_crc(buf);
+        let v2 = encode_head_record(&ContextHead {
+            context_id: 8,
+            head_turn_id: 43,
+            head_depth: 0,
+            created_at_unix_ms: 1_700_000_000_000,
+            flags: 0,
+            turn_count: 1,
+        })
+        .unwrap();
+
+        // The colliding record is followed by another, and also ends the file.
+        let mut log = Cursor::new([v1.clone(), v2].concat());
+        let head = read_head_record(&mut log).unwrap().unwrap();
+        assert_eq!((head.context_id, head.head_turn_id), (context_id, 42));
+        let next = read_head_record(&mut log).unwrap().unwrap();
+        assert_eq!(next.context_id, 8);
+        assert!(read_head_record(&mut log).unwrap().is_none());
+
+        let head = read_head_record(&mut Cursor::new(v1)).unwrap().unwrap();
+        assert_eq!(head.context_id, context_id);
+    }
+
+    #[test]
+    fn rejects_unknown_record_versions_and_bad_crc() {
+        let mut v2 = encode_turn_record(&sample_turn()).unwrap();
+        v2[4] = 9;
+        assert!(matches!(
+            read_turn_record(&mut Cursor::new(v2.clone())),
+            Err(StoreError::InvalidInput(_))
+        ));
+
+        v2[4] = TURN_RECORD_VERSION;
+        let last = v2.len() - 1;
+        v2[last] ^= 0xff;
+        assert!(matches!(
+            read_turn_record(&mut Cursor::new(v2)),
+            Err(StoreError::Corrupt(_))
+        ));
+    }
+}
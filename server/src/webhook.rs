@@ -0,0 +1,205 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pushes store events to an HTTP endpoint, for consumers that can't hold an
+//! SSE connection open.
+//!
+//! An [`EventBus`] subscriber filters events by type and queues them; a
+//! delivery thread POSTs each one with retries. The queue is bounded, so a
+//! downstream that stays down costs dropped events (counted in
+//! `webhook.dropped_total`) rather than unbounded memory.
+
+use std::collections::HashSet;
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::events::EventBus;
+use crate::metrics::Metrics;
+
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    pub url: String,
+    /// SSE event names to deliver, e.g. `turn_appended`; empty delivers all.
+    pub event_types: HashSet<String>,
+    /// Events waiting for delivery before new ones are dropped.
+    pub queue_capacity: usize,
+    /// Tries per event, including the first.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubles on each further retry.
+    pub retry_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl WebhookOptions {
+    /// `None` unless `CXDB_WEBHOOK_URL` is set.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("CXDB_WEBHOOK_URL")
+            .ok()
+            .filter(|v| !v.is_empty())?;
+        let env_u64 = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let event_types = std::env::var("CXDB_WEBHOOK_EVENTS")
+            .map(|v| {
+                v.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            url,
+            event_types,
+            queue_capacity: env_u64("CXDB_WEBHOOK_QUEUE", 1024).max(1) as usize,
+            max_attempts: env_u64("CXDB_WEBHOOK_MAX_ATTEMPTS", 5).max(1) as u32,
+            retry_backoff: Duration::from_millis(env_u64("CXDB_WEBHOOK_RETRY_MS", 500)),
+            timeout: Duration::from_millis(env_u64("CXDB_WEBHOOK_TIMEOUT_MS", 5000)),
+        })
+    }
+}
+
+/// Subscribe to `event_bus` and deliver matching events to the webhook until
+/// the bus is dropped. Returns the delivery thread.
+pub fn spawn_webhook(
+    event_bus: &EventBus,
+    options: WebhookOptions,
+    metrics: Arc<Metrics>,
+) -> JoinHandle<()> {
+    let subscriber = event_bus.subscribe();
+    let (tx, rx) = mpsc::sync_channel::<(&'static str, String)>(options.queue_capacity);
+
+    let event_types = options.event_types.clone();
+    let queue_metrics = Arc::clone(&metrics);
+    thread::spawn(move || {
+        while let Some(event) = subscriber.recv() {
+            let (event_type, data) = event.to_sse();
+            if !event_types.is_empty() && !event_types.contains(event_type) {
+                continue;
+            }
+            match tx.try_send((event_type, data)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => queue_metrics.record_webhook_delivery(false),
+                Err(TrySendError::Disconnected(_)) => break,
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+        for (event_type, data) in rx {
+            let delivered = deliver(&agent, &options, event_type, &data);
+            metrics.record_webhook_delivery(delivered);
+        }
+    })
+}
+
+/// POST one event, retrying transport errors, 429s and 5xx responses.
+/// Other 4xx responses won't improve on retry and are given up at once.
+fn deliver(agent: &ureq::Agent, options: &WebhookOptions, event_type: &str, data: &str) -> bool {
+    let mut backoff = options.retry_backoff;
+    for attempt in 1..=options.max_attempts {
+        let error = match agent
+            .post(&options.url)
+            .set("Content-Type", "application/json")
+            .set("X-Cxdb-Event", event_type)
+            .send_string(data)
+        {
+            Ok(_) => return true,
+            Err(error) => error,
+        };
+        let retryable = match &error {
+            ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+            ureq::Error::Transport(_) => true,
+        };
+        if !retryable || attempt == options.max_attempts {
+            tracing::warn!(
+                event_type,
+                attempts = attempt,
+                "dropping webhook event: {error}"
+            );
+            return false;
+        }
+        thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::StoreEvent;
+
+    fn turn_appended(turn_id: u64) -> StoreEvent {
+        StoreEvent::TurnAppended {
+            context_id: "1".to_string(),
+            turn_id: turn_id.to_string(),
+            parent_turn_id: "0".to_string(),
+            depth: 0,
+            declared_type_id: None,
+            declared_type_version: None,
+        }
+    }
+
+    #[test]
+    fn delivers_filtered_events_with_retries() {
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("mock server");
+        let url = format!("http://{}/hook", server.server_addr());
+        let bus = EventBus::new();
+        let metrics = Arc::new(Metrics::new(std::path::PathBuf::from("/tmp")));
+        let options = WebhookOptions {
+            url,
+            event_types: HashSet::from(["turn_appended".to_string()]),
+            queue_capacity: 16,
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+        };
+        spawn_webhook(&bus, options, Arc::clone(&metrics));
+
+        bus.publish(StoreEvent::ClientConnected {
+            session_id: "7".to_string(),
+            client_tag: "filtered-out".to_string(),
+        });
+        bus.publish(turn_appended(41));
+
+        let mut received = Vec::new();
+        for status in [503, 200] {
+            let mut request = server
+                .recv_timeout(Duration::from_secs(5))
+                .expect("recv")
+                .expect("webhook request");
+            let event_type = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("X-Cxdb-Event"))
+                .map(|h| h.value.to_string());
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            received.push((event_type, body));
+            request.respond(tiny_http::Response::empty(status)).unwrap();
+        }
+
+        // The 503 was retried with the same payload; the filtered event
+        // never arrived.
+        assert_eq!(received[0], received[1]);
+        assert_eq!(received[0].0.as_deref(), Some("turn_appended"));
+        let body: serde_json::Value = serde_json::from_str(&received[0].1).unwrap();
+        assert_eq!(body["turn_id"], "41");
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while metrics.webhook_counts().0 == 0 && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(metrics.webhook_counts(), (1, 0));
+        assert!(server
+            .recv_timeout(Duration::from_millis(50))
+            .unwrap()
+            .is_none());
+    }
+}
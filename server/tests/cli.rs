@@ -0,0 +1,187 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::{Command, Output};
+
+use cxdb_server::store::Store;
+use tempfile::tempdir;
+
+fn cxdb_server(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cxdb-server"))
+        .args(args)
+        .output()
+        .expect("run cxdb-server")
+}
+
+fn write_fixture(dir: &Path) {
+    let mut store = Store::open(dir).expect("open store");
+    let ctx = store.create_context(0).expect("create context");
+    let mut parent = 0;
+    for text in ["first turn", "second turn"] {
+        let payload = text.as_bytes();
+        let hash = blake3::hash(payload);
+        let (turn, _) = store
+            .append_turn(
+                ctx.context_id,
+                parent,
+                "com.example.Test".to_string(),
+                1,
+                1,
+                0,
+                payload.len() as u32,
+                *hash.as_bytes(),
+                payload,
+            )
+            .expect("append");
+        parent = turn.turn_id;
+    }
+}
+
+#[test]
+fn verify_subcommand_reports_a_corrupted_blob() {
+    let dir = tempdir().expect("tempdir");
+    write_fixture(dir.path());
+    let data_dir = dir.path().to_str().unwrap();
+
+    let output = cxdb_server(&["verify", "--data-dir", data_dir]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "verify failed: {stdout}");
+    assert!(
+        stdout.contains("verify: 2 turns, 2 blobs checked"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("0 problems"), "{stdout}");
+
+    // Flip the last byte of the pack, inside the second payload.
+    let mut pack = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(dir.path().join("blobs").join("blobs.pack"))
+        .expect("open pack");
+    pack.seek(SeekFrom::End(-1)).unwrap();
+    pack.write_all(b"!").unwrap();
+    drop(pack);
+
+    let output = cxdb_server(&["verify", &format!("--data-dir={data_dir}")]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("problem: turn 2: payload"), "{stdout}");
+    assert!(stdout.contains("1 problems"), "{stdout}");
+
+    let output = cxdb_server(&["frobnicate"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage: cxdb-server"));
+}
+
+#[test]
+fn dump_prints_records_and_reports_a_torn_tail() {
+    let dir = tempdir().expect("tempdir");
+    write_fixture(dir.path());
+    // A second, empty context that `--context 1` leaves out.
+    Store::open(dir.path())
+        .expect("open store")
+        .create_context(0)
+        .expect("create context");
+    // Half a record, as a crash mid-append would leave.
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(dir.path().join("turns").join("turns.log"))
+        .expect("open turns.log");
+    log.write_all(&[0x43, 0x58, 0x54, 0x52, 2, 9]).unwrap();
+    drop(log);
+    let data_dir = dir.path().to_str().unwrap();
+
+    let output = cxdb_server(&["dump", "--data-dir", data_dir, "--context", "1"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    let turn_rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("turns.log:"))
+        .skip(2)
+        .map(|line| line.split_whitespace().collect())
+        .filter(|fields: &Vec<&str>| fields.len() == 7)
+        .collect();
+    // turn, parent and depth columns of the context's chain.
+    let chain: Vec<&[&str]> = turn_rows.iter().map(|row| &row[1..4]).collect();
+    assert_eq!(chain, [["1", "0", "0"], ["2", "1", "1"]], "{stdout}");
+    // Only context 1's heads: its creation and one record per append.
+    assert!(stdout.starts_with("heads.tbl: 3 records"), "{stdout}");
+    assert!(
+        stdout.contains("turns.log: unreadable from offset") && stdout.contains("partial record"),
+        "{stdout}"
+    );
+
+    // Dumping read nothing back into place: the torn bytes are still there.
+    let output = cxdb_server(&["dump", "--data-dir", data_dir]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(6 bytes): partial record"), "{stdout}");
+    assert!(stdout.contains("heads.tbl: 4 records"), "{stdout}");
+}
+
+#[test]
+fn import_loads_jsonl_into_one_context_per_group() {
+    let dir = tempdir().expect("tempdir");
+    let data_dir = dir.path().join("fresh");
+    let file = dir.path().join("turns.jsonl");
+    std::fs::write(
+        &file,
+        [
+            r#"{"context": "conv-a", "type_id": "com.example.Message", "type_version": 1, "data": {"role": "user", "text": "hi"}}"#,
+            r#"{"context": 7, "type_id": "com.example.Message", "type_version": 1, "payload": {"text": "other"}}"#,
+            r#"{"context": "conv-a", "type_id": "com.example.Message", "type_version": 1, "data": {"role": "assistant", "text": "hello"}}"#,
+            "",
+            r#"{"context": "conv-a", "type_id": "#,
+            r#"{"context": "conv-a", "type_version": 1, "data": {}}"#,
+            r#"{"context": "conv-a", "type_id": "com.example.Message", "type_version": 1, "data": {"text": "bye"}}"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+
+    let output = cxdb_server(&[
+        "import",
+        "--data-dir",
+        data_dir.to_str().unwrap(),
+        "--file",
+        file.to_str().unwrap(),
+    ]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(
+        stdout.contains("import: 2 contexts created, 4 turns appended, 2 lines rejected"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("rejected line 5: invalid json"), "{stdout}");
+    assert!(
+        stdout.contains("rejected line 6: missing type_id"),
+        "{stdout}"
+    );
+
+    let mut store = Store::open(&data_dir).expect("open store");
+    let mut contexts = store.list_recent_contexts(10);
+    contexts.sort_by_key(|c| c.context_id);
+    let counts: Vec<u64> = contexts.iter().map(|c| c.turn_count).collect();
+    assert_eq!(counts, [3, 1]);
+
+    let turns = store
+        .get_last(contexts[0].context_id, 10, true)
+        .expect("get_last");
+    let texts: Vec<String> = turns
+        .iter()
+        .map(|turn| {
+            let payload = turn.payload.as_deref().expect("payload");
+            let value = rmpv::decode::read_value(&mut &payload[..]).expect("msgpack");
+            let text = value
+                .as_map()
+                .and_then(|map| map.iter().find(|(k, _)| k.as_str() == Some("text")))
+                .and_then(|(_, v)| v.as_str())
+                .expect("text field");
+            text.to_string()
+        })
+        .collect();
+    assert_eq!(texts, ["hi", "hello", "bye"]);
+    assert_eq!(turns[0].meta.declared_type_id, "com.example.Message");
+}
@@ -5,7 +5,10 @@
 //!
 //! End-to-end tests covering the CQL parser, indexes, and executor.
 
-use cxdb_server::cql::{execute, parse, Expression, Operator, SecondaryIndexes, Value};
+use cxdb_server::cql::ast::CqlErrorType;
+use cxdb_server::cql::{
+    execute, execute_with_limits, parse, CqlLimits, Expression, Operator, SecondaryIndexes, Value,
+};
 use cxdb_server::store::{ContextMetadata, Provenance};
 use std::collections::HashSet;
 
@@ -23,6 +26,7 @@ fn create_test_indexes() -> SecondaryIndexes {
             service_name: Some("dotrunner".to_string()),
             ..Default::default()
         }),
+        derived_title: None,
     };
     indexes.add_context(1, Some(&meta1), 1000, 5);
 
@@ -36,6 +40,7 @@ fn create_test_indexes() -> SecondaryIndexes {
             service_name: Some("gen".to_string()),
             ..Default::default()
         }),
+        derived_title: None,
     };
     indexes.add_context(2, Some(&meta2), 2000, 3);
 
@@ -49,6 +54,7 @@ fn create_test_indexes() -> SecondaryIndexes {
             service_name: Some("dotrunner".to_string()),
             ..Default::default()
         }),
+        derived_title: None,
     };
     indexes.add_context(3, Some(&meta3), 3000, 10);
 
@@ -62,6 +68,7 @@ fn create_test_indexes() -> SecondaryIndexes {
             service_name: Some("generator".to_string()),
             ..Default::default()
         }),
+        derived_title: None,
     };
     indexes.add_context(4, Some(&meta4), 4000, 2);
 
@@ -75,6 +82,7 @@ fn create_test_indexes() -> SecondaryIndexes {
             service_name: Some("dot-test".to_string()),
             ..Default::default()
         }),
+        derived_title: None,
     };
     indexes.add_context(5, Some(&meta5), 5000, 7);
 
@@ -292,6 +300,41 @@ fn test_execute_not_query() {
     assert!(result.contains(&5));
 }
 
+#[test]
+fn test_execute_rejects_queries_past_limits() {
+    let indexes = create_test_indexes();
+    let live_contexts = HashSet::new();
+    let limits = CqlLimits {
+        max_clauses: 4,
+        ..CqlLimits::unlimited()
+    };
+
+    let wide = (0..5)
+        .map(|i| format!(r#"tag ^= "a{i}""#))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let query = parse(&wide).unwrap();
+    let err = execute_with_limits(&query.ast, &indexes, &live_contexts, &limits).unwrap_err();
+    assert!(matches!(err.error_type, CqlErrorType::LimitExceeded));
+    assert!(err.message.contains("5 clauses"), "{}", err.message);
+
+    // IN list items count as clauses too.
+    let query = parse(r#"tag IN ("a", "b", "c", "d", "e")"#).unwrap();
+    assert!(execute_with_limits(&query.ast, &indexes, &live_contexts, &limits).is_err());
+
+    let query = parse(r#"tag IN ("a", "b") OR user = "jay""#).unwrap();
+    assert!(execute_with_limits(&query.ast, &indexes, &live_contexts, &limits).is_ok());
+
+    // NOT expands to every other context.
+    let limits = CqlLimits {
+        max_candidates: 3,
+        ..CqlLimits::unlimited()
+    };
+    let query = parse(r#"NOT tag = "test""#).unwrap();
+    let err = execute_with_limits(&query.ast, &indexes, &live_contexts, &limits).unwrap_err();
+    assert!(matches!(err.error_type, CqlErrorType::LimitExceeded));
+}
+
 #[test]
 fn test_execute_prefix_query() {
     let indexes = create_test_indexes();
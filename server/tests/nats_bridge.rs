@@ -0,0 +1,65 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Needs `--features nats` and a broker at `CXDB_TEST_NATS_URL`, e.g.
+//! `docker run -p 4222:4222 nats` then
+//! `CXDB_TEST_NATS_URL=nats://127.0.0.1:4222 cargo test --features nats`.
+#![cfg(feature = "nats")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cxdb_server::events::{EventBus, StoreEvent};
+use cxdb_server::metrics::Metrics;
+use cxdb_server::nats_bridge::{spawn_nats_bridge, NatsBridgeOptions, DEFAULT_QUEUE_CAPACITY};
+use futures::StreamExt;
+
+#[test]
+fn events_are_published_under_their_type_subject() {
+    let Ok(url) = std::env::var("CXDB_TEST_NATS_URL") else {
+        eprintln!("skipping: CXDB_TEST_NATS_URL is not set");
+        return;
+    };
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    let client = runtime
+        .block_on(async_nats::connect(url.as_str()))
+        .expect("connect test client");
+    let mut messages = runtime
+        .block_on(client.subscribe("cxdb-test.events.>"))
+        .expect("subscribe");
+    runtime.block_on(client.flush()).expect("flush");
+
+    let bus = EventBus::new();
+    let dir = tempfile::tempdir().expect("tempdir");
+    spawn_nats_bridge(
+        &bus,
+        NatsBridgeOptions {
+            url,
+            subject_prefix: "cxdb-test.events".to_string(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        },
+        Arc::new(Metrics::new(dir.path().to_path_buf())),
+    );
+    let event = StoreEvent::ClientConnected {
+        session_id: "9".to_string(),
+        client_tag: "nats-test".to_string(),
+    };
+
+    // The bridge connects in the background; republish until it is up.
+    let message = runtime.block_on(async {
+        loop {
+            bus.publish(event.clone());
+            if let Ok(Some(message)) =
+                tokio::time::timeout(Duration::from_millis(200), messages.next()).await
+            {
+                break message;
+            }
+        }
+    });
+    assert_eq!(
+        message.subject.as_str(),
+        "cxdb-test.events.client_connected"
+    );
+    let body: serde_json::Value = serde_json::from_slice(&message.payload).expect("json");
+    assert_eq!(body["client_tag"], "nats-test");
+}
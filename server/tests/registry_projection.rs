@@ -1,10 +1,14 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use cxdb_server::projection::project_msgpack;
-use cxdb_server::projection::{BytesRender, EnumRender, RenderOptions, TimeRender, U64Format};
-use cxdb_server::registry::Registry;
+use cxdb_server::error::StoreError;
+use cxdb_server::projection::{project_msgpack, project_protobuf, proto_schema};
+use cxdb_server::projection::{
+    BytesRender, EnumRender, RenderOptions, TimeRender, U64Format, DEFAULT_MAX_DEPTH,
+};
+use cxdb_server::registry::{json_schema, ItemsSpec, Registry, RegistryOptions, RendererFilter};
 use rmpv::Value;
+use serde_json::json;
 use tempfile::tempdir;
 
 fn default_options() -> RenderOptions {
@@ -14,6 +18,9 @@ fn default_options() -> RenderOptions {
         enum_render: EnumRender::Label,
         time_render: TimeRender::Iso,
         include_unknown: true,
+        max_depth: DEFAULT_MAX_DEPTH,
+        strict: false,
+        bundle_id: None,
     }
 }
 
@@ -68,6 +75,9 @@ fn registry_ingest_and_project() {
         enum_render: EnumRender::Label,
         time_render: TimeRender::Iso,
         include_unknown: true,
+        max_depth: DEFAULT_MAX_DEPTH,
+        strict: false,
+        bundle_id: None,
     };
 
     let projection = project_msgpack(&buf, desc, &registry, &options).expect("project");
@@ -334,6 +344,377 @@ fn get_all_renderers() {
     assert_eq!(c_renderer.component.as_ref().unwrap(), "CWrapper");
 }
 
+#[test]
+fn renderer_filters_narrow_by_prefix_and_builtin() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "renderer-filter-test",
+      "types": {
+        "chat:Message": {
+          "versions": { "1": { "fields": {}, "renderer": { "esm_url": "builtin:Message" } } }
+        },
+        "chat:ToolCall": {
+          "versions": { "1": { "fields": {}, "renderer": { "esm_url": "https://cdn.example.com/tool.js" } } }
+        },
+        "agent:Plan": {
+          "versions": { "1": { "fields": {}, "renderer": { "esm_url": "builtin:Plan" } } }
+        },
+        "agent:Step": {
+          "versions": { "1": { "fields": {} } }
+        }
+      },
+      "enums": {}
+    }
+    "#;
+    registry
+        .put_bundle("renderer-filter-test", bundle.as_bytes())
+        .expect("put bundle");
+
+    let ids = |filter: RendererFilter| -> Vec<String> {
+        registry.get_renderers(&filter).into_keys().collect()
+    };
+
+    assert_eq!(
+        ids(RendererFilter::default()),
+        vec!["agent:Plan", "chat:Message", "chat:ToolCall"]
+    );
+    assert_eq!(
+        ids(RendererFilter {
+            type_prefix: Some("chat:".into()),
+            ..RendererFilter::default()
+        }),
+        vec!["chat:Message", "chat:ToolCall"]
+    );
+    assert_eq!(
+        ids(RendererFilter {
+            type_prefix: Some("chat:".into()),
+            builtin_only: true,
+        }),
+        vec!["chat:Message"]
+    );
+}
+
+#[test]
+fn strict_registry_requires_integrity_for_remote_renderers() {
+    let bundle = |bundle_id: &str, renderer: &str| {
+        format!(
+            r#"{{
+              "registry_version": 1,
+              "bundle_id": "{bundle_id}",
+              "types": {{
+                "chat:Chart": {{
+                  "versions": {{ "1": {{ "fields": {{}}, "renderer": {renderer} }} }}
+                }}
+              }},
+              "enums": {{}}
+            }}"#
+        )
+    };
+    let remote = r#"{ "esm_url": "https://cdn.example.com/chart.js" }"#;
+    let pinned = r#"{ "esm_url": "https://cdn.example.com/chart.js", "integrity": "sha384-abc" }"#;
+
+    let dir = tempdir().expect("tempdir");
+    let strict = RegistryOptions {
+        require_renderer_integrity: true,
+        ..RegistryOptions::default()
+    };
+    let mut registry = Registry::open_with_options(dir.path(), strict).expect("open registry");
+    let err = registry
+        .put_bundle("unpinned", bundle("unpinned", remote).as_bytes())
+        .expect_err("remote renderer without integrity");
+    assert!(err.to_string().contains("integrity"), "{err}");
+    registry
+        .put_bundle("pinned", bundle("pinned", pinned).as_bytes())
+        .expect("pinned renderer");
+    assert!(registry.get_bundle("unpinned").is_none());
+
+    // Off by default.
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+    registry
+        .put_bundle("unpinned", bundle("unpinned", remote).as_bytes())
+        .expect("lenient registry accepts unpinned renderer");
+}
+
+#[test]
+fn enum_labels_must_be_unique_within_an_enum() {
+    let bundle = |bundle_id: &str, mapping: &str| {
+        format!(
+            r#"{{
+              "registry_version": 1,
+              "bundle_id": "{bundle_id}",
+              "types": {{}},
+              "enums": {{ "com.example.Role": {mapping} }}
+            }}"#
+        )
+    };
+
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+    let err = registry
+        .put_bundle(
+            "dup",
+            bundle("dup", r#"{ "1": "user", "2": "assistant", "3": "user" }"#).as_bytes(),
+        )
+        .expect_err("duplicate label");
+    assert!(matches!(err, StoreError::InvalidInput(_)), "{err}");
+    assert!(err.to_string().contains("\"user\""), "{err}");
+    assert!(registry.get_enum("com.example.Role").is_none());
+
+    registry
+        .put_bundle(
+            "unique",
+            bundle("unique", r#"{ "1": "user", "2": "assistant" }"#).as_bytes(),
+        )
+        .expect("unique labels");
+    assert_eq!(registry.get_enum("com.example.Role").unwrap().len(), 2);
+}
+
+#[test]
+fn cyclic_type_ref_projection_stops_at_max_depth() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+
+    // A tree node whose child is another node: a self-referential type.
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "cyclic",
+      "types": {
+        "test:Node": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "name", "type": "string" },
+                "2": { "name": "child", "type": "ref", "ref": "test:Node" }
+              }
+            }
+          }
+        }
+      },
+      "enums": {}
+    }
+    "#;
+    registry
+        .put_bundle("cyclic", bundle.as_bytes())
+        .expect("put bundle");
+
+    // 200 levels of nesting, far past the limit.
+    let mut value = Value::Map(vec![(Value::from(1), Value::from("leaf"))]);
+    for _ in 0..200 {
+        value = Value::Map(vec![
+            (Value::from(1), Value::from("node")),
+            (Value::from(2), value),
+        ]);
+    }
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode msgpack");
+
+    let desc = registry.get_type_version("test:Node", 1).expect("desc");
+    let options = RenderOptions {
+        max_depth: 3,
+        ..default_options()
+    };
+    let projection = project_msgpack(&buf, desc, &registry, &options).expect("project");
+
+    // The top-level node plus three followed refs are projected.
+    let mut node = &projection.data;
+    for _ in 0..4 {
+        assert_eq!(node["name"], "node");
+        assert!(node.get("$max_depth_exceeded").is_none());
+        node = &node["child"];
+    }
+    // Past the limit the value is raw: keyed by tag, not field name.
+    assert_eq!(node["$max_depth_exceeded"], true);
+    assert_eq!(node["1"], "node");
+    assert!(node.get("name").is_none());
+}
+
+#[test]
+fn nested_array_and_typed_map_specs_parse() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "containers",
+      "types": {
+        "test:Grid": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "rows", "type": "array", "items": { "type": "array", "items": "f64" } },
+                "2": { "name": "cells", "type": "array", "items": { "type": "array", "items": { "type": "array", "items": { "ref": "test:Cell" } } } },
+                "3": { "name": "labels", "type": "map", "key_type": "u64", "value_type": "string" },
+                "4": { "name": "lists", "type": "map", "key_type": "string", "value_type": { "type": "array", "items": "u32" } }
+              }
+            }
+          }
+        },
+        "test:Cell": {
+          "versions": {
+            "1": { "fields": { "1": { "name": "v", "type": "string" } } }
+          }
+        }
+      },
+      "enums": {}
+    }
+    "#;
+    registry
+        .put_bundle("containers", bundle.as_bytes())
+        .expect("put bundle");
+
+    let desc = registry.get_type_version("test:Grid", 1).expect("desc");
+    assert_eq!(
+        desc.fields[&1].items,
+        Some(ItemsSpec::Array(Box::new(ItemsSpec::Simple("f64".into()))))
+    );
+    assert_eq!(
+        desc.fields[&2].items,
+        Some(ItemsSpec::Array(Box::new(ItemsSpec::Array(Box::new(
+            ItemsSpec::Ref("test:Cell".into())
+        )))))
+    );
+    assert_eq!(desc.fields[&3].key_type.as_deref(), Some("u64"));
+    assert_eq!(
+        desc.fields[&3].value_type,
+        Some(ItemsSpec::Simple("string".into()))
+    );
+    assert_eq!(
+        desc.fields[&4].value_type,
+        Some(ItemsSpec::Array(Box::new(ItemsSpec::Simple("u32".into()))))
+    );
+}
+
+#[test]
+fn nested_array_and_typed_map_fields_project() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "containers",
+      "types": {
+        "test:Grid": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "cells", "type": "array", "items": { "type": "array", "items": { "ref": "test:Cell" } } },
+                "2": { "name": "labels", "type": "map", "key_type": "u64", "value_type": { "ref": "test:Cell" } }
+              }
+            }
+          }
+        },
+        "test:Cell": {
+          "versions": {
+            "1": { "fields": { "1": { "name": "v", "type": "string" } } }
+          }
+        }
+      },
+      "enums": {}
+    }
+    "#;
+    registry
+        .put_bundle("containers", bundle.as_bytes())
+        .expect("put bundle");
+
+    let cell = |v: &str| Value::Map(vec![(Value::from(1), Value::from(v))]);
+    let value = Value::Map(vec![
+        (
+            Value::from(1),
+            Value::Array(vec![
+                Value::Array(vec![cell("a"), cell("b")]),
+                Value::Array(vec![]),
+                Value::Array(vec![cell("c")]),
+            ]),
+        ),
+        (
+            Value::from(2),
+            Value::Map(vec![(Value::from(7), cell("seven"))]),
+        ),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode msgpack");
+
+    let desc = registry.get_type_version("test:Grid", 1).expect("desc");
+    let projection = project_msgpack(&buf, desc, &registry, &default_options()).expect("project");
+
+    assert_eq!(
+        projection.data["cells"],
+        json!([[{"v": "a"}, {"v": "b"}], [], [{"v": "c"}]])
+    );
+    assert_eq!(projection.data["labels"], json!({"7": {"v": "seven"}}));
+}
+
+#[test]
+fn strict_projection_reports_type_mismatches() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "strict",
+      "types": {
+        "test:Event": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "count", "type": "u32" },
+                "2": { "name": "name", "type": "string" },
+                "3": { "name": "tags", "type": "array", "items": "string" },
+                "4": { "name": "note", "type": "string", "optional": true }
+              }
+            }
+          }
+        }
+      },
+      "enums": {}
+    }
+    "#;
+    registry
+        .put_bundle("strict", bundle.as_bytes())
+        .expect("put bundle");
+
+    // count holds a string and tags[1] an integer; note is an explicit nil.
+    let value = Value::Map(vec![
+        (Value::from(1), Value::from("seven")),
+        (Value::from(2), Value::from("ok")),
+        (
+            Value::from(3),
+            Value::Array(vec![Value::from("a"), Value::from(2)]),
+        ),
+        (Value::from(4), Value::Nil),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode msgpack");
+    let desc = registry.get_type_version("test:Event", 1).expect("desc");
+
+    let lenient = project_msgpack(&buf, desc, &registry, &default_options()).expect("project");
+    assert_eq!(lenient.data["count"], serde_json::Value::Null);
+    assert!(lenient.type_errors.is_empty());
+
+    let options = RenderOptions {
+        strict: true,
+        ..default_options()
+    };
+    let strict = project_msgpack(&buf, desc, &registry, &options).expect("project");
+    let errors: Vec<String> = strict.type_errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        errors,
+        vec![
+            "count: expected u32, found string",
+            "tags[1]: expected string, found integer",
+        ]
+    );
+}
+
 #[test]
 fn map_with_ref_recursively_projects() {
     // Regression test: a bundle schema may use `"type": "map"` with a separate
@@ -512,3 +893,606 @@ fn array_shorthand_ref_recursively_projects() {
         "numeric key '1' should not appear in shorthand ref array items"
     );
 }
+
+#[test]
+fn corrupt_bundle_files_are_skipped_only_when_enabled() {
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut registry = Registry::open(dir.path()).expect("open registry");
+        registry
+            .put_bundle(
+                "good",
+                br#"{"registry_version": 1, "bundle_id": "good", "types": {}, "enums": {}}"#,
+            )
+            .expect("put bundle");
+    }
+    std::fs::write(
+        dir.path().join("bundle_bad.json"),
+        b"{\"registry_version\": 1, \"bun",
+    )
+    .expect("write corrupt bundle");
+
+    let err = Registry::open(dir.path()).expect_err("fail fast by default");
+    assert!(matches!(err, StoreError::Corrupt(_)), "{err}");
+
+    let options = RegistryOptions {
+        skip_bad_bundles: true,
+        ..RegistryOptions::default()
+    };
+    let registry = Registry::open_with_options(dir.path(), options).expect("open registry");
+    assert!(registry.has_bundle("good"));
+    assert_eq!(registry.stats().bundles_total, 1);
+    let skipped = registry.skipped_bundles();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].path, dir.path().join("bundle_bad.json"));
+    assert!(!skipped[0].error.is_empty());
+}
+
+#[test]
+fn bundle_and_type_limits_reject_over_limit_puts() {
+    let bundle = |id: &str, types: &[&str]| {
+        let types: serde_json::Map<String, serde_json::Value> = types
+            .iter()
+            .map(|t| {
+                let spec = json!({"versions": {"1": {"fields": {
+                    "1": {"name": "text", "type": "string"}
+                }}}});
+                (t.to_string(), spec)
+            })
+            .collect();
+        json!({"registry_version": 1, "bundle_id": id, "types": types, "enums": {}}).to_string()
+    };
+    let dir = tempdir().expect("tempdir");
+    let options = RegistryOptions {
+        max_bundles: 2,
+        max_types: 3,
+        ..RegistryOptions::default()
+    };
+    let mut registry =
+        Registry::open_with_options(dir.path(), options.clone()).expect("open registry");
+    registry
+        .put_bundle(
+            "b1",
+            bundle("b1", &["com.example.A", "com.example.B"]).as_bytes(),
+        )
+        .expect("first bundle");
+
+    // A bundle adding two more types would make four.
+    let err = registry
+        .put_bundle(
+            "b2",
+            bundle("b2", &["com.example.B", "com.example.C", "com.example.D"]).as_bytes(),
+        )
+        .expect_err("over the type limit");
+    assert!(matches!(err, StoreError::InvalidInput(_)), "{err}");
+    assert!(err.to_string().contains("CXDB_MAX_TYPES"), "{err}");
+    assert!(registry.get_latest_type_version("com.example.C").is_none());
+    assert!(!registry.has_bundle("b2"));
+
+    // Redefining known types only counts the new one.
+    registry
+        .put_bundle(
+            "b2",
+            bundle("b2", &["com.example.A", "com.example.C"]).as_bytes(),
+        )
+        .expect("second bundle, at the limits");
+    let err = registry
+        .put_bundle("b3", bundle("b3", &["com.example.A"]).as_bytes())
+        .expect_err("over the bundle limit");
+    assert!(err.to_string().contains("CXDB_MAX_BUNDLES"), "{err}");
+    // Re-putting a stored bundle is still fine.
+    registry
+        .put_bundle(
+            "b1",
+            bundle("b1", &["com.example.A", "com.example.B"]).as_bytes(),
+        )
+        .expect("idempotent put");
+
+    // What is already on disk loads even under a tighter limit.
+    let tighter = RegistryOptions {
+        max_bundles: 1,
+        ..options
+    };
+    let registry = Registry::open_with_options(dir.path(), tighter).expect("reopen");
+    assert_eq!(registry.stats().bundles_total, 2);
+}
+
+#[test]
+fn stale_tmp_bundle_files_are_ignored_on_open() {
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut registry = Registry::open(dir.path()).expect("open registry");
+        registry
+            .put_bundle(
+                "good",
+                br#"{"registry_version": 1, "bundle_id": "good", "types": {}, "enums": {}}"#,
+            )
+            .expect("put bundle");
+    }
+    // What a crash between write and rename leaves behind.
+    std::fs::write(dir.path().join("bundle_torn.json.tmp"), b"{\"registry_ver")
+        .expect("write tmp file");
+
+    let registry = Registry::open(dir.path()).expect("open ignores tmp files");
+    assert!(registry.has_bundle("good"));
+    assert_eq!(registry.stats().bundles_total, 1);
+    assert!(registry.skipped_bundles().is_empty());
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .expect("read dir")
+        .map(|e| e.expect("entry").file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("bundle_good"))
+        .collect();
+    assert_eq!(leftovers.len(), 1);
+    assert!(leftovers[0].ends_with(".json"), "{leftovers:?}");
+}
+
+#[test]
+fn bundle_ids_that_sanitize_alike_persist_separately() {
+    let bundle = |bundle_id: &str, type_id: &str| {
+        format!(
+            r#"{{"registry_version": 1, "bundle_id": "{bundle_id}",
+                "types": {{"{type_id}": {{"versions": {{"1": {{"fields": {{}}}}}}}}}},
+                "enums": {{}}}}"#
+        )
+    };
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut registry = Registry::open(dir.path()).expect("open registry");
+        for (bundle_id, type_id) in [("a/b", "t.Slash"), ("a_b", "t.Underscore")] {
+            registry
+                .put_bundle(bundle_id, bundle(bundle_id, type_id).as_bytes())
+                .expect("put bundle");
+        }
+    }
+    let refs = std::fs::read_dir(dir.path())
+        .expect("read dir")
+        .filter(|e| e.as_ref().expect("entry").path().is_file())
+        .count();
+    assert_eq!(refs, 2);
+
+    let registry = Registry::open(dir.path()).expect("reopen registry");
+    assert_eq!(registry.stats().bundles_total, 2);
+    let slash = String::from_utf8(registry.get_bundle("a/b").expect("a/b").to_vec()).unwrap();
+    let underscore = String::from_utf8(registry.get_bundle("a_b").expect("a_b").to_vec()).unwrap();
+    assert!(slash.contains("t.Slash"));
+    assert!(underscore.contains("t.Underscore"));
+    assert!(registry.get_type_version("t.Slash", 1).is_some());
+    assert!(registry.get_type_version("t.Underscore", 1).is_some());
+}
+
+#[test]
+fn identical_bundle_bodies_are_stored_once() {
+    // No bundle_id in the body, so it can be put under any id.
+    let body = br#"{"registry_version": 1, "types": {"com.example.Shared": {"versions": {"1": {"fields": {"1": {"name": "text", "type": "string"}}}}}}, "enums": {}}"#;
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut registry = Registry::open(dir.path()).expect("open registry");
+        registry.put_bundle("team-a", body).expect("put team-a");
+        registry.put_bundle("team-b", body).expect("put team-b");
+        let stats = registry.stats();
+        assert_eq!((stats.bundles_total, stats.bundle_bodies_total), (2, 1));
+        assert_eq!(registry.bundle_hash("team-a"), Some(blake3::hash(body)));
+    }
+    let objects = std::fs::read_dir(dir.path().join("objects"))
+        .expect("objects dir")
+        .count();
+    assert_eq!(objects, 1);
+
+    let registry = Registry::open(dir.path()).expect("reopen registry");
+    assert_eq!(registry.get_bundle("team-a").as_deref(), Some(&body[..]));
+    assert_eq!(registry.get_bundle("team-b").as_deref(), Some(&body[..]));
+    assert_eq!(registry.stats().bundle_bodies_total, 1);
+    assert_eq!(
+        registry
+            .get_latest_type_version_in_bundle("team-b", "com.example.Shared")
+            .map(|v| v.version),
+        Some(1)
+    );
+
+    // A body naming its own id still has to match the path.
+    let mut registry = registry;
+    let named = br#"{"registry_version": 1, "bundle_id": "team-a", "types": {}, "enums": {}}"#;
+    assert!(registry.put_bundle("team-c", named).is_err());
+}
+
+#[test]
+fn pinned_projection_resolves_refs_and_enums_through_the_registry() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+    let put = |registry: &mut Registry, bundle_id: &str, types: serde_json::Value| {
+        let enums = if bundle_id == "base" {
+            json!({"com.example.Mood": {"1": "happy"}})
+        } else {
+            json!({})
+        };
+        let bundle = json!({"registry_version": 1, "types": types, "enums": enums});
+        registry.put_bundle(bundle_id, bundle.to_string().as_bytes())
+    };
+    put(
+        &mut registry,
+        "base",
+        json!({"com.example.Person": {"versions": {"1": {"fields": {
+            "1": {"name": "name", "type": "string"}
+        }}}}}),
+    )
+    .expect("put base");
+    put(
+        &mut registry,
+        "notes",
+        json!({"com.example.Note": {"versions": {"1": {"fields": {
+            "1": {"name": "author", "type": "ref", "ref": "com.example.Person"},
+            "2": {"name": "mood", "type": "u8", "enum": "com.example.Mood"}
+        }}}}}),
+    )
+    .expect("put notes");
+    put(
+        &mut registry,
+        "people-v2",
+        json!({"com.example.Person": {"versions": {"2": {"fields": {
+            "1": {"name": "full_name", "type": "string"}
+        }}}}}),
+    )
+    .expect("put people-v2");
+
+    // A bundle that fails enum validation leaves no pinned versions behind.
+    let err = put(
+        &mut registry,
+        "broken",
+        json!({"com.example.Broken": {"versions": {"1": {"fields": {
+            "1": {"name": "mood", "type": "u8", "enum": "com.example.Missing"}
+        }}}}}),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("missing enum"), "{err}");
+    assert!(registry
+        .get_latest_type_version_in_bundle("broken", "com.example.Broken")
+        .is_none());
+
+    let value = Value::Map(vec![
+        (
+            Value::Integer(1.into()),
+            Value::Map(vec![(
+                Value::Integer(1.into()),
+                Value::String("ada".into()),
+            )]),
+        ),
+        (Value::Integer(2.into()), Value::Integer(1.into())),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode msgpack");
+    let desc = registry
+        .get_type_version("com.example.Note", 1)
+        .expect("descriptor");
+    let project = |bundle_id: Option<&str>| {
+        let options = RenderOptions {
+            bundle_id: bundle_id.map(str::to_string),
+            ..default_options()
+        };
+        project_msgpack(&buf, desc, &registry, &options)
+            .expect("project")
+            .data
+    };
+
+    // Pinned to a bundle that defines Person, the nested ref uses its
+    // version; the enum comes from that bundle too.
+    let data = project(Some("base"));
+    assert_eq!(data["author"]["name"], "ada");
+    assert_eq!(data["mood"], "happy");
+
+    // Pinned to a bundle that defines neither, both resolve through the
+    // registry: the latest Person, and the enum an earlier bundle published.
+    let data = project(Some("notes"));
+    assert_eq!(data["author"]["full_name"], "ada");
+    assert_eq!(data["mood"], "happy");
+    assert_eq!(project(None), data);
+}
+
+#[test]
+fn compressed_bundles_reload_identically() {
+    let body = br#"{"registry_version": 1, "bundle_id": "packed", "types": {"com.example.Packed": {"versions": {"1": {"fields": {"1": {"name": "text", "type": "string"}, "2": {"name": "note", "type": "string"}}}}}}, "enums": {}}"#;
+    let compressed = RegistryOptions {
+        compress_on_disk: true,
+        compress_in_memory: true,
+        bundle_cache_entries: 1,
+        ..RegistryOptions::default()
+    };
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut registry =
+            Registry::open_with_options(dir.path(), compressed.clone()).expect("open registry");
+        registry.put_bundle("packed", body).expect("put");
+        assert_eq!(registry.get_bundle("packed").as_deref(), Some(&body[..]));
+    }
+    let hex = blake3::hash(body).to_hex();
+    let stored = std::fs::read(dir.path().join("objects").join(format!("{hex}.json.zst")))
+        .expect("compressed object");
+    assert_ne!(stored, body);
+    assert!(!dir
+        .path()
+        .join("objects")
+        .join(format!("{hex}.json"))
+        .exists());
+
+    for options in [compressed, RegistryOptions::default()] {
+        let registry = Registry::open_with_options(dir.path(), options).expect("reopen registry");
+        // Twice: once decompressed, once from the cache.
+        for _ in 0..2 {
+            assert_eq!(registry.get_bundle("packed").as_deref(), Some(&body[..]));
+        }
+        // The ETag is the hash of the uncompressed body.
+        assert_eq!(registry.bundle_hash("packed"), Some(blake3::hash(body)));
+        assert!(registry
+            .get_latest_type_version("com.example.Packed")
+            .is_some());
+    }
+}
+
+/// Decode a protobuf message into JSON by field name, driven by the field
+/// declarations in `schema` (the generated `.proto`).
+fn decode_with_schema(schema: &str, message: &str, mut bytes: &[u8]) -> serde_json::Value {
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = bytes[0];
+            *bytes = &bytes[1..];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    // "message Name {" ... "}" -> tag -> (repeated, type, field name)
+    let body = schema
+        .split(&format!("message {message} {{\n"))
+        .nth(1)
+        .expect("message in schema")
+        .split("\n}")
+        .next()
+        .unwrap();
+    let mut fields = std::collections::HashMap::new();
+    for line in body.lines().map(str::trim).filter(|l| !l.starts_with("//")) {
+        let decl = line.split(';').next().unwrap();
+        let (lhs, tag) = decl.split_once(" = ").expect("field declaration");
+        let words: Vec<&str> = lhs.split_whitespace().collect();
+        let repeated = words[0] == "repeated";
+        let (ty, name) = (words[words.len() - 2], words[words.len() - 1]);
+        fields.insert(tag.parse::<u64>().unwrap(), (repeated, ty, name));
+    }
+
+    let mut out = serde_json::Map::new();
+    while !bytes.is_empty() {
+        let key = varint(&mut bytes);
+        let (tag, wire) = (key >> 3, key & 7);
+        let &(repeated, ty, name) = fields.get(&tag).expect("tag declared in schema");
+        let decoded: Vec<serde_json::Value> = if wire == 0 {
+            let v = varint(&mut bytes);
+            vec![match ty {
+                "int64" | "int32" => json!(v as i64),
+                "bool" => json!(v != 0),
+                _ => json!(v),
+            }]
+        } else {
+            assert_eq!(wire, 2, "unexpected wire type");
+            let len = varint(&mut bytes) as usize;
+            let (chunk, rest) = bytes.split_at(len);
+            bytes = rest;
+            match ty {
+                "string" => vec![json!(std::str::from_utf8(chunk).unwrap())],
+                "bytes" => vec![json!(chunk)],
+                "uint64" | "uint32" | "int64" | "int32" | "bool" => {
+                    // Packed repeated scalars.
+                    let mut packed = chunk;
+                    let mut items = Vec::new();
+                    while !packed.is_empty() {
+                        items.push(json!(varint(&mut packed)));
+                    }
+                    items
+                }
+                nested => vec![decode_with_schema(schema, nested, chunk)],
+            }
+        };
+        if repeated {
+            let list = out.entry(name).or_insert_with(|| json!([]));
+            list.as_array_mut().unwrap().extend(decoded);
+        } else {
+            out.insert(name.to_string(), decoded.into_iter().next().unwrap());
+        }
+    }
+    serde_json::Value::Object(out)
+}
+
+#[test]
+fn protobuf_projection_decodes_against_generated_schema() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "proto-test",
+      "types": {
+        "com.example.Message": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "role", "type": "u8", "enum": "com.example.Role" },
+                "2": { "name": "text", "type": "string" },
+                "3": { "name": "offset", "type": "int64" },
+                "4": { "name": "done", "type": "bool", "optional": true },
+                "5": { "name": "blob", "type": "bytes" },
+                "6": { "name": "ids", "type": "array", "items": "u64" },
+                "7": { "name": "author", "type": "ref", "ref": "com.example.Author" },
+                "8": { "name": "tools", "type": "array", "items": { "type": "ref", "ref": "com.example.Tool" } },
+                "9": { "name": "attrs", "type": "map", "key_type": "string", "value_type": "string" }
+              }
+            }
+          }
+        },
+        "com.example.Author": {
+          "versions": { "1": { "fields": { "1": { "name": "name", "type": "string" } } } }
+        },
+        "com.example.Tool": {
+          "versions": { "1": { "fields": { "1": { "name": "name", "type": "string" } } } }
+        }
+      },
+      "enums": { "com.example.Role": { "1": "system", "2": "user" } }
+    }
+    "#;
+    registry
+        .put_bundle("proto-test", bundle.as_bytes())
+        .expect("put bundle");
+    let desc = registry
+        .get_type_version("com.example.Message", 1)
+        .expect("descriptor");
+
+    let named =
+        |name: &str| Value::Map(vec![(Value::Integer(1.into()), Value::String(name.into()))]);
+    let value = Value::Map(vec![
+        (Value::Integer(1.into()), Value::Integer(2.into())),
+        (Value::Integer(2.into()), Value::String("hello".into())),
+        (Value::Integer(3.into()), Value::Integer((-5).into())),
+        (Value::Integer(4.into()), Value::Boolean(true)),
+        (Value::Integer(5.into()), Value::Binary(vec![0, 1, 2])),
+        (
+            Value::Integer(6.into()),
+            Value::Array(vec![Value::Integer(1.into()), Value::Integer(300.into())]),
+        ),
+        (Value::Integer(7.into()), named("ada")),
+        (
+            Value::Integer(8.into()),
+            Value::Array(vec![named("grep"), named("sed")]),
+        ),
+        (
+            Value::Integer(9.into()),
+            Value::Map(vec![(Value::String("k".into()), Value::String("v".into()))]),
+        ),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode msgpack");
+
+    let schema = proto_schema("com.example.Message", desc, &registry);
+    assert!(schema.contains("syntax = \"proto3\";"), "{schema}");
+    assert!(
+        schema.contains("  uint32 role = 1; // enum com.example.Role\n"),
+        "{schema}"
+    );
+    assert!(schema.contains("  optional bool done = 4;\n"), "{schema}");
+    assert!(
+        schema.contains("  repeated com_example_Tool tools = 8;\n"),
+        "{schema}"
+    );
+    assert!(schema.contains("message com_example_Author {"), "{schema}");
+    assert!(
+        schema.contains("// attrs = 9 omitted: map is not supported"),
+        "{schema}"
+    );
+
+    let message = project_protobuf(&buf, desc, &registry).expect("project protobuf");
+    let decoded = decode_with_schema(&schema, "com_example_Message", &message);
+    assert_eq!(
+        decoded,
+        json!({
+            "role": 2,
+            "text": "hello",
+            "offset": -5,
+            "done": true,
+            "blob": [0, 1, 2],
+            "ids": [1, 300],
+            "author": {"name": "ada"},
+            "tools": [{"name": "grep"}, {"name": "sed"}],
+        })
+    );
+}
+
+#[test]
+fn json_schema_maps_enums_arrays_of_refs_and_optional_fields() {
+    let dir = tempdir().expect("tempdir");
+    let mut registry = Registry::open(dir.path()).expect("open registry");
+    let bundle = r#"
+    {
+      "registry_version": 1,
+      "bundle_id": "schema-test",
+      "types": {
+        "com.example.Message": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "role", "type": "u8", "enum": "com.example.Role" },
+                "2": { "name": "text", "type": "string", "optional": true },
+                "3": { "name": "tools", "type": "array", "items": { "type": "ref", "ref": "com.example.Tool" } },
+                "4": { "name": "sent_at", "type": "unix_ms" }
+              }
+            }
+          }
+        },
+        "com.example.Tool": {
+          "versions": {
+            "1": {
+              "fields": {
+                "1": { "name": "name", "type": "string" },
+                "2": { "name": "args", "type": "map", "key_type": "string", "value_type": "string", "optional": true }
+              }
+            }
+          }
+        }
+      },
+      "enums": { "com.example.Role": { "2": "user", "1": "system", "3": "tool" } }
+    }
+    "#;
+    registry
+        .put_bundle("schema-test", bundle.as_bytes())
+        .expect("put bundle");
+    let desc = registry
+        .get_type_version("com.example.Message", 1)
+        .expect("descriptor");
+
+    let schema = json_schema("com.example.Message", desc, &registry);
+    assert_eq!(
+        schema["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+    assert_eq!(schema["title"], "com.example.Message");
+    assert_eq!(schema["type"], "object");
+    assert_eq!(schema["required"], json!(["role", "tools", "sent_at"]));
+
+    let props = &schema["properties"];
+    assert_eq!(props["role"], json!({"enum": ["system", "user", "tool"]}));
+    assert_eq!(props["text"], json!({"type": "string"}));
+    assert_eq!(
+        props["sent_at"],
+        json!({"type": "string", "format": "date-time"})
+    );
+    assert_eq!(
+        props["tools"],
+        json!({"type": "array", "items": {"$ref": "#/$defs/com.example.Tool"}})
+    );
+
+    let tool = &schema["$defs"]["com.example.Tool"];
+    assert_eq!(tool["required"], json!(["name"]));
+    assert_eq!(
+        tool["properties"]["args"],
+        json!({"type": "object", "additionalProperties": {"type": "string"}})
+    );
+
+    // Every reference resolves within the document.
+    fn refs(value: &serde_json::Value, out: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(obj) => {
+                if let Some(r) = obj.get("$ref").and_then(|r| r.as_str()) {
+                    out.push(r.to_string());
+                }
+                obj.values().for_each(|v| refs(v, out));
+            }
+            serde_json::Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    refs(&schema, &mut found);
+    assert!(!found.is_empty());
+    for r in found {
+        let pointer = r.strip_prefix('#').expect("local ref");
+        assert!(schema.pointer(pointer).is_some(), "dangling {r}");
+    }
+}
@@ -1,8 +1,25 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use blake3::Hasher;
-use cxdb_server::store::Store;
+use cxdb_server::blob_store::{BlobCodec, BlobKey};
+use cxdb_server::clock::MockClock;
+use cxdb_server::context_meta::{MetadataOverlay, TagNormalization};
+use cxdb_server::error::{NotFoundKind, StoreError};
+use cxdb_server::interceptor::{AppendContext, AppendInterceptor, MaxPayloadSize};
+use cxdb_server::metrics::SessionTracker;
+use cxdb_server::protocol::{
+    encode_get_last_batch_resp, encode_turns, map_store_error, parse_get_last_batch, MsgType,
+};
+use cxdb_server::store::{
+    spawn_index_warmup, top_contexts, RetentionPolicy, SearchOptions, Store, StoreOptions,
+    TopContextsBy,
+};
+use cxdb_server::turn_store::TurnRecord;
 use rmpv::Value;
 use tempfile::tempdir;
 
@@ -157,6 +174,508 @@ fn indexes_parent_child_context_lineage() {
     assert_eq!(descendants, vec![grandchild.context_id, child.context_id]);
 }
 
+#[test]
+fn missing_blob_and_context_map_to_distinct_errors() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let blob_err = store.get_blob(&[7u8; 32]).expect_err("missing blob");
+    assert!(matches!(
+        blob_err,
+        StoreError::NotFound(NotFoundKind::Blob, _)
+    ));
+    assert_eq!(map_store_error(&blob_err), (404, "blob: blob".to_string()));
+
+    let ctx_err = store.get_head(99).expect_err("missing context");
+    assert!(matches!(
+        ctx_err,
+        StoreError::NotFound(NotFoundKind::Context, _)
+    ));
+    assert_eq!(
+        map_store_error(&ctx_err),
+        (404, "context: context".to_string())
+    );
+
+    let fork_err = store.fork_context(42).expect_err("missing base turn");
+    assert_eq!(map_store_error(&fork_err).0, 409);
+}
+
+#[test]
+fn get_last_batch_groups_turns_per_context() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let a = store.create_context(0).expect("create a").context_id;
+    let b = store.create_context(0).expect("create b").context_id;
+    let a1 = append_bytes(&mut store, a, 0, b"a1");
+    let a2 = append_bytes(&mut store, a, a1.turn_id, b"a2");
+    let b1 = append_bytes(&mut store, b, 0, b"b1");
+
+    let mut request = Vec::new();
+    request.extend_from_slice(&1u32.to_le_bytes());
+    request.extend_from_slice(&2u32.to_le_bytes());
+    for (ctx, limit) in [(a, 1u32), (b, 10u32)] {
+        request.extend_from_slice(&ctx.to_le_bytes());
+        request.extend_from_slice(&limit.to_le_bytes());
+    }
+    let req = parse_get_last_batch(&request).expect("parse batch");
+    assert_eq!(req.entries, vec![(a, 1), (b, 10)]);
+
+    let groups = store
+        .get_last_batch(&req.entries, req.include_payload != 0, 100)
+        .expect("get last batch");
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].0, a);
+    assert_eq!(groups[0].1.len(), 1);
+    assert_eq!(groups[0].1[0].record.turn_id, a2.turn_id);
+    assert_eq!(groups[0].1[0].payload.as_deref(), Some(&b"a2"[..]));
+    assert_eq!(groups[1].0, b);
+    assert_eq!(groups[1].1[0].record.turn_id, b1.turn_id);
+
+    let resp = encode_get_last_batch_resp(&groups).expect("encode batch");
+    assert_eq!(u32::from_le_bytes(resp[0..4].try_into().unwrap()), 2);
+    assert_eq!(u64::from_le_bytes(resp[4..12].try_into().unwrap()), a);
+    let body_len = u32::from_le_bytes(resp[12..16].try_into().unwrap()) as usize;
+    // Each group body is a GET_LAST response starting with its turn count.
+    assert_eq!(u32::from_le_bytes(resp[16..20].try_into().unwrap()), 1);
+    let next = 16 + body_len;
+    assert_eq!(
+        u64::from_le_bytes(resp[next..next + 8].try_into().unwrap()),
+        b
+    );
+
+    let missing = store.get_last_batch(&[(a, 1), (999, 1)], false, 100);
+    assert!(missing.is_err());
+    // Three turns against a budget of two.
+    let over = store.get_last_batch(&[(a, 10), (b, 10)], false, 2);
+    assert!(matches!(over, Err(StoreError::InvalidInput(_))));
+    assert_eq!(MsgType::GetLastBatch as u16, 12);
+}
+
+#[test]
+fn turn_count_increments_across_appends() {
+    let dir = tempdir().expect("tempdir");
+    let (ctx, fork) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create context").context_id;
+        assert_eq!(store.get_head(ctx).unwrap().turn_count, 0);
+
+        let first = append_bytes(&mut store, ctx, 0, b"one");
+        assert_eq!(store.get_head(ctx).unwrap().turn_count, 1);
+        append_bytes(&mut store, ctx, 0, b"two");
+        append_bytes(&mut store, ctx, 0, b"three");
+        assert_eq!(store.get_head(ctx).unwrap().turn_count, 3);
+
+        // A fork starts with its base chain and counts its own appends on top.
+        let fork = store.fork_context(first.turn_id).expect("fork").context_id;
+        assert_eq!(store.get_head(fork).unwrap().turn_count, 1);
+        append_bytes(&mut store, fork, 0, b"fork");
+        assert_eq!(store.get_head(fork).unwrap().turn_count, 2);
+
+        let found = store
+            .search_contexts("turn_count >= 3", &HashSet::new(), None)
+            .expect("search");
+        assert_eq!(found.context_ids, vec![ctx]);
+        (ctx, fork)
+    };
+
+    let store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.get_head(ctx).unwrap().turn_count, 3);
+    assert_eq!(store.get_head(fork).unwrap().turn_count, 2);
+}
+
+#[test]
+fn create_with_metadata_is_indexed_before_first_turn() {
+    let dir = tempdir().expect("tempdir");
+    let ctx = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let overlay = MetadataOverlay {
+            title: Some("Release planning".to_string()),
+            labels: Some(vec!["triage".to_string()]),
+            ..Default::default()
+        };
+        let ctx = store
+            .create_context_with_metadata(0, overlay)
+            .expect("create context")
+            .context_id;
+
+        let found = store
+            .search_contexts(r#"title = "Release planning""#, &HashSet::new(), None)
+            .expect("search");
+        assert_eq!(found.context_ids, vec![ctx]);
+        let found = store
+            .search_contexts(r#"label = "triage""#, &HashSet::new(), None)
+            .expect("search");
+        assert_eq!(found.context_ids, vec![ctx]);
+
+        // The first turn still contributes its own metadata; the overlay
+        // title wins over nothing and the payload's client_tag fills in.
+        let payload = encode_context_metadata_payload(Some(ctx + 100), None);
+        let hash = blake3::hash(&payload);
+        let (_, metadata) = store
+            .append_turn(
+                ctx,
+                0,
+                "cxdb.ConversationItem".to_string(),
+                1,
+                1,
+                0,
+                payload.len() as u32,
+                *hash.as_bytes(),
+                &payload,
+            )
+            .expect("append first turn");
+        let metadata = metadata.expect("first turn metadata");
+        assert_eq!(metadata.title.as_deref(), Some("Release planning"));
+        assert_eq!(metadata.client_tag.as_deref(), Some("test-client"));
+        assert_eq!(
+            metadata.provenance.and_then(|p| p.parent_context_id),
+            Some(ctx + 100)
+        );
+        ctx
+    };
+
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    let metadata = store.get_context_metadata(ctx).expect("metadata");
+    assert_eq!(metadata.title.as_deref(), Some("Release planning"));
+    assert_eq!(metadata.labels, Some(vec!["triage".to_string()]));
+    assert_eq!(metadata.client_tag.as_deref(), Some("test-client"));
+}
+
+#[test]
+fn reading_empty_context_metadata_does_not_block_first_turn_extraction() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+
+    // Listing an empty context looks up its metadata before any turn exists.
+    assert!(store.get_context_metadata(ctx).is_none());
+
+    let payload = encode_context_metadata_payload(None, None);
+    let hash = blake3::hash(&payload);
+    let (_, metadata) = store
+        .append_turn(
+            ctx,
+            0,
+            "cxdb.ConversationItem".to_string(),
+            1,
+            1,
+            0,
+            payload.len() as u32,
+            *hash.as_bytes(),
+            &payload,
+        )
+        .expect("append first turn");
+    assert_eq!(
+        metadata.and_then(|m| m.client_tag).as_deref(),
+        Some("test-client")
+    );
+    assert_eq!(
+        store
+            .get_context_metadata(ctx)
+            .and_then(|m| m.client_tag)
+            .as_deref(),
+        Some("test-client")
+    );
+}
+
+#[test]
+fn appending_to_mid_chain_parent_branches_without_moving_head() {
+    let dir = tempdir().expect("tempdir");
+    let (ctx, main, branch_tip) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create context").context_id;
+        let main: Vec<TurnRecord> = (0..3)
+            .map(|i| append_bytes(&mut store, ctx, 0, format!("main-{i}").as_bytes()))
+            .collect();
+
+        let branch = append_bytes(&mut store, ctx, main[0].turn_id, b"branch-1");
+        let head = store.get_head(ctx).expect("head");
+        assert_eq!(head.head_turn_id, main[2].turn_id);
+        assert_eq!(head.head_depth, 2);
+        assert_eq!(head.turn_count, 4);
+        assert_eq!(store.branch_tips(ctx), vec![branch.turn_id]);
+
+        // Extending the branch moves its tip; the head still stays put.
+        let tip = append_bytes(&mut store, ctx, branch.turn_id, b"branch-2");
+        assert_eq!(store.branch_tips(ctx), vec![tip.turn_id]);
+        assert_eq!(store.get_head(ctx).unwrap().head_turn_id, main[2].turn_id);
+
+        // A default append (parent 0) still extends the head.
+        let next = append_bytes(&mut store, ctx, 0, b"main-3");
+        assert_eq!(next.parent_turn_id, main[2].turn_id);
+        assert_eq!(store.get_head(ctx).unwrap().head_turn_id, next.turn_id);
+        (ctx, main, tip)
+    };
+
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.branch_tips(ctx), vec![branch_tip.turn_id]);
+
+    // Both tips walk back to the shared root.
+    let head_turns = store.get_last(ctx, 10, false).expect("head chain");
+    assert_eq!(head_turns.len(), 4);
+    assert_eq!(head_turns[0].record.turn_id, main[0].turn_id);
+    let branch_turns = store
+        .get_before(ctx, branch_tip.turn_id, 10, false)
+        .expect("branch chain");
+    let ids: Vec<u64> = branch_turns.iter().map(|t| t.record.turn_id).collect();
+    assert_eq!(ids, vec![main[0].turn_id, branch_tip.parent_turn_id]);
+}
+
+#[test]
+fn branches_report_each_tip_with_its_fork_point() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let main: Vec<TurnRecord> = (0..4)
+        .map(|i| append_bytes(&mut store, ctx, 0, format!("main-{i}").as_bytes()))
+        .collect();
+    let side = append_bytes(&mut store, ctx, main[1].turn_id, b"side-1");
+    let side_tip = append_bytes(&mut store, ctx, side.turn_id, b"side-2");
+
+    let branches = store.branches(ctx).expect("branches");
+    assert_eq!(branches.len(), 2);
+    assert!(branches[0].is_head);
+    assert_eq!(branches[0].tip_turn_id, main[3].turn_id);
+    assert_eq!(branches[0].depth, 3);
+    assert!(!branches[1].is_head);
+    assert_eq!(branches[1].tip_turn_id, side_tip.turn_id);
+    assert_eq!(branches[1].depth, 3);
+    assert_eq!(branches[1].fork_turn_id, main[1].turn_id);
+}
+
+#[test]
+fn set_head_swaps_head_with_branch_tip_and_persists() {
+    let dir = tempdir().expect("tempdir");
+    let (ctx, old_head, tip) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create context").context_id;
+        let root = append_bytes(&mut store, ctx, 0, b"root");
+        let old_head = append_bytes(&mut store, ctx, 0, b"main");
+        let tip = append_bytes(&mut store, ctx, root.turn_id, b"branch");
+
+        let err = store.set_head(ctx, root.turn_id).unwrap_err();
+        assert!(matches!(err, StoreError::InvalidInput(_)));
+
+        let head = store.set_head(ctx, tip.turn_id).expect("set head");
+        assert_eq!(head.head_turn_id, tip.turn_id);
+        assert_eq!(head.head_depth, 1);
+        assert_eq!(store.branch_tips(ctx), vec![old_head.turn_id]);
+
+        // Default appends now extend the promoted branch.
+        let next = append_bytes(&mut store, ctx, 0, b"after");
+        assert_eq!(next.parent_turn_id, tip.turn_id);
+        (ctx, old_head, next)
+    };
+
+    let store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.get_head(ctx).unwrap().head_turn_id, tip.turn_id);
+    assert_eq!(store.branch_tips(ctx), vec![old_head.turn_id]);
+}
+
+#[test]
+fn set_head_torn_before_branch_record_is_finished_on_reopen() {
+    let dir = tempdir().expect("tempdir");
+    let (ctx, old_head, tip) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create context").context_id;
+        let root = append_bytes(&mut store, ctx, 0, b"root");
+        let old_head = append_bytes(&mut store, ctx, 0, b"main");
+        let tip = append_bytes(&mut store, ctx, root.turn_id, b"branch");
+        store.set_head(ctx, tip.turn_id).expect("set head");
+        (ctx, old_head, tip)
+    };
+
+    // Drop the branch record set_head wrote after the head record, as if
+    // the process died between the two writes.
+    let branches = dir.path().join("turns").join("branches.tbl");
+    let len = std::fs::metadata(&branches).expect("branches.tbl").len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&branches)
+        .and_then(|f| f.set_len(len - 28))
+        .expect("truncate branches.tbl");
+
+    for _ in 0..2 {
+        let store = Store::open(dir.path()).expect("reopen store");
+        assert_eq!(store.get_head(ctx).unwrap().head_turn_id, tip.turn_id);
+        assert_eq!(store.branch_tips(ctx), vec![old_head.turn_id]);
+    }
+}
+
+#[test]
+fn legacy_head_records_load_without_turn_count() {
+    let dir = tempdir().expect("tempdir");
+    let turns_dir = dir.path().join("turns");
+    std::fs::create_dir_all(&turns_dir).expect("create turns dir");
+
+    // v1 layout: no magic/version byte and no turn_count.
+    let mut record = Vec::new();
+    record.extend_from_slice(&7u64.to_le_bytes());
+    record.extend_from_slice(&0u64.to_le_bytes());
+    record.extend_from_slice(&0u32.to_le_bytes());
+    record.extend_from_slice(&0u32.to_le_bytes());
+    record.extend_from_slice(&1_700_000_000_000u64.to_le_bytes());
+    let crc = crc32fast::hash(&record);
+    record.extend_from_slice(&crc.to_le_bytes());
+    std::fs::write(turns_dir.join("heads.tbl"), &record).expect("write heads");
+
+    let mut store = Store::open(dir.path()).expect("open store");
+    let head = store.get_head(7).expect("legacy head");
+    assert_eq!(head.created_at_unix_ms, 1_700_000_000_000);
+    assert_eq!(head.turn_count, 0);
+
+    append_bytes(&mut store, 7, 0, b"after upgrade");
+    drop(store);
+
+    // The upgraded head is appended as a v2 record after the v1 one.
+    let store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.get_head(7).unwrap().turn_count, 1);
+}
+
+#[test]
+fn context_stats_totals_payload_and_fs_bytes() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let first = append_bytes(&mut store, ctx, 0, &[1u8; 100]);
+    append_bytes(&mut store, ctx, 0, &[2u8; 250]);
+    let third = append_bytes(&mut store, ctx, 0, &[3u8; 50]);
+
+    let file = vec![7u8; 40];
+    let file_hash = *blake3::hash(&file).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(file_hash, &file)
+        .expect("put file");
+    let tree = Value::Array(vec![Value::Map(vec![
+        (Value::from(1), Value::from("notes.txt")),
+        (Value::from(2), Value::from(0)),
+        (Value::from(3), Value::from(0o644)),
+        (Value::from(4), Value::from(file.len() as u64)),
+        (Value::from(5), Value::Binary(file_hash.to_vec())),
+    ])]);
+    let mut tree_bytes = Vec::new();
+    rmpv::encode::write_value(&mut tree_bytes, &tree).expect("encode tree");
+    let tree_hash = *blake3::hash(&tree_bytes).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(tree_hash, &tree_bytes)
+        .expect("put tree");
+    // The same snapshot attached twice is only counted once.
+    store
+        .attach_fs(first.turn_id, tree_hash)
+        .expect("attach fs");
+    store
+        .attach_fs(third.turn_id, tree_hash)
+        .expect("attach fs");
+
+    let stats = store.context_stats(ctx).expect("context stats");
+    assert_eq!(stats.turn_count, 3);
+    assert_eq!(stats.head_depth, 2);
+    assert_eq!(stats.payload_bytes, 400);
+    assert_eq!(
+        stats.fs_snapshot_bytes,
+        (tree_bytes.len() + file.len()) as u64
+    );
+
+    let fork = store.fork_context(first.turn_id).expect("fork").context_id;
+    let fork_stats = store.context_stats(fork).expect("fork stats");
+    assert_eq!(fork_stats.turn_count, 1);
+    assert_eq!(fork_stats.payload_bytes, 100);
+
+    // A side branch counts toward the context's turns but not the head
+    // chain's payload bytes.
+    append_bytes(&mut store, ctx, first.turn_id, &[4u8; 10]);
+    let stats = store.context_stats(ctx).expect("context stats");
+    assert_eq!(stats.turn_count, 4);
+    assert_eq!(stats.payload_bytes, 400);
+
+    assert!(matches!(
+        store.context_stats(999),
+        Err(StoreError::NotFound(NotFoundKind::Context, _))
+    ));
+}
+
+#[test]
+fn top_contexts_orders_by_bytes_or_turns() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    // small: 3 turns / 30 bytes, large: 1 turn / 500 bytes, medium: 2 turns / 200 bytes
+    let small = store.create_context(0).expect("create").context_id;
+    for i in 0..3u8 {
+        append_bytes(&mut store, small, 0, &[i; 10]);
+    }
+    let large = store.create_context(0).expect("create").context_id;
+    append_bytes(&mut store, large, 0, &[9u8; 500]);
+    let medium = store.create_context(0).expect("create").context_id;
+    append_bytes(&mut store, medium, 0, &[5u8; 100]);
+    append_bytes(&mut store, medium, 0, &[6u8; 100]);
+    store.create_context(0).expect("create empty");
+    let store = Mutex::new(store);
+
+    let by_bytes = top_contexts(&store, TopContextsBy::Bytes, 3);
+    let ids: Vec<u64> = by_bytes.contexts.iter().map(|s| s.context_id).collect();
+    assert_eq!(ids, vec![large, medium, small]);
+    assert_eq!(by_bytes.contexts[0].payload_bytes, 500);
+    assert!(by_bytes.skipped.is_empty());
+
+    let by_turns = top_contexts(&store, TopContextsBy::Turns, 2);
+    let ids: Vec<u64> = by_turns.contexts.iter().map(|s| s.context_id).collect();
+    assert_eq!(ids, vec![small, medium]);
+}
+
+#[test]
+fn get_at_depth_returns_window_ending_at_depth() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let mut turns = Vec::new();
+    for i in 0..6u8 {
+        turns.push(append_bytes(&mut store, ctx, 0, &[i]));
+    }
+
+    let window = store.get_at_depth(ctx, 3, 2, true).expect("around depth 3");
+    let depths: Vec<u32> = window.iter().map(|t| t.record.depth).collect();
+    assert_eq!(depths, vec![2, 3]);
+    assert_eq!(window[1].payload.as_deref(), Some(&[3u8][..]));
+
+    // Past the head: the nearest turn is the head itself.
+    let clamped = store.get_at_depth(ctx, 100, 2, false).expect("clamped");
+    let depths: Vec<u32> = clamped.iter().map(|t| t.record.depth).collect();
+    assert_eq!(depths, vec![4, 5]);
+
+    // A fork inherits the base chain, so shallow depths resolve to base turns.
+    let fork = store
+        .fork_context(turns[2].turn_id)
+        .expect("fork")
+        .context_id;
+    append_bytes(&mut store, fork, 0, b"fork");
+    let inherited = store.get_at_depth(fork, 1, 1, false).expect("fork depth 1");
+    assert_eq!(inherited[0].record.turn_id, turns[1].turn_id);
+}
+
+fn append_bytes(store: &mut Store, context_id: u64, parent: u64, payload: &[u8]) -> TurnRecord {
+    let hash = blake3::hash(payload);
+    let (record, _meta) = store
+        .append_turn(
+            context_id,
+            parent,
+            "com.example.Test".to_string(),
+            1,
+            1,
+            0,
+            payload.len() as u32,
+            *hash.as_bytes(),
+            payload,
+        )
+        .expect("append turn");
+    record
+}
+
 fn encode_context_metadata_payload(
     parent_context_id: Option<u64>,
     root_context_id: Option<u64>,
@@ -179,3 +698,945 @@ fn encode_context_metadata_payload(
     rmpv::encode::write_value(&mut payload, &root).expect("encode payload");
     payload
 }
+
+#[cfg(unix)]
+#[test]
+fn data_files_are_private_by_default() {
+    use std::os::unix::fs::PermissionsExt;
+
+    fn check_modes(path: &std::path::Path) {
+        for entry in std::fs::read_dir(path).expect("read dir") {
+            let path = entry.expect("entry").path();
+            let meta = std::fs::metadata(&path).expect("metadata");
+            let mode = meta.permissions().mode() & 0o777;
+            if meta.is_dir() {
+                assert_eq!(mode, 0o700, "{}", path.display());
+                check_modes(&path);
+            } else {
+                assert_eq!(mode, 0o600, "{}", path.display());
+            }
+        }
+    }
+
+    let dir = tempdir().expect("tempdir");
+    let data_dir = dir.path().join("data");
+    let mut store = Store::open(&data_dir).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+    append_bytes(&mut store, ctx, 0, b"secret");
+    cxdb_server::registry::Registry::open(&data_dir.join("registry")).expect("open registry");
+
+    let mode = std::fs::metadata(&data_dir).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode, 0o700);
+    check_modes(&data_dir);
+}
+
+fn encode_msgpack(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, value).expect("encode msgpack");
+    out
+}
+
+#[test]
+fn canonicalization_dedups_reordered_msgpack_maps() {
+    let dir = tempdir().expect("tempdir");
+    let options = StoreOptions {
+        canonicalize_msgpack: true,
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+
+    let a = encode_msgpack(&Value::Map(vec![
+        (Value::from(1), Value::from("user")),
+        (
+            Value::from(2),
+            Value::Map(vec![
+                (Value::from("b"), Value::from(2)),
+                (Value::from("a"), Value::from(1)),
+            ]),
+        ),
+    ]));
+    let b = encode_msgpack(&Value::Map(vec![
+        (
+            Value::from(2),
+            Value::Map(vec![
+                (Value::from("a"), Value::from(1)),
+                (Value::from("b"), Value::from(2)),
+            ]),
+        ),
+        (Value::from(1), Value::from("user")),
+    ]));
+    assert_ne!(a, b);
+
+    let first = append_bytes(&mut store, ctx, 0, &a);
+    let second = append_bytes(&mut store, ctx, first.turn_id, &b);
+    assert_eq!(first.payload_hash, second.payload_hash);
+    assert_ne!(first.payload_hash, *blake3::hash(&a).as_bytes());
+    assert_eq!(store.blob_store.stats().blobs_total, 1);
+
+    // Non-msgpack bytes are rejected rather than stored verbatim.
+    let truncated = b"\x92\x01";
+    let hash = blake3::hash(truncated);
+    let err = store
+        .append_turn(ctx, 0, "t".into(), 1, 1, 0, 2, *hash.as_bytes(), truncated)
+        .unwrap_err();
+    assert!(matches!(err, StoreError::InvalidInput(_)));
+}
+
+#[test]
+fn turn_context_ownership_survives_reopen() {
+    let dir = tempdir().expect("tempdir");
+    let (base, fork, t1, t2, side, t3) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let base = store.create_context(0).expect("create").context_id;
+        let t1 = append_bytes(&mut store, base, 0, b"one").turn_id;
+        let t2 = append_bytes(&mut store, base, 0, b"two").turn_id;
+        // A side branch off t1 never moves the head.
+        let side = append_bytes(&mut store, base, t1, b"side").turn_id;
+        let fork = store.fork_context(t2).expect("fork").context_id;
+        let t3 = append_bytes(&mut store, fork, 0, b"three").turn_id;
+        (base, fork, t1, t2, side, t3)
+    };
+
+    let store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.turn_context(t1), Some(base));
+    assert_eq!(store.turn_context(t2), Some(base));
+    assert_eq!(store.turn_context(side), Some(base));
+    assert_eq!(store.turn_context(t3), Some(fork));
+}
+
+#[test]
+fn derived_title_fills_in_only_when_no_explicit_title() {
+    let dir = tempdir().expect("tempdir");
+    let options = StoreOptions {
+        derived_title_chars: 12,
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("open store");
+
+    let untitled = store.create_context(0).expect("create").context_id;
+    let payload = encode_msgpack(&Value::Map(vec![
+        (
+            Value::from(2),
+            Value::from("How do I rotate my API keys?\nDetails follow"),
+        ),
+        (Value::from(1), Value::from(7)),
+    ]));
+    append_bytes(&mut store, untitled, 0, &payload);
+    let meta = store.get_context_metadata(untitled).expect("metadata");
+    assert_eq!(meta.title, None);
+    assert_eq!(meta.derived_title.as_deref(), Some("How do I rot"));
+
+    // An explicit title in the metadata block wins and nothing is derived.
+    let titled = store.create_context(0).expect("create").context_id;
+    let payload = encode_msgpack(&Value::Map(vec![
+        (Value::from(1), Value::from("hello there")),
+        (
+            Value::from(30),
+            Value::Map(vec![(Value::from(2), Value::from("Explicit"))]),
+        ),
+    ]));
+    append_bytes(&mut store, titled, 0, &payload);
+    let meta = store.get_context_metadata(titled).expect("metadata");
+    assert_eq!(meta.title.as_deref(), Some("Explicit"));
+    assert_eq!(meta.derived_title, None);
+
+    // An overlay title set later overrides the derived one.
+    let later = store
+        .create_context_with_metadata(
+            0,
+            MetadataOverlay {
+                title: Some("Renamed".into()),
+                ..MetadataOverlay::default()
+            },
+        )
+        .expect("create")
+        .context_id;
+    append_bytes(&mut store, later, 0, b"\x81\x01\xa4text");
+    let meta = store.get_context_metadata(later).expect("metadata");
+    assert_eq!(meta.title.as_deref(), Some("Renamed"));
+}
+
+#[test]
+fn blob_stats_report_compression_effectiveness() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    assert_eq!(store.stats().blobs_compression_ratio, 1.0);
+
+    // Pseudo-random bytes don't shrink, so they're stored raw.
+    let mut noise = vec![0u8; 64 * 1024];
+    blake3::Hasher::new()
+        .update(b"noise")
+        .finalize_xof()
+        .fill(&mut noise);
+    store
+        .blob_store
+        .put_if_absent(*blake3::hash(&noise).as_bytes(), &noise)
+        .expect("put noise");
+    let stats = store.stats();
+    assert_eq!(stats.blobs_compressed_total, 0);
+    assert_eq!(stats.blobs_compression_ratio, 1.0);
+
+    let zeros = vec![0u8; 64 * 1024];
+    let zeros_hash = *blake3::hash(&zeros).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(zeros_hash, &zeros)
+        .expect("put zeros");
+    let stats = store.stats();
+    assert_eq!(stats.blobs_total, 2);
+    assert_eq!(stats.blobs_compressed_total, 1);
+    let stored =
+        (noise.len() as u64 + store.blob_store.stored_len(&zeros_hash).unwrap() as u64) as f64;
+    let expected = (noise.len() + zeros.len()) as f64 / stored;
+    assert!((stats.blobs_compression_ratio - expected).abs() < 1e-9);
+    assert!(stats.blobs_compression_ratio > 1.9);
+}
+
+#[test]
+fn reads_report_stored_codec_not_append_codec() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create").context_id;
+
+    // Noise doesn't shrink, so the blob store keeps it raw even though the
+    // client appended it zstd-compressed.
+    let mut raw = vec![0u8; 4096];
+    blake3::Hasher::new()
+        .update(b"codec")
+        .finalize_xof()
+        .fill(&mut raw);
+    let compressed = zstd::encode_all(&raw[..], 3).expect("zstd");
+    let (record, _meta) = store
+        .append_turn(
+            ctx,
+            0,
+            "com.example.Test".to_string(),
+            1,
+            1,
+            1,
+            raw.len() as u32,
+            *blake3::hash(&raw).as_bytes(),
+            &compressed,
+        )
+        .expect("append zstd turn");
+
+    let last = store.get_last(ctx, 1, true).expect("get last");
+    assert_eq!(last[0].record.turn_id, record.turn_id);
+    assert_eq!(last[0].payload.as_deref(), Some(&raw[..]));
+    assert_eq!(last[0].meta.append_compression, 1);
+    assert_eq!(last[0].meta.uncompressed_len, raw.len() as u32);
+
+    let without_payload = store.get_last(ctx, 1, false).expect("get last");
+    let resp = encode_turns(&without_payload).expect("encode");
+    // count, turn_id, parent_turn_id, depth, type_id_len, type_id, version, encoding
+    let at = 4 + 8 + 8 + 4 + 4 + "com.example.Test".len() + 4 + 4;
+    let compression = u32::from_le_bytes(resp[at..at + 4].try_into().unwrap());
+    let uncompressed_len = u32::from_le_bytes(resp[at + 4..at + 8].try_into().unwrap());
+    assert_eq!(compression, 0);
+    assert_eq!(uncompressed_len, raw.len() as u32);
+}
+
+#[test]
+fn blobs_below_compress_threshold_are_stored_raw() {
+    let dir = tempdir().expect("tempdir");
+    let options = StoreOptions {
+        blob_compress_min_len: 1024,
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("open store");
+
+    let small = vec![b'a'; 512];
+    let small_hash = *blake3::hash(&small).as_bytes();
+    let entry = store
+        .blob_store
+        .put_if_absent(small_hash, &small)
+        .expect("put small");
+    assert_eq!(entry.codec, BlobCodec::None);
+    assert_eq!(entry.stored_len, small.len() as u32);
+
+    let large = vec![b'a'; 64 * 1024];
+    let large_hash = *blake3::hash(&large).as_bytes();
+    let entry = store
+        .blob_store
+        .put_if_absent(large_hash, &large)
+        .expect("put large");
+    assert_eq!(entry.codec, BlobCodec::Zstd);
+    assert!((entry.stored_len as usize) < large.len());
+
+    assert_eq!(store.blob_store.get(&small_hash).expect("get small"), small);
+    assert_eq!(store.blob_store.get(&large_hash).expect("get large"), large);
+}
+
+#[test]
+fn tiny_payloads_are_inlined_instead_of_stored_as_blobs() {
+    let dir = tempdir().expect("tempdir");
+    let options = || StoreOptions {
+        inline_payload_threshold: 16,
+        ..StoreOptions::default()
+    };
+    let (context_id, tiny_turn) = {
+        let mut store = Store::open_with_options(dir.path(), options()).expect("open store");
+        let context_id = store.create_context(0).expect("create").context_id;
+        let tiny = append_bytes(&mut store, context_id, 0, b"abc");
+        assert_eq!(store.blob_store.stats().blobs_total, 0);
+
+        let large = vec![b'x'; 64];
+        append_bytes(&mut store, context_id, tiny.turn_id, &large);
+        assert_eq!(
+            store.blob_store.hashes(),
+            vec![*blake3::hash(&large).as_bytes()]
+        );
+        (context_id, tiny.turn_id)
+    };
+
+    let mut store = Store::open_with_options(dir.path(), options()).expect("reopen store");
+    let turns = store.get_last(context_id, 2, true).expect("get_last");
+    assert_eq!(turns[0].record.turn_id, tiny_turn);
+    assert_eq!(turns[0].payload.as_deref(), Some(&b"abc"[..]));
+    assert_eq!(turns[0].stored_codec, BlobCodec::None);
+    assert_eq!(turns[1].payload.as_deref(), Some(&[b'x'; 64][..]));
+    assert_eq!(
+        store
+            .get_turn(tiny_turn)
+            .expect("get_turn")
+            .payload
+            .as_deref(),
+        Some(&b"abc"[..])
+    );
+    assert_eq!(
+        store
+            .context_stats(context_id)
+            .expect("stats")
+            .payload_bytes,
+        3 + 64
+    );
+
+    let report = store.verify().expect("verify");
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(store.gc().expect("gc").blobs_removed, 0);
+}
+
+#[test]
+fn encrypted_blobs_round_trip_without_plaintext_in_the_pack() {
+    let dir = tempdir().expect("tempdir");
+    let key = BlobKey::new([7; 32]);
+    // Inlining would put tiny payloads in turns.meta in the clear, so the
+    // threshold is ignored while a key is set.
+    let options = || StoreOptions {
+        blob_encryption_key: Some(key.clone()),
+        inline_payload_threshold: 1024,
+        ..StoreOptions::default()
+    };
+    let secret = b"the launch code is 0000, do not share it with anyone".repeat(4);
+    let (context_id, turn_id) = {
+        let mut store = Store::open_with_options(dir.path(), options()).expect("open store");
+        let context_id = store.create_context(0).expect("create").context_id;
+        let turn = append_bytes(&mut store, context_id, 0, &secret);
+        (context_id, turn.turn_id)
+    };
+
+    for file in [
+        dir.path().join("blobs").join("blobs.pack"),
+        dir.path().join("turns").join("turns.meta"),
+    ] {
+        let bytes = std::fs::read(&file).expect("read data file");
+        assert!(
+            !bytes
+                .windows(16)
+                .any(|w| secret.windows(16).any(|s| s == w)),
+            "plaintext in {}",
+            file.display()
+        );
+    }
+
+    let mut store = Store::open_with_options(dir.path(), options()).expect("reopen store");
+    let turns = store.get_last(context_id, 1, true).expect("get_last");
+    assert_eq!(turns[0].record.turn_id, turn_id);
+    assert_eq!(turns[0].payload.as_deref(), Some(&secret[..]));
+    // Content-addressed over the plaintext, so a second put dedups.
+    let hash = *blake3::hash(&secret).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(hash, &secret)
+        .expect("put again");
+    assert_eq!(store.blob_store.hashes(), vec![hash]);
+
+    let mut wrong_key = Store::open_with_options(
+        dir.path(),
+        StoreOptions {
+            blob_encryption_key: Some(BlobKey::new([8; 32])),
+            ..StoreOptions::default()
+        },
+    )
+    .expect("open with another key");
+    assert!(matches!(
+        wrong_key.blob_store.get(&hash),
+        Err(StoreError::Corrupt(_))
+    ));
+    drop(wrong_key);
+    let mut no_key = Store::open(dir.path()).expect("open without a key");
+    assert!(no_key.blob_store.get(&hash).is_err());
+}
+
+#[test]
+fn explain_matches_reports_matched_fields_and_values() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let root = store.create_context(0).expect("create").context_id;
+    append_bytes(
+        &mut store,
+        root,
+        0,
+        &encode_context_metadata_payload(None, None),
+    );
+    let child = store.create_context(0).expect("create").context_id;
+    let payload = encode_context_metadata_payload(Some(root), Some(root));
+    append_bytes(&mut store, child, 0, &payload);
+
+    let explain = SearchOptions {
+        explain_matches: true,
+        ..SearchOptions::default()
+    };
+    let search = |query: &str| {
+        store
+            .search_contexts_with_options(query, &HashSet::new(), None, &explain)
+            .expect("search")
+    };
+
+    let found = search(r#"tag = "test-client""#);
+    assert_eq!(found.context_ids, vec![child, root]);
+    for id in &found.context_ids {
+        assert_eq!(
+            serde_json::Value::Object(found.matched_on[id].clone()),
+            serde_json::json!({"tag": "test-client"})
+        );
+    }
+
+    // Negated comparisons don't explain anything.
+    let found = search(&format!(r#"tag = "test-client" AND NOT parent = {root}"#));
+    assert_eq!(found.context_ids, vec![root]);
+    assert_eq!(
+        serde_json::Value::Object(found.matched_on[&root].clone()),
+        serde_json::json!({"tag": "test-client"})
+    );
+
+    let found = search(&format!("parent = {root} OR depth = 0"));
+    assert_eq!(
+        serde_json::Value::Object(found.matched_on[&child].clone()),
+        serde_json::json!({"parent": root.to_string(), "depth": 0})
+    );
+
+    // Off by default.
+    let found = store
+        .search_contexts(r#"tag = "test-client""#, &HashSet::new(), None)
+        .expect("search");
+    assert!(found.matched_on.is_empty());
+}
+
+#[test]
+fn background_index_warmup_opens_without_reading_blobs_and_converges() {
+    let dir = tempdir().expect("tempdir");
+    let contexts: Vec<u64> = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        (0..5)
+            .map(|_| {
+                let ctx = store.create_context(0).expect("create").context_id;
+                let payload = encode_context_metadata_payload(None, None);
+                append_bytes(&mut store, ctx, 0, &payload);
+                ctx
+            })
+            .collect()
+    };
+    let query = r#"tag = "test-client""#;
+
+    let options = StoreOptions {
+        background_index_warmup: true,
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("reopen");
+    assert_eq!(store.blob_store.pack_reads(), 0);
+    let progress = store.index_warmup_progress();
+    assert_eq!((progress.indexed, progress.total), (0, 5));
+    let found = store
+        .search_contexts(query, &HashSet::new(), None)
+        .expect("search");
+    assert!(found.partial);
+    assert!(found.context_ids.is_empty());
+
+    // Metadata is still served on demand, and appending to a context the
+    // warmup hasn't reached indexes it from its real first turn.
+    let first = contexts[0];
+    assert_eq!(
+        store
+            .get_context_metadata(first)
+            .and_then(|m| m.client_tag)
+            .as_deref(),
+        Some("test-client")
+    );
+    let head = store.get_head(first).expect("head").head_turn_id;
+    let (_, metadata) = store
+        .append_turn(
+            first,
+            head,
+            "com.example.Test".to_string(),
+            1,
+            1,
+            0,
+            2,
+            *blake3::hash(b"hi").as_bytes(),
+            b"hi",
+        )
+        .expect("append");
+    assert!(metadata.is_none());
+    assert_eq!(store.index_warmup_progress().indexed, 1);
+
+    assert!(!store.warm_indexes(2));
+    let found = store
+        .search_contexts(query, &HashSet::new(), None)
+        .expect("search");
+    assert!(found.partial);
+    assert_eq!(found.context_ids.len(), 3);
+
+    let store = std::sync::Arc::new(std::sync::Mutex::new(store));
+    spawn_index_warmup(store.clone()).join().expect("warmup");
+    let store = store.lock().unwrap();
+    assert!(store.index_warmup_progress().is_complete());
+    let found = store
+        .search_contexts(query, &HashSet::new(), None)
+        .expect("search");
+    assert!(!found.partial);
+    let mut expected = contexts.clone();
+    expected.sort_unstable_by(|a, b| b.cmp(a));
+    assert_eq!(found.context_ids, expected);
+    let found = store
+        .search_contexts("turn_count = 2", &HashSet::new(), None)
+        .expect("search");
+    assert_eq!(found.context_ids, vec![first]);
+}
+
+#[test]
+fn archived_context_reloads_on_access() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let turns: Vec<TurnRecord> = (0..4u8)
+        .map(|i| append_bytes(&mut store, ctx, 0, &[i]))
+        .collect();
+    // The fork keeps the first two turns reachable from a resident context.
+    let fork = store
+        .fork_context(turns[1].turn_id)
+        .expect("fork")
+        .context_id;
+    append_bytes(&mut store, fork, 0, b"fork");
+    let before = store.get_last(ctx, 10, true).expect("get_last");
+
+    assert_eq!(store.archive_context(ctx).expect("archive"), 2);
+    assert!(store.turn_store.is_archived(ctx));
+    assert!(store.turn_store.get_turn(turns[3].turn_id).is_err());
+    assert_eq!(store.turn_store.stats().turns_total, 5);
+    assert_eq!(store.list_recent_contexts(10).len(), 2);
+
+    let after = store
+        .get_last(ctx, 10, true)
+        .expect("get_last after archive");
+    assert!(!store.turn_store.is_archived(ctx));
+    assert_eq!(after.len(), before.len());
+    for (a, b) in after.iter().zip(&before) {
+        assert_eq!(a.record.turn_id, b.record.turn_id);
+        assert_eq!(a.record.depth, b.record.depth);
+        assert_eq!(a.meta.declared_type_id, b.meta.declared_type_id);
+        assert_eq!(a.payload, b.payload);
+    }
+
+    // Idle sweep: nothing has been touched within a zero window.
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    assert_eq!(
+        store
+            .archive_idle_contexts(std::time::Duration::ZERO)
+            .expect("sweep"),
+        2
+    );
+    let next = append_bytes(&mut store, ctx, 0, b"next");
+    assert_eq!(next.parent_turn_id, turns[3].turn_id);
+    let fork_turns = store.get_last(fork, 10, false).expect("fork get_last");
+    assert_eq!(fork_turns[0].record.turn_id, turns[0].turn_id);
+    assert_eq!(fork_turns.len(), 3);
+}
+
+#[test]
+fn retention_tombstones_turns_beyond_the_newest_n() {
+    let dir = tempdir().expect("tempdir");
+    let options = StoreOptions {
+        retention: RetentionPolicy {
+            max_turns: Some(3),
+            max_age: None,
+        },
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options.clone()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let turns: Vec<TurnRecord> = (0..8u8)
+        .map(|i| append_bytes(&mut store, ctx, 0, &[i]))
+        .collect();
+    // A label overrides the global policy.
+    let labelled = store
+        .create_context_with_metadata(
+            0,
+            MetadataOverlay {
+                labels: Some(vec!["retain:2".to_string()]),
+                ..MetadataOverlay::default()
+            },
+        )
+        .expect("create labelled")
+        .context_id;
+    for i in 0..4u8 {
+        append_bytes(&mut store, labelled, 0, &[i]);
+    }
+
+    assert_eq!(store.apply_retention().expect("retention"), 4 + 1);
+    assert_eq!(store.apply_retention().expect("idempotent"), 0);
+
+    let head = store.get_head(ctx).expect("head");
+    assert_eq!(head.head_turn_id, turns[7].turn_id);
+    assert_eq!(head.head_depth, 7);
+    let visible: Vec<u32> = store
+        .get_last(ctx, 100, false)
+        .expect("get_last")
+        .iter()
+        .map(|t| t.record.depth)
+        .collect();
+    // The first turn carries the context's metadata and is always kept.
+    assert_eq!(visible, vec![0, 5, 6, 7]);
+    assert!(matches!(
+        store.get_turn(turns[3].turn_id),
+        Err(StoreError::NotFound(NotFoundKind::Turn, _))
+    ));
+    let labelled_depths: Vec<u32> = store
+        .get_last(labelled, 100, false)
+        .expect("labelled get_last")
+        .iter()
+        .map(|t| t.record.depth)
+        .collect();
+    assert_eq!(labelled_depths, vec![0, 2, 3]);
+
+    // Appends keep numbering from the untouched head, and tombstones persist.
+    let next = append_bytes(&mut store, ctx, 0, b"next");
+    assert_eq!(next.depth, 8);
+    drop(store);
+    let mut store = Store::open_with_options(dir.path(), options).expect("reopen");
+    let window = store
+        .get_before(ctx, turns[6].turn_id, 3, true)
+        .expect("before");
+    let depths: Vec<u32> = window.iter().map(|t| t.record.depth).collect();
+    assert_eq!(depths, vec![5]);
+    assert_eq!(window[0].payload.as_deref(), Some(&[5u8][..]));
+}
+
+#[test]
+fn retention_sweeps_leave_archived_contexts_archived() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    assert!(!store.has_retention_policies());
+
+    let labelled = store
+        .create_context_with_metadata(
+            0,
+            MetadataOverlay {
+                labels: Some(vec!["retain:1".to_string()]),
+                ..MetadataOverlay::default()
+            },
+        )
+        .expect("create labelled")
+        .context_id;
+    for i in 0..4u8 {
+        append_bytes(&mut store, labelled, 0, &[i]);
+    }
+    assert!(store.has_retention_policies());
+
+    store.archive_context(labelled).expect("archive");
+    assert_eq!(store.retention_candidates(), Vec::<u64>::new());
+    assert_eq!(store.apply_retention().expect("retention"), 0);
+    assert!(store.turn_store.is_archived(labelled));
+
+    // Once something restores it, the next sweep catches up.
+    store.get_last(labelled, 1, false).expect("restore");
+    assert_eq!(store.apply_retention().expect("retention"), 2);
+}
+
+struct RejectType(&'static str);
+
+impl AppendInterceptor for RejectType {
+    fn before_append(
+        &self,
+        ctx: &AppendContext<'_>,
+        _payload: &[u8],
+    ) -> cxdb_server::error::Result<Option<Vec<u8>>> {
+        if ctx.declared_type_id == self.0 {
+            return Err(StoreError::InvalidInput(format!(
+                "{} is not allowed",
+                self.0
+            )));
+        }
+        Ok(None)
+    }
+}
+
+struct Suffix(&'static [u8]);
+
+impl AppendInterceptor for Suffix {
+    fn before_append(
+        &self,
+        _ctx: &AppendContext<'_>,
+        payload: &[u8],
+    ) -> cxdb_server::error::Result<Option<Vec<u8>>> {
+        Ok(Some([payload, self.0].concat()))
+    }
+}
+
+#[test]
+fn interceptors_reject_or_rewrite_appends() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open_with_interceptors(
+        dir.path(),
+        StoreOptions::default(),
+        vec![
+            Box::new(RejectType("com.example.Secret")),
+            Box::new(Suffix(b"!")),
+            Box::new(MaxPayloadSize { max_bytes: 8 }),
+        ],
+    )
+    .expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+
+    let payload = b"hidden";
+    let rejected = store.append_turn(
+        ctx,
+        0,
+        "com.example.Secret".to_string(),
+        1,
+        1,
+        0,
+        payload.len() as u32,
+        *blake3::hash(payload).as_bytes(),
+        payload,
+    );
+    assert!(matches!(rejected, Err(StoreError::InvalidInput(msg)) if msg.contains("not allowed")));
+    assert_eq!(store.get_head(ctx).expect("head").head_turn_id, 0);
+
+    // The rewrite runs before the size guard, which sees the longer payload.
+    let stored = append_bytes(&mut store, ctx, 0, b"hello");
+    assert_eq!(stored.payload_hash, *blake3::hash(b"hello!").as_bytes());
+    let turn = store.get_turn(stored.turn_id).expect("turn");
+    assert_eq!(turn.payload.as_deref(), Some(&b"hello!"[..]));
+    assert_eq!(turn.meta.uncompressed_len, 6);
+
+    let too_big = b"12345678";
+    let result = store.append_turn(
+        ctx,
+        0,
+        "com.example.Test".to_string(),
+        1,
+        1,
+        0,
+        too_big.len() as u32,
+        *blake3::hash(too_big).as_bytes(),
+        too_big,
+    );
+    assert!(matches!(result, Err(StoreError::InvalidInput(msg)) if msg.contains("limit is 8")));
+    assert_eq!(store.get_head(ctx).expect("head").turn_count, 1);
+}
+
+#[test]
+fn gc_and_compact_reclaim_tombstoned_payloads() {
+    let dir = tempdir().expect("tempdir");
+    let options = StoreOptions {
+        retention: RetentionPolicy {
+            max_turns: Some(2),
+            max_age: None,
+        },
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options.clone()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let turns: Vec<TurnRecord> = (0..5u8)
+        .map(|i| append_bytes(&mut store, ctx, 0, &[i; 64]))
+        .collect();
+    // The first turn is always kept, so only the middle two are tombstoned.
+    assert_eq!(store.apply_retention().expect("retention"), 2);
+
+    let before = store.verify().expect("verify");
+    assert!(before.is_ok(), "{:?}", before.problems);
+    assert_eq!(before.blobs_unreferenced, 2);
+
+    let gc = store.gc().expect("gc");
+    assert_eq!((gc.blobs_kept, gc.blobs_removed), (3, 2));
+    assert!(!store.blob_store.contains(&turns[1].payload_hash));
+    let stale_idx = std::fs::read(dir.path().join("blobs/blobs.idx")).expect("read idx");
+    let compact = store.compact().expect("compact");
+    assert!(compact.pack_bytes_after < compact.pack_bytes_before);
+    assert!(!dir.path().join("blobs/blobs.compacting").exists());
+    drop(store);
+
+    // A crash after the pack swap but before the index swap leaves the old
+    // index next to the new pack; the marker makes open rebuild the index.
+    std::fs::write(dir.path().join("blobs/blobs.idx"), stale_idx).expect("write idx");
+    std::fs::write(dir.path().join("blobs/blobs.compacting"), b"").expect("write marker");
+
+    let mut store = Store::open_with_options(dir.path(), options).expect("reopen store");
+    let after = store.verify().expect("verify");
+    assert!(after.is_ok(), "{:?}", after.problems);
+    assert_eq!((after.blobs_checked, after.blobs_unreferenced), (3, 0));
+    let payload = store
+        .get_blob(&turns[4].payload_hash)
+        .expect("kept payload");
+    assert_eq!(payload, vec![4u8; 64]);
+
+    let reindex = store.reindex().expect("reindex");
+    assert_eq!(
+        (
+            reindex.turns_indexed,
+            reindex.blobs_indexed,
+            reindex.pack_bytes_skipped
+        ),
+        (5, 3, 0)
+    );
+}
+
+#[test]
+fn idempotency_keys_conflict_on_a_new_payload_and_expire() {
+    let dir = tempdir().expect("tempdir");
+    let clock = Arc::new(MockClock::new(1_700_000_000_000));
+    let options = || StoreOptions {
+        clock: clock.clone(),
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let first = append_bytes(&mut store, ctx, 0, b"first");
+    store
+        .record_idempotency_key(ctx, b"k1".to_vec(), first.payload_hash, first.turn_id)
+        .expect("record key");
+
+    let replay = store
+        .idempotent_turn(ctx, b"k1", &first.payload_hash)
+        .expect("lookup");
+    assert_eq!(replay.map(|r| r.turn_id), Some(first.turn_id));
+    let err = store
+        .idempotent_turn(ctx, b"k1", blake3::hash(b"other").as_bytes())
+        .expect_err("reused key");
+    assert!(matches!(err, StoreError::Conflict(_)), "{err}");
+    assert_eq!(map_store_error(&err).0, 409);
+
+    // Once expired, the key answers nothing and the table drops it on open.
+    clock.advance(Duration::from_secs(25 * 60 * 60));
+    assert!(store
+        .idempotent_turn(ctx, b"k1", &first.payload_hash)
+        .expect("lookup")
+        .is_none());
+    let second = append_bytes(&mut store, ctx, 0, b"second");
+    store
+        .record_idempotency_key(ctx, b"k2".to_vec(), second.payload_hash, second.turn_id)
+        .expect("record key");
+    let table = dir.path().join("turns").join("idempotency.tbl");
+    let before = std::fs::metadata(&table).expect("table").len();
+    drop(store);
+
+    let mut store = Store::open_with_options(dir.path(), options()).expect("reopen store");
+    assert_eq!(std::fs::metadata(&table).expect("table").len(), before / 2);
+    let replay = store
+        .idempotent_turn(ctx, b"k2", &second.payload_hash)
+        .expect("lookup");
+    assert_eq!(replay.map(|r| r.turn_id), Some(second.turn_id));
+}
+
+#[test]
+fn timestamps_come_from_the_injected_clock() {
+    let dir = tempdir().expect("tempdir");
+    let clock = Arc::new(MockClock::new(1_700_000_000_000));
+    let options = StoreOptions {
+        clock: clock.clone(),
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    assert_eq!(ctx.created_at_unix_ms, 1_700_000_000_000);
+    let first = append_bytes(&mut store, ctx.context_id, 0, b"first");
+    assert_eq!(first.created_at_unix_ms, 1_700_000_000_000);
+
+    clock.advance(Duration::from_secs(90));
+    let second = append_bytes(&mut store, ctx.context_id, 0, b"second");
+    assert_eq!(second.created_at_unix_ms, 1_700_000_090_000);
+    // The head record is restamped on every append.
+    assert_eq!(
+        store
+            .get_head(ctx.context_id)
+            .expect("head")
+            .created_at_unix_ms,
+        1_700_000_090_000
+    );
+}
+
+#[test]
+fn tag_normalization_coalesces_mixed_case_tags() {
+    let tags = ["Kilroy", " kilroy", "KILROY "];
+    let query_ids = |store: &Store, query: &str| {
+        let mut ids = store
+            .search_contexts(query, &HashSet::new(), None)
+            .expect("search")
+            .context_ids;
+        ids.sort_unstable();
+        ids
+    };
+    let create_tagged = |store: &mut Store| -> Vec<u64> {
+        tags.iter()
+            .map(|tag| {
+                store
+                    .create_context_with_metadata(
+                        0,
+                        MetadataOverlay {
+                            client_tag: Some(tag.to_string()),
+                            ..MetadataOverlay::default()
+                        },
+                    )
+                    .expect("create")
+                    .context_id
+            })
+            .collect()
+    };
+
+    // By default tags are exact strings.
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ids = create_tagged(&mut store);
+    assert_eq!(query_ids(&store, r#"tag = "Kilroy""#), ids[..1]);
+
+    let dir = tempdir().expect("tempdir");
+    assert!(matches!(
+        TagNormalization::parse("lowercase,trimm"),
+        Err(StoreError::InvalidInput(msg)) if msg.contains("trimm")
+    ));
+    let normalization = TagNormalization::parse("lowercase,trim").expect("parse");
+    let options = StoreOptions {
+        tag_normalization: normalization,
+        ..StoreOptions::default()
+    };
+    let mut store = Store::open_with_options(dir.path(), options).expect("open store");
+    let ids = create_tagged(&mut store);
+    for id in &ids {
+        let metadata = store.get_context_metadata(*id).expect("metadata");
+        assert_eq!(metadata.client_tag.as_deref(), Some("kilroy"));
+    }
+    assert_eq!(query_ids(&store, r#"tag = "Kilroy""#), ids);
+    assert_eq!(query_ids(&store, r#"tag = "kilroy""#), ids);
+    assert_eq!(query_ids(&store, r#"tag ^= "KIL""#), ids);
+
+    let tracker = SessionTracker::new().with_tag_normalization(normalization);
+    for (session_id, tag) in tags.iter().enumerate() {
+        tracker.register(session_id as u64, tag.to_string(), None);
+    }
+    assert_eq!(tracker.get_active_tags(), ["kilroy"]);
+}